@@ -0,0 +1,28 @@
+-- Magic links emailed to subscribers so that they can access or erase their data
+CREATE TABLE data_access_tokens(
+   token TEXT NOT NULL,
+   PRIMARY KEY (token),
+   subscriber_id uuid NOT NULL
+      REFERENCES subscriptions (id) ON DELETE CASCADE,
+   created_at timestamptz NOT NULL,
+   expires_at timestamptz NOT NULL
+);
+
+-- Hashes of erased email addresses, so that they are not imported again
+CREATE TABLE suppressed_emails(
+   email_hash TEXT NOT NULL,
+   PRIMARY KEY (email_hash),
+   suppressed_at timestamptz NOT NULL
+);
+
+CREATE TABLE audit_log(
+   id uuid NOT NULL,
+   PRIMARY KEY (id),
+   -- NULL when the action was taken by the subscriber themselves
+   actor_user_id uuid NULL
+      REFERENCES users (user_id),
+   action TEXT NOT NULL,
+   subject TEXT NOT NULL,
+   details JSONB NOT NULL,
+   created_at timestamptz NOT NULL
+);
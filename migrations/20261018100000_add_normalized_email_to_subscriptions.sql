@@ -0,0 +1,45 @@
+BEGIN;
+    -- IDN domains are normalized to punycode by the application, which SQL cannot do:
+    -- they have to be converted before migrating, or the unique index below would not
+    -- catch them signing up again.
+    DO $$
+    BEGIN
+        IF EXISTS (
+            SELECT 1 FROM subscriptions
+            WHERE substring(email from '@([^@]*)$') !~ '^[\x01-\x7f]*$'
+        ) THEN
+            RAISE EXCEPTION 'Some subscriptions have a non-ASCII email domain. Rewrite these domains in punycode (`xn--...`) and run the migration again.';
+        END IF;
+    END $$;
+
+    ALTER TABLE subscriptions ADD COLUMN email_normalized TEXT NULL;
+
+    -- Backfill the normalized form for historical entries: trimmed, with a lowercased domain,
+    -- as `SubscriberEmail::normalized` does for ASCII domains.
+    UPDATE subscriptions SET email = trim(email);
+    UPDATE subscriptions
+        SET email_normalized =
+            substring(email from '^(.*)@[^@]*$') || '@' || lower(substring(email from '@([^@]*)$'));
+
+    -- Deduplicate addresses only differing by case, keeping the confirmed (or oldest) row
+    CREATE TEMPORARY TABLE duplicate_subscriptions ON COMMIT DROP AS
+        SELECT id FROM (
+            SELECT id, row_number() OVER (
+                PARTITION BY lower(email_normalized)
+                ORDER BY (status = 'confirmed') DESC, subscribed_at, id
+            ) AS position
+            FROM subscriptions
+        ) AS ranked
+        WHERE position > 1;
+
+    DELETE FROM subscription_tokens
+        WHERE subscriber_id IN (SELECT id FROM duplicate_subscriptions);
+    DELETE FROM subscriptions
+        WHERE id IN (SELECT id FROM duplicate_subscriptions);
+
+    -- Uniqueness is now enforced on the normalized form, regardless of case
+    ALTER TABLE subscriptions ALTER COLUMN email_normalized SET NOT NULL;
+    ALTER TABLE subscriptions DROP CONSTRAINT subscriptions_email_key;
+    CREATE UNIQUE INDEX subscriptions_email_normalized_key
+        ON subscriptions (lower(email_normalized));
+COMMIT;
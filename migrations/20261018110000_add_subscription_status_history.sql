@@ -0,0 +1,29 @@
+BEGIN;
+    ALTER TABLE subscriptions
+        ADD CONSTRAINT subscriptions_status_check CHECK (status IN (
+            'pending_confirmation', 'confirmed', 'unsubscribed', 'bounced', 'complained', 'deleted'
+        ));
+
+    ALTER TABLE subscriptions ADD COLUMN status_changed_at timestamptz NULL;
+    UPDATE subscriptions SET status_changed_at = subscribed_at;
+    ALTER TABLE subscriptions ALTER COLUMN status_changed_at SET NOT NULL;
+
+    -- When and why each subscriber changed status
+    CREATE TABLE subscription_status_history(
+       id uuid NOT NULL,
+       PRIMARY KEY (id),
+       subscriber_id uuid NOT NULL
+          REFERENCES subscriptions (id) ON DELETE CASCADE,
+       -- NULL for the status a subscriber was created with
+       from_status TEXT NULL,
+       to_status TEXT NOT NULL,
+       reason TEXT NOT NULL,
+       changed_at timestamptz NOT NULL
+    );
+    CREATE INDEX subscription_status_history_subscriber_id_idx
+        ON subscription_status_history (subscriber_id, changed_at);
+
+    INSERT INTO subscription_status_history (id, subscriber_id, from_status, to_status, reason, changed_at)
+    SELECT md5(random()::text || id::text)::uuid, id, NULL, status, 'Recorded before status history was kept', subscribed_at
+    FROM subscriptions;
+COMMIT;
@@ -0,0 +1,5 @@
+BEGIN;
+    ALTER TABLE subscriptions ADD COLUMN locale TEXT NULL;
+    UPDATE subscriptions SET locale = 'en';
+    ALTER TABLE subscriptions ALTER COLUMN locale SET NOT NULL;
+COMMIT;
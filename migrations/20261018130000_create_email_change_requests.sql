@@ -0,0 +1,13 @@
+-- Email address changes requested through the magic link, pending confirmation
+-- of the new address. The subscription keeps its address until then.
+CREATE TABLE email_change_requests(
+   token TEXT NOT NULL,
+   PRIMARY KEY (token),
+   subscriber_id uuid NOT NULL
+      REFERENCES subscriptions (id) ON DELETE CASCADE,
+   new_email TEXT NOT NULL,
+   new_email_normalized TEXT NOT NULL,
+   created_at timestamptz NOT NULL,
+   expires_at timestamptz NOT NULL
+);
+CREATE INDEX email_change_requests_subscriber_id_idx ON email_change_requests (subscriber_id);
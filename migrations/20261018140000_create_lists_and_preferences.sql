@@ -0,0 +1,29 @@
+BEGIN;
+    -- The lists, or topics, subscribers can pick from in the preference center
+    CREATE TABLE lists(
+       id uuid NOT NULL,
+       PRIMARY KEY (id),
+       slug TEXT NOT NULL UNIQUE,
+       name TEXT NOT NULL,
+       created_at timestamptz NOT NULL
+    );
+
+    CREATE TABLE subscription_lists(
+       subscriber_id uuid NOT NULL
+          REFERENCES subscriptions (id) ON DELETE CASCADE,
+       list_id uuid NOT NULL
+          REFERENCES lists (id) ON DELETE CASCADE,
+       PRIMARY KEY (subscriber_id, list_id)
+    );
+    CREATE INDEX subscription_lists_list_id_idx ON subscription_lists (list_id);
+
+    -- Everyone was receiving the one newsletter so far
+    INSERT INTO lists (id, slug, name, created_at)
+        VALUES (md5(random()::text)::uuid, 'newsletter', 'Newsletter', now());
+    INSERT INTO subscription_lists (subscriber_id, list_id)
+        SELECT subscriptions.id, lists.id FROM subscriptions CROSS JOIN lists;
+
+    ALTER TABLE subscriptions
+        ADD COLUMN delivery_frequency TEXT NOT NULL DEFAULT 'every_issue'
+        CHECK (delivery_frequency IN ('every_issue', 'weekly_digest'));
+COMMIT;
@@ -0,0 +1,10 @@
+-- Where subscribers came from, as reported by the subscribe form or the page hosting it
+ALTER TABLE subscriptions
+    ADD COLUMN source TEXT NULL,
+    ADD COLUMN utm_source TEXT NULL,
+    ADD COLUMN utm_medium TEXT NULL,
+    ADD COLUMN utm_campaign TEXT NULL,
+    ADD COLUMN utm_term TEXT NULL,
+    ADD COLUMN utm_content TEXT NULL,
+    ADD COLUMN referrer TEXT NULL,
+    ADD COLUMN landing_url TEXT NULL;
@@ -0,0 +1,18 @@
+-- Every newsletter issue published, with its content and send state
+CREATE TABLE newsletter_issues(
+   id uuid NOT NULL,
+   PRIMARY KEY (id),
+   title TEXT NOT NULL,
+   html_content TEXT NOT NULL,
+   text_content TEXT NOT NULL,
+   author_user_id uuid NOT NULL
+      REFERENCES users (user_id),
+   -- the list the issue is sent to, every confirmed subscriber when NULL
+   list_id uuid NULL
+      REFERENCES lists (id) ON DELETE SET NULL,
+   status TEXT NOT NULL
+      CONSTRAINT newsletter_issues_status_check CHECK (status IN ('sending', 'sent')),
+   created_at timestamptz NOT NULL,
+   sent_at timestamptz NULL
+);
+CREATE INDEX newsletter_issues_created_at_idx ON newsletter_issues (created_at);
@@ -0,0 +1,14 @@
+-- Responses to requests carrying an `Idempotency-Key`, replayed when the request is retried
+CREATE TABLE idempotency(
+   user_id uuid NOT NULL
+      REFERENCES users (user_id),
+   idempotency_key TEXT NOT NULL,
+   PRIMARY KEY (user_id, idempotency_key),
+   -- a hex encoded SHA-256 of the request, telling a retry from another request reusing the key
+   request_hash TEXT NOT NULL,
+   -- NULL until the first request is done
+   response_status_code SMALLINT NULL,
+   response_headers JSONB NULL,
+   response_body BYTEA NULL,
+   created_at timestamptz NOT NULL
+);
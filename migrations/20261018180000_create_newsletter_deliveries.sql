@@ -0,0 +1,18 @@
+-- One row per subscriber an issue is sent to, claimed and processed by the delivery worker
+CREATE TABLE newsletter_deliveries(
+   issue_id uuid NOT NULL
+      REFERENCES newsletter_issues (id) ON DELETE CASCADE,
+   subscriber_id uuid NOT NULL
+      REFERENCES subscriptions (id) ON DELETE CASCADE,
+   PRIMARY KEY (issue_id, subscriber_id),
+   status TEXT NOT NULL
+      CONSTRAINT newsletter_deliveries_status_check
+      CHECK (status IN ('pending', 'sent', 'skipped', 'failed')),
+   created_at timestamptz NOT NULL,
+   -- when the delivery left the pending state
+   processed_at timestamptz NULL
+);
+-- the worker only ever looks for pending deliveries
+CREATE INDEX newsletter_deliveries_pending_idx
+   ON newsletter_deliveries (created_at) WHERE status = 'pending';
+CREATE INDEX newsletter_deliveries_subscriber_id_idx ON newsletter_deliveries (subscriber_id);
@@ -0,0 +1,27 @@
+-- Failed deliveries are retried until they run out of attempts and land in the dead letter state
+ALTER TABLE newsletter_deliveries ADD COLUMN attempts SMALLINT NOT NULL DEFAULT 0;
+ALTER TABLE newsletter_deliveries ADD COLUMN next_attempt_at timestamptz NULL;
+UPDATE newsletter_deliveries SET next_attempt_at = created_at;
+ALTER TABLE newsletter_deliveries ALTER COLUMN next_attempt_at SET NOT NULL;
+
+ALTER TABLE newsletter_deliveries DROP CONSTRAINT newsletter_deliveries_status_check;
+UPDATE newsletter_deliveries SET status = 'dead_letter' WHERE status = 'failed';
+ALTER TABLE newsletter_deliveries ADD CONSTRAINT newsletter_deliveries_status_check
+   CHECK (status IN ('pending', 'sent', 'skipped', 'dead_letter'));
+
+DROP INDEX newsletter_deliveries_pending_idx;
+CREATE INDEX newsletter_deliveries_pending_idx
+   ON newsletter_deliveries (next_attempt_at) WHERE status = 'pending';
+
+-- Every attempt at sending a delivery, with the error if it failed
+CREATE TABLE delivery_attempts(
+   issue_id uuid NOT NULL,
+   subscriber_id uuid NOT NULL,
+   attempt SMALLINT NOT NULL,
+   PRIMARY KEY (issue_id, subscriber_id, attempt),
+   FOREIGN KEY (issue_id, subscriber_id)
+      REFERENCES newsletter_deliveries (issue_id, subscriber_id) ON DELETE CASCADE,
+   attempted_at timestamptz NOT NULL,
+   -- NULL when the attempt succeeded
+   error TEXT NULL
+);
@@ -0,0 +1,8 @@
+-- Issues can be scheduled to start sending at a later time, and cancelled until then
+ALTER TABLE newsletter_issues ADD COLUMN send_at timestamptz NULL;
+ALTER TABLE newsletter_issues DROP CONSTRAINT newsletter_issues_status_check;
+ALTER TABLE newsletter_issues ADD CONSTRAINT newsletter_issues_status_check
+   CHECK (status IN ('scheduled', 'cancelled', 'sending', 'sent'));
+-- the scheduler only ever looks for scheduled issues
+CREATE INDEX newsletter_issues_scheduled_idx
+   ON newsletter_issues (send_at) WHERE status = 'scheduled';
@@ -0,0 +1,22 @@
+-- Issues can start as drafts, edited until an explicit send
+ALTER TABLE newsletter_issues DROP CONSTRAINT newsletter_issues_status_check;
+ALTER TABLE newsletter_issues ADD CONSTRAINT newsletter_issues_status_check
+   CHECK (status IN ('draft', 'scheduled', 'cancelled', 'sending', 'sent'));
+
+-- Every saved version of the content of an issue, the latest one being the current content
+CREATE TABLE newsletter_issue_revisions(
+   issue_id uuid NOT NULL
+      REFERENCES newsletter_issues (id) ON DELETE CASCADE,
+   revision INTEGER NOT NULL,
+   PRIMARY KEY (issue_id, revision),
+   title TEXT NOT NULL,
+   html_content TEXT NOT NULL,
+   text_content TEXT NOT NULL,
+   author_user_id uuid NOT NULL
+      REFERENCES users (user_id),
+   created_at timestamptz NOT NULL
+);
+INSERT INTO newsletter_issue_revisions
+   (issue_id, revision, title, html_content, text_content, author_user_id, created_at)
+SELECT id, 1, title, html_content, text_content, author_user_id, created_at
+FROM newsletter_issues;
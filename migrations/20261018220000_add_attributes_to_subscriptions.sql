@@ -0,0 +1,2 @@
+-- Custom fields of a subscriber, such as their company, available as merge tags in issues
+ALTER TABLE subscriptions ADD COLUMN attributes JSONB NOT NULL DEFAULT '{}'::jsonb;
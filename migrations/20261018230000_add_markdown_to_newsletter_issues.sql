@@ -0,0 +1,3 @@
+-- The Markdown source of issues authored in Markdown, kept so the draft can be edited again
+ALTER TABLE newsletter_issues ADD COLUMN markdown_content TEXT NULL;
+ALTER TABLE newsletter_issue_revisions ADD COLUMN markdown_content TEXT NULL;
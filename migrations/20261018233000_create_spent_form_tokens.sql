@@ -0,0 +1,7 @@
+-- Nonces of the subscribe form tokens already submitted, so that a token cannot be replayed
+CREATE TABLE spent_form_tokens(
+   nonce TEXT NOT NULL,
+   PRIMARY KEY (nonce),
+   -- the token is rejected as expired past this point, and can then be purged
+   expires_at timestamptz NOT NULL
+);
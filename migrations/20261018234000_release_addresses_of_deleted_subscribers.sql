@@ -0,0 +1,10 @@
+-- Deleted subscribers give their address back, so that it can subscribe again as a new subscriber.
+-- The address is kept aside for data access and erasure requests to still find them.
+ALTER TABLE subscriptions ADD COLUMN deleted_email_normalized TEXT NULL;
+CREATE INDEX subscriptions_deleted_email_normalized_idx
+    ON subscriptions (lower(deleted_email_normalized));
+
+UPDATE subscriptions
+    SET deleted_email_normalized = email_normalized,
+        email_normalized = 'deleted:' || id::text
+    WHERE status = 'deleted';
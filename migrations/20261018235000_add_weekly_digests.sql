@@ -0,0 +1,9 @@
+-- Subscribers asking for a weekly digest get their deliveries in the `digest` state,
+-- sent together at most once a week
+ALTER TABLE subscriptions ADD COLUMN last_digest_at timestamptz NULL;
+
+ALTER TABLE newsletter_deliveries DROP CONSTRAINT newsletter_deliveries_status_check;
+ALTER TABLE newsletter_deliveries ADD CONSTRAINT newsletter_deliveries_status_check
+   CHECK (status IN ('pending', 'digest', 'sent', 'skipped', 'dead_letter'));
+CREATE INDEX newsletter_deliveries_digest_idx
+   ON newsletter_deliveries (subscriber_id) WHERE status = 'digest';
@@ -0,0 +1,2 @@
+-- Where test sends go when an editor asks for a copy of their own
+ALTER TABLE users ADD COLUMN email TEXT NULL;
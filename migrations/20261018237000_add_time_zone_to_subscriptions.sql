@@ -0,0 +1,3 @@
+-- Scheduled issues reach every subscriber at the time of day they were scheduled for,
+-- on the subscriber's own clock
+ALTER TABLE subscriptions ADD COLUMN time_zone TEXT NOT NULL DEFAULT 'UTC';
@@ -0,0 +1,93 @@
+//! Where subscribers came from: the campaign, page and referrer that brought them in.
+use serde::{Deserialize, Serialize};
+
+/// Attribution values are user supplied, anything longer than this is cut.
+const MAX_LENGTH: usize = 500;
+
+/// The source label used for subscribers without one in reports.
+pub const UNKNOWN_SOURCE: &str = "(none)";
+
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Attribution {
+    /// A free-form label, e.g. `footer` or `podcast`
+    pub source: Option<String>,
+    pub utm_source: Option<String>,
+    pub utm_medium: Option<String>,
+    pub utm_campaign: Option<String>,
+    pub utm_term: Option<String>,
+    pub utm_content: Option<String>,
+    /// The page the subscriber was on before landing on the subscribe form
+    pub referrer: Option<String>,
+    /// The page hosting the subscribe form
+    pub landing_url: Option<String>,
+}
+
+impl Attribution {
+    /// Trims every value, drops the empty ones and cuts the overly long ones.
+    pub fn sanitized(self) -> Self {
+        Self {
+            source: clean(self.source),
+            utm_source: clean(self.utm_source),
+            utm_medium: clean(self.utm_medium),
+            utm_campaign: clean(self.utm_campaign),
+            utm_term: clean(self.utm_term),
+            utm_content: clean(self.utm_content),
+            referrer: clean(self.referrer),
+            landing_url: clean(self.landing_url),
+        }
+    }
+
+    /// The non-empty values, by field name.
+    pub fn fields(&self) -> Vec<(&'static str, &str)> {
+        [
+            ("source", &self.source),
+            ("utm_source", &self.utm_source),
+            ("utm_medium", &self.utm_medium),
+            ("utm_campaign", &self.utm_campaign),
+            ("utm_term", &self.utm_term),
+            ("utm_content", &self.utm_content),
+            ("referrer", &self.referrer),
+            ("landing_url", &self.landing_url),
+        ]
+        .into_iter()
+        .filter_map(|(name, value)| value.as_deref().map(|v| (name, v)))
+        .collect()
+    }
+}
+
+fn clean(value: Option<String>) -> Option<String> {
+    let value = value?;
+    let value = value.trim();
+    if value.is_empty() {
+        return None;
+    }
+    Some(value.chars().take(MAX_LENGTH).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Attribution, MAX_LENGTH};
+
+    #[test]
+    fn values_are_trimmed_and_empty_ones_dropped() {
+        let attribution = Attribution {
+            source: Some("  footer ".into()),
+            utm_source: Some("   ".into()),
+            ..Default::default()
+        }
+        .sanitized();
+        assert_eq!(attribution.source.as_deref(), Some("footer"));
+        assert_eq!(attribution.utm_source, None);
+        assert_eq!(attribution.fields(), vec![("source", "footer")]);
+    }
+
+    #[test]
+    fn long_values_are_cut() {
+        let attribution = Attribution {
+            landing_url: Some("é".repeat(MAX_LENGTH * 2)),
+            ..Default::default()
+        }
+        .sanitized();
+        assert_eq!(attribution.landing_url.unwrap().chars().count(), MAX_LENGTH);
+    }
+}
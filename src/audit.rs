@@ -0,0 +1,50 @@
+use anyhow::Context;
+use chrono::Utc;
+use sqlx::{Postgres, Transaction};
+use uuid::Uuid;
+
+/// Who performed an audited action.
+#[derive(Debug, Clone, Copy)]
+pub enum Actor {
+    /// A member of staff, authenticated as the given user.
+    User(Uuid),
+    /// The subscriber themselves, e.g. through a magic link.
+    Subscriber,
+}
+
+impl Actor {
+    fn user_id(&self) -> Option<Uuid> {
+        match self {
+            Self::User(user_id) => Some(*user_id),
+            Self::Subscriber => None,
+        }
+    }
+}
+
+/// Appends an entry to the audit log as part of the given transaction,
+/// so that the entry is only stored if the audited change is.
+#[tracing::instrument(name = "Record an audit log entry", skip(tx, details))]
+pub async fn record(
+    tx: &mut Transaction<'_, Postgres>,
+    actor: Actor,
+    action: &str,
+    subject: &str,
+    details: serde_json::Value,
+) -> anyhow::Result<()> {
+    sqlx::query!(
+        r#"
+        INSERT INTO audit_log (id, actor_user_id, action, subject, details, created_at)
+        VALUES ($1, $2, $3, $4, $5, $6)
+        "#,
+        Uuid::new_v4(),
+        actor.user_id(),
+        action,
+        subject,
+        details,
+        Utc::now(),
+    )
+    .execute(tx)
+    .await
+    .context("Failed to record an audit log entry")?;
+    Ok(())
+}
@@ -0,0 +1,305 @@
+//! Authoring newsletter issues in Markdown.
+//!
+//! The Markdown source is rendered twice: to sanitized HTML wrapped in the email layout, and
+//! to plain text with headings underlined and links listed as footnotes. Merge tags are
+//! swapped for placeholders while rendering, so that they reach the stored bodies unchanged.
+use pulldown_cmark::{Event, HeadingLevel, Parser, Tag, TagEnd};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    merge_tags::Template,
+    plain_text::{self, TextWriter},
+};
+
+/// Where the rendered HTML goes in the layout.
+const CONTENT_PLACEHOLDER: &str = "{{ content }}";
+
+/// The HTML document Markdown issues are wrapped in, with a `{{ content }}` placeholder.
+///
+/// The layout is trusted: it is not sanitized, and can use merge tags such as
+/// `{{ unsubscribe_url }}`.
+#[derive(Debug)]
+pub struct EmailLayout(String);
+
+impl EmailLayout {
+    pub fn new(layout: String) -> Result<Self, String> {
+        let Some((before, after)) = layout.split_once(CONTENT_PLACEHOLDER) else {
+            return Err(format!("The email layout has no `{CONTENT_PLACEHOLDER}`"));
+        };
+        for part in [before, after] {
+            Template::parse(part).map_err(|e| format!("Invalid email layout: {e}"))?;
+        }
+        Ok(Self(layout))
+    }
+
+    pub fn load(path: &str) -> Result<Self, String> {
+        let layout = std::fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read the email layout at {path}: {e}"))?;
+        Self::new(layout)
+    }
+
+    fn wrap(&self, html: &str) -> String {
+        self.0.replacen(CONTENT_PLACEHOLDER, html, 1)
+    }
+}
+
+/// The content of an issue as sent by its author: Markdown, explicit bodies, or both, in
+/// which case the explicit bodies replace the ones rendered from Markdown. Without a text
+/// body, it is derived from the explicit HTML body if any, so that both bodies match, or
+/// rendered from the Markdown otherwise.
+#[derive(Deserialize, Serialize)]
+pub struct Content {
+    #[serde(default)]
+    pub markdown: Option<String>,
+    #[serde(default)]
+    pub html: Option<String>,
+    #[serde(default)]
+    pub text: Option<String>,
+}
+
+/// Both bodies of an issue, ready to be stored.
+#[derive(Debug)]
+pub struct Bodies {
+    pub html: String,
+    pub text: String,
+    pub markdown: Option<String>,
+}
+
+impl Content {
+    pub fn bodies(&self, layout: &EmailLayout) -> Result<Bodies, String> {
+        match (&self.markdown, &self.html, &self.text) {
+            (Some(markdown), html, text) => {
+                let rendered = render(markdown, layout)
+                    .map_err(|e| format!("Invalid merge tag in the Markdown content: {e}"))?;
+                let text = match (text, html) {
+                    (Some(text), _) => text.clone(),
+                    (None, Some(html)) => plain_text::from_html(html),
+                    (None, None) => rendered.text,
+                };
+                Ok(Bodies {
+                    html: html.clone().unwrap_or(rendered.html),
+                    text,
+                    markdown: Some(markdown.clone()),
+                })
+            }
+            (None, Some(html), text) => Ok(Bodies {
+                html: html.clone(),
+                text: text.clone().unwrap_or_else(|| plain_text::from_html(html)),
+                markdown: None,
+            }),
+            (None, None, _) => Err("The content must be given as `markdown` or `html`".into()),
+        }
+    }
+}
+
+/// Renders Markdown to both bodies of an issue.
+fn render(
+    markdown: &str,
+    layout: &EmailLayout,
+) -> Result<Bodies, crate::merge_tags::TemplateError> {
+    let template = Template::parse(markdown)?;
+    let prefix = placeholder_prefix(markdown);
+    let mut tags = Vec::new();
+    let protected = template.replace_tags(|tag| {
+        tags.push(tag.to_string());
+        format!("{prefix}{}x", tags.len() - 1)
+    });
+    let restore = |mut rendered: String| {
+        // in reverse, so that placeholder 1 does not match the start of placeholder 10
+        for (i, tag) in tags.iter().enumerate().rev() {
+            rendered = rendered.replace(&format!("{prefix}{i}x"), tag);
+        }
+        rendered
+    };
+
+    let mut html = String::new();
+    pulldown_cmark::html::push_html(&mut html, Parser::new(&protected));
+    Ok(Bodies {
+        html: restore(layout.wrap(&ammonia::clean(&html))),
+        text: restore(to_text(&protected)),
+        markdown: Some(markdown.to_string()),
+    })
+}
+
+/// A run of letters that Markdown and the sanitizer leave alone, and that is not in `source`.
+fn placeholder_prefix(source: &str) -> String {
+    let mut rng = rand::thread_rng();
+    loop {
+        let prefix = format!("mergetag{:08x}n", rng.gen::<u32>());
+        if !source.contains(&prefix) {
+            return prefix;
+        }
+    }
+}
+
+/// Renders Markdown as plain text.
+fn to_text(markdown: &str) -> String {
+    let mut writer = TextWriter::default();
+    for event in Parser::new(markdown) {
+        match event {
+            Event::Start(tag) => start(&mut writer, tag),
+            Event::End(tag) => end(&mut writer, tag),
+            Event::Text(text) | Event::Code(text) => writer.push(&text),
+            Event::SoftBreak | Event::HardBreak => writer.newline(),
+            Event::Rule => writer.rule(),
+            // raw HTML has no plain text equivalent
+            _ => {}
+        }
+    }
+    writer.finish()
+}
+
+fn start(writer: &mut TextWriter, tag: Tag) {
+    match tag {
+        Tag::Paragraph => writer.start_block(),
+        Tag::Heading { .. } => writer.start_heading(),
+        Tag::BlockQuote(_) => writer.start_prefixed("> "),
+        Tag::CodeBlock(_) => writer.start_prefixed("    "),
+        Tag::List(first) => writer.start_list(first),
+        Tag::Item => writer.start_item(),
+        Tag::Link { dest_url, .. } | Tag::Image { dest_url, .. } => {
+            writer.start_link(Some(&dest_url))
+        }
+        _ => {}
+    }
+}
+
+fn end(writer: &mut TextWriter, tag: TagEnd) {
+    match tag {
+        TagEnd::Paragraph => writer.end_block(),
+        TagEnd::Heading(level) => writer.end_heading(level == HeadingLevel::H1),
+        TagEnd::BlockQuote(_) | TagEnd::CodeBlock => writer.end_prefixed(),
+        TagEnd::List(_) => writer.end_list(),
+        TagEnd::Item => writer.end_item(),
+        TagEnd::Link | TagEnd::Image => writer.end_link(),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{to_text, Content, EmailLayout};
+    use claims::assert_err;
+
+    fn layout() -> EmailLayout {
+        EmailLayout::new("<body>{{ content }}<a href=\"{{ unsubscribe_url }}\">x</a></body>".into())
+            .unwrap()
+    }
+
+    fn markdown(source: &str) -> Content {
+        Content {
+            markdown: Some(source.into()),
+            html: None,
+            text: None,
+        }
+    }
+
+    #[test]
+    fn headings_are_underlined_and_links_become_footnotes() {
+        let text = to_text(
+            "# Weekly news\n\nRead [the post](https://example.com/post) and \
+            [the docs](https://example.com/docs), or <https://example.com>.\n\n\
+            ## Also\n\nSee [the post](https://example.com/post) again.",
+        );
+        assert_eq!(
+            text,
+            "Weekly news\n===========\n\n\
+            Read the post[1] and the docs[2], or https://example.com.\n\n\
+            Also\n----\n\n\
+            See the post[1] again.\n\n\
+            [1] https://example.com/post\n\
+            [2] https://example.com/docs"
+        );
+    }
+
+    #[test]
+    fn lists_quotes_and_code_are_laid_out() {
+        let text = to_text(
+            "Intro\n\n- one\n- two\n  - nested\n\n1. first\n2. second\n\n> quoted\n> text\n\n    code\n",
+        );
+        assert_eq!(
+            text,
+            "Intro\n\n- one\n- two\n  - nested\n\n1. first\n2. second\n\n> quoted\n> text\n\n    code"
+        );
+    }
+
+    #[test]
+    fn links_starting_on_a_new_line_after_trailing_spaces_are_footnoted() {
+        let bodies = markdown("Hello   [\nit](https://example.com/post)")
+            .bodies(&layout())
+            .unwrap();
+        assert_eq!(bodies.text, "Hello\nit[1]\n\n[1] https://example.com/post");
+    }
+
+    #[test]
+    fn markdown_is_rendered_to_sanitized_html_in_the_layout() {
+        let bodies =
+            markdown("Hello *there*\n\n<script>alert(1)</script><b onclick=\"x()\">hi</b>")
+                .bodies(&layout())
+                .unwrap();
+        assert!(bodies
+            .html
+            .starts_with("<body><p>Hello <em>there</em></p>\n"));
+        assert!(!bodies.html.contains("script"));
+        assert!(!bodies.html.contains("onclick"));
+        assert!(bodies
+            .html
+            .ends_with("<a href=\"{{ unsubscribe_url }}\">x</a></body>"));
+        // raw HTML has no place in the text version
+        assert_eq!(bodies.text, "Hello there");
+    }
+
+    #[test]
+    fn merge_tags_survive_rendering() {
+        let bodies = markdown(
+            "Hi {{ _first_name_ | default: \"*you*\" }}, [unsubscribe]({{ unsubscribe_url }})",
+        )
+        .bodies(&layout())
+        .unwrap();
+        assert!(bodies.html.contains(
+            "<p>Hi {{ _first_name_ | default: \"*you*\" }}, \
+            <a href=\"{{ unsubscribe_url }}\" rel=\"noopener noreferrer\">unsubscribe</a></p>"
+        ));
+        assert_eq!(
+            bodies.text,
+            "Hi {{ _first_name_ | default: \"*you*\" }}, unsubscribe[1]\n\n[1] {{ unsubscribe_url }}"
+        );
+        assert_err!(markdown("Hi {{ name").bodies(&layout()));
+    }
+
+    #[test]
+    fn explicit_bodies_win_and_missing_text_is_derived() {
+        let content = Content {
+            markdown: Some("# Title".into()),
+            html: Some("<h1>Custom</h1>".into()),
+            text: None,
+        };
+        let bodies = content.bodies(&layout()).unwrap();
+        assert_eq!(bodies.html, "<h1>Custom</h1>");
+        assert_eq!(bodies.text, "Custom\n======");
+        assert_eq!(bodies.markdown.as_deref(), Some("# Title"));
+
+        let content = Content {
+            markdown: None,
+            html: Some("<p>Only <b>HTML</b></p>".into()),
+            text: None,
+        };
+        let bodies = content.bodies(&layout()).unwrap();
+        assert_eq!(bodies.html, "<p>Only <b>HTML</b></p>");
+        assert_eq!(bodies.text, "Only HTML");
+
+        let content = Content {
+            markdown: None,
+            html: None,
+            text: Some("Only text".into()),
+        };
+        assert_err!(content.bodies(&layout()));
+    }
+
+    #[test]
+    fn the_layout_needs_a_content_placeholder() {
+        assert_err!(EmailLayout::new("<body></body>".into()));
+        assert_err!(EmailLayout::new("<body>{{ content }}{{ oops</body>".into()));
+    }
+}
@@ -0,0 +1,337 @@
+//! Defences against bots using the public subscribe form to mail-bomb third parties.
+use anyhow::Context;
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use hmac::{Hmac, Mac};
+use rand::Rng;
+use reqwest::Client;
+use secrecy::{ExposeSecret, Secret};
+use sha2::Sha256;
+use sqlx::{PgPool, Postgres, Transaction};
+
+use crate::configuration::BotProtectionSettings;
+
+/// A signed timestamp and nonce embedded in the subscribe form when it is rendered.
+///
+/// When the form comes back we know how long it took to fill it in: humans need a few
+/// seconds, bots posting straight away don't. The signature stops the timestamp from being forged,
+/// and the nonce is spent on submission so that a harvested token cannot be replayed.
+pub struct FormToken;
+
+impl FormToken {
+    pub fn issue(secret: &Secret<String>) -> String {
+        Self::issue_at(secret, Utc::now())
+    }
+
+    /// Issues a token for a form rendered at `issued_at`.
+    pub fn issue_at(secret: &Secret<String>, issued_at: DateTime<Utc>) -> String {
+        let timestamp = issued_at.timestamp();
+        let nonce = hex::encode(rand::thread_rng().gen::<[u8; 16]>());
+        let signature = hex::encode(sign(secret, timestamp, &nonce));
+        format!("{timestamp}.{nonce}.{signature}")
+    }
+
+    /// Checks the token signature and that the form was submitted
+    /// between `min_age` and `max_age` after being rendered.
+    pub fn verify(
+        token: &str,
+        secret: &Secret<String>,
+        min_age: Duration,
+        max_age: Duration,
+    ) -> Result<VerifiedFormToken, String> {
+        let invalid = || "The form is invalid, please reload the page and try again".to_string();
+
+        let mut parts = token.splitn(3, '.');
+        let (Some(timestamp), Some(nonce), Some(signature)) =
+            (parts.next(), parts.next(), parts.next())
+        else {
+            return Err(invalid());
+        };
+        let timestamp: i64 = timestamp.parse().map_err(|_| invalid())?;
+        let signature = hex::decode(signature).map_err(|_| invalid())?;
+        mac(secret, timestamp, nonce)
+            .verify_slice(&signature)
+            .map_err(|_| invalid())?;
+
+        let issued_at = Utc
+            .timestamp_opt(timestamp, 0)
+            .single()
+            .ok_or_else(invalid)?;
+        let age = Utc::now() - issued_at;
+        if age < min_age {
+            return Err("The form was submitted too quickly, please try again".into());
+        }
+        if age > max_age {
+            return Err("The form has expired, please reload the page and try again".into());
+        }
+        Ok(VerifiedFormToken {
+            nonce: nonce.to_string(),
+            expires_at: issued_at + max_age,
+        })
+    }
+}
+
+/// A form token with a valid signature and age, not spent yet.
+#[derive(Debug)]
+pub struct VerifiedFormToken {
+    nonce: String,
+    expires_at: DateTime<Utc>,
+}
+
+impl VerifiedFormToken {
+    /// Spends the token along with the submission it came with.
+    ///
+    /// Returns `false` if it was already spent by an earlier submission.
+    pub async fn spend(
+        &self,
+        transaction: &mut Transaction<'_, Postgres>,
+    ) -> Result<bool, sqlx::Error> {
+        let spent = sqlx::query!(
+            r#"
+            INSERT INTO spent_form_tokens (nonce, expires_at) VALUES ($1, $2)
+            ON CONFLICT DO NOTHING
+            "#,
+            self.nonce,
+            self.expires_at,
+        )
+        .execute(transaction)
+        .await?
+        .rows_affected();
+        Ok(spent == 1)
+    }
+}
+
+/// Deletes the spent tokens that expired anyway.
+#[tracing::instrument(name = "Purge expired spent form tokens", skip(pool), err)]
+pub async fn purge_spent_form_tokens(pool: &PgPool) -> anyhow::Result<u64> {
+    let purged = sqlx::query!(
+        r#"DELETE FROM spent_form_tokens WHERE expires_at < $1"#,
+        Utc::now()
+    )
+    .execute(pool)
+    .await
+    .context("Failed to purge the expired spent form tokens")?
+    .rows_affected();
+    Ok(purged)
+}
+
+fn mac(secret: &Secret<String>, timestamp: i64, nonce: &str) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.expose_secret().as_bytes())
+        .expect("HMAC can take a key of any size");
+    mac.update(b"subscribe-form:");
+    mac.update(timestamp.to_string().as_bytes());
+    mac.update(b":");
+    mac.update(nonce.as_bytes());
+    mac
+}
+
+fn sign(secret: &Secret<String>, timestamp: i64, nonce: &str) -> Vec<u8> {
+    mac(secret, timestamp, nonce)
+        .finalize()
+        .into_bytes()
+        .to_vec()
+}
+
+/// Everything the subscribe handler needs to tell humans and bots apart.
+pub struct BotProtection {
+    hmac_secret: Secret<String>,
+    min_submit_time: Duration,
+    max_form_age: Duration,
+    require_form_token: bool,
+    challenge_verifier: Box<dyn ChallengeVerifier>,
+}
+
+impl BotProtection {
+    pub fn new(
+        hmac_secret: Secret<String>,
+        settings: &BotProtectionSettings,
+        challenge_verifier: Box<dyn ChallengeVerifier>,
+    ) -> Self {
+        Self {
+            hmac_secret,
+            min_submit_time: Duration::seconds(settings.min_submit_seconds),
+            max_form_age: Duration::seconds(settings.max_form_age_seconds),
+            require_form_token: settings.require_form_token,
+            challenge_verifier,
+        }
+    }
+
+    pub fn issue_form_token(&self) -> String {
+        FormToken::issue(&self.hmac_secret)
+    }
+
+    /// Checks the form token, if any, which must then be spent with the submission.
+    /// Submissions without a token are only accepted when tokens are not required,
+    /// e.g. to allow API clients.
+    pub fn check_form_token(
+        &self,
+        token: Option<&str>,
+    ) -> Result<Option<VerifiedFormToken>, String> {
+        match token.filter(|t| !t.is_empty()) {
+            Some(token) => FormToken::verify(
+                token,
+                &self.hmac_secret,
+                self.min_submit_time,
+                self.max_form_age,
+            )
+            .map(Some),
+            None if self.require_form_token => {
+                Err("The form is invalid, please reload the page and try again".into())
+            }
+            None => Ok(None),
+        }
+    }
+
+    pub async fn verify_challenge(
+        &self,
+        response: Option<&str>,
+        remote_ip: Option<&str>,
+    ) -> anyhow::Result<bool> {
+        self.challenge_verifier.verify(response, remote_ip).await
+    }
+}
+
+/// Verifies the response to a challenge (e.g. a CAPTCHA) solved by the user
+/// before a new subscriber is stored.
+#[async_trait::async_trait]
+pub trait ChallengeVerifier: Send + Sync {
+    /// Returns `Ok(false)` if the challenge was not solved.
+    async fn verify(&self, response: Option<&str>, remote_ip: Option<&str>)
+        -> anyhow::Result<bool>;
+}
+
+/// Accepts every submission, used when no challenge is configured.
+pub struct NoopChallengeVerifier;
+
+#[async_trait::async_trait]
+impl ChallengeVerifier for NoopChallengeVerifier {
+    async fn verify(&self, _: Option<&str>, _: Option<&str>) -> anyhow::Result<bool> {
+        Ok(true)
+    }
+}
+
+/// Verifies challenge responses against a siteverify-style endpoint, as exposed by
+/// reCAPTCHA, hCaptcha and Turnstile: the secret and the response are posted as a form
+/// and the endpoint answers with a JSON body containing a `success` boolean.
+pub struct HttpChallengeVerifier {
+    http_client: Client,
+    verify_url: String,
+    secret: Secret<String>,
+}
+
+#[derive(serde::Deserialize)]
+struct VerifyResponse {
+    success: bool,
+}
+
+impl HttpChallengeVerifier {
+    pub fn new(verify_url: String, secret: Secret<String>, timeout: std::time::Duration) -> Self {
+        let http_client = Client::builder()
+            .timeout(timeout)
+            .build()
+            .expect("failed to create an http_client");
+        Self {
+            http_client,
+            verify_url,
+            secret,
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl ChallengeVerifier for HttpChallengeVerifier {
+    async fn verify(
+        &self,
+        response: Option<&str>,
+        remote_ip: Option<&str>,
+    ) -> anyhow::Result<bool> {
+        let Some(response) = response.filter(|r| !r.is_empty()) else {
+            return Ok(false);
+        };
+
+        let mut form = vec![
+            ("secret", self.secret.expose_secret().as_str()),
+            ("response", response),
+        ];
+        if let Some(remote_ip) = remote_ip {
+            form.push(("remoteip", remote_ip));
+        }
+
+        let outcome: VerifyResponse = self
+            .http_client
+            .post(&self.verify_url)
+            .form(&form)
+            .send()
+            .await
+            .context("Failed to call the challenge verification endpoint")?
+            .error_for_status()
+            .context("The challenge verification endpoint returned an error")?
+            .json()
+            .await
+            .context("Failed to parse the challenge verification response")?;
+        Ok(outcome.success)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FormToken, VerifiedFormToken};
+    use chrono::{Duration, Utc};
+    use claims::{assert_err, assert_ok};
+    use secrecy::Secret;
+
+    fn secret() -> Secret<String> {
+        Secret::new("a-secret".into())
+    }
+
+    fn verify(token: &str) -> Result<VerifiedFormToken, String> {
+        FormToken::verify(token, &secret(), Duration::seconds(3), Duration::hours(1))
+    }
+
+    #[test]
+    fn a_token_submitted_in_time_is_accepted() {
+        let token = FormToken::issue_at(&secret(), Utc::now() - Duration::seconds(10));
+        assert_ok!(verify(&token));
+    }
+
+    #[test]
+    fn a_token_submitted_too_quickly_is_rejected() {
+        let token = FormToken::issue(&secret());
+        assert_err!(verify(&token));
+    }
+
+    #[test]
+    fn an_expired_token_is_rejected() {
+        let token = FormToken::issue_at(&secret(), Utc::now() - Duration::hours(2));
+        assert_err!(verify(&token));
+    }
+
+    #[test]
+    fn a_tampered_token_is_rejected() {
+        let token = FormToken::issue_at(&secret(), Utc::now() - Duration::seconds(10));
+        let (timestamp, rest) = token.split_once('.').unwrap();
+        let forged = format!(
+            "{}.{rest}",
+            (Utc::now() - Duration::seconds(20)).timestamp()
+        );
+        assert_err!(verify(&forged));
+        let (_, signature) = rest.split_once('.').unwrap();
+        let other_nonce = format!("{timestamp}.{}.{signature}", "0".repeat(32));
+        assert_err!(verify(&other_nonce));
+
+        let other_secret = FormToken::issue_at(
+            &Secret::new("another-secret".into()),
+            Utc::now() - Duration::seconds(10),
+        );
+        assert_err!(verify(&other_secret));
+        assert_err!(verify("garbage"));
+    }
+
+    #[test]
+    fn every_token_has_its_own_nonce() {
+        let issued_at = Utc::now() - Duration::seconds(10);
+        let first = verify(&FormToken::issue_at(&secret(), issued_at)).unwrap();
+        let second = verify(&FormToken::issue_at(&secret(), issued_at)).unwrap();
+        assert_ne!(first.nonce, second.nonce);
+        assert_eq!(first.expires_at, second.expires_at);
+    }
+}
@@ -0,0 +1,169 @@
+//! Periodic purge of subscribers who never confirmed their subscription.
+use std::time::{Duration, Instant};
+
+use anyhow::Context;
+use chrono::Utc;
+use serde::Serialize;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+    bot_protection::purge_spent_form_tokens,
+    configuration::{CleanupSettings, Settings},
+    domain::SubscriptionStatus,
+    startup::get_connection_pool,
+};
+
+#[derive(Clone, Copy, Debug)]
+pub struct CleanupOptions {
+    /// Subscribers pending confirmation for longer than this are purged
+    pub max_pending_age: chrono::Duration,
+    pub batch_size: i64,
+    /// Only count the subscribers that would be purged
+    pub dry_run: bool,
+}
+
+impl From<&CleanupSettings> for CleanupOptions {
+    fn from(settings: &CleanupSettings) -> Self {
+        Self {
+            max_pending_age: chrono::Duration::hours(settings.max_pending_age_hours),
+            batch_size: settings.batch_size,
+            dry_run: settings.dry_run,
+        }
+    }
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct CleanupReport {
+    pub dry_run: bool,
+    /// Subscribers purged, or that would have been purged in a dry run
+    pub purged: u64,
+    pub batches: u64,
+    pub elapsed_ms: u128,
+}
+
+/// Runs the cleanup every `interval_seconds` until the process stops.
+pub async fn run_worker_until_stopped(config: Settings) -> anyhow::Result<()> {
+    let pool = get_connection_pool(&config.database);
+    let options = CleanupOptions::from(&config.cleanup);
+    let interval = Duration::from_secs(config.cleanup.interval_seconds);
+    loop {
+        // a failed run is retried at the next tick
+        let _ = purge_stale_subscriptions(&pool, options).await;
+        let _ = purge_spent_form_tokens(&pool).await;
+        tokio::time::sleep(interval).await;
+    }
+}
+
+/// Deletes subscribers stuck in `pending_confirmation`, with their tokens, in batches.
+///
+/// Each batch is its own transaction, so a long purge does not hold locks on the whole
+/// table, and rows locked by a concurrent confirmation are skipped until the next run.
+#[tracing::instrument(
+    name = "Purge stale unconfirmed subscriptions",
+    skip(pool),
+    fields(purged = tracing::field::Empty, batches = tracing::field::Empty),
+    err
+)]
+pub async fn purge_stale_subscriptions(
+    pool: &PgPool,
+    options: CleanupOptions,
+) -> anyhow::Result<CleanupReport> {
+    let started_at = Instant::now();
+    let cutoff = Utc::now() - options.max_pending_age;
+    let mut report = CleanupReport {
+        dry_run: options.dry_run,
+        ..Default::default()
+    };
+
+    if options.dry_run {
+        let row = sqlx::query!(
+            r#"
+            SELECT count(*) as "count!" FROM subscriptions
+            WHERE status = $1 AND status_changed_at < $2
+            "#,
+            SubscriptionStatus::PendingConfirmation.as_str(),
+            cutoff,
+        )
+        .fetch_one(pool)
+        .await
+        .context("Failed to count the stale unconfirmed subscriptions")?;
+        report.purged = row.count as u64;
+    } else {
+        loop {
+            let purged = purge_batch(pool, cutoff, options.batch_size).await?;
+            if purged == 0 {
+                break;
+            }
+            report.purged += purged;
+            report.batches += 1;
+        }
+    }
+
+    report.elapsed_ms = started_at.elapsed().as_millis();
+    let span = tracing::Span::current();
+    span.record("purged", report.purged);
+    span.record("batches", report.batches);
+    tracing::info!(
+        purged = report.purged,
+        batches = report.batches,
+        elapsed_ms = report.elapsed_ms as u64,
+        dry_run = report.dry_run,
+        "Purged stale unconfirmed subscriptions"
+    );
+    Ok(report)
+}
+
+async fn purge_batch(
+    pool: &PgPool,
+    cutoff: chrono::DateTime<Utc>,
+    batch_size: i64,
+) -> anyhow::Result<u64> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+
+    let ids: Vec<Uuid> = sqlx::query!(
+        r#"
+        SELECT id FROM subscriptions
+        WHERE status = $1 AND status_changed_at < $2
+        ORDER BY status_changed_at
+        LIMIT $3
+        FOR UPDATE SKIP LOCKED
+        "#,
+        SubscriptionStatus::PendingConfirmation.as_str(),
+        cutoff,
+        batch_size,
+    )
+    .fetch_all(&mut transaction)
+    .await
+    .context("Failed to select a batch of stale unconfirmed subscriptions")?
+    .into_iter()
+    .map(|r| r.id)
+    .collect();
+    if ids.is_empty() {
+        return Ok(0);
+    }
+
+    sqlx::query!(
+        r#"DELETE FROM subscription_tokens WHERE subscriber_id = ANY($1)"#,
+        &ids[..]
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to delete the tokens of stale unconfirmed subscriptions")?;
+
+    // the status history and data access tokens are removed by the `ON DELETE CASCADE`
+    let deleted = sqlx::query!(r#"DELETE FROM subscriptions WHERE id = ANY($1)"#, &ids[..])
+        .execute(&mut transaction)
+        .await
+        .context("Failed to delete a batch of stale unconfirmed subscriptions")?
+        .rows_affected();
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to purge stale subscriptions")?;
+    Ok(deleted)
+}
@@ -6,7 +6,17 @@ use sqlx::{
     ConnectOptions,
 };
 
-use crate::{domain::SubscriberEmail, email_client::EmailClient};
+use crate::{
+    authoring::EmailLayout,
+    bot_protection::{
+        BotProtection, ChallengeVerifier, HttpChallengeVerifier, NoopChallengeVerifier,
+    },
+    domain::SubscriberEmail,
+    email_client::EmailClient,
+    i18n::Catalogs,
+    rate_limit::RateLimiter,
+    signup_policy::SignupPolicy,
+};
 
 #[derive(Deserialize, Clone)]
 pub struct Settings {
@@ -14,6 +24,14 @@ pub struct Settings {
     pub database: DatabaseSettings,
     pub application: ApplicationSettings,
     pub email_client: EmailClientSettings,
+    pub signup_policy: SignupPolicySettings,
+    pub bot_protection: BotProtectionSettings,
+    pub rate_limit: RateLimitSettings,
+    pub cleanup: CleanupSettings,
+    pub i18n: I18nSettings,
+    pub idempotency: IdempotencySettings,
+    pub delivery: DeliverySettings,
+    pub authoring: AuthoringSettings,
 }
 
 /// Read the application settings from a configuration file
@@ -57,6 +75,7 @@ pub struct ApplicationSettings {
     pub port: u16,
     pub host: String,
     pub base_url: String,
+    pub hmac_secret: Secret<String>,
 }
 
 /// The possible runtime environment for our application.
@@ -154,3 +173,157 @@ impl EmailClientSettings {
         std::time::Duration::from_millis(self.timeout_milliseconds)
     }
 }
+
+#[derive(Deserialize, Clone)]
+pub struct SignupPolicySettings {
+    /// Path of the blocklist file, relative to the working directory
+    pub blocklist_path: String,
+}
+
+impl SignupPolicySettings {
+    pub fn policy(&self) -> SignupPolicy {
+        SignupPolicy::new(&self.blocklist_path)
+    }
+}
+
+#[derive(Deserialize, Clone)]
+pub struct BotProtectionSettings {
+    /// Submissions of the subscribe form faster than this are considered to come from bots
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub min_submit_seconds: i64,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_form_age_seconds: i64,
+    /// Reject submissions that don't carry the signed timestamp embedded in our own form
+    pub require_form_token: bool,
+    /// The optional challenge (e.g. a CAPTCHA) solved before subscribing
+    pub challenge: Option<ChallengeSettings>,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct ChallengeSettings {
+    pub verify_url: String,
+    pub secret: Secret<String>,
+    pub timeout_milliseconds: u64,
+}
+
+impl BotProtectionSettings {
+    pub fn bot_protection(&self, hmac_secret: Secret<String>) -> BotProtection {
+        let verifier: Box<dyn ChallengeVerifier> = match &self.challenge {
+            Some(c) => Box::new(HttpChallengeVerifier::new(
+                c.verify_url.clone(),
+                c.secret.clone(),
+                std::time::Duration::from_millis(c.timeout_milliseconds),
+            )),
+            None => Box::new(NoopChallengeVerifier),
+        };
+        BotProtection::new(hmac_secret, self, verifier)
+    }
+}
+
+#[derive(Deserialize, Clone)]
+pub struct RateLimitSettings {
+    /// Proxies (addresses or networks such as `10.0.0.0/8`) whose `X-Forwarded-For` is honoured
+    pub trusted_proxies: Vec<String>,
+    pub routes: Vec<RouteLimitSettings>,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct RouteLimitSettings {
+    pub method: String,
+    /// The pattern of the route as registered, e.g. `/admin/newsletters/{issue_id}`
+    pub path: String,
+    pub per_ip: LimitSettings,
+    /// An additional limit keyed by the value of a form field, e.g. the email address
+    pub per_field: Option<FieldLimitSettings>,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct FieldLimitSettings {
+    pub field: String,
+    #[serde(flatten)]
+    pub limits: LimitSettings,
+}
+
+/// A token bucket holding up to `burst` requests, refilled at `per_minute` requests per minute.
+#[derive(Deserialize, Clone)]
+pub struct LimitSettings {
+    pub burst: u32,
+    pub per_minute: u32,
+}
+
+impl RateLimitSettings {
+    pub fn rate_limiter(&self) -> Result<RateLimiter, String> {
+        RateLimiter::new(self)
+    }
+}
+
+#[derive(Deserialize, Clone)]
+pub struct CleanupSettings {
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_pending_age_hours: i64,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub interval_seconds: u64,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub batch_size: i64,
+    /// Log how many subscribers would be purged without deleting anything
+    pub dry_run: bool,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct IdempotencySettings {
+    /// Reject `POST /newsletters` requests without an `Idempotency-Key` header
+    pub required: bool,
+    /// How long a saved response is replayed for, after which the key can be reused
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub ttl_hours: i64,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct DeliverySettings {
+    /// How many times a delivery is attempted before it is dead-lettered
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_attempts: i16,
+    /// The delay before the first retry, doubled for every subsequent one
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub base_backoff_seconds: u64,
+    #[serde(deserialize_with = "deserialize_number_from_string")]
+    pub max_backoff_seconds: u64,
+    /// The seed list a test send goes to when no address is given
+    #[serde(default)]
+    pub test_recipients: Vec<String>,
+}
+
+impl DeliverySettings {
+    pub fn test_recipients(&self) -> Result<Vec<SubscriberEmail>, String> {
+        self.test_recipients
+            .iter()
+            .map(|email| SubscriberEmail::parse(email.clone()))
+            .collect()
+    }
+}
+
+#[derive(Deserialize, Clone)]
+pub struct AuthoringSettings {
+    /// The HTML document issues written in Markdown are wrapped in, relative to the working
+    /// directory. It must contain a `{{ content }}` placeholder.
+    pub layout_path: String,
+}
+
+impl AuthoringSettings {
+    pub fn layout(&self) -> Result<EmailLayout, String> {
+        EmailLayout::load(&self.layout_path)
+    }
+}
+
+#[derive(Deserialize, Clone)]
+pub struct I18nSettings {
+    /// The directory holding a `<locale>.yaml` message catalog per supported locale
+    pub catalogs_path: String,
+    pub default_locale: String,
+}
+
+impl I18nSettings {
+    pub fn catalogs(&self) -> Result<Catalogs, config::ConfigError> {
+        Catalogs::load(&self.catalogs_path, &self.default_locale)
+    }
+}
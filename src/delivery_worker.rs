@@ -0,0 +1,708 @@
+//! Background delivery of newsletter issues, queued in `newsletter_deliveries`.
+//!
+//! Every worker claims one pending delivery at a time with `FOR UPDATE SKIP LOCKED`, so any
+//! number of them can run side by side, and a delivery interrupted by a crash is picked up
+//! again once its transaction has been rolled back.
+//!
+//! Every attempt at sending is recorded in `delivery_attempts`. Transient failures are retried
+//! with exponential backoff, up to the configured number of attempts; deliveries failing
+//! permanently, or running out of attempts, are dead-lettered until an admin replays them.
+//!
+//! The deliveries of subscribers asking for a weekly digest wait in the `digest` state, and
+//! are sent together, at most once a week, in a single email.
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Context};
+use chrono::{DateTime, Utc};
+use rand::Rng;
+use reqwest::StatusCode;
+use secrecy::Secret;
+use serde_json::Map;
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::{
+    configuration::{DeliverySettings, Settings},
+    domain::{DeliveryStatus, SubscriberEmail, SubscriptionStatus},
+    email_client::EmailClient,
+    merge_tags::{personalize, MergeFields},
+    newsletter_issues::{email_bodies, mark_issue_sent_if_done, start_due_issues},
+    preferences::preferences_link,
+    startup::get_connection_pool,
+    utils::html_escape,
+};
+
+/// How long the worker waits before looking again when the queue is empty.
+const EMPTY_QUEUE_INTERVAL: Duration = Duration::from_secs(10);
+/// How long the worker waits after failing to process a delivery.
+const ERROR_INTERVAL: Duration = Duration::from_secs(1);
+/// How often the worker looks for scheduled issues due to start sending, and for weekly
+/// digests due to be sent, even mid-send.
+const SCHEDULE_INTERVAL: Duration = Duration::from_secs(10);
+/// The least time between two digests sent to the same subscriber.
+const DIGEST_INTERVAL_DAYS: i64 = 7;
+
+/// Builds the preference center link appended to every issue.
+pub struct PreferencesLinks {
+    pub base_url: String,
+    pub hmac_secret: Secret<String>,
+}
+
+impl PreferencesLinks {
+    fn link(&self, subscriber_id: Uuid) -> String {
+        preferences_link(&self.base_url, &self.hmac_secret, subscriber_id)
+    }
+}
+
+/// How failed deliveries are retried.
+#[derive(Clone, Copy, Debug)]
+pub struct RetryPolicy {
+    /// Including the first one
+    pub max_attempts: i16,
+    pub base_backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+impl From<&DeliverySettings> for RetryPolicy {
+    fn from(settings: &DeliverySettings) -> Self {
+        Self {
+            max_attempts: settings.max_attempts,
+            base_backoff: Duration::from_secs(settings.base_backoff_seconds),
+            max_backoff: Duration::from_secs(settings.max_backoff_seconds),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// The delay before retrying a delivery whose `attempt`th attempt (starting at 1) failed.
+    ///
+    /// The delay doubles with every attempt, up to `max_backoff`, and is then jittered down
+    /// by up to half so that deliveries failing together are not all retried together.
+    pub fn backoff(&self, attempt: i16) -> Duration {
+        let exponent = u32::try_from(attempt.saturating_sub(1)).unwrap_or(0);
+        let delay = self
+            .base_backoff
+            .saturating_mul(2u32.saturating_pow(exponent))
+            .min(self.max_backoff);
+        let half = delay / 2;
+        half + half.mul_f64(rand::thread_rng().gen::<f64>())
+    }
+}
+
+/// Whether a failed send is worth retrying: timeouts, connection errors,
+/// rate limiting and server errors are; any other rejection is not.
+fn is_transient(error: &reqwest::Error) -> bool {
+    match error.status() {
+        Some(status) => is_transient_status(status),
+        None => true,
+    }
+}
+
+fn is_transient_status(status: StatusCode) -> bool {
+    status.is_server_error()
+        || status == StatusCode::TOO_MANY_REQUESTS
+        || status == StatusCode::REQUEST_TIMEOUT
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ExecutionOutcome {
+    TaskCompleted,
+    EmptyQueue,
+}
+
+/// Starts scheduled issues, sends the due weekly digests and processes the delivery queue
+/// until the process stops.
+pub async fn run_worker_until_stopped(config: Settings) -> anyhow::Result<()> {
+    let pool = get_connection_pool(&config.database);
+    let email_client = config.email_client.client();
+    let retry_policy = RetryPolicy::from(&config.delivery);
+    let links = PreferencesLinks {
+        base_url: config.application.base_url,
+        hmac_secret: config.application.hmac_secret,
+    };
+    let mut next_schedule_check = Instant::now();
+    loop {
+        if Instant::now() >= next_schedule_check {
+            // a failed run is retried at the next check
+            let _ = start_due_issues(&pool).await;
+            let _ = send_due_digests(&pool, &email_client, &links, &retry_policy).await;
+            next_schedule_check = Instant::now() + SCHEDULE_INTERVAL;
+        }
+        match try_execute_task(&pool, &email_client, &links, &retry_policy).await {
+            Ok(ExecutionOutcome::TaskCompleted) => {}
+            Ok(ExecutionOutcome::EmptyQueue) => tokio::time::sleep(EMPTY_QUEUE_INTERVAL).await,
+            Err(_) => tokio::time::sleep(ERROR_INTERVAL).await,
+        }
+    }
+}
+
+/// Claims a pending delivery due for an attempt, if any, and sends it.
+///
+/// The delivery stays locked until the outcome of the attempt is recorded, in the same transaction.
+#[tracing::instrument(
+    name = "Deliver a newsletter issue",
+    skip_all,
+    fields(issue_id=tracing::field::Empty, subscriber_id=tracing::field::Empty),
+    err
+)]
+pub async fn try_execute_task(
+    pool: &PgPool,
+    email_client: &EmailClient,
+    links: &PreferencesLinks,
+    retry_policy: &RetryPolicy,
+) -> anyhow::Result<ExecutionOutcome> {
+    let Some((mut transaction, delivery)) = dequeue_delivery(pool).await? else {
+        return Ok(ExecutionOutcome::EmptyQueue);
+    };
+    let span = tracing::Span::current();
+    span.record("issue_id", tracing::field::display(&delivery.issue_id));
+    span.record(
+        "subscriber_id",
+        tracing::field::display(&delivery.subscriber_id),
+    );
+
+    let now = Utc::now();
+    let attempt = delivery.attempts + 1;
+    match send_delivery(&mut transaction, email_client, links, &delivery).await? {
+        SendOutcome::Skipped => {
+            update_delivery(
+                &mut transaction,
+                &delivery,
+                DeliveryStatus::Skipped,
+                delivery.attempts,
+                Some(now),
+            )
+            .await?
+        }
+        SendOutcome::Sent => {
+            record_attempt(&mut transaction, &delivery, None).await?;
+            update_delivery(
+                &mut transaction,
+                &delivery,
+                DeliveryStatus::Sent,
+                attempt,
+                Some(now),
+            )
+            .await?
+        }
+        SendOutcome::Failed { error, transient } => {
+            record_attempt(&mut transaction, &delivery, Some(&error)).await?;
+            if transient && attempt < retry_policy.max_attempts {
+                let next_attempt_at = now
+                    + chrono::Duration::from_std(retry_policy.backoff(attempt))
+                        .context("The backoff is out of range")?;
+                schedule_retry(&mut transaction, &delivery, attempt, next_attempt_at).await?
+            } else {
+                tracing::error!(attempt, "Dead-lettering a delivery");
+                update_delivery(
+                    &mut transaction,
+                    &delivery,
+                    DeliveryStatus::DeadLetter,
+                    attempt,
+                    Some(now),
+                )
+                .await?
+            }
+        }
+    }
+    mark_issue_sent_if_done(&mut transaction, delivery.issue_id).await?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to record a delivery")?;
+
+    Ok(ExecutionOutcome::TaskCompleted)
+}
+
+/// Sends the weekly digest of every subscriber due one, and returns how many were sent.
+///
+/// A digest gathers every issue waiting for the subscriber, in the order they were published.
+#[tracing::instrument(name = "Send the due weekly digests", skip_all, err)]
+pub async fn send_due_digests(
+    pool: &PgPool,
+    email_client: &EmailClient,
+    links: &PreferencesLinks,
+    retry_policy: &RetryPolicy,
+) -> anyhow::Result<u64> {
+    let mut sent = 0;
+    // every digest handled leaves its subscriber out of the next query, whatever the outcome
+    while let Some(outcome) = try_send_digest(pool, email_client, links, retry_policy).await? {
+        if matches!(outcome, SendOutcome::Sent) {
+            sent += 1;
+        }
+    }
+    Ok(sent)
+}
+
+/// Claims a subscriber due a digest, if any, and sends it.
+///
+/// The subscriber and their deliveries stay locked until the outcome is recorded.
+async fn try_send_digest(
+    pool: &PgPool,
+    email_client: &EmailClient,
+    links: &PreferencesLinks,
+    retry_policy: &RetryPolicy,
+) -> anyhow::Result<Option<SendOutcome>> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    let now = Utc::now();
+    let subscriber = sqlx::query!(
+        r#"
+        SELECT id, email, name, status, attributes FROM subscriptions
+        WHERE (last_digest_at IS NULL OR last_digest_at <= $1) AND EXISTS (
+            SELECT 1 FROM newsletter_deliveries
+            WHERE subscriber_id = subscriptions.id AND status = $2 AND next_attempt_at <= $3
+        )
+        FOR UPDATE SKIP LOCKED
+        LIMIT 1
+        "#,
+        now - chrono::Duration::days(DIGEST_INTERVAL_DAYS),
+        DeliveryStatus::Digest.as_str(),
+        now,
+    )
+    .fetch_optional(&mut transaction)
+    .await
+    .context("Failed to claim a subscriber due a digest")?;
+    let Some(subscriber) = subscriber else {
+        return Ok(None);
+    };
+    let issues = sqlx::query!(
+        r#"
+        SELECT d.issue_id, d.attempts, i.title, i.html_content, i.text_content
+        FROM newsletter_deliveries d JOIN newsletter_issues i ON i.id = d.issue_id
+        WHERE d.subscriber_id = $1 AND d.status = $2 AND d.next_attempt_at <= $3
+        ORDER BY d.created_at
+        FOR UPDATE OF d
+        "#,
+        subscriber.id,
+        DeliveryStatus::Digest.as_str(),
+        now,
+    )
+    .fetch_all(&mut transaction)
+    .await
+    .context("Failed to fetch the issues of a digest")?;
+
+    let email = match SubscriberEmail::parse(subscriber.email.clone()) {
+        // the subscriber may have unsubscribed since the issues were published
+        Ok(email) if subscriber.status == SubscriptionStatus::Confirmed.as_str() => Some(email),
+        Ok(_) => None,
+        Err(error) => {
+            tracing::warn!(
+                error.cause_chain = ?anyhow!(error),
+                "Skipping the digest of a subscriber. \
+                Their stored contact details are invalid",
+            );
+            None
+        }
+    };
+    let Some(email) = email else {
+        for issue in &issues {
+            let delivery = Delivery {
+                issue_id: issue.issue_id,
+                subscriber_id: subscriber.id,
+                attempts: issue.attempts,
+            };
+            update_delivery(
+                &mut transaction,
+                &delivery,
+                DeliveryStatus::Skipped,
+                delivery.attempts,
+                Some(now),
+            )
+            .await?;
+        }
+        transaction
+            .commit()
+            .await
+            .context("Failed to commit SQL transaction to record a digest")?;
+        return Ok(Some(SendOutcome::Skipped));
+    };
+
+    let link = links.link(subscriber.id);
+    let no_attributes = Map::new();
+    let fields = MergeFields {
+        name: &subscriber.name,
+        email: email.as_ref(),
+        unsubscribe_url: &link,
+        attributes: subscriber.attributes.as_object().unwrap_or(&no_attributes),
+    };
+    let mut deliveries = Vec::new();
+    let mut contents = Vec::new();
+    for issue in issues {
+        let delivery = Delivery {
+            issue_id: issue.issue_id,
+            subscriber_id: subscriber.id,
+            attempts: issue.attempts,
+        };
+        match personalize(
+            &issue.title,
+            &issue.html_content,
+            &issue.text_content,
+            &fields,
+        ) {
+            Ok(content) => {
+                deliveries.push(delivery);
+                contents.push(content);
+            }
+            // as for a single delivery, a failure here will not go away on retry
+            Err(error) => {
+                record_attempt(&mut transaction, &delivery, Some(&error.to_string())).await?;
+                update_delivery(
+                    &mut transaction,
+                    &delivery,
+                    DeliveryStatus::DeadLetter,
+                    delivery.attempts + 1,
+                    Some(now),
+                )
+                .await?;
+            }
+        }
+    }
+    if contents.is_empty() {
+        transaction
+            .commit()
+            .await
+            .context("Failed to commit SQL transaction to record a digest")?;
+        return Ok(Some(SendOutcome::Skipped));
+    }
+
+    let subject = match contents.len() {
+        1 => contents[0].subject.clone(),
+        n => format!(
+            "Your weekly digest: {} and {} more",
+            contents[0].subject,
+            n - 1
+        ),
+    };
+    let html_content = contents
+        .iter()
+        .map(|c| format!("<h2>{}</h2>{}", html_escape(&c.subject), c.html))
+        .collect::<Vec<_>>()
+        .join("<hr />");
+    let text_content = contents
+        .iter()
+        .map(|c| {
+            let underline = "=".repeat(c.subject.chars().count());
+            format!("{}\n{underline}\n\n{}", c.subject, c.text)
+        })
+        .collect::<Vec<_>>()
+        .join("\n\n");
+    let (html_body, text_body) = email_bodies(&html_content, &text_content, &link);
+    let outcome = match email_client
+        .send_email(&email, &subject, &html_body, &text_body)
+        .await
+    {
+        Ok(()) => SendOutcome::Sent,
+        Err(error) => {
+            tracing::warn!(
+                error.cause_chain = ?error,
+                error.message = %error,
+                "Failed to deliver a digest to a confirmed subscriber",
+            );
+            SendOutcome::Failed {
+                transient: is_transient(&error),
+                error: error.to_string(),
+            }
+        }
+    };
+
+    match &outcome {
+        SendOutcome::Sent => {
+            for delivery in &deliveries {
+                record_attempt(&mut transaction, delivery, None).await?;
+                update_delivery(
+                    &mut transaction,
+                    delivery,
+                    DeliveryStatus::Sent,
+                    delivery.attempts + 1,
+                    Some(now),
+                )
+                .await?;
+            }
+            sqlx::query!(
+                r#"UPDATE subscriptions SET last_digest_at = $1 WHERE id = $2"#,
+                now,
+                subscriber.id,
+            )
+            .execute(&mut transaction)
+            .await
+            .context("Failed to record when a digest was sent")?;
+        }
+        SendOutcome::Failed { error, transient } => {
+            // the issues of a digest are retried together
+            let attempt = deliveries.iter().map(|d| d.attempts + 1).max().unwrap_or(1);
+            let next_attempt_at = now
+                + chrono::Duration::from_std(retry_policy.backoff(attempt))
+                    .context("The backoff is out of range")?;
+            for delivery in &deliveries {
+                record_attempt(&mut transaction, delivery, Some(error)).await?;
+                if *transient && attempt < retry_policy.max_attempts {
+                    schedule_retry(
+                        &mut transaction,
+                        delivery,
+                        delivery.attempts + 1,
+                        next_attempt_at,
+                    )
+                    .await?
+                } else {
+                    tracing::error!(attempt, "Dead-lettering a delivery of a digest");
+                    update_delivery(
+                        &mut transaction,
+                        delivery,
+                        DeliveryStatus::DeadLetter,
+                        delivery.attempts + 1,
+                        Some(now),
+                    )
+                    .await?
+                }
+            }
+        }
+        SendOutcome::Skipped => {}
+    }
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to record a digest")?;
+    Ok(Some(outcome))
+}
+
+struct Delivery {
+    issue_id: Uuid,
+    subscriber_id: Uuid,
+    /// The attempts made since the delivery was queued, or last replayed
+    attempts: i16,
+}
+
+enum SendOutcome {
+    Sent,
+    /// The subscriber is no longer confirmed, or their address is invalid
+    Skipped,
+    Failed {
+        error: String,
+        transient: bool,
+    },
+}
+
+async fn dequeue_delivery(
+    pool: &PgPool,
+) -> anyhow::Result<Option<(Transaction<'static, Postgres>, Delivery)>> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    let delivery = sqlx::query_as!(
+        Delivery,
+        r#"
+        SELECT issue_id, subscriber_id, attempts FROM newsletter_deliveries
+        WHERE status = $1 AND next_attempt_at <= $2
+        ORDER BY next_attempt_at
+        FOR UPDATE SKIP LOCKED
+        LIMIT 1
+        "#,
+        DeliveryStatus::Pending.as_str(),
+        Utc::now(),
+    )
+    .fetch_optional(&mut transaction)
+    .await
+    .context("Failed to claim a pending delivery")?;
+    Ok(delivery.map(|d| (transaction, d)))
+}
+
+/// Attempts are numbered across replays, so they are not numbered from `delivery.attempts`.
+async fn record_attempt(
+    tx: &mut Transaction<'_, Postgres>,
+    delivery: &Delivery,
+    error: Option<&str>,
+) -> anyhow::Result<()> {
+    sqlx::query!(
+        r#"
+        INSERT INTO delivery_attempts (issue_id, subscriber_id, attempt, attempted_at, error)
+        SELECT $1, $2, COALESCE(MAX(attempt), 0) + 1, $3, $4 FROM delivery_attempts
+        WHERE issue_id = $1 AND subscriber_id = $2
+        "#,
+        delivery.issue_id,
+        delivery.subscriber_id,
+        Utc::now(),
+        error,
+    )
+    .execute(&mut *tx)
+    .await
+    .context("Failed to record a delivery attempt")?;
+    Ok(())
+}
+
+/// Moves the delivery out of the pending, or digest, state.
+async fn update_delivery(
+    tx: &mut Transaction<'_, Postgres>,
+    delivery: &Delivery,
+    status: DeliveryStatus,
+    attempts: i16,
+    processed_at: Option<DateTime<Utc>>,
+) -> anyhow::Result<()> {
+    sqlx::query!(
+        r#"
+        UPDATE newsletter_deliveries SET status = $1, attempts = $2, processed_at = $3
+        WHERE issue_id = $4 AND subscriber_id = $5
+        "#,
+        status.as_str(),
+        attempts,
+        processed_at,
+        delivery.issue_id,
+        delivery.subscriber_id,
+    )
+    .execute(&mut *tx)
+    .await
+    .context("Failed to record the outcome of a delivery")?;
+    Ok(())
+}
+
+/// Keeps the delivery pending, or waiting for a digest, to be attempted again at `next_attempt_at`.
+async fn schedule_retry(
+    tx: &mut Transaction<'_, Postgres>,
+    delivery: &Delivery,
+    attempts: i16,
+    next_attempt_at: DateTime<Utc>,
+) -> anyhow::Result<()> {
+    sqlx::query!(
+        r#"
+        UPDATE newsletter_deliveries SET attempts = $1, next_attempt_at = $2
+        WHERE issue_id = $3 AND subscriber_id = $4
+        "#,
+        attempts,
+        next_attempt_at,
+        delivery.issue_id,
+        delivery.subscriber_id,
+    )
+    .execute(&mut *tx)
+    .await
+    .context("Failed to schedule the retry of a delivery")?;
+    Ok(())
+}
+
+/// Sends the issue to the subscriber, unless they are no longer confirmed.
+async fn send_delivery(
+    tx: &mut Transaction<'_, Postgres>,
+    email_client: &EmailClient,
+    links: &PreferencesLinks,
+    delivery: &Delivery,
+) -> anyhow::Result<SendOutcome> {
+    let subscriber = sqlx::query!(
+        r#"SELECT email, name, status, attributes FROM subscriptions WHERE id = $1"#,
+        delivery.subscriber_id
+    )
+    .fetch_one(&mut *tx)
+    .await
+    .context("Failed to fetch the subscriber of a delivery")?;
+    // the subscriber may have unsubscribed since the issue was published
+    if subscriber.status != SubscriptionStatus::Confirmed.as_str() {
+        return Ok(SendOutcome::Skipped);
+    }
+    let email = match SubscriberEmail::parse(subscriber.email.clone()) {
+        Ok(email) => email,
+        Err(error) => {
+            tracing::warn!(
+                error.cause_chain = ?anyhow!(error),
+                "Skipping a confirmed subscriber. \
+                Their stored contact details are invalid",
+            );
+            return Ok(SendOutcome::Skipped);
+        }
+    };
+
+    let issue = sqlx::query!(
+        r#"SELECT title, html_content, text_content FROM newsletter_issues WHERE id = $1"#,
+        delivery.issue_id
+    )
+    .fetch_one(&mut *tx)
+    .await
+    .context("Failed to fetch the issue of a delivery")?;
+
+    let link = links.link(delivery.subscriber_id);
+    let no_attributes = Map::new();
+    let fields = MergeFields {
+        name: &subscriber.name,
+        email: email.as_ref(),
+        unsubscribe_url: &link,
+        attributes: subscriber.attributes.as_object().unwrap_or(&no_attributes),
+    };
+    // templates are checked when the issue is stored, a failure here will not go away on retry
+    let content = match personalize(
+        &issue.title,
+        &issue.html_content,
+        &issue.text_content,
+        &fields,
+    ) {
+        Ok(content) => content,
+        Err(error) => {
+            return Ok(SendOutcome::Failed {
+                transient: false,
+                error: error.to_string(),
+            })
+        }
+    };
+    let (html_body, text_body) = email_bodies(&content.html, &content.text, &link);
+    let sent = email_client
+        .send_email(&email, &content.subject, &html_body, &text_body)
+        .await;
+    match sent {
+        Ok(()) => Ok(SendOutcome::Sent),
+        Err(error) => {
+            tracing::warn!(
+                error.cause_chain = ?error,
+                error.message = %error,
+                "Failed to deliver issue to a confirmed subscriber",
+            );
+            Ok(SendOutcome::Failed {
+                transient: is_transient(&error),
+                error: error.to_string(),
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{is_transient_status, RetryPolicy};
+    use reqwest::StatusCode;
+    use std::time::Duration;
+
+    fn policy() -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: 5,
+            base_backoff: Duration::from_secs(30),
+            max_backoff: Duration::from_secs(3600),
+        }
+    }
+
+    #[test]
+    fn the_backoff_doubles_with_every_attempt_within_the_jitter() {
+        for (attempt, delay) in [(1, 30), (2, 60), (3, 120), (4, 240)] {
+            let backoff = policy().backoff(attempt);
+            let delay = Duration::from_secs(delay);
+            assert!(
+                backoff >= delay / 2 && backoff <= delay,
+                "{backoff:?} is not within {delay:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn the_backoff_is_capped() {
+        for attempt in [8, 20, i16::MAX] {
+            assert!(policy().backoff(attempt) <= Duration::from_secs(3600));
+            assert!(policy().backoff(attempt) >= Duration::from_secs(1800));
+        }
+    }
+
+    #[test]
+    fn only_server_errors_and_throttling_are_transient() {
+        for status in [500, 502, 503, 429, 408] {
+            assert!(is_transient_status(StatusCode::from_u16(status).unwrap()));
+        }
+        for status in [400, 401, 404, 422] {
+            assert!(!is_transient_status(StatusCode::from_u16(status).unwrap()));
+        }
+    }
+}
@@ -0,0 +1,65 @@
+use serde::{Deserialize, Serialize};
+
+/// How often a subscriber wants to hear from us.
+///
+/// Stored as its snake case name in `subscriptions.delivery_frequency`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DeliveryFrequency {
+    #[default]
+    EveryIssue,
+    WeeklyDigest,
+}
+
+impl DeliveryFrequency {
+    pub const ALL: [Self; 2] = [Self::EveryIssue, Self::WeeklyDigest];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::EveryIssue => "every_issue",
+            Self::WeeklyDigest => "weekly_digest",
+        }
+    }
+
+    /// How the frequency is shown to subscribers.
+    pub fn label(&self) -> &'static str {
+        match self {
+            Self::EveryIssue => "Every issue",
+            Self::WeeklyDigest => "A weekly digest",
+        }
+    }
+}
+
+impl std::fmt::Display for DeliveryFrequency {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl TryFrom<String> for DeliveryFrequency {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        Self::ALL
+            .into_iter()
+            .find(|frequency| frequency.as_str() == s)
+            .ok_or_else(|| format!("{s} is not a valid delivery frequency"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::DeliveryFrequency;
+    use claims::assert_err;
+
+    #[test]
+    fn frequencies_round_trip_through_their_name() {
+        for frequency in DeliveryFrequency::ALL {
+            assert_eq!(
+                DeliveryFrequency::try_from(frequency.as_str().to_string()),
+                Ok(frequency)
+            );
+        }
+        assert_err!(DeliveryFrequency::try_from("daily".to_string()));
+    }
+}
@@ -0,0 +1,72 @@
+use serde::{Deserialize, Serialize};
+
+/// Where the delivery of an issue to a single subscriber is.
+///
+/// Stored as its snake case name in `newsletter_deliveries.status`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DeliveryStatus {
+    /// Waiting for the delivery worker
+    Pending,
+    /// Waiting for the weekly digest of a subscriber who asked for one
+    Digest,
+    Sent,
+    /// The subscriber was no longer confirmed, or their address no longer valid, at send time
+    Skipped,
+    /// Failed permanently, or ran out of attempts; waiting for an admin to replay it
+    DeadLetter,
+}
+
+impl DeliveryStatus {
+    pub const ALL: [Self; 5] = [
+        Self::Pending,
+        Self::Digest,
+        Self::Sent,
+        Self::Skipped,
+        Self::DeadLetter,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Pending => "pending",
+            Self::Digest => "digest",
+            Self::Sent => "sent",
+            Self::Skipped => "skipped",
+            Self::DeadLetter => "dead_letter",
+        }
+    }
+}
+
+impl std::fmt::Display for DeliveryStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl TryFrom<String> for DeliveryStatus {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        Self::ALL
+            .into_iter()
+            .find(|status| status.as_str() == s)
+            .ok_or_else(|| format!("{s} is not a valid delivery status"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::DeliveryStatus;
+    use claims::assert_err;
+
+    #[test]
+    fn statuses_round_trip_through_their_name() {
+        for status in DeliveryStatus::ALL {
+            assert_eq!(
+                DeliveryStatus::try_from(status.as_str().to_string()),
+                Ok(status)
+            );
+        }
+        assert_err!(DeliveryStatus::try_from("bounced".to_string()));
+    }
+}
@@ -0,0 +1,71 @@
+use serde::{Deserialize, Serialize};
+
+/// Where a newsletter issue is in its send.
+///
+/// Stored as its snake case name in `newsletter_issues.status`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IssueStatus {
+    /// Being written, not sent to anyone until an explicit send
+    Draft,
+    /// Waiting for its `send_at` time
+    Scheduled,
+    /// Cancelled before it started sending
+    Cancelled,
+    Sending,
+    Sent,
+}
+
+impl IssueStatus {
+    pub const ALL: [Self; 5] = [
+        Self::Draft,
+        Self::Scheduled,
+        Self::Cancelled,
+        Self::Sending,
+        Self::Sent,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Draft => "draft",
+            Self::Scheduled => "scheduled",
+            Self::Cancelled => "cancelled",
+            Self::Sending => "sending",
+            Self::Sent => "sent",
+        }
+    }
+}
+
+impl std::fmt::Display for IssueStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl TryFrom<String> for IssueStatus {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        Self::ALL
+            .into_iter()
+            .find(|status| status.as_str() == s)
+            .ok_or_else(|| format!("{s} is not a valid issue status"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::IssueStatus;
+    use claims::assert_err;
+
+    #[test]
+    fn statuses_round_trip_through_their_name() {
+        for status in IssueStatus::ALL {
+            assert_eq!(
+                IssueStatus::try_from(status.as_str().to_string()),
+                Ok(status)
+            );
+        }
+        assert_err!(IssueStatus::try_from("archived".to_string()));
+    }
+}
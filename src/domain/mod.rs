@@ -1,8 +1,16 @@
+mod delivery_frequency;
+mod delivery_status;
+mod issue_status;
 mod subscriber_email;
 mod subscriber_name;
+mod subscription_status;
 
+pub use delivery_frequency::DeliveryFrequency;
+pub use delivery_status::DeliveryStatus;
+pub use issue_status::IssueStatus;
 pub use subscriber_email::SubscriberEmail;
-use subscriber_name::SubscriberName;
+pub use subscriber_name::SubscriberName;
+pub use subscription_status::{InvalidTransition, SubscriptionStatus};
 
 use crate::routes::FormData;
 
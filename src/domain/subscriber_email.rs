@@ -1,25 +1,51 @@
+/// A valid email address, kept both as typed by the subscriber (minus surrounding whitespace)
+/// and in a normalized form used for uniqueness and lookups.
 #[derive(Debug, Clone)]
-pub struct SubscriberEmail(String);
+pub struct SubscriberEmail {
+    display: String,
+    normalized: String,
+}
 
 impl SubscriberEmail {
     pub fn parse(s: String) -> Result<SubscriberEmail, String> {
-        if !validator::validate_email(&s) {
+        let display = s.trim().to_string();
+        if !validator::validate_email(&display) {
             return Err(format!("{} is not a valid subscriber email", s));
         }
-        Ok(Self(s))
+        let normalized =
+            normalize(&display).ok_or_else(|| format!("{} is not a valid subscriber email", s))?;
+        Ok(Self {
+            display,
+            normalized,
+        })
+    }
+
+    /// The canonical form of the address: the domain is lowercased and IDN domains are
+    /// converted to punycode, while the local part is kept as is.
+    ///
+    /// Two addresses only differing by case are still considered the same subscriber,
+    /// the database enforces it with a case-insensitive unique index on this form.
+    pub fn normalized(&self) -> &str {
+        &self.normalized
     }
 }
 
+fn normalize(email: &str) -> Option<String> {
+    let (local, domain) = email.rsplit_once('@')?;
+    let domain = idna::domain_to_ascii(domain).ok()?;
+    Some(format!("{local}@{domain}"))
+}
+
 impl AsRef<str> for SubscriberEmail {
     fn as_ref(&self) -> &str {
-        &self.0
+        &self.display
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::SubscriberEmail;
-    use claims::assert_err;
+    use claims::{assert_err, assert_ok};
     use fake::{faker::internet::en::SafeEmail, Fake};
 
     #[test]
@@ -40,6 +66,27 @@ mod tests {
         assert_err!(SubscriberEmail::parse(email));
     }
 
+    #[test]
+    fn surrounding_whitespace_is_trimmed() {
+        let email = assert_ok!(SubscriberEmail::parse("  ursula@domain.com\n".to_string()));
+        assert_eq!(email.as_ref(), "ursula@domain.com");
+        assert_eq!(email.normalized(), "ursula@domain.com");
+    }
+
+    #[test]
+    fn the_domain_is_lowercased_but_the_display_form_is_kept() {
+        let email = assert_ok!(SubscriberEmail::parse("Ursula@Example.COM".to_string()));
+        assert_eq!(email.as_ref(), "Ursula@Example.COM");
+        assert_eq!(email.normalized(), "Ursula@example.com");
+    }
+
+    #[test]
+    fn idn_domains_are_converted_to_punycode() {
+        let email = assert_ok!(SubscriberEmail::parse("ursula@Bücher.example".to_string()));
+        assert_eq!(email.as_ref(), "ursula@Bücher.example");
+        assert_eq!(email.normalized(), "ursula@xn--bcher-kva.example");
+    }
+
     #[derive(Debug, Clone)]
     struct ValidEmailFixture(pub String);
 
@@ -0,0 +1,129 @@
+use serde::{Deserialize, Serialize};
+
+/// Where a subscriber is in the subscription lifecycle.
+///
+/// Statuses are stored as their snake case name in `subscriptions.status`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SubscriptionStatus {
+    PendingConfirmation,
+    Confirmed,
+    Unsubscribed,
+    Bounced,
+    Complained,
+    Deleted,
+}
+
+impl SubscriptionStatus {
+    pub const ALL: [Self; 6] = [
+        Self::PendingConfirmation,
+        Self::Confirmed,
+        Self::Unsubscribed,
+        Self::Bounced,
+        Self::Complained,
+        Self::Deleted,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::PendingConfirmation => "pending_confirmation",
+            Self::Confirmed => "confirmed",
+            Self::Unsubscribed => "unsubscribed",
+            Self::Bounced => "bounced",
+            Self::Complained => "complained",
+            Self::Deleted => "deleted",
+        }
+    }
+
+    /// Whether a subscriber can move from this status to `next`.
+    ///
+    /// Subscribers coming back after unsubscribing or bouncing have to confirm their
+    /// address again, a spam complaint is final and nothing comes back from deletion: the
+    /// address has to subscribe again as a new subscriber.
+    pub fn can_transition_to(&self, next: Self) -> bool {
+        use SubscriptionStatus::*;
+        matches!(
+            (self, next),
+            (
+                PendingConfirmation,
+                Confirmed | Unsubscribed | Bounced | Deleted
+            ) | (Confirmed, Unsubscribed | Bounced | Complained | Deleted)
+                | (Unsubscribed, PendingConfirmation | Deleted)
+                | (Bounced, PendingConfirmation | Unsubscribed | Deleted)
+                | (Complained, Deleted)
+        )
+    }
+
+    pub fn transition_to(self, next: Self) -> Result<Self, InvalidTransition> {
+        if self.can_transition_to(next) {
+            Ok(next)
+        } else {
+            Err(InvalidTransition {
+                from: self,
+                to: next,
+            })
+        }
+    }
+}
+
+impl std::fmt::Display for SubscriptionStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl TryFrom<String> for SubscriptionStatus {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        Self::ALL
+            .into_iter()
+            .find(|status| status.as_str() == s)
+            .ok_or_else(|| format!("{s} is not a valid subscription status"))
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("A subscriber cannot go from {from} to {to}")]
+pub struct InvalidTransition {
+    pub from: SubscriptionStatus,
+    pub to: SubscriptionStatus,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SubscriptionStatus::{self, *};
+    use claims::{assert_err, assert_ok_eq};
+
+    #[test]
+    fn statuses_round_trip_through_their_name() {
+        for status in SubscriptionStatus::ALL {
+            assert_eq!(
+                SubscriptionStatus::try_from(status.as_str().to_string()),
+                Ok(status)
+            );
+        }
+        assert_err!(SubscriptionStatus::try_from("pending".to_string()));
+    }
+
+    #[test]
+    fn the_happy_path_is_allowed() {
+        assert_ok_eq!(PendingConfirmation.transition_to(Confirmed), Confirmed);
+        assert_ok_eq!(Confirmed.transition_to(Unsubscribed), Unsubscribed);
+        assert_ok_eq!(
+            Unsubscribed.transition_to(PendingConfirmation),
+            PendingConfirmation
+        );
+    }
+
+    #[test]
+    fn illegal_transitions_are_rejected() {
+        assert_err!(Unsubscribed.transition_to(Confirmed));
+        assert_err!(PendingConfirmation.transition_to(Complained));
+        assert_err!(Complained.transition_to(PendingConfirmation));
+        for status in SubscriptionStatus::ALL {
+            assert_err!(Deleted.transition_to(status));
+            assert_err!(status.transition_to(status));
+        }
+    }
+}
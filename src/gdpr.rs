@@ -0,0 +1,451 @@
+//! Data subject requests: access to, and erasure of, everything we store about a subscriber.
+use std::collections::HashSet;
+
+use anyhow::Context;
+use chrono::{DateTime, Duration, Utc};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::attribution::Attribution;
+use crate::audit::{self, Actor};
+use crate::domain::SubscriberEmail;
+use crate::routes::generate_subscription_token;
+
+/// How long a data access magic link stays valid.
+const DATA_ACCESS_TOKEN_TTL_HOURS: i64 = 24;
+
+/// Everything stored about a single subscriber.
+#[derive(Serialize)]
+pub struct SubscriberData {
+    pub subscription: SubscriptionRecord,
+    /// The names of the lists the subscriber receives
+    pub lists: Vec<String>,
+    pub attribution: Attribution,
+    pub subscription_tokens: Vec<String>,
+    pub data_access_tokens: Vec<DataAccessTokenRecord>,
+    pub status_history: Vec<StatusChangeRecord>,
+    pub email_change_requests: Vec<EmailChangeRecord>,
+    /// The newsletter issues sent, or queued to be sent, to the subscriber
+    pub deliveries: Vec<DeliveryRecord>,
+    /// The deleted subscriptions of the same address
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub previous_subscriptions: Vec<SubscriberData>,
+}
+
+#[derive(Serialize)]
+pub struct SubscriptionRecord {
+    pub id: Uuid,
+    pub email: String,
+    pub name: String,
+    pub status: String,
+    pub locale: String,
+    pub delivery_frequency: String,
+    pub subscribed_at: DateTime<Utc>,
+    /// Custom fields, such as the ones imported from extra CSV columns
+    pub attributes: serde_json::Value,
+}
+
+#[derive(Serialize)]
+pub struct DataAccessTokenRecord {
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+}
+
+#[derive(Serialize)]
+pub struct StatusChangeRecord {
+    pub from_status: Option<String>,
+    pub to_status: String,
+    pub reason: String,
+    pub changed_at: DateTime<Utc>,
+}
+
+#[derive(Serialize)]
+pub struct EmailChangeRecord {
+    pub new_email: String,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+}
+
+#[derive(Serialize)]
+pub struct DeliveryRecord {
+    pub issue_id: Uuid,
+    pub title: String,
+    pub status: String,
+    pub created_at: DateTime<Utc>,
+    pub processed_at: Option<DateTime<Utc>>,
+}
+
+/// The hash stored in the suppression list in place of an erased email address.
+///
+/// It is computed from the normalized form of the address (see [`SubscriberEmail::normalized`]).
+pub fn email_hash(email: &str) -> String {
+    let digest = Sha256::digest(email.trim().to_lowercase().as_bytes());
+    format!("{:x}", digest)
+}
+
+/// Looks up every subscriber who used the normalized form of the email address, deleted
+/// ones included, the current subscriber first. An invalid address never matches anyone.
+#[tracing::instrument(name = "Find subscribers by email", skip(pool, email))]
+pub async fn find_subscriber_ids(pool: &PgPool, email: &str) -> anyhow::Result<Vec<Uuid>> {
+    let Ok(email) = SubscriberEmail::parse(email.to_string()) else {
+        return Ok(Vec::new());
+    };
+    subscriber_ids_by_address(pool, email.normalized()).await
+}
+
+/// Every subscriber who used the address of the given one, itself included.
+#[tracing::instrument(name = "Find subscribers sharing an address", skip(pool))]
+pub async fn subscriber_ids_sharing_address(
+    pool: &PgPool,
+    subscriber_id: Uuid,
+) -> anyhow::Result<Vec<Uuid>> {
+    let row = sqlx::query!(
+        r#"
+        SELECT COALESCE(deleted_email_normalized, email_normalized) AS "address!"
+        FROM subscriptions WHERE id = $1
+        "#,
+        subscriber_id
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to fetch the address of a subscriber")?;
+    match row {
+        Some(row) => subscriber_ids_by_address(pool, &row.address).await,
+        None => Ok(Vec::new()),
+    }
+}
+
+async fn subscriber_ids_by_address(pool: &PgPool, normalized: &str) -> anyhow::Result<Vec<Uuid>> {
+    let rows = sqlx::query!(
+        r#"
+        SELECT id FROM subscriptions
+        WHERE lower(email_normalized) = lower($1) OR lower(deleted_email_normalized) = lower($1)
+        ORDER BY deleted_email_normalized IS NOT NULL, subscribed_at DESC
+        "#,
+        normalized
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to look up subscribers by email")?;
+    Ok(rows.into_iter().map(|r| r.id).collect())
+}
+
+/// Collects everything stored about the subscribers and records the access in the audit log.
+///
+/// The data of the first one is returned, with the others as its previous subscriptions.
+/// Returns `None` if none of them exist.
+#[tracing::instrument(name = "Collect subscriber data", skip(pool))]
+pub async fn collect_subscriber_data(
+    pool: &PgPool,
+    subscriber_ids: &[Uuid],
+    actor: Actor,
+) -> anyhow::Result<Option<SubscriberData>> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+
+    let mut collected = Vec::with_capacity(subscriber_ids.len());
+    for &subscriber_id in subscriber_ids {
+        let Some(data) = collect_one(&mut transaction, subscriber_id).await? else {
+            continue;
+        };
+        audit::record(
+            &mut transaction,
+            actor,
+            "subscriber_data_exported",
+            &subscriber_id.to_string(),
+            serde_json::json!({}),
+        )
+        .await?;
+        collected.push(data);
+    }
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to export subscriber data")?;
+
+    let mut collected = collected.into_iter();
+    Ok(collected.next().map(|mut data| {
+        data.previous_subscriptions = collected.collect();
+        data
+    }))
+}
+
+async fn collect_one(
+    transaction: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+) -> anyhow::Result<Option<SubscriberData>> {
+    let subscription = sqlx::query_as!(
+        SubscriptionRecord,
+        r#"
+        SELECT id, email, name, status, locale, delivery_frequency, subscribed_at, attributes
+        FROM subscriptions WHERE id = $1
+        "#,
+        subscriber_id
+    )
+    .fetch_optional(&mut *transaction)
+    .await
+    .context("Failed to fetch the subscription")?;
+    let Some(subscription) = subscription else {
+        return Ok(None);
+    };
+
+    let lists = sqlx::query!(
+        r#"
+        SELECT name FROM lists JOIN subscription_lists ON list_id = lists.id
+        WHERE subscriber_id = $1 ORDER BY name
+        "#,
+        subscriber_id
+    )
+    .fetch_all(&mut *transaction)
+    .await
+    .context("Failed to fetch the lists of the subscriber")?
+    .into_iter()
+    .map(|r| r.name)
+    .collect();
+
+    let attribution = sqlx::query_as!(
+        Attribution,
+        r#"
+        SELECT source, utm_source, utm_medium, utm_campaign, utm_term, utm_content, referrer, landing_url
+        FROM subscriptions WHERE id = $1
+        "#,
+        subscriber_id
+    )
+    .fetch_one(&mut *transaction)
+    .await
+    .context("Failed to fetch the attribution of the subscriber")?;
+
+    let subscription_tokens = sqlx::query!(
+        r#"SELECT subscription_token FROM subscription_tokens WHERE subscriber_id = $1"#,
+        subscriber_id
+    )
+    .fetch_all(&mut *transaction)
+    .await
+    .context("Failed to fetch the subscription tokens")?
+    .into_iter()
+    .map(|r| r.subscription_token)
+    .collect();
+
+    let data_access_tokens = sqlx::query_as!(
+        DataAccessTokenRecord,
+        r#"SELECT created_at, expires_at FROM data_access_tokens WHERE subscriber_id = $1"#,
+        subscriber_id
+    )
+    .fetch_all(&mut *transaction)
+    .await
+    .context("Failed to fetch the data access tokens")?;
+
+    let status_history = sqlx::query_as!(
+        StatusChangeRecord,
+        r#"
+        SELECT from_status, to_status, reason, changed_at FROM subscription_status_history
+        WHERE subscriber_id = $1 ORDER BY changed_at
+        "#,
+        subscriber_id
+    )
+    .fetch_all(&mut *transaction)
+    .await
+    .context("Failed to fetch the status history")?;
+
+    let email_change_requests = sqlx::query_as!(
+        EmailChangeRecord,
+        r#"
+        SELECT new_email, created_at, expires_at FROM email_change_requests
+        WHERE subscriber_id = $1 ORDER BY created_at
+        "#,
+        subscriber_id
+    )
+    .fetch_all(&mut *transaction)
+    .await
+    .context("Failed to fetch the email change requests")?;
+
+    let deliveries = sqlx::query_as!(
+        DeliveryRecord,
+        r#"
+        SELECT d.issue_id, i.title, d.status, d.created_at, d.processed_at
+        FROM newsletter_deliveries d JOIN newsletter_issues i ON i.id = d.issue_id
+        WHERE d.subscriber_id = $1 ORDER BY d.created_at
+        "#,
+        subscriber_id
+    )
+    .fetch_all(&mut *transaction)
+    .await
+    .context("Failed to fetch the deliveries")?;
+
+    Ok(Some(SubscriberData {
+        subscription,
+        lists,
+        attribution,
+        subscription_tokens,
+        data_access_tokens,
+        status_history,
+        email_change_requests,
+        deliveries,
+        previous_subscriptions: Vec::new(),
+    }))
+}
+
+/// Permanently deletes the subscribers, leaving behind only the hash of their address in the
+/// suppression list and an audit log entry each. Returns how many existed.
+#[tracing::instrument(name = "Erase subscribers", skip(pool))]
+pub async fn erase_subscribers(
+    pool: &PgPool,
+    subscriber_ids: &[Uuid],
+    actor: Actor,
+) -> anyhow::Result<u64> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+
+    let mut erased = 0;
+    for &subscriber_id in subscriber_ids {
+        // deleted subscribers released their normalized address, it was kept aside
+        let row = sqlx::query!(
+            r#"
+            SELECT COALESCE(deleted_email_normalized, email_normalized) AS "address!"
+            FROM subscriptions WHERE id = $1 FOR UPDATE
+            "#,
+            subscriber_id
+        )
+        .fetch_optional(&mut transaction)
+        .await
+        .context("Failed to fetch the subscriber to erase")?;
+        let Some(row) = row else {
+            continue;
+        };
+
+        delete_subscriber(&mut transaction, subscriber_id).await?;
+
+        let hash = email_hash(&row.address);
+        sqlx::query!(
+            r#"
+            INSERT INTO suppressed_emails (email_hash, suppressed_at) VALUES ($1, $2)
+            ON CONFLICT (email_hash) DO NOTHING
+            "#,
+            hash,
+            Utc::now(),
+        )
+        .execute(&mut transaction)
+        .await
+        .context("Failed to suppress the erased email address")?;
+
+        audit::record(
+            &mut transaction,
+            actor,
+            "subscriber_erased",
+            &hash,
+            serde_json::json!({ "subscriber_id": subscriber_id }),
+        )
+        .await?;
+        erased += 1;
+    }
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to erase a subscriber")?;
+    Ok(erased)
+}
+
+/// Deletes the subscription row and every row referencing it.
+async fn delete_subscriber(
+    tx: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+) -> anyhow::Result<()> {
+    sqlx::query!(
+        r#"DELETE FROM subscription_tokens WHERE subscriber_id = $1"#,
+        subscriber_id
+    )
+    .execute(&mut *tx)
+    .await
+    .context("Failed to delete the subscription tokens")?;
+
+    // data access tokens, the status history and the deliveries are removed by the `ON DELETE CASCADE`
+    sqlx::query!(r#"DELETE FROM subscriptions WHERE id = $1"#, subscriber_id)
+        .execute(&mut *tx)
+        .await
+        .context("Failed to delete the subscription")?;
+    Ok(())
+}
+
+/// Returns the subset of the given email hashes that are in the suppression list.
+pub async fn suppressed_hashes(
+    pool: &PgPool,
+    hashes: &[String],
+) -> anyhow::Result<HashSet<String>> {
+    let out = sqlx::query!(
+        r#"SELECT email_hash FROM suppressed_emails WHERE email_hash = ANY($1)"#,
+        hashes
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to query the suppression list")?
+    .into_iter()
+    .map(|r| r.email_hash)
+    .collect();
+    Ok(out)
+}
+
+/// Stores and returns a new token for a data access magic link.
+#[tracing::instrument(name = "Issue a data access token", skip(pool))]
+pub async fn issue_data_access_token(pool: &PgPool, subscriber_id: Uuid) -> anyhow::Result<String> {
+    let token = generate_subscription_token();
+    let now = Utc::now();
+    sqlx::query!(
+        r#"
+        INSERT INTO data_access_tokens (token, subscriber_id, created_at, expires_at)
+        VALUES ($1, $2, $3, $4)
+        "#,
+        token,
+        subscriber_id,
+        now,
+        now + Duration::hours(DATA_ACCESS_TOKEN_TTL_HOURS),
+    )
+    .execute(pool)
+    .await
+    .context("Failed to store a data access token")?;
+    Ok(token)
+}
+
+/// Resolves an unexpired data access token to the subscriber it was issued for.
+#[tracing::instrument(name = "Get subscriber_id from data access token", skip(pool, token))]
+pub async fn subscriber_id_from_data_access_token(
+    pool: &PgPool,
+    token: &str,
+) -> anyhow::Result<Option<Uuid>> {
+    let row = sqlx::query!(
+        r#"SELECT subscriber_id FROM data_access_tokens WHERE token = $1 AND expires_at > $2"#,
+        token,
+        Utc::now(),
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to look up a data access token")?;
+    Ok(row.map(|r| r.subscriber_id))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::email_hash;
+
+    #[test]
+    fn email_hash_ignores_case_and_surrounding_whitespace() {
+        assert_eq!(
+            email_hash(" Ursula@Example.com "),
+            email_hash("ursula@example.com")
+        );
+    }
+
+    #[test]
+    fn email_hash_does_not_contain_the_address() {
+        let hash = email_hash("ursula@example.com");
+        assert_eq!(hash.len(), 64);
+        assert!(!hash.contains("ursula"));
+    }
+}
@@ -0,0 +1,179 @@
+//! Per-locale message catalogs for the emails and pages shown to subscribers.
+use std::collections::HashMap;
+use std::path::Path;
+
+use serde::Deserialize;
+
+/// The messages of a single locale.
+///
+/// `{link}` in a message is replaced with the link the message is about.
+#[derive(Deserialize, Clone, Debug)]
+pub struct Catalog {
+    pub confirmation_email: EmailMessages,
+    pub confirmation_page: ConfirmationPageMessages,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct EmailMessages {
+    pub subject: String,
+    pub html: String,
+    pub text: String,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub struct ConfirmationPageMessages {
+    pub title: String,
+    pub confirmed: String,
+    pub invalid_link: String,
+    pub cannot_confirm: String,
+    pub error: String,
+}
+
+/// Replaces the `{link}` placeholder in a message.
+pub fn with_link(message: &str, link: &str) -> String {
+    message.replace("{link}", link)
+}
+
+/// The message catalogs of every supported locale.
+pub struct Catalogs {
+    default_locale: String,
+    catalogs: HashMap<String, Catalog>,
+}
+
+impl Catalogs {
+    /// Loads every `<locale>.yaml` file in `dir`.
+    ///
+    /// Each catalog is layered on top of the catalog of the default locale,
+    /// so that a partial translation falls back to it for the missing messages.
+    pub fn load(dir: impl AsRef<Path>, default_locale: &str) -> Result<Self, config::ConfigError> {
+        let dir = dir.as_ref();
+        let default_file = dir.join(default_locale);
+
+        let mut catalogs = HashMap::new();
+        let entries = std::fs::read_dir(dir).map_err(|e| config::ConfigError::Foreign(e.into()))?;
+        for entry in entries {
+            let path = entry
+                .map_err(|e| config::ConfigError::Foreign(e.into()))?
+                .path();
+            if path.extension().and_then(|e| e.to_str()) != Some("yaml") {
+                continue;
+            }
+            let Some(locale) = path.file_stem().and_then(|s| s.to_str()) else {
+                continue;
+            };
+
+            let mut settings = config::Config::default();
+            settings.merge(config::File::from(default_file.clone()).required(true))?;
+            settings.merge(config::File::from(path.clone()))?;
+            catalogs.insert(locale.to_lowercase(), settings.try_into()?);
+        }
+
+        Ok(Self {
+            default_locale: default_locale.to_lowercase(),
+            catalogs,
+        })
+    }
+
+    pub fn default_locale(&self) -> &str {
+        &self.default_locale
+    }
+
+    /// The catalog for `locale`, or for the default locale if `locale` is not supported.
+    pub fn get(&self, locale: &str) -> &Catalog {
+        self.catalogs
+            .get(locale)
+            .or_else(|| self.catalogs.get(&self.default_locale))
+            .expect("the catalog of the default locale is always loaded")
+    }
+
+    /// Picks the supported locale to use for a subscriber: the one they asked for explicitly,
+    /// if any, else the best match in their `Accept-Language` header, else the default one.
+    pub fn negotiate(&self, requested: Option<&str>, accept_language: Option<&str>) -> String {
+        requested
+            .and_then(|r| self.supported(r))
+            .or_else(|| {
+                accept_language
+                    .map(parse_accept_language)
+                    .unwrap_or_default()
+                    .into_iter()
+                    .find_map(|tag| self.supported(&tag))
+            })
+            .unwrap_or_else(|| self.default_locale.clone())
+    }
+
+    /// Matches a language tag such as `pt-BR` against the supported locales,
+    /// falling back to its primary language, `pt`.
+    fn supported(&self, tag: &str) -> Option<String> {
+        let tag = tag.trim().to_lowercase().replace('_', "-");
+        let primary = tag.split('-').next().unwrap_or_default().to_string();
+        [tag, primary]
+            .into_iter()
+            .find(|candidate| self.catalogs.contains_key(candidate))
+    }
+}
+
+/// The language tags of an `Accept-Language` header, by decreasing preference.
+fn parse_accept_language(header: &str) -> Vec<String> {
+    let mut tags: Vec<(String, f32)> = header
+        .split(',')
+        .filter_map(|item| {
+            let mut parts = item.split(';');
+            let tag = parts.next()?.trim();
+            if tag.is_empty() || tag == "*" {
+                return None;
+            }
+            let quality = parts
+                .find_map(|p| p.trim().strip_prefix("q="))
+                .and_then(|q| q.parse().ok())
+                .unwrap_or(1.0);
+            (quality > 0.0).then(|| (tag.to_string(), quality))
+        })
+        .collect();
+    // a stable sort keeps the order of the header for equal qualities
+    tags.sort_by(|a, b| b.1.total_cmp(&a.1));
+    tags.into_iter().map(|(tag, _)| tag).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_accept_language, Catalogs};
+
+    fn catalogs() -> Catalogs {
+        Catalogs::load("locales", "en").expect("failed to load the message catalogs")
+    }
+
+    #[test]
+    fn accept_language_is_sorted_by_quality() {
+        assert_eq!(
+            parse_accept_language("fr-CH, fr;q=0.9, en;q=0.8, de;q=0.95, *;q=0.5, es;q=0"),
+            vec!["fr-CH", "de", "fr", "en"]
+        );
+        assert!(parse_accept_language("").is_empty());
+    }
+
+    #[test]
+    fn the_requested_locale_wins_over_accept_language() {
+        let catalogs = catalogs();
+        assert_eq!(catalogs.negotiate(Some("es"), Some("de")), "es");
+        assert_eq!(catalogs.negotiate(Some("xx"), Some("de")), "de");
+    }
+
+    #[test]
+    fn regional_variants_fall_back_to_their_language_then_to_the_default() {
+        let catalogs = catalogs();
+        assert_eq!(catalogs.negotiate(None, Some("de-AT,en;q=0.5")), "de");
+        assert_eq!(catalogs.negotiate(Some("pt_BR"), None), "pt");
+        assert_eq!(catalogs.negotiate(None, Some("ja")), "en");
+        assert_eq!(catalogs.negotiate(None, None), "en");
+    }
+
+    #[test]
+    fn every_catalog_has_every_message() {
+        let catalogs = catalogs();
+        for locale in ["en", "de", "es", "fr", "pt"] {
+            let catalog = catalogs.get(locale);
+            assert!(catalog.confirmation_email.html.contains("{link}"));
+            assert!(catalog.confirmation_email.text.contains("{link}"));
+        }
+    }
+}
@@ -0,0 +1,227 @@
+//! Replaying the response to a retried request instead of processing it again.
+use actix_web::{body::to_bytes, http::StatusCode, HttpResponse};
+use anyhow::{anyhow, Context};
+use chrono::{Duration, Utc};
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+/// The name of the header carrying the idempotency key.
+pub const IDEMPOTENCY_KEY_HEADER: &str = "Idempotency-Key";
+
+const MAX_KEY_LENGTH: usize = 50;
+
+/// A key chosen by the client to identify a request across its retries.
+#[derive(Debug)]
+pub struct IdempotencyKey(String);
+
+impl TryFrom<String> for IdempotencyKey {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        if s.trim().is_empty() {
+            return Err("The idempotency key cannot be empty".into());
+        }
+        if s.len() >= MAX_KEY_LENGTH {
+            return Err(format!(
+                "The idempotency key must be shorter than {MAX_KEY_LENGTH} characters"
+            ));
+        }
+        Ok(Self(s))
+    }
+}
+
+impl AsRef<str> for IdempotencyKey {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+/// The hash stored with a claim, computed from the parsed body of the request so that
+/// a retry formatted differently still matches.
+pub fn request_hash(body: &impl Serialize) -> anyhow::Result<String> {
+    let body = serde_json::to_vec(body).context("Failed to serialize the request")?;
+    Ok(hex::encode(Sha256::digest(body)))
+}
+
+// the transaction is short-lived and moved around once, boxing it buys nothing
+#[allow(clippy::large_enum_variant)]
+pub enum NextAction {
+    /// The request is new: process it, then hand the response to [`save_response`].
+    StartProcessing(Transaction<'static, Postgres>),
+    /// The request was already processed.
+    ReturnSavedResponse(HttpResponse),
+    /// The key was already used for a different request.
+    RejectReusedKey,
+}
+
+/// Claims the key for this request, or returns the response saved by the first request using it.
+///
+/// The claim is a row inserted in a transaction that stays open until the response is saved.
+/// A concurrent request with the same key blocks on the insert until then, and gets the
+/// saved response once the first one is done, or processes the request itself if it failed.
+///
+/// A request whose hash differs from the one stored with the claim is not a retry, and is
+/// rejected rather than answered with the response to another request.
+#[tracing::instrument(name = "Claim an idempotency key", skip(pool))]
+pub async fn try_processing(
+    pool: &PgPool,
+    idempotency_key: &IdempotencyKey,
+    user_id: Uuid,
+    request_hash: &str,
+    ttl: Duration,
+) -> anyhow::Result<NextAction> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+
+    loop {
+        // past its time to live, a key can be used for a new request
+        sqlx::query!(
+            r#"
+            DELETE FROM idempotency
+            WHERE user_id = $1 AND idempotency_key = $2 AND created_at < $3
+            "#,
+            user_id,
+            idempotency_key.as_ref(),
+            Utc::now() - ttl,
+        )
+        .execute(&mut transaction)
+        .await
+        .context("Failed to delete an expired idempotency key")?;
+
+        let claimed = sqlx::query!(
+            r#"
+            INSERT INTO idempotency (user_id, idempotency_key, request_hash, created_at)
+            VALUES ($1, $2, $3, $4)
+            ON CONFLICT DO NOTHING
+            "#,
+            user_id,
+            idempotency_key.as_ref(),
+            request_hash,
+            Utc::now(),
+        )
+        .execute(&mut transaction)
+        .await
+        .context("Failed to claim an idempotency key")?
+        .rows_affected();
+
+        if claimed > 0 {
+            return Ok(NextAction::StartProcessing(transaction));
+        }
+        // the saved response may have expired, and been deleted by another request, since
+        // the insert: the key is then free to be claimed again
+        if let Some(saved) = get_saved_response(pool, idempotency_key, user_id).await? {
+            return Ok(if saved.request_hash == request_hash {
+                NextAction::ReturnSavedResponse(saved.response)
+            } else {
+                NextAction::RejectReusedKey
+            });
+        }
+    }
+}
+
+struct SavedResponse {
+    request_hash: String,
+    response: HttpResponse,
+}
+
+async fn get_saved_response(
+    pool: &PgPool,
+    idempotency_key: &IdempotencyKey,
+    user_id: Uuid,
+) -> anyhow::Result<Option<SavedResponse>> {
+    let saved = sqlx::query!(
+        r#"
+        SELECT
+            request_hash,
+            response_status_code AS "response_status_code!",
+            response_headers AS "response_headers!",
+            response_body AS "response_body!"
+        FROM idempotency
+        WHERE user_id = $1 AND idempotency_key = $2
+        "#,
+        user_id,
+        idempotency_key.as_ref(),
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to fetch a saved response")?;
+
+    let Some(saved) = saved else {
+        return Ok(None);
+    };
+    let status_code = StatusCode::from_u16(saved.response_status_code.try_into()?)?;
+    let headers: Vec<(String, String)> = serde_json::from_value(saved.response_headers)
+        .context("Failed to parse the headers of a saved response")?;
+    let mut response = HttpResponse::build(status_code);
+    for header in headers {
+        response.append_header(header);
+    }
+    Ok(Some(SavedResponse {
+        request_hash: saved.request_hash,
+        response: response.body(saved.response_body),
+    }))
+}
+
+/// Saves the response to the request that claimed the key, releasing the claim.
+#[tracing::instrument(
+    name = "Save the response to an idempotent request",
+    skip(transaction, http_response)
+)]
+pub async fn save_response(
+    mut transaction: Transaction<'static, Postgres>,
+    idempotency_key: &IdempotencyKey,
+    user_id: Uuid,
+    http_response: HttpResponse,
+) -> anyhow::Result<HttpResponse> {
+    let (response_head, body) = http_response.into_parts();
+    let body = to_bytes(body)
+        .await
+        .map_err(|e| anyhow!("Failed to read the response body: {e}"))?;
+    let status_code = response_head.status().as_u16() as i16;
+    // headers that are not valid UTF-8 are not replayed
+    let headers: Vec<(String, String)> = response_head
+        .headers()
+        .iter()
+        .filter_map(|(name, value)| Some((name.to_string(), value.to_str().ok()?.to_string())))
+        .collect();
+
+    sqlx::query!(
+        r#"
+        UPDATE idempotency
+        SET response_status_code = $3, response_headers = $4, response_body = $5
+        WHERE user_id = $1 AND idempotency_key = $2
+        "#,
+        user_id,
+        idempotency_key.as_ref(),
+        status_code,
+        serde_json::to_value(headers)?,
+        body.as_ref(),
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to save the response to an idempotent request")?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to save a response")?;
+
+    Ok(response_head.set_body(body).map_into_boxed_body())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::IdempotencyKey;
+    use claims::{assert_err, assert_ok};
+
+    #[test]
+    fn keys_must_be_non_empty_and_short() {
+        assert_err!(IdempotencyKey::try_from("".to_string()));
+        assert_err!(IdempotencyKey::try_from("  ".to_string()));
+        assert_err!(IdempotencyKey::try_from("a".repeat(50)));
+        assert_ok!(IdempotencyKey::try_from("a".repeat(49)));
+    }
+}
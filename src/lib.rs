@@ -1,7 +1,24 @@
+pub mod attribution;
+pub mod audit;
+pub mod authoring;
+pub mod bot_protection;
+pub mod cleanup_worker;
 pub mod configuration;
+pub mod delivery_worker;
 pub mod domain;
 pub mod email_client;
+pub mod gdpr;
+pub mod i18n;
+pub mod idempotency;
+pub mod merge_tags;
+pub mod newsletter_issues;
+pub mod plain_text;
+pub mod preferences;
+pub mod rate_limit;
 pub mod routes;
+pub mod signup_policy;
 pub mod startup;
+pub mod subscriber_import;
+pub mod subscription_lifecycle;
 pub mod telemetry;
 pub mod utils;
@@ -1,14 +1,117 @@
-use zero2prod::configuration::get_configuration;
+use std::fmt::{Debug, Display};
+
+use anyhow::Context;
+use tokio::task::JoinError;
+use zero2prod::cleanup_worker::{
+    purge_stale_subscriptions, run_worker_until_stopped, CleanupOptions,
+};
+use zero2prod::configuration::{get_configuration, Settings};
+use zero2prod::delivery_worker;
 use zero2prod::startup::Application;
+use zero2prod::subscriber_import::{import_from_csv, ImportOptions};
 use zero2prod::telemetry;
 
 #[tokio::main]
-async fn main() -> std::io::Result<()> {
+async fn main() -> anyhow::Result<()> {
     let subscriber = telemetry::get_subscriber("zero2prod", "info", std::io::stdout);
     telemetry::init_subscriber(subscriber);
 
     let config = get_configuration().expect("failed to read configuration");
-    let server = Application::build(config).await?;
 
-    server.run_until_stopped().await
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match args.first().map(String::as_str) {
+        Some("import") => import(config, &args[1..]).await,
+        Some("cleanup") => cleanup(config, &args[1..]).await,
+        Some(other) => anyhow::bail!(
+            "Unknown command `{other}`. \
+            Usage: zero2prod [import <file.csv> [--confirmed] | cleanup [--dry-run]]"
+        ),
+        None => {
+            let application = Application::build(config.clone()).await?;
+            let application_task = tokio::spawn(application.run_until_stopped());
+            let delivery_task =
+                tokio::spawn(delivery_worker::run_worker_until_stopped(config.clone()));
+            let worker_task = tokio::spawn(run_worker_until_stopped(config));
+
+            tokio::select! {
+                o = application_task => report_exit("API", o),
+                o = delivery_task => report_exit("Delivery worker", o),
+                o = worker_task => report_exit("Cleanup worker", o),
+            };
+            Ok(())
+        }
+    }
+}
+
+fn report_exit(task_name: &str, outcome: Result<Result<(), impl Debug + Display>, JoinError>) {
+    match outcome {
+        Ok(Ok(())) => {
+            tracing::info!("{} has exited", task_name)
+        }
+        Ok(Err(e)) => {
+            tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "{} failed",
+                task_name
+            )
+        }
+        Err(e) => {
+            tracing::error!(
+                error.cause_chain = ?e,
+                error.message = %e,
+                "'{}' task failed to complete",
+                task_name
+            )
+        }
+    }
+}
+
+/// `zero2prod cleanup [--dry-run]`
+///
+/// Runs the purge of stale unconfirmed subscriptions once, outside of its schedule.
+async fn cleanup(config: Settings, args: &[String]) -> anyhow::Result<()> {
+    let mut options = CleanupOptions::from(&config.cleanup);
+    options.dry_run |= args.iter().any(|a| a == "--dry-run");
+
+    let pool = zero2prod::startup::get_connection_pool(&config.database);
+    let report = purge_stale_subscriptions(&pool, options).await?;
+
+    println!("{}", serde_json::to_string_pretty(&report)?);
+    Ok(())
+}
+
+/// `zero2prod import <file.csv> [--confirmed]`
+///
+/// The command line equivalent of `POST /admin/subscribers/import`.
+async fn import(config: Settings, args: &[String]) -> anyhow::Result<()> {
+    let path = args
+        .iter()
+        .find(|a| !a.starts_with("--"))
+        .context("Usage: zero2prod import <file.csv> [--confirmed]")?;
+    let options = ImportOptions {
+        confirmed: args.iter().any(|a| a == "--confirmed"),
+    };
+
+    let file = std::fs::File::open(path).with_context(|| format!("Failed to open {path}"))?;
+    let source = futures::io::AllowStdIo::new(std::io::BufReader::new(file));
+
+    let pool = zero2prod::startup::get_connection_pool(&config.database);
+    let email_client = config.email_client.client();
+    let signup_policy = config.signup_policy.policy();
+    let catalogs = config.i18n.catalogs()?;
+
+    let report = import_from_csv(
+        source,
+        &pool,
+        &email_client,
+        &config.application.base_url,
+        &signup_policy,
+        &catalogs,
+        options,
+    )
+    .await?;
+
+    println!("{}", serde_json::to_string_pretty(&report)?);
+    Ok(())
 }
@@ -0,0 +1,298 @@
+//! Personalizing newsletter issues with merge tags such as `{{ name }}`.
+//!
+//! A tag names a field of the subscriber, and can carry a default used when the field is
+//! missing or empty: `{{ first_name | default: "there" }}`. The fields are `name`, `email`,
+//! `unsubscribe_url` and the subscriber's custom attributes.
+use std::borrow::Cow;
+
+use serde_json::{Map, Value};
+
+use crate::utils::html_escape;
+
+/// A syntax error in a template, reported when the issue is stored rather than when it is sent.
+#[derive(Debug, PartialEq, Eq, thiserror::Error)]
+#[error("{message} (line {line})")]
+pub struct TemplateError {
+    pub line: usize,
+    pub message: String,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Part<'a> {
+    Text(&'a str),
+    Tag {
+        /// The tag as written, braces included
+        source: &'a str,
+        field: String,
+        default: Option<&'a str>,
+    },
+}
+
+/// A parsed template, borrowing its text from the source.
+#[derive(Debug)]
+pub struct Template<'a> {
+    parts: Vec<Part<'a>>,
+}
+
+/// The values the tags of a template are replaced with for one subscriber.
+pub struct MergeFields<'a> {
+    pub name: &'a str,
+    pub email: &'a str,
+    pub unsubscribe_url: &'a str,
+    pub attributes: &'a Map<String, Value>,
+}
+
+impl MergeFields<'_> {
+    fn get(&self, field: &str) -> Option<Cow<'_, str>> {
+        let value = match field {
+            "name" => Cow::Borrowed(self.name),
+            "email" => Cow::Borrowed(self.email),
+            "unsubscribe_url" => Cow::Borrowed(self.unsubscribe_url),
+            _ => match self.attributes.get(field)? {
+                Value::String(s) => Cow::Borrowed(s.as_str()),
+                Value::Null => return None,
+                other => Cow::Owned(other.to_string()),
+            },
+        };
+        Some(value).filter(|v| !v.is_empty())
+    }
+}
+
+impl<'a> Template<'a> {
+    pub fn parse(source: &'a str) -> Result<Self, TemplateError> {
+        let mut parts = Vec::new();
+        let mut rest = source;
+        while let Some(start) = rest.find("{{") {
+            if start > 0 {
+                parts.push(Part::Text(&rest[..start]));
+            }
+            let line = line_of(source, rest, start);
+            let Some(end) = rest[start..].find("}}") else {
+                return Err(TemplateError {
+                    line,
+                    message: "`{{` is never closed by `}}`".into(),
+                });
+            };
+            parts.push(parse_tag(
+                &rest[start..start + end + 2],
+                &rest[start + 2..start + end],
+                line,
+            )?);
+            rest = &rest[start + end + 2..];
+        }
+        if !rest.is_empty() {
+            parts.push(Part::Text(rest));
+        }
+        Ok(Self { parts })
+    }
+
+    /// Renders the template for plain text, such as the subject or the text body.
+    pub fn render_text(&self, fields: &MergeFields) -> String {
+        self.render(fields, |v| v.to_string())
+    }
+
+    /// Renders the template for the HTML body, escaping the substituted values.
+    pub fn render_html(&self, fields: &MergeFields) -> String {
+        self.render(fields, html_escape)
+    }
+
+    /// The template with every tag replaced by `replace(tag)`, `tag` being the tag as written.
+    ///
+    /// Lets a template go through a transformation that would mangle its tags, such as
+    /// Markdown rendering, by swapping them for placeholders and back.
+    pub fn replace_tags(&self, mut replace: impl FnMut(&str) -> String) -> String {
+        let mut out = String::new();
+        for part in &self.parts {
+            match part {
+                Part::Text(text) => out.push_str(text),
+                Part::Tag { source, .. } => out.push_str(&replace(source)),
+            }
+        }
+        out
+    }
+
+    fn render(&self, fields: &MergeFields, escape: impl Fn(&str) -> String) -> String {
+        let mut out = String::new();
+        for part in &self.parts {
+            match part {
+                Part::Text(text) => out.push_str(text),
+                Part::Tag { field, default, .. } => {
+                    let value = fields.get(field);
+                    out.push_str(&escape(value.as_deref().or(*default).unwrap_or_default()));
+                }
+            }
+        }
+        out
+    }
+}
+
+/// The line, counted from 1, of `rest[offset..]` within `source`.
+fn line_of(source: &str, rest: &str, offset: usize) -> usize {
+    let position = source.len() - rest.len() + offset;
+    source[..position].matches('\n').count() + 1
+}
+
+fn parse_tag<'a>(source: &'a str, tag: &'a str, line: usize) -> Result<Part<'a>, TemplateError> {
+    let error = |message: String| TemplateError { line, message };
+    let (field, filter) = match tag.split_once('|') {
+        Some((field, filter)) => (field.trim(), Some(filter.trim())),
+        None => (tag.trim(), None),
+    };
+    let valid_field = field
+        .chars()
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && field.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if !valid_field {
+        return Err(error(format!(
+            "`{field}` is not a valid merge tag, use letters, digits and underscores"
+        )));
+    }
+    let default = match filter {
+        None => None,
+        Some(filter) => {
+            let value = filter
+                .strip_prefix("default:")
+                .map(str::trim)
+                .ok_or_else(|| {
+                    error(format!(
+                        "`{filter}` is not a known filter, only `default: \"...\"` is"
+                    ))
+                })?;
+            let value = value
+                .strip_prefix('"')
+                .and_then(|v| v.strip_suffix('"'))
+                .filter(|v| !v.contains('"'))
+                .ok_or_else(|| {
+                    error(format!(
+                        "The default of `{field}` must be a double-quoted string"
+                    ))
+                })?;
+            Some(value)
+        }
+    };
+    Ok(Part::Tag {
+        source,
+        field: field.to_lowercase(),
+        default,
+    })
+}
+
+/// The subject and both bodies of an issue, personalized for one subscriber.
+pub struct Personalized {
+    pub subject: String,
+    pub html: String,
+    pub text: String,
+}
+
+/// Renders the title and both bodies of an issue for one subscriber.
+pub fn personalize(
+    title: &str,
+    html: &str,
+    text: &str,
+    fields: &MergeFields,
+) -> Result<Personalized, TemplateError> {
+    Ok(Personalized {
+        subject: Template::parse(title)?.render_text(fields),
+        html: Template::parse(html)?.render_html(fields),
+        text: Template::parse(text)?.render_text(fields),
+    })
+}
+
+/// Checks the syntax of the templates of an issue: its title and both bodies.
+pub fn check(title: &str, html: &str, text: &str) -> Result<(), String> {
+    for (part, source) in [
+        ("title", title),
+        ("HTML content", html),
+        ("text content", text),
+    ] {
+        Template::parse(source).map_err(|e| format!("Invalid merge tag in the {part}: {e}"))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{MergeFields, Template};
+    use claims::{assert_err, assert_ok};
+    use serde_json::{json, Map, Value};
+
+    fn attributes() -> Map<String, Value> {
+        json!({ "company": "Acme & Co", "plan": null, "seats": 3 })
+            .as_object()
+            .unwrap()
+            .clone()
+    }
+
+    fn render(source: &str, html: bool) -> String {
+        let attributes = attributes();
+        let fields = MergeFields {
+            name: "Ursula <Le Guin>",
+            email: "ursula@example.com",
+            unsubscribe_url: "https://example.com/unsubscribe?token=a&b",
+            attributes: &attributes,
+        };
+        let template = Template::parse(source).unwrap();
+        if html {
+            template.render_html(&fields)
+        } else {
+            template.render_text(&fields)
+        }
+    }
+
+    #[test]
+    fn tags_are_replaced_by_the_subscriber_fields() {
+        assert_eq!(
+            render("Hi {{ name }}, from {{company}} ({{ seats }})", false),
+            "Hi Ursula <Le Guin>, from Acme & Co (3)"
+        );
+        assert_eq!(
+            render("{{ unsubscribe_url }}", false),
+            "https://example.com/unsubscribe?token=a&b"
+        );
+    }
+
+    #[test]
+    fn values_are_escaped_in_html() {
+        assert_eq!(
+            render("<p>Hi {{ name }} of {{ company }}</p>", true),
+            "<p>Hi Ursula &lt;Le Guin&gt; of Acme &amp; Co</p>"
+        );
+    }
+
+    #[test]
+    fn defaults_replace_missing_fields() {
+        assert_eq!(
+            render(
+                r#"{{ plan | default: "free" }} {{ city|default:"" }}!"#,
+                false
+            ),
+            "free !"
+        );
+        assert_eq!(
+            render(r#"{{ Name | default: "you" }}"#, false),
+            "Ursula <Le Guin>"
+        );
+        assert_eq!(render("[{{ missing }}]", false), "[]");
+        assert_eq!(render(r#"{{ x | default: "<b>" }}"#, true), "&lt;b&gt;");
+    }
+
+    #[test]
+    fn text_without_tags_is_left_alone() {
+        assert_eq!(render("", false), "");
+        assert_eq!(render("a { b } c }}", false), "a { b } c }}");
+    }
+
+    #[test]
+    fn syntax_errors_are_reported_with_their_line() {
+        let error = Template::parse("Hi\nthere {{ name").unwrap_err();
+        assert_eq!(error.line, 2);
+        assert_err!(Template::parse("{{ }}"));
+        assert_err!(Template::parse("{{ first name }}"));
+        assert_err!(Template::parse("{{ 1st }}"));
+        assert_err!(Template::parse("{{ name | upper }}"));
+        assert_err!(Template::parse("{{ name | default: there }}"));
+        assert_err!(Template::parse(r#"{{ name | default: "a"b" }}"#));
+        assert_ok!(Template::parse(r#"{{ _name2 | default: "a | b" }}"#));
+    }
+}
@@ -0,0 +1,683 @@
+//! Persisted newsletter issues: drafts and their revisions, what went out, when and who sent it.
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::domain::{DeliveryFrequency, DeliveryStatus, IssueStatus, SubscriptionStatus};
+
+/// The content of an issue about to be stored, as a new draft or a new revision of one.
+pub struct NewIssue<'a> {
+    pub title: &'a str,
+    pub html_content: &'a str,
+    pub text_content: &'a str,
+    /// The source of the bodies, when they were rendered from Markdown
+    pub markdown_content: Option<&'a str>,
+    pub list_id: Option<Uuid>,
+}
+
+#[derive(Serialize)]
+pub struct IssueSummary {
+    pub id: Uuid,
+    pub title: String,
+    pub status: String,
+    /// The username of the author
+    pub author: String,
+    /// The slug of the list the issue is sent to, if any
+    pub list: Option<String>,
+    pub created_at: DateTime<Utc>,
+    /// When a scheduled issue starts sending
+    pub send_at: Option<DateTime<Utc>>,
+    pub sent_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Serialize)]
+pub struct Issue {
+    #[serde(flatten)]
+    pub summary: IssueSummary,
+    pub html_content: String,
+    pub text_content: String,
+    pub markdown_content: Option<String>,
+    /// The number of the revision holding the current content
+    pub revision: i32,
+    pub deliveries: DeliveryCounts,
+}
+
+#[derive(Serialize)]
+pub struct RevisionSummary {
+    pub revision: i32,
+    pub title: String,
+    /// The username of the author of the revision
+    pub author: String,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Serialize)]
+pub struct Revision {
+    #[serde(flatten)]
+    pub summary: RevisionSummary,
+    pub html_content: String,
+    pub text_content: String,
+    pub markdown_content: Option<String>,
+}
+
+/// How many deliveries of an issue are in each state.
+#[derive(Debug, Default, PartialEq, Eq, Serialize)]
+pub struct DeliveryCounts {
+    pub pending: i64,
+    pub digest: i64,
+    pub sent: i64,
+    pub skipped: i64,
+    pub dead_letter: i64,
+}
+
+/// Stores a new draft, with its first revision, and returns its id.
+#[tracing::instrument(name = "Store a newsletter issue", skip(tx, issue))]
+pub async fn create_issue(
+    tx: &mut Transaction<'_, Postgres>,
+    author_user_id: Uuid,
+    issue: &NewIssue<'_>,
+) -> anyhow::Result<Uuid> {
+    let issue_id = Uuid::new_v4();
+    sqlx::query!(
+        r#"
+        INSERT INTO newsletter_issues
+            (id, title, html_content, text_content, markdown_content, author_user_id, list_id,
+            status, created_at)
+        VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+        "#,
+        issue_id,
+        issue.title,
+        issue.html_content,
+        issue.text_content,
+        issue.markdown_content,
+        author_user_id,
+        issue.list_id,
+        IssueStatus::Draft.as_str(),
+        Utc::now(),
+    )
+    .execute(&mut *tx)
+    .await
+    .context("Failed to store a newsletter issue")?;
+    insert_revision(tx, issue_id, author_user_id, issue).await?;
+    Ok(issue_id)
+}
+
+/// The status of an issue, locked until the end of the transaction so that it can be
+/// checked before changing the issue. `None` if the issue does not exist.
+pub async fn lock_issue(
+    tx: &mut Transaction<'_, Postgres>,
+    issue_id: Uuid,
+) -> anyhow::Result<Option<IssueStatus>> {
+    let row = sqlx::query!(
+        r#"SELECT status FROM newsletter_issues WHERE id = $1 FOR UPDATE"#,
+        issue_id
+    )
+    .fetch_optional(&mut *tx)
+    .await
+    .context("Failed to lock a newsletter issue")?;
+    row.map(|r| IssueStatus::try_from(r.status).map_err(|e| anyhow::anyhow!(e)))
+        .transpose()
+}
+
+/// Replaces the content of a draft, keeping the previous one as a revision, and
+/// returns the number of the new revision.
+///
+/// The caller is expected to have checked that the issue is a draft with [`lock_issue`].
+#[tracing::instrument(name = "Update a newsletter draft", skip(tx, issue))]
+pub async fn update_draft(
+    tx: &mut Transaction<'_, Postgres>,
+    issue_id: Uuid,
+    author_user_id: Uuid,
+    issue: &NewIssue<'_>,
+) -> anyhow::Result<i32> {
+    sqlx::query!(
+        r#"
+        UPDATE newsletter_issues
+        SET title = $1, html_content = $2, text_content = $3, markdown_content = $4, list_id = $5
+        WHERE id = $6
+        "#,
+        issue.title,
+        issue.html_content,
+        issue.text_content,
+        issue.markdown_content,
+        issue.list_id,
+        issue_id,
+    )
+    .execute(&mut *tx)
+    .await
+    .context("Failed to update a newsletter draft")?;
+    insert_revision(tx, issue_id, author_user_id, issue).await
+}
+
+/// Makes the content of an earlier revision the current content of a draft, as a new
+/// revision, and returns its number. `None` if there is no such revision.
+///
+/// The caller is expected to have checked that the issue is a draft with [`lock_issue`].
+#[tracing::instrument(name = "Restore a revision of a newsletter draft", skip(tx))]
+pub async fn restore_revision(
+    tx: &mut Transaction<'_, Postgres>,
+    issue_id: Uuid,
+    author_user_id: Uuid,
+    revision: i32,
+) -> anyhow::Result<Option<i32>> {
+    let restored = sqlx::query!(
+        r#"
+        UPDATE newsletter_issues i
+        SET title = r.title, html_content = r.html_content, text_content = r.text_content,
+            markdown_content = r.markdown_content
+        FROM newsletter_issue_revisions r
+        WHERE i.id = $1 AND r.issue_id = i.id AND r.revision = $2
+        RETURNING r.title, r.html_content, r.text_content, r.markdown_content, i.list_id
+        "#,
+        issue_id,
+        revision,
+    )
+    .fetch_optional(&mut *tx)
+    .await
+    .context("Failed to restore a revision of a newsletter draft")?;
+    let Some(restored) = restored else {
+        return Ok(None);
+    };
+    let issue = NewIssue {
+        title: &restored.title,
+        html_content: &restored.html_content,
+        text_content: &restored.text_content,
+        markdown_content: restored.markdown_content.as_deref(),
+        list_id: restored.list_id,
+    };
+    insert_revision(tx, issue_id, author_user_id, &issue)
+        .await
+        .map(Some)
+}
+
+async fn insert_revision(
+    tx: &mut Transaction<'_, Postgres>,
+    issue_id: Uuid,
+    author_user_id: Uuid,
+    issue: &NewIssue<'_>,
+) -> anyhow::Result<i32> {
+    let row = sqlx::query!(
+        r#"
+        INSERT INTO newsletter_issue_revisions
+            (issue_id, revision, title, html_content, text_content, markdown_content,
+            author_user_id, created_at)
+        SELECT $1, COALESCE(MAX(revision), 0) + 1, $2, $3, $4, $5, $6, $7
+        FROM newsletter_issue_revisions WHERE issue_id = $1
+        RETURNING revision
+        "#,
+        issue_id,
+        issue.title,
+        issue.html_content,
+        issue.text_content,
+        issue.markdown_content,
+        author_user_id,
+        Utc::now(),
+    )
+    .fetch_one(&mut *tx)
+    .await
+    .context("Failed to store a revision of a newsletter issue")?;
+    Ok(row.revision)
+}
+
+/// The revisions of an issue, oldest first.
+#[tracing::instrument(name = "List the revisions of a newsletter issue", skip(pool))]
+pub async fn list_revisions(pool: &PgPool, issue_id: Uuid) -> anyhow::Result<Vec<RevisionSummary>> {
+    sqlx::query_as!(
+        RevisionSummary,
+        r#"
+        SELECT r.revision, r.title, u.username AS author, r.created_at
+        FROM newsletter_issue_revisions r JOIN users u ON u.user_id = r.author_user_id
+        WHERE r.issue_id = $1
+        ORDER BY r.revision
+        "#,
+        issue_id
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to list the revisions of a newsletter issue")
+}
+
+#[tracing::instrument(name = "Fetch a revision of a newsletter issue", skip(pool))]
+pub async fn get_revision(
+    pool: &PgPool,
+    issue_id: Uuid,
+    revision: i32,
+) -> anyhow::Result<Option<Revision>> {
+    let row = sqlx::query!(
+        r#"
+        SELECT r.revision, r.title, u.username AS author, r.created_at, r.html_content,
+            r.text_content, r.markdown_content
+        FROM newsletter_issue_revisions r JOIN users u ON u.user_id = r.author_user_id
+        WHERE r.issue_id = $1 AND r.revision = $2
+        "#,
+        issue_id,
+        revision,
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to fetch a revision of a newsletter issue")?;
+    Ok(row.map(|r| Revision {
+        summary: RevisionSummary {
+            revision: r.revision,
+            title: r.title,
+            author: r.author,
+            created_at: r.created_at,
+        },
+        html_content: r.html_content,
+        text_content: r.text_content,
+        markdown_content: r.markdown_content,
+    }))
+}
+
+/// Sends a draft: its deliveries are queued right away, or once `send_at` comes.
+/// Returns the new status of the issue.
+///
+/// `send_at` is a time of day on the subscribers' clocks: `2026-10-19T08:00:00Z` reaches a
+/// subscriber in Paris at 8am Paris time. The issue starts sending as soon as that time
+/// comes for one of its subscribers, and can no longer be rescheduled or cancelled then.
+///
+/// The caller is expected to have checked that the issue is a draft with [`lock_issue`].
+#[tracing::instrument(name = "Send a newsletter issue", skip(tx))]
+pub async fn send_issue(
+    tx: &mut Transaction<'_, Postgres>,
+    issue_id: Uuid,
+    send_at: Option<DateTime<Utc>>,
+) -> anyhow::Result<IssueStatus> {
+    if let Some(send_at) = send_at {
+        sqlx::query!(
+            r#"UPDATE newsletter_issues SET status = $1, send_at = $2 WHERE id = $3"#,
+            IssueStatus::Scheduled.as_str(),
+            send_at,
+            issue_id,
+        )
+        .execute(&mut *tx)
+        .await
+        .context("Failed to schedule a newsletter issue")?;
+        return Ok(IssueStatus::Scheduled);
+    }
+    let issue = sqlx::query!(
+        r#"SELECT list_id FROM newsletter_issues WHERE id = $1"#,
+        issue_id
+    )
+    .fetch_one(&mut *tx)
+    .await
+    .context("Failed to fetch a newsletter issue")?;
+    begin_sending(tx, issue_id, issue.list_id, None).await?;
+    Ok(IssueStatus::Sending)
+}
+
+/// Marks the issue as sending and queues its deliveries.
+async fn begin_sending(
+    tx: &mut Transaction<'_, Postgres>,
+    issue_id: Uuid,
+    list_id: Option<Uuid>,
+    send_at: Option<DateTime<Utc>>,
+) -> anyhow::Result<()> {
+    sqlx::query!(
+        r#"UPDATE newsletter_issues SET status = $1 WHERE id = $2"#,
+        IssueStatus::Sending.as_str(),
+        issue_id,
+    )
+    .execute(&mut *tx)
+    .await
+    .context("Failed to mark a newsletter issue as sending")?;
+    enqueue_deliveries(tx, issue_id, list_id, send_at).await?;
+    // no worker will ever pick up an issue without pending deliveries
+    mark_issue_sent_if_done(tx, issue_id).await?;
+    Ok(())
+}
+
+/// The HTML and text bodies of the email sent for an issue, with the link to the preference center.
+pub fn email_bodies(
+    html_content: &str,
+    text_content: &str,
+    preferences_link: &str,
+) -> (String, String) {
+    (
+        format!("{html_content}<p><a href=\"{preferences_link}\">Manage your preferences</a></p>"),
+        format!("{text_content}\n\nManage your preferences: {preferences_link}"),
+    )
+}
+
+/// Queues a delivery of the issue for every confirmed subscriber of `list_id`, or of any
+/// list if not given, and returns how many were queued.
+///
+/// The deliveries of a scheduled issue wait for `send_at` in each subscriber's time zone.
+///
+/// Subscribers who opted out of every list in the preference center receive nothing, and
+/// the deliveries of those asking for a weekly digest wait for it.
+#[tracing::instrument(name = "Queue the deliveries of a newsletter issue", skip(tx))]
+pub async fn enqueue_deliveries(
+    tx: &mut Transaction<'_, Postgres>,
+    issue_id: Uuid,
+    list_id: Option<Uuid>,
+    send_at: Option<DateTime<Utc>>,
+) -> anyhow::Result<u64> {
+    let queued = sqlx::query!(
+        r#"
+        INSERT INTO newsletter_deliveries
+            (issue_id, subscriber_id, status, created_at, next_attempt_at)
+        SELECT $1, id, CASE WHEN delivery_frequency = $6 THEN $7 ELSE $2 END, $3,
+            COALESCE(($8::timestamptz AT TIME ZONE 'UTC') AT TIME ZONE time_zone, $3)
+        FROM subscriptions
+        WHERE status = $4
+            AND EXISTS (
+                SELECT 1 FROM subscription_lists
+                WHERE subscriber_id = subscriptions.id AND ($5::uuid IS NULL OR list_id = $5)
+            )
+        "#,
+        issue_id,
+        DeliveryStatus::Pending.as_str(),
+        Utc::now(),
+        SubscriptionStatus::Confirmed.as_str(),
+        list_id,
+        DeliveryFrequency::WeeklyDigest.as_str(),
+        DeliveryStatus::Digest.as_str(),
+        send_at,
+    )
+    .execute(&mut *tx)
+    .await
+    .context("Failed to queue the deliveries of a newsletter issue")?
+    .rows_affected();
+    Ok(queued)
+}
+
+/// Starts sending the scheduled issues whose time has come, and returns their ids.
+///
+/// An issue is due once `send_at` has come in the time zone of one of its confirmed
+/// subscribers, or in UTC. The recipients are picked then rather than when the issue was
+/// scheduled, so subscribers who joined or left in between are taken into account, and
+/// those further west get their delivery later on. Issues locked by a concurrent
+/// scheduler, or by an admin rescheduling them, are left for the next run.
+#[tracing::instrument(name = "Start sending scheduled newsletter issues", skip(pool), err)]
+pub async fn start_due_issues(pool: &PgPool) -> anyhow::Result<Vec<Uuid>> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    let due = sqlx::query!(
+        r#"
+        SELECT i.id, i.list_id, i.send_at FROM newsletter_issues i
+        WHERE i.status = $1
+            AND (
+                i.send_at <= $2
+                OR EXISTS (
+                    SELECT 1 FROM subscriptions s
+                    JOIN subscription_lists l ON l.subscriber_id = s.id
+                    WHERE s.status = $3
+                        AND (i.list_id IS NULL OR l.list_id = i.list_id)
+                        AND (i.send_at AT TIME ZONE 'UTC') AT TIME ZONE s.time_zone <= $2
+                )
+            )
+        ORDER BY i.send_at
+        FOR UPDATE OF i SKIP LOCKED
+        "#,
+        IssueStatus::Scheduled.as_str(),
+        Utc::now(),
+        SubscriptionStatus::Confirmed.as_str(),
+    )
+    .fetch_all(&mut transaction)
+    .await
+    .context("Failed to fetch the scheduled issues due")?;
+
+    for issue in &due {
+        begin_sending(&mut transaction, issue.id, issue.list_id, issue.send_at).await?;
+    }
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to start scheduled issues")?;
+    Ok(due.into_iter().map(|i| i.id).collect())
+}
+
+/// Moves a scheduled issue to `send_at`. Returns `false` if the issue is not scheduled.
+#[tracing::instrument(name = "Reschedule a newsletter issue", skip(tx))]
+pub async fn reschedule_issue(
+    tx: &mut Transaction<'_, Postgres>,
+    issue_id: Uuid,
+    send_at: DateTime<Utc>,
+) -> anyhow::Result<bool> {
+    let updated = sqlx::query!(
+        r#"UPDATE newsletter_issues SET send_at = $1 WHERE id = $2 AND status = $3"#,
+        send_at,
+        issue_id,
+        IssueStatus::Scheduled.as_str(),
+    )
+    .execute(&mut *tx)
+    .await
+    .context("Failed to reschedule a newsletter issue")?
+    .rows_affected();
+    Ok(updated > 0)
+}
+
+/// Cancels a scheduled issue. Returns `false` if the issue is not scheduled.
+#[tracing::instrument(name = "Cancel a newsletter issue", skip(tx))]
+pub async fn cancel_issue(
+    tx: &mut Transaction<'_, Postgres>,
+    issue_id: Uuid,
+) -> anyhow::Result<bool> {
+    let updated = sqlx::query!(
+        r#"UPDATE newsletter_issues SET status = $1 WHERE id = $2 AND status = $3"#,
+        IssueStatus::Cancelled.as_str(),
+        issue_id,
+        IssueStatus::Scheduled.as_str(),
+    )
+    .execute(&mut *tx)
+    .await
+    .context("Failed to cancel a newsletter issue")?
+    .rows_affected();
+    Ok(updated > 0)
+}
+
+/// A delivery of an issue, with the error of its last attempt.
+#[derive(Serialize)]
+pub struct DeliveryDetails {
+    pub subscriber_id: Uuid,
+    pub email: String,
+    pub status: String,
+    /// The attempts made since the delivery was queued, or last replayed
+    pub attempts: i16,
+    pub next_attempt_at: DateTime<Utc>,
+    pub processed_at: Option<DateTime<Utc>>,
+    pub last_error: Option<String>,
+}
+
+/// The deliveries of an issue, all of them or only those in `status`.
+#[tracing::instrument(name = "List the deliveries of a newsletter issue", skip(pool))]
+pub async fn list_deliveries(
+    pool: &PgPool,
+    issue_id: Uuid,
+    status: Option<DeliveryStatus>,
+) -> anyhow::Result<Vec<DeliveryDetails>> {
+    sqlx::query_as!(
+        DeliveryDetails,
+        r#"
+        SELECT d.subscriber_id, s.email, d.status, d.attempts, d.next_attempt_at, d.processed_at,
+            (
+                SELECT error FROM delivery_attempts a
+                WHERE a.issue_id = d.issue_id AND a.subscriber_id = d.subscriber_id
+                ORDER BY attempt DESC LIMIT 1
+            ) AS last_error
+        FROM newsletter_deliveries d JOIN subscriptions s ON s.id = d.subscriber_id
+        WHERE d.issue_id = $1 AND ($2::text IS NULL OR d.status = $2)
+        ORDER BY s.email
+        "#,
+        issue_id,
+        status.map(|s| s.as_str()),
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to list the deliveries of a newsletter issue")
+}
+
+/// Queues the dead-lettered deliveries of an issue again, with a fresh set of attempts,
+/// and returns how many were queued.
+///
+/// An issue already marked as sent goes back to sending until they are processed.
+#[tracing::instrument(name = "Replay dead-lettered deliveries", skip(tx))]
+pub async fn replay_dead_letters(
+    tx: &mut Transaction<'_, Postgres>,
+    issue_id: Uuid,
+) -> anyhow::Result<u64> {
+    let replayed = sqlx::query!(
+        r#"
+        UPDATE newsletter_deliveries
+        SET status = $1, attempts = 0, next_attempt_at = $2, processed_at = NULL
+        WHERE issue_id = $3 AND status = $4
+        "#,
+        DeliveryStatus::Pending.as_str(),
+        Utc::now(),
+        issue_id,
+        DeliveryStatus::DeadLetter.as_str(),
+    )
+    .execute(&mut *tx)
+    .await
+    .context("Failed to replay dead-lettered deliveries")?
+    .rows_affected();
+
+    if replayed > 0 {
+        sqlx::query!(
+            r#"UPDATE newsletter_issues SET status = $1, sent_at = NULL WHERE id = $2"#,
+            IssueStatus::Sending.as_str(),
+            issue_id,
+        )
+        .execute(&mut *tx)
+        .await
+        .context("Failed to mark a newsletter issue as sending")?;
+    }
+    Ok(replayed)
+}
+
+/// Marks the issue as sent once none of its deliveries are pending any more.
+///
+/// Deliveries waiting for a weekly digest do not hold it back.
+///
+/// The issue row is locked first, so that of two workers finishing the last deliveries
+/// at the same time the second one sees what the first one did.
+#[tracing::instrument(name = "Mark a newsletter issue as sent", skip(tx))]
+pub async fn mark_issue_sent_if_done(
+    tx: &mut Transaction<'_, Postgres>,
+    issue_id: Uuid,
+) -> anyhow::Result<()> {
+    sqlx::query!(
+        r#"SELECT id FROM newsletter_issues WHERE id = $1 FOR UPDATE"#,
+        issue_id
+    )
+    .fetch_optional(&mut *tx)
+    .await
+    .context("Failed to lock a newsletter issue")?;
+    sqlx::query!(
+        r#"
+        UPDATE newsletter_issues SET status = $1, sent_at = $2
+        WHERE id = $3 AND status = $4 AND NOT EXISTS (
+            SELECT 1 FROM newsletter_deliveries WHERE issue_id = $3 AND status = $5
+        )
+        "#,
+        IssueStatus::Sent.as_str(),
+        Utc::now(),
+        issue_id,
+        IssueStatus::Sending.as_str(),
+        DeliveryStatus::Pending.as_str(),
+    )
+    .execute(&mut *tx)
+    .await
+    .context("Failed to mark a newsletter issue as sent")?;
+    Ok(())
+}
+
+/// The most recent issues, newest first.
+#[tracing::instrument(name = "List newsletter issues", skip(pool))]
+pub async fn list_issues(pool: &PgPool, limit: i64) -> anyhow::Result<Vec<IssueSummary>> {
+    sqlx::query_as!(
+        IssueSummary,
+        r#"
+        SELECT i.id, i.title, i.status, u.username AS author, l.slug AS "list?",
+            i.created_at, i.send_at, i.sent_at
+        FROM newsletter_issues i
+        JOIN users u ON u.user_id = i.author_user_id
+        LEFT JOIN lists l ON l.id = i.list_id
+        ORDER BY i.created_at DESC, i.id DESC
+        LIMIT $1
+        "#,
+        limit,
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to list newsletter issues")
+}
+
+#[tracing::instrument(name = "Fetch a newsletter issue", skip(pool))]
+pub async fn get_issue(pool: &PgPool, issue_id: Uuid) -> anyhow::Result<Option<Issue>> {
+    let row = sqlx::query!(
+        r#"
+        SELECT i.id, i.title, i.status, u.username AS author, l.slug AS "list?",
+            i.created_at, i.send_at, i.sent_at, i.html_content, i.text_content, i.markdown_content
+        FROM newsletter_issues i
+        JOIN users u ON u.user_id = i.author_user_id
+        LEFT JOIN lists l ON l.id = i.list_id
+        WHERE i.id = $1
+        "#,
+        issue_id,
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to fetch a newsletter issue")?;
+    let Some(r) = row else {
+        return Ok(None);
+    };
+    let deliveries = delivery_counts(pool, issue_id).await?;
+    let revision = sqlx::query!(
+        r#"SELECT MAX(revision) AS "revision!" FROM newsletter_issue_revisions WHERE issue_id = $1"#,
+        issue_id
+    )
+    .fetch_one(pool)
+    .await
+    .context("Failed to fetch the current revision of a newsletter issue")?
+    .revision;
+
+    Ok(Some(Issue {
+        summary: IssueSummary {
+            id: r.id,
+            title: r.title,
+            status: r.status,
+            author: r.author,
+            list: r.list,
+            created_at: r.created_at,
+            send_at: r.send_at,
+            sent_at: r.sent_at,
+        },
+        html_content: r.html_content,
+        text_content: r.text_content,
+        markdown_content: r.markdown_content,
+        revision,
+        deliveries,
+    }))
+}
+
+async fn delivery_counts(pool: &PgPool, issue_id: Uuid) -> anyhow::Result<DeliveryCounts> {
+    let rows = sqlx::query!(
+        r#"
+        SELECT status, COUNT(*) AS "count!" FROM newsletter_deliveries
+        WHERE issue_id = $1 GROUP BY status
+        "#,
+        issue_id
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to count the deliveries of a newsletter issue")?;
+
+    let mut counts = DeliveryCounts::default();
+    for row in rows {
+        let count = match DeliveryStatus::try_from(row.status).map_err(|e| anyhow::anyhow!(e))? {
+            DeliveryStatus::Pending => &mut counts.pending,
+            DeliveryStatus::Digest => &mut counts.digest,
+            DeliveryStatus::Sent => &mut counts.sent,
+            DeliveryStatus::Skipped => &mut counts.skipped,
+            DeliveryStatus::DeadLetter => &mut counts.dead_letter,
+        };
+        *count = row.count;
+    }
+    Ok(counts)
+}
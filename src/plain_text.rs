@@ -0,0 +1,462 @@
+//! The plain text version of newsletter issues.
+//!
+//! Text is laid out the same way whether it comes from Markdown or HTML: headings
+//! underlined, list items bulleted or numbered, and links listed as numbered footnotes.
+use std::collections::HashSet;
+
+use scraper::{node::Node, ElementRef, Html};
+use serde::Serialize;
+use similar::TextDiff;
+
+/// Below this similarity, a text body is flagged as not matching its HTML body.
+const MIN_SIMILARITY: f32 = 0.5;
+
+/// A text body shorter than this fraction of its HTML body is flagged as a placeholder.
+const MIN_LENGTH_RATIO: f32 = 0.3;
+
+/// Lays out plain text, driven by the structure of a document.
+#[derive(Default)]
+pub(crate) struct TextWriter {
+    out: String,
+    /// Written at the start of every line: quote markers and list indentation
+    prefixes: Vec<String>,
+    at_line_start: bool,
+    /// The text of the heading being written, underlined once it ends
+    heading: Option<String>,
+    /// The destination of every open link, and where its text starts
+    links: Vec<(Option<String>, usize)>,
+    /// The next number of every open list, `None` for bullet lists
+    lists: Vec<Option<u64>>,
+    footnotes: Vec<String>,
+}
+
+impl TextWriter {
+    /// Separates the block about to start from the previous one by a blank line.
+    pub fn start_block(&mut self) {
+        if self.out.is_empty() {
+            self.at_line_start = true;
+            return;
+        }
+        if !self.at_line_start {
+            self.newline();
+        }
+        if !self.out.ends_with("\n\n") && self.lists.is_empty() {
+            self.newline();
+        }
+    }
+
+    pub fn end_block(&mut self) {
+        if !self.at_line_start {
+            self.newline();
+        }
+    }
+
+    pub fn start_heading(&mut self) {
+        self.start_block();
+        self.heading = Some(String::new());
+    }
+
+    /// Writes the heading underlined with `=` for the top level, `-` for the others.
+    pub fn end_heading(&mut self, top_level: bool) {
+        let heading = self.heading.take().unwrap_or_default();
+        let heading = heading.split_whitespace().collect::<Vec<_>>().join(" ");
+        if heading.is_empty() {
+            return;
+        }
+        let underline = if top_level { "=" } else { "-" };
+        self.push(&heading);
+        self.newline();
+        self.push(&underline.repeat(heading.chars().count()));
+        self.end_block();
+    }
+
+    /// Starts a block whose lines all start with `prefix`, such as a quote.
+    pub fn start_prefixed(&mut self, prefix: &str) {
+        self.start_block();
+        self.prefixes.push(prefix.into());
+    }
+
+    pub fn end_prefixed(&mut self) {
+        self.prefixes.pop();
+        self.end_block();
+    }
+
+    /// Starts a list numbered from `first`, or a bullet list.
+    pub fn start_list(&mut self, first: Option<u64>) {
+        if self.lists.is_empty() {
+            self.start_block();
+        }
+        self.lists.push(first);
+    }
+
+    pub fn end_list(&mut self) {
+        self.lists.pop();
+        if self.lists.is_empty() {
+            self.end_block();
+        }
+    }
+
+    pub fn start_item(&mut self) {
+        if !self.at_line_start {
+            self.newline();
+        }
+        let marker = match self.lists.last_mut() {
+            Some(Some(number)) => {
+                *number += 1;
+                format!("{}. ", *number - 1)
+            }
+            _ => "- ".into(),
+        };
+        self.push(&marker);
+        self.prefixes.push(" ".repeat(marker.len()));
+    }
+
+    pub fn end_item(&mut self) {
+        self.prefixes.pop();
+    }
+
+    /// Starts a link to `url`, footnoted once it ends. Links without a useful destination,
+    /// such as anchors within the page, are written as their text alone.
+    pub fn start_link(&mut self, url: Option<&str>) {
+        let url = url.filter(|u| !u.is_empty() && !u.starts_with('#'));
+        let start = self.target().len();
+        self.links.push((url.map(str::to_string), start));
+    }
+
+    pub fn end_link(&mut self) {
+        let Some((Some(url), start)) = self.links.pop() else {
+            return;
+        };
+        let text = self.target()[start..].trim().to_string();
+        // autolinks already show their destination
+        if text == url || url.strip_prefix("mailto:") == Some(text.as_str()) {
+            return;
+        }
+        // an image link, for instance, has no text of its own
+        if text.is_empty() {
+            self.push(&url);
+            return;
+        }
+        let number = match self.footnotes.iter().position(|u| *u == url) {
+            Some(i) => i + 1,
+            None => {
+                self.footnotes.push(url);
+                self.footnotes.len()
+            }
+        };
+        self.push(&format!("[{number}]"));
+    }
+
+    pub fn rule(&mut self) {
+        self.start_block();
+        self.push("* * *");
+        self.end_block();
+    }
+
+    /// Where text goes: the heading being written, or the output.
+    fn target(&mut self) -> &mut String {
+        self.heading.as_mut().unwrap_or(&mut self.out)
+    }
+
+    /// Writes text as is, line breaks included.
+    pub fn push(&mut self, text: &str) {
+        if self.heading.is_some() {
+            self.target().push_str(text);
+            return;
+        }
+        for (i, line) in text.split('\n').enumerate() {
+            if i > 0 {
+                self.newline();
+            }
+            if line.is_empty() {
+                continue;
+            }
+            if self.at_line_start {
+                let prefix: String = self.prefixes.concat();
+                self.out.push_str(&prefix);
+                self.at_line_start = false;
+            }
+            self.out.push_str(line);
+        }
+    }
+
+    /// Writes text the way HTML displays it, with runs of whitespace collapsed.
+    pub fn push_collapsed(&mut self, text: &str) {
+        let words: Vec<&str> = text.split_whitespace().collect();
+        let ends_with_space = |s: &str| s.is_empty() || s.ends_with([' ', '\n']);
+        let at_start = self.at_line_start || ends_with_space(self.target());
+        let mut collapsed = String::new();
+        if text.starts_with(char::is_whitespace) && !at_start {
+            collapsed.push(' ');
+        }
+        collapsed.push_str(&words.join(" "));
+        if text.ends_with(char::is_whitespace) && !words.is_empty() {
+            collapsed.push(' ');
+        }
+        self.push(&collapsed);
+    }
+
+    /// Trailing spaces are left for [`TextWriter::finish`] to trim: an open link may
+    /// have recorded where its text starts among them.
+    pub fn newline(&mut self) {
+        if self.heading.is_some() {
+            self.target().push(' ');
+            return;
+        }
+        self.out.push('\n');
+        self.at_line_start = true;
+    }
+
+    /// The text, followed by the footnotes.
+    pub fn finish(mut self) -> String {
+        let mut out = self
+            .out
+            .split('\n')
+            .map(|line| line.trim_end_matches(' '))
+            .collect::<Vec<_>>()
+            .join("\n")
+            .trim_end()
+            .to_string();
+        if !self.footnotes.is_empty() {
+            out.push_str("\n\n");
+            for (i, url) in self.footnotes.drain(..).enumerate() {
+                out.push_str(&format!("[{}] {url}\n", i + 1));
+            }
+        }
+        out.trim_end().to_string()
+    }
+}
+
+/// A readable text version of an HTML body, for authors who only wrote the HTML one.
+pub fn from_html(html: &str) -> String {
+    let document = Html::parse_document(html);
+    let mut writer = TextWriter::default();
+    write_children(&mut writer, document.root_element(), false);
+    writer.finish()
+}
+
+fn write_children(writer: &mut TextWriter, element: ElementRef, preformatted: bool) {
+    for child in element.children() {
+        match child.value() {
+            Node::Text(text) if preformatted => writer.push(text),
+            Node::Text(text) => writer.push_collapsed(text),
+            Node::Element(_) => {
+                if let Some(child) = ElementRef::wrap(child) {
+                    write_element(writer, child, preformatted);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+fn write_element(writer: &mut TextWriter, element: ElementRef, preformatted: bool) {
+    let name = element.value().name();
+    match name {
+        "head" | "script" | "style" | "title" | "template" | "noscript" => {}
+        "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => {
+            writer.start_heading();
+            write_children(writer, element, preformatted);
+            writer.end_heading(name == "h1");
+        }
+        "ul" | "ol" => {
+            let first = (name == "ol").then(|| {
+                element
+                    .value()
+                    .attr("start")
+                    .and_then(|s| s.trim().parse().ok())
+                    .unwrap_or(1)
+            });
+            writer.start_list(first);
+            write_children(writer, element, preformatted);
+            writer.end_list();
+        }
+        "li" => {
+            writer.start_item();
+            write_children(writer, element, preformatted);
+            writer.end_item();
+        }
+        "a" => {
+            writer.start_link(element.value().attr("href"));
+            write_children(writer, element, preformatted);
+            writer.end_link();
+        }
+        "blockquote" => {
+            writer.start_prefixed("> ");
+            write_children(writer, element, preformatted);
+            writer.end_prefixed();
+        }
+        "pre" => {
+            writer.start_prefixed("    ");
+            write_children(writer, element, true);
+            writer.end_prefixed();
+        }
+        "br" => writer.newline(),
+        "hr" => writer.rule(),
+        "img" => {
+            if let Some(alt) = element.value().attr("alt").filter(|a| !a.trim().is_empty()) {
+                writer.push_collapsed(&format!(" {} ", alt.trim()));
+            }
+        }
+        "td" | "th" => {
+            writer.push_collapsed(" ");
+            write_children(writer, element, preformatted);
+            writer.push_collapsed(" ");
+        }
+        "p" | "div" | "section" | "article" | "header" | "footer" | "main" | "nav" | "aside"
+        | "table" | "tr" | "figure" | "figcaption" | "address" | "center" | "dl" | "dt" | "dd"
+        | "form" | "fieldset" | "body" => {
+            writer.start_block();
+            write_children(writer, element, preformatted);
+            writer.end_block();
+        }
+        _ => write_children(writer, element, preformatted),
+    }
+}
+
+/// How well a text body matches its HTML body.
+#[derive(Debug, Serialize)]
+pub struct TextCheck {
+    /// From 0, nothing in common, to 1, the same words in the same order
+    pub similarity: f32,
+    /// Whether the text body should be looked at before sending
+    pub mismatch: bool,
+    pub warnings: Vec<String>,
+    /// The text body derived from the HTML one, for comparison
+    pub derived_text: String,
+}
+
+/// Compares a text body with the text derived from its HTML body, flagging the text
+/// bodies that are placeholders or that drifted from the HTML one.
+pub fn check(html: &str, text: &str) -> TextCheck {
+    let derived_text = from_html(html);
+    let derived_words = words(&derived_text);
+    let words = words(text);
+    let similarity = TextDiff::from_words(derived_words.as_str(), words.as_str()).ratio();
+
+    let mut warnings = Vec::new();
+    let derived_count = derived_words.split(' ').filter(|w| !w.is_empty()).count();
+    let count = words.split(' ').filter(|w| !w.is_empty()).count();
+    if (count as f32) < derived_count as f32 * MIN_LENGTH_RATIO {
+        warnings.push(format!(
+            "The text body has {count} words, against {derived_count} in the HTML body"
+        ));
+    }
+    if similarity < MIN_SIMILARITY {
+        warnings.push(format!(
+            "The text body is only {:.0}% similar to the HTML body",
+            similarity * 100.0
+        ));
+    }
+    let document = Html::parse_document(html);
+    let selector = scraper::Selector::parse("a[href]").expect("a valid selector");
+    // every link once, in the order of the document
+    let mut seen = HashSet::new();
+    let missing_links: Vec<&str> = document
+        .select(&selector)
+        .filter_map(|a| a.value().attr("href"))
+        .filter(|href| href.starts_with("http") && !text.contains(href) && seen.insert(*href))
+        .collect();
+    if !missing_links.is_empty() {
+        warnings.push(format!(
+            "The text body is missing links of the HTML body: {}",
+            missing_links.join(", ")
+        ));
+    }
+
+    TextCheck {
+        similarity,
+        mismatch: !warnings.is_empty(),
+        warnings,
+        derived_text,
+    }
+}
+
+/// The words of a text, lowercased, leaving out link destinations and footnote markers.
+fn words(text: &str) -> String {
+    text.split_whitespace()
+        .filter(|w| !w.contains("://") && !w.starts_with("mailto:"))
+        .map(|w| {
+            w.chars()
+                .filter(|c| c.is_alphanumeric())
+                .collect::<String>()
+                .to_lowercase()
+        })
+        .filter(|w| !w.is_empty() && !w.chars().all(|c| c.is_ascii_digit()))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{check, from_html};
+
+    #[test]
+    fn html_is_turned_into_readable_text() {
+        let html = r#"<!DOCTYPE html><html><head><title>Ignored</title>
+            <style>p { color: red }</style></head>
+            <body>
+              <h1>Weekly   news</h1>
+              <p>Hi {{ name }},
+                 read <a href="https://example.com/post">the <b>post</b></a>
+                 or <a href="https://example.com">https://example.com</a>.</p>
+              <p>Line<br>break</p>
+              <ul><li>one</li><li>two</li></ul>
+              <ol start="3"><li>three</li></ol>
+              <h2>Sponsor</h2>
+              <table><tr><td><img src="logo.png" alt="Acme"></td><td>Rockets</td></tr></table>
+              <script>alert(1)</script>
+            </body></html>"#;
+        assert_eq!(
+            from_html(html),
+            "Weekly news\n===========\n\n\
+            Hi {{ name }}, read the post[1] or https://example.com.\n\n\
+            Line\nbreak\n\n\
+            - one\n- two\n\n\
+            3. three\n\n\
+            Sponsor\n-------\n\n\
+            Acme Rockets\n\n\
+            [1] https://example.com/post"
+        );
+    }
+
+    #[test]
+    fn preformatted_text_and_quotes_are_kept() {
+        assert_eq!(
+            from_html("<blockquote><p>Quoted</p></blockquote><pre>a  b\nc</pre>"),
+            "> Quoted\n\n    a  b\n    c"
+        );
+    }
+
+    #[test]
+    fn matching_text_bodies_pass_the_check() {
+        let html = r#"<h1>News</h1><p>We shipped <a href="https://example.com/x">the feature</a>
+            everyone asked for, and fixed many bugs.</p>"#;
+        let text = "News\n\nWe shipped the feature (https://example.com/x) everyone asked for, \
+            and fixed many bugs.";
+        let result = check(html, text);
+        assert!(!result.mismatch, "{:?}", result.warnings);
+        assert!(result.similarity > 0.9);
+    }
+
+    #[test]
+    fn placeholder_text_bodies_are_flagged() {
+        let html = r#"<p>We shipped <a href="https://example.com/x">the feature</a> everyone
+            asked for, fixed many bugs and wrote a long post about both.</p>"#;
+        let result = check(html, "See the HTML version.");
+        assert!(result.mismatch);
+        assert_eq!(result.warnings.len(), 3, "{:?}", result.warnings);
+    }
+
+    #[test]
+    fn missing_links_are_listed_once() {
+        let html = r#"<p><a href="https://example.com/a">A</a> <a href="https://example.com/b">B</a>
+            <a href="https://example.com/a">A again</a></p>"#;
+        let result = check(html, "A B A again");
+        assert!(result.warnings.contains(
+            &"The text body is missing links of the HTML body: \
+            https://example.com/a, https://example.com/b"
+                .to_string()
+        ));
+    }
+}
@@ -0,0 +1,170 @@
+//! The preference center: what subscribers receive and how often, reachable without logging in.
+use anyhow::Context;
+use hmac::{Hmac, Mac};
+use secrecy::{ExposeSecret, Secret};
+use serde::Serialize;
+use sha2::Sha256;
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+/// A signed subscriber id, embedded in the preference center link of every email.
+///
+/// The link has to keep working for as long as the emails containing it are around,
+/// so the token does not expire and is not stored: the signature is all it takes.
+pub struct PreferencesToken;
+
+impl PreferencesToken {
+    pub fn issue(secret: &Secret<String>, subscriber_id: Uuid) -> String {
+        let signature = mac(secret, subscriber_id).finalize().into_bytes();
+        format!("{subscriber_id}.{}", hex::encode(signature))
+    }
+
+    /// The subscriber the token was issued for, if the signature is valid.
+    pub fn verify(token: &str, secret: &Secret<String>) -> Option<Uuid> {
+        let (subscriber_id, signature) = token.split_once('.')?;
+        let subscriber_id: Uuid = subscriber_id.parse().ok()?;
+        let signature = hex::decode(signature).ok()?;
+        mac(secret, subscriber_id)
+            .verify_slice(&signature)
+            .ok()
+            .map(|_| subscriber_id)
+    }
+}
+
+fn mac(secret: &Secret<String>, subscriber_id: Uuid) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.expose_secret().as_bytes())
+        .expect("HMAC can take a key of any size");
+    mac.update(b"preferences:");
+    mac.update(subscriber_id.as_bytes());
+    mac
+}
+
+/// The link to the preference center of a subscriber.
+pub fn preferences_link(base_url: &str, secret: &Secret<String>, subscriber_id: Uuid) -> String {
+    format!(
+        "{base_url}/subscriptions/preferences?token={}",
+        PreferencesToken::issue(secret, subscriber_id)
+    )
+}
+
+/// A list, or topic, subscribers can pick.
+#[derive(Debug, Clone, Serialize)]
+pub struct List {
+    pub id: Uuid,
+    pub slug: String,
+    pub name: String,
+}
+
+#[tracing::instrument(name = "Fetch the lists", skip(pool))]
+pub async fn all_lists(pool: &PgPool) -> anyhow::Result<Vec<List>> {
+    sqlx::query_as!(List, r#"SELECT id, slug, name FROM lists ORDER BY name"#)
+        .fetch_all(pool)
+        .await
+        .context("Failed to fetch the lists")
+}
+
+#[tracing::instrument(name = "Get a list by slug", skip(pool))]
+pub async fn list_id_by_slug(pool: &PgPool, slug: &str) -> anyhow::Result<Option<Uuid>> {
+    let row = sqlx::query!(r#"SELECT id FROM lists WHERE slug = $1"#, slug)
+        .fetch_optional(pool)
+        .await
+        .context("Failed to look up a list")?;
+    Ok(row.map(|r| r.id))
+}
+
+/// The ids of the lists a subscriber receives.
+#[tracing::instrument(name = "Fetch the lists of a subscriber", skip(pool))]
+pub async fn subscriber_list_ids(pool: &PgPool, subscriber_id: Uuid) -> anyhow::Result<Vec<Uuid>> {
+    let ids = sqlx::query!(
+        r#"SELECT list_id FROM subscription_lists WHERE subscriber_id = $1"#,
+        subscriber_id
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to fetch the lists of a subscriber")?
+    .into_iter()
+    .map(|r| r.list_id)
+    .collect();
+    Ok(ids)
+}
+
+/// Signs new subscribers up to every list; they can opt out of some in the preference center.
+#[tracing::instrument(name = "Add new subscribers to every list", skip(tx, subscriber_ids))]
+pub async fn join_all_lists(
+    tx: &mut Transaction<'_, Postgres>,
+    subscriber_ids: &[Uuid],
+) -> anyhow::Result<()> {
+    sqlx::query!(
+        r#"
+        INSERT INTO subscription_lists (subscriber_id, list_id)
+        SELECT subscriber_id, lists.id FROM UNNEST($1::uuid[]) AS t(subscriber_id) CROSS JOIN lists
+        ON CONFLICT DO NOTHING
+        "#,
+        subscriber_ids,
+    )
+    .execute(&mut *tx)
+    .await
+    .context("Failed to add new subscribers to the lists")?;
+    Ok(())
+}
+
+/// Replaces the lists a subscriber receives with `list_ids`.
+#[tracing::instrument(name = "Set the lists of a subscriber", skip(tx))]
+pub async fn set_lists(
+    tx: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    list_ids: &[Uuid],
+) -> anyhow::Result<()> {
+    sqlx::query!(
+        r#"DELETE FROM subscription_lists WHERE subscriber_id = $1"#,
+        subscriber_id
+    )
+    .execute(&mut *tx)
+    .await
+    .context("Failed to clear the lists of a subscriber")?;
+    sqlx::query!(
+        r#"
+        INSERT INTO subscription_lists (subscriber_id, list_id)
+        SELECT $1, id FROM lists WHERE id = ANY($2)
+        "#,
+        subscriber_id,
+        list_ids,
+    )
+    .execute(&mut *tx)
+    .await
+    .context("Failed to store the lists of a subscriber")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PreferencesToken;
+    use secrecy::Secret;
+    use uuid::Uuid;
+
+    fn secret() -> Secret<String> {
+        Secret::new("a-very-secret-key".to_string())
+    }
+
+    #[test]
+    fn a_token_resolves_to_the_subscriber_it_was_issued_for() {
+        let subscriber_id = Uuid::new_v4();
+        let token = PreferencesToken::issue(&secret(), subscriber_id);
+        assert_eq!(
+            PreferencesToken::verify(&token, &secret()),
+            Some(subscriber_id)
+        );
+    }
+
+    #[test]
+    fn tampered_tokens_are_rejected() {
+        let token = PreferencesToken::issue(&secret(), Uuid::new_v4());
+        let (_, signature) = token.split_once('.').unwrap();
+        let forged = format!("{}.{signature}", Uuid::new_v4());
+        assert_eq!(PreferencesToken::verify(&forged, &secret()), None);
+
+        let other_secret = Secret::new("another-key".to_string());
+        assert_eq!(PreferencesToken::verify(&token, &other_secret), None);
+        assert_eq!(PreferencesToken::verify("not-a-token", &secret()), None);
+    }
+}
@@ -0,0 +1,432 @@
+//! Token bucket rate limiting of selected routes, keyed by client IP and optionally by a form field.
+use std::collections::HashMap;
+use std::future::{ready, Ready};
+use std::net::IpAddr;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use actix_web::{
+    body::EitherBody,
+    dev::{forward_ready, Payload, Service, ServiceRequest, ServiceResponse, Transform},
+    error::PayloadError,
+    http::{
+        header::{self, HeaderValue},
+        Method,
+    },
+    web::{Bytes, BytesMut},
+    HttpMessage, HttpResponse,
+};
+use futures::{future::LocalBoxFuture, stream, Stream, StreamExt};
+
+use crate::configuration::{LimitSettings, RateLimitSettings};
+
+/// Form bodies larger than this are not searched for the field used as a rate limiting key.
+const MAX_KEYED_BODY_BYTES: usize = 64 * 1024;
+
+/// Buckets are dropped once they are full again, but only when there are more than this.
+const MAX_IDLE_BUCKETS: usize = 10_000;
+
+/// The IP address of the client that sent the request, as seen through the trusted proxies.
+///
+/// Added to the request extensions by the [`RateLimiter`] middleware.
+#[derive(Clone, Copy, Debug)]
+pub struct ClientIp(pub IpAddr);
+
+/// An IP network, e.g. `10.0.0.0/8`, or a single address.
+#[derive(Clone, Debug, PartialEq)]
+pub struct IpNetwork {
+    address: IpAddr,
+    prefix_len: u8,
+}
+
+impl IpNetwork {
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let invalid = || format!("{s} is not a valid IP address or network");
+        let (address, prefix_len) = match s.trim().split_once('/') {
+            Some((address, prefix_len)) => (
+                address.parse::<IpAddr>().map_err(|_| invalid())?,
+                Some(prefix_len.parse::<u8>().map_err(|_| invalid())?),
+            ),
+            None => (s.trim().parse::<IpAddr>().map_err(|_| invalid())?, None),
+        };
+        let max_len = if address.is_ipv4() { 32 } else { 128 };
+        let prefix_len = prefix_len.unwrap_or(max_len);
+        if prefix_len > max_len {
+            return Err(invalid());
+        }
+        Ok(Self {
+            address,
+            prefix_len,
+        })
+    }
+
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        let (network, ip, len) = match (self.address, ip) {
+            (IpAddr::V4(n), IpAddr::V4(ip)) => (
+                u32::from(n) as u128,
+                u32::from(ip) as u128,
+                self.prefix_len + 96,
+            ),
+            (IpAddr::V6(n), IpAddr::V6(ip)) => (u128::from(n), u128::from(ip), self.prefix_len),
+            _ => return false,
+        };
+        let mask = u128::MAX.checked_shl(128 - len as u32).unwrap_or(0);
+        network & mask == ip & mask
+    }
+}
+
+/// Resolves the client IP of a request.
+///
+/// `X-Forwarded-For` is only honoured when the request comes from a trusted proxy: the
+/// header is then read right to left, skipping the hops added by other trusted proxies.
+pub fn client_ip(
+    peer: Option<IpAddr>,
+    forwarded_for: Option<&str>,
+    trusted_proxies: &[IpNetwork],
+) -> Option<IpAddr> {
+    let is_trusted = |ip: IpAddr| trusted_proxies.iter().any(|n| n.contains(ip));
+    let peer = peer?;
+    if !is_trusted(peer) {
+        return Some(peer);
+    }
+
+    let hops: Vec<IpAddr> = forwarded_for
+        .unwrap_or_default()
+        .split(',')
+        .filter_map(|hop| hop.trim().parse().ok())
+        .collect();
+    let client = hops
+        .iter()
+        .rev()
+        .find(|ip| !is_trusted(**ip))
+        // every hop is one of our proxies: the first one is as close to the client as we get
+        .or_else(|| hops.first())
+        .copied();
+    Some(client.unwrap_or(peer))
+}
+
+/// A set of token buckets sharing the same limits.
+struct Buckets {
+    capacity: f64,
+    refill_per_second: f64,
+    buckets: Mutex<HashMap<String, Bucket>>,
+}
+
+struct Bucket {
+    tokens: f64,
+    updated_at: Instant,
+}
+
+impl Buckets {
+    /// Fails if the buckets would never hold a token: with no `burst`, or never refill.
+    fn new(limits: &LimitSettings) -> Result<Self, String> {
+        if limits.burst == 0 || limits.per_minute == 0 {
+            return Err("Rate limits need a `burst` and a `per_minute` of at least 1".into());
+        }
+        Ok(Self {
+            capacity: limits.burst as f64,
+            refill_per_second: limits.per_minute as f64 / 60.0,
+            buckets: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Takes a token from the bucket for `key`, or returns how long to wait for the next one.
+    fn take(&self, key: &str, now: Instant) -> Result<(), Duration> {
+        let mut buckets = self
+            .buckets
+            .lock()
+            .expect("the rate limiter lock is poisoned");
+        if buckets.len() > MAX_IDLE_BUCKETS {
+            buckets.retain(|_, b| self.refilled(b, now) < self.capacity);
+        }
+
+        let bucket = buckets.entry(key.to_string()).or_insert(Bucket {
+            tokens: self.capacity,
+            updated_at: now,
+        });
+        bucket.tokens = self.refilled(bucket, now);
+        bucket.updated_at = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            return Ok(());
+        }
+        Err(Duration::from_secs_f64(
+            (1.0 - bucket.tokens) / self.refill_per_second,
+        ))
+    }
+
+    fn refilled(&self, bucket: &Bucket, now: Instant) -> f64 {
+        let elapsed = now.duration_since(bucket.updated_at).as_secs_f64();
+        (bucket.tokens + elapsed * self.refill_per_second).min(self.capacity)
+    }
+}
+
+struct RouteLimiter {
+    method: Method,
+    /// The pattern of the route, e.g. `/subscriptions/{id}`, as registered in the app
+    path: String,
+    per_ip: Buckets,
+    per_field: Option<(String, Buckets)>,
+}
+
+/// Middleware limiting the rate of requests to the routes listed in [`RateLimitSettings`].
+///
+/// Limited requests get a `429 Too Many Requests` with a `Retry-After` header.
+#[derive(Clone)]
+pub struct RateLimiter {
+    trusted_proxies: Arc<Vec<IpNetwork>>,
+    routes: Arc<Vec<RouteLimiter>>,
+}
+
+impl RateLimiter {
+    pub fn new(settings: &RateLimitSettings) -> Result<Self, String> {
+        let trusted_proxies = settings
+            .trusted_proxies
+            .iter()
+            .map(|s| IpNetwork::parse(s))
+            .collect::<Result<_, _>>()?;
+        let routes = settings
+            .routes
+            .iter()
+            .map(|route| {
+                Ok(RouteLimiter {
+                    method: Method::from_bytes(route.method.to_uppercase().as_bytes())
+                        .map_err(|_| format!("{} is not a valid HTTP method", route.method))?,
+                    path: route.path.clone(),
+                    per_ip: Buckets::new(&route.per_ip)?,
+                    per_field: route
+                        .per_field
+                        .as_ref()
+                        .map(|f| Ok::<_, String>((f.field.clone(), Buckets::new(&f.limits)?)))
+                        .transpose()?,
+                })
+            })
+            .collect::<Result<_, String>>()?;
+        Ok(Self {
+            trusted_proxies: Arc::new(trusted_proxies),
+            routes: Arc::new(routes),
+        })
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for RateLimiter
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = actix_web::Error;
+    type Transform = RateLimiterMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RateLimiterMiddleware {
+            service: Rc::new(service),
+            limiter: self.clone(),
+        }))
+    }
+}
+
+pub struct RateLimiterMiddleware<S> {
+    service: Rc<S>,
+    limiter: RateLimiter,
+}
+
+impl<S, B> Service<ServiceRequest> for RateLimiterMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = actix_web::Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, mut req: ServiceRequest) -> Self::Future {
+        let service = Rc::clone(&self.service);
+        let limiter = self.limiter.clone();
+
+        Box::pin(async move {
+            let forwarded_for = req
+                .headers()
+                .get("X-Forwarded-For")
+                .and_then(|h| h.to_str().ok());
+            let ip = client_ip(
+                req.peer_addr().map(|a| a.ip()),
+                forwarded_for,
+                &limiter.trusted_proxies,
+            );
+            if let Some(ip) = ip {
+                req.extensions_mut().insert(ClientIp(ip));
+            }
+
+            // match on the route pattern, so that path parameters don't dodge the limit
+            let pattern = req.match_pattern();
+            let route = limiter
+                .routes
+                .iter()
+                .find(|r| r.method == req.method() && pattern.as_deref() == Some(r.path.as_str()));
+            let Some(route) = route else {
+                return service.call(req).await.map(|r| r.map_into_left_body());
+            };
+
+            let now = Instant::now();
+            let mut outcome = match ip {
+                Some(ip) => route.per_ip.take(&ip.to_string(), now),
+                None => Ok(()),
+            };
+            if let (Ok(()), Some((field, buckets))) = (outcome, &route.per_field) {
+                if let Some(value) = read_form_field(&mut req, field).await {
+                    outcome = buckets.take(&value.trim().to_lowercase(), now);
+                }
+            }
+
+            match outcome {
+                Ok(()) => service.call(req).await.map(|r| r.map_into_left_body()),
+                Err(retry_after) => {
+                    tracing::warn!(
+                        client_ip = ?ip,
+                        path = %req.path(),
+                        "Rate limit exceeded"
+                    );
+                    let response = too_many_requests(retry_after);
+                    Ok(req.into_response(response).map_into_right_body())
+                }
+            }
+        })
+    }
+}
+
+fn too_many_requests(retry_after: Duration) -> HttpResponse {
+    // round up, so that clients retrying right on time find a token
+    let seconds = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+    let mut response = HttpResponse::TooManyRequests().finish();
+    response
+        .headers_mut()
+        .insert(header::RETRY_AFTER, HeaderValue::from(seconds));
+    response
+}
+
+/// Reads `field` from a urlencoded form body, putting the body back for the handler.
+async fn read_form_field(req: &mut ServiceRequest, field: &str) -> Option<String> {
+    if req.content_type() != "application/x-www-form-urlencoded" {
+        return None;
+    }
+
+    let mut payload = req.parts_mut().1.take();
+    let mut body = BytesMut::new();
+    let mut error = None;
+    while body.len() <= MAX_KEYED_BODY_BYTES {
+        match payload.next().await {
+            Some(Ok(chunk)) => body.extend_from_slice(&chunk),
+            Some(Err(e)) => {
+                error = Some(e);
+                break;
+            }
+            None => break,
+        }
+    }
+
+    let body = body.freeze();
+    let value = if error.is_none() && body.len() <= MAX_KEYED_BODY_BYTES {
+        // no early return: the payload has to be restored whatever the body holds
+        serde_urlencoded::from_bytes::<Vec<(String, String)>>(&body)
+            .ok()
+            .and_then(|pairs| pairs.into_iter().find(|(k, _)| k == field))
+            .map(|(_, v)| v)
+    } else {
+        None
+    };
+
+    // the handler gets what was read, followed by whatever is left of the body (or the error)
+    let read: Result<Bytes, PayloadError> = Ok(body);
+    let rest = stream::iter(error.map(Err)).chain(payload);
+    let restored: Pin<Box<dyn Stream<Item = Result<Bytes, PayloadError>>>> =
+        Box::pin(stream::once(async move { read }).chain(rest));
+    req.set_payload(Payload::from(restored));
+    value
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{client_ip, Buckets, IpNetwork};
+    use crate::configuration::LimitSettings;
+    use claims::{assert_err, assert_ok};
+    use std::time::{Duration, Instant};
+
+    fn networks(s: &[&str]) -> Vec<IpNetwork> {
+        s.iter().map(|n| IpNetwork::parse(n).unwrap()).collect()
+    }
+
+    #[test]
+    fn networks_contain_the_addresses_in_their_prefix() {
+        let network = IpNetwork::parse("10.0.0.0/8").unwrap();
+        assert!(network.contains("10.1.2.3".parse().unwrap()));
+        assert!(!network.contains("11.0.0.1".parse().unwrap()));
+        assert!(!network.contains("::1".parse().unwrap()));
+
+        let single = IpNetwork::parse("192.168.1.1").unwrap();
+        assert!(single.contains("192.168.1.1".parse().unwrap()));
+        assert!(!single.contains("192.168.1.2".parse().unwrap()));
+
+        assert!(IpNetwork::parse("fd00::/8")
+            .unwrap()
+            .contains("fd12::1".parse().unwrap()));
+        assert_err!(IpNetwork::parse("10.0.0.0/33"));
+        assert_err!(IpNetwork::parse("localhost"));
+    }
+
+    #[test]
+    fn forwarded_for_is_ignored_unless_the_peer_is_a_trusted_proxy() {
+        let peer = Some("203.0.113.7".parse().unwrap());
+        assert_eq!(
+            client_ip(peer, Some("198.51.100.1"), &networks(&["10.0.0.0/8"])),
+            peer
+        );
+    }
+
+    #[test]
+    fn forwarded_for_is_read_right_to_left_skipping_trusted_proxies() {
+        let peer = Some("10.0.0.1".parse().unwrap());
+        let trusted = networks(&["10.0.0.0/8"]);
+        assert_eq!(
+            client_ip(peer, Some("1.1.1.1, 198.51.100.1, 10.0.0.2"), &trusted),
+            Some("198.51.100.1".parse().unwrap())
+        );
+        assert_eq!(
+            client_ip(peer, Some("10.0.0.3, 10.0.0.2"), &trusted),
+            Some("10.0.0.3".parse().unwrap())
+        );
+        assert_eq!(client_ip(peer, None, &trusted), peer);
+    }
+
+    #[test]
+    fn buckets_allow_bursts_and_refill_over_time() {
+        let buckets = Buckets::new(&LimitSettings {
+            burst: 2,
+            per_minute: 60,
+        })
+        .unwrap();
+        let now = Instant::now();
+        assert_ok!(buckets.take("a", now));
+        assert_ok!(buckets.take("a", now));
+        let retry_after = buckets.take("a", now).unwrap_err();
+        assert!(retry_after <= Duration::from_secs(1));
+
+        // other keys have their own bucket
+        assert_ok!(buckets.take("b", now));
+
+        assert_ok!(buckets.take("a", now + Duration::from_secs(1)));
+    }
+
+    #[test]
+    fn buckets_that_never_hold_a_token_are_rejected() {
+        for (burst, per_minute) in [(0, 60), (2, 0)] {
+            assert!(Buckets::new(&LimitSettings { burst, per_minute }).is_err());
+        }
+    }
+}
@@ -0,0 +1,113 @@
+use actix_web::{http::header, web::Data, HttpRequest, HttpResponse, ResponseError};
+use anyhow::anyhow;
+use reqwest::{header::HeaderValue, StatusCode, Url};
+
+use crate::{startup::ApplicationBaseUrl, utils};
+
+use super::auth::AuthError;
+
+mod newsletter_drafts;
+mod newsletter_issues;
+mod signup_report;
+mod subscriber_actions;
+mod subscriber_data;
+mod subscribers;
+mod subscribers_export;
+mod subscribers_import;
+
+pub use newsletter_drafts::{
+    check_newsletter_text, create_newsletter_draft, diff_newsletter_issue_revisions,
+    newsletter_issue_revision, newsletter_issue_revisions, preview_newsletter_issue,
+    restore_newsletter_issue_revision, send_newsletter_draft, send_test_newsletter_issue,
+    update_newsletter_draft,
+};
+pub use newsletter_issues::{
+    cancel_newsletter_issue, list_newsletter_issues, newsletter_issue, newsletter_issue_deliveries,
+    replay_newsletter_issue_deliveries, reschedule_newsletter_issue,
+};
+pub use signup_report::signup_report;
+pub use subscriber_actions::change_subscriber_status;
+pub use subscriber_data::{erase_subscriber_data, export_subscriber_data};
+pub use subscribers::{browse_subscribers, subscriber_details};
+pub use subscribers_export::export_subscribers;
+pub use subscribers_import::import_subscribers;
+
+/// The error returned by every handler behind the `/admin` scope.
+#[allow(clippy::enum_variant_names)]
+#[derive(thiserror::Error)]
+pub enum AdminError {
+    #[error("Authentication failed")]
+    AuthError(#[source] anyhow::Error),
+
+    #[error("{0}")]
+    ValidationError(String),
+
+    #[error("{0}")]
+    Conflict(String),
+
+    #[error("The request was sent from another site")]
+    CrossSiteRequest,
+
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl From<AuthError> for AdminError {
+    fn from(e: AuthError) -> Self {
+        match e {
+            AuthError::InvalidCredentials(_) => Self::AuthError(e.into()),
+            AuthError::UnexpectedError(_) => Self::UnexpectedError(e.into()),
+        }
+    }
+}
+
+impl std::fmt::Debug for AdminError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        utils::error_chain_fmt(self, f)
+    }
+}
+
+impl ResponseError for AdminError {
+    fn error_response(&self) -> HttpResponse {
+        match self {
+            Self::UnexpectedError(_) => HttpResponse::new(StatusCode::INTERNAL_SERVER_ERROR),
+            Self::ValidationError(message) => HttpResponse::BadRequest().body(message.clone()),
+            Self::Conflict(message) => HttpResponse::Conflict().body(message.clone()),
+            Self::CrossSiteRequest => HttpResponse::Forbidden().body(self.to_string()),
+            Self::AuthError(_) => {
+                let mut response = HttpResponse::new(StatusCode::UNAUTHORIZED);
+                let header_value = HeaderValue::from_str(r#"Basic realm="admin""#).unwrap();
+                response
+                    .headers_mut()
+                    .insert(header::WWW_AUTHENTICATE, header_value);
+                response
+            }
+        }
+    }
+}
+
+/// Rejects a state-changing request sent by a page of another site, which the browser of a
+/// logged-in admin would otherwise send along with their Basic auth credentials.
+///
+/// Browsers send an `Origin` with every cross-site `POST`, falling back to `Referer` here;
+/// a request with neither does not come from a browser and is let through.
+fn check_origin(request: &HttpRequest) -> Result<(), AdminError> {
+    let base_url = request
+        .app_data::<Data<ApplicationBaseUrl>>()
+        .ok_or_else(|| anyhow!("The base URL is not configured"))?;
+    let expected = Url::parse(&base_url.0)
+        .map_err(|e| anyhow!("The base URL is invalid: {e}"))?
+        .origin();
+    let headers = request.headers();
+    let Some(sender) = headers
+        .get(header::ORIGIN)
+        .or_else(|| headers.get(header::REFERER))
+    else {
+        return Ok(());
+    };
+    let sender = sender.to_str().ok().and_then(|s| Url::parse(s).ok());
+    match sender {
+        Some(sender) if sender.origin() == expected => Ok(()),
+        _ => Err(AdminError::CrossSiteRequest),
+    }
+}
@@ -0,0 +1,611 @@
+use actix_web::{
+    http::header::ContentType,
+    web::{Data, Form, Json, Path, Query},
+    HttpRequest, HttpResponse,
+};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use similar::TextDiff;
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::{
+    audit::{self, Actor},
+    authoring::{Bodies, Content, EmailLayout},
+    domain::{IssueStatus, SubscriberEmail},
+    email_client::EmailClient,
+    merge_tags::{self, personalize, MergeFields},
+    newsletter_issues::{
+        create_issue, email_bodies, get_issue, get_revision, list_revisions, lock_issue,
+        restore_revision, send_issue, update_draft, NewIssue, Revision,
+    },
+    plain_text,
+    preferences::list_id_by_slug,
+    routes::auth::authenticate,
+    startup::TestRecipients,
+    utils::html_escape,
+};
+
+use super::{check_origin, AdminError};
+
+#[derive(Deserialize)]
+pub struct DraftBody {
+    title: String,
+    content: Content,
+    /// The slug of the list to send the issue to, every confirmed subscriber if missing
+    #[serde(default)]
+    list: Option<String>,
+}
+
+/// Start a new newsletter issue as a draft; nothing is sent until it is explicitly sent.
+#[tracing::instrument(
+    name = "Create a newsletter draft",
+    skip(body, pool, layout, request),
+    fields(user_id=tracing::field::Empty)
+)]
+pub async fn create_newsletter_draft(
+    body: Json<DraftBody>,
+    pool: Data<PgPool>,
+    layout: Data<EmailLayout>,
+    request: HttpRequest,
+) -> Result<HttpResponse, AdminError> {
+    check_origin(&request)?;
+    let user_id = authenticate(request.headers(), &pool).await?;
+    tracing::Span::current().record("user_id", tracing::field::display(&user_id));
+
+    let bodies = bodies(&body, &layout)?;
+    let list_id = list_id(&pool, &body).await?;
+    let mut transaction = begin(&pool).await?;
+    let issue_id = create_issue(
+        &mut transaction,
+        user_id,
+        &new_issue(&body.title, &bodies, list_id),
+    )
+    .await?;
+    commit(transaction).await?;
+
+    let issue = get_issue(&pool, issue_id)
+        .await?
+        .context("The new draft disappeared")?;
+    Ok(HttpResponse::Created().json(issue))
+}
+
+/// Replace the content of a draft, keeping the previous content as a revision.
+#[tracing::instrument(
+    name = "Update a newsletter draft",
+    skip(body, pool, layout, request),
+    fields(user_id=tracing::field::Empty)
+)]
+pub async fn update_newsletter_draft(
+    issue_id: Path<Uuid>,
+    body: Json<DraftBody>,
+    pool: Data<PgPool>,
+    layout: Data<EmailLayout>,
+    request: HttpRequest,
+) -> Result<HttpResponse, AdminError> {
+    check_origin(&request)?;
+    let user_id = authenticate(request.headers(), &pool).await?;
+    tracing::Span::current().record("user_id", tracing::field::display(&user_id));
+
+    let issue_id = issue_id.into_inner();
+    let bodies = bodies(&body, &layout)?;
+    let list_id = list_id(&pool, &body).await?;
+    let mut transaction = begin(&pool).await?;
+    if !lock_draft(&mut transaction, issue_id).await? {
+        return Ok(HttpResponse::NotFound().finish());
+    }
+    update_draft(
+        &mut transaction,
+        issue_id,
+        user_id,
+        &new_issue(&body.title, &bodies, list_id),
+    )
+    .await?;
+    commit(transaction).await?;
+
+    let issue = get_issue(&pool, issue_id)
+        .await?
+        .context("The updated draft disappeared")?;
+    Ok(HttpResponse::Ok().json(issue))
+}
+
+#[derive(Deserialize)]
+pub struct PreviewParameters {
+    /// The revision to preview, the current content if missing
+    revision: Option<i32>,
+}
+
+/// The email as subscribers will see it: the HTML version rendered, and the text version.
+#[tracing::instrument(
+    name = "Preview a newsletter issue",
+    skip(parameters, pool, request),
+    fields(user_id=tracing::field::Empty)
+)]
+pub async fn preview_newsletter_issue(
+    issue_id: Path<Uuid>,
+    parameters: Query<PreviewParameters>,
+    pool: Data<PgPool>,
+    request: HttpRequest,
+) -> Result<HttpResponse, AdminError> {
+    let user_id = authenticate(request.headers(), &pool).await?;
+    tracing::Span::current().record("user_id", tracing::field::display(&user_id));
+
+    let issue_id = issue_id.into_inner();
+    let Some(issue) = get_issue(&pool, issue_id).await? else {
+        return Ok(HttpResponse::NotFound().finish());
+    };
+    let revision = parameters.revision.unwrap_or(issue.revision);
+    let Some(revision) = get_revision(&pool, issue_id, revision).await? else {
+        return Ok(HttpResponse::NotFound().finish());
+    };
+
+    // the link only points somewhere once it is personalized for a subscriber
+    let (html_body, text_body) = email_bodies(&revision.html_content, &revision.text_content, "#");
+    let html = format!(
+        r#"<!DOCTYPE html>
+<html lang="en">
+  <head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8" />
+    <title>Preview: {title}</title>
+  </head>
+  <body>
+    <h1>{title}</h1>
+    <p>Revision {revision} of {revisions}, {status}</p>
+    <h2>HTML version</h2>
+    <iframe sandbox srcdoc="{html_body}" title="HTML version" style="width: 100%; height: 600px"></iframe>
+    <h2>Text version</h2>
+    <pre>{text_body}</pre>
+  </body>
+</html>
+"#,
+        title = html_escape(&revision.summary.title),
+        revision = revision.summary.revision,
+        revisions = issue.revision,
+        status = html_escape(&issue.summary.status),
+        html_body = html_escape(&html_body),
+        text_body = html_escape(&text_body),
+    );
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(html))
+}
+
+/// The revisions of a newsletter issue, oldest first, without their content.
+#[tracing::instrument(
+    name = "List the revisions of a newsletter issue",
+    skip(pool, request),
+    fields(user_id=tracing::field::Empty)
+)]
+pub async fn newsletter_issue_revisions(
+    issue_id: Path<Uuid>,
+    pool: Data<PgPool>,
+    request: HttpRequest,
+) -> Result<HttpResponse, AdminError> {
+    let user_id = authenticate(request.headers(), &pool).await?;
+    tracing::Span::current().record("user_id", tracing::field::display(&user_id));
+
+    let revisions = list_revisions(&pool, issue_id.into_inner()).await?;
+    if revisions.is_empty() {
+        return Ok(HttpResponse::NotFound().finish());
+    }
+    Ok(HttpResponse::Ok().json(revisions))
+}
+
+/// A single revision of a newsletter issue, with its content.
+#[tracing::instrument(
+    name = "Show a revision of a newsletter issue",
+    skip(pool, request),
+    fields(user_id=tracing::field::Empty)
+)]
+pub async fn newsletter_issue_revision(
+    path: Path<(Uuid, i32)>,
+    pool: Data<PgPool>,
+    request: HttpRequest,
+) -> Result<HttpResponse, AdminError> {
+    let user_id = authenticate(request.headers(), &pool).await?;
+    tracing::Span::current().record("user_id", tracing::field::display(&user_id));
+
+    let (issue_id, revision) = path.into_inner();
+    match get_revision(&pool, issue_id, revision).await? {
+        Some(revision) => Ok(HttpResponse::Ok().json(revision)),
+        None => Ok(HttpResponse::NotFound().finish()),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct DiffParameters {
+    from: i32,
+    /// The current revision if missing
+    to: Option<i32>,
+}
+
+/// Unified diffs of the title and every version of the content, between two revisions.
+#[derive(Serialize)]
+struct RevisionDiff {
+    from: i32,
+    to: i32,
+    title: String,
+    html_content: String,
+    text_content: String,
+    markdown_content: String,
+}
+
+/// What changed between two revisions of a newsletter issue.
+#[tracing::instrument(
+    name = "Diff two revisions of a newsletter issue",
+    skip(parameters, pool, request),
+    fields(user_id=tracing::field::Empty)
+)]
+pub async fn diff_newsletter_issue_revisions(
+    issue_id: Path<Uuid>,
+    parameters: Query<DiffParameters>,
+    pool: Data<PgPool>,
+    request: HttpRequest,
+) -> Result<HttpResponse, AdminError> {
+    let user_id = authenticate(request.headers(), &pool).await?;
+    tracing::Span::current().record("user_id", tracing::field::display(&user_id));
+
+    let issue_id = issue_id.into_inner();
+    let Some(issue) = get_issue(&pool, issue_id).await? else {
+        return Ok(HttpResponse::NotFound().finish());
+    };
+    let to = parameters.to.unwrap_or(issue.revision);
+    let (Some(old), Some(new)) = (
+        get_revision(&pool, issue_id, parameters.from).await?,
+        get_revision(&pool, issue_id, to).await?,
+    ) else {
+        return Ok(HttpResponse::NotFound().finish());
+    };
+    Ok(HttpResponse::Ok().json(diff(&old, &new)))
+}
+
+fn diff(old: &Revision, new: &Revision) -> RevisionDiff {
+    let from = format!("revision {}", old.summary.revision);
+    let to = format!("revision {}", new.summary.revision);
+    let unified = |old: &str, new: &str| {
+        TextDiff::from_lines(old, new)
+            .unified_diff()
+            .header(&from, &to)
+            .to_string()
+    };
+    RevisionDiff {
+        from: old.summary.revision,
+        to: new.summary.revision,
+        title: unified(&old.summary.title, &new.summary.title),
+        html_content: unified(&old.html_content, &new.html_content),
+        text_content: unified(&old.text_content, &new.text_content),
+        markdown_content: unified(
+            old.markdown_content.as_deref().unwrap_or_default(),
+            new.markdown_content.as_deref().unwrap_or_default(),
+        ),
+    }
+}
+
+/// Make an earlier revision the current content of a draft, as a new revision.
+#[tracing::instrument(
+    name = "Restore a revision of a newsletter draft",
+    skip(pool, request),
+    fields(user_id=tracing::field::Empty)
+)]
+pub async fn restore_newsletter_issue_revision(
+    path: Path<(Uuid, i32)>,
+    pool: Data<PgPool>,
+    request: HttpRequest,
+) -> Result<HttpResponse, AdminError> {
+    check_origin(&request)?;
+    let user_id = authenticate(request.headers(), &pool).await?;
+    tracing::Span::current().record("user_id", tracing::field::display(&user_id));
+
+    let (issue_id, revision) = path.into_inner();
+    let mut transaction = begin(&pool).await?;
+    if !lock_draft(&mut transaction, issue_id).await? {
+        return Ok(HttpResponse::NotFound().finish());
+    }
+    if restore_revision(&mut transaction, issue_id, user_id, revision)
+        .await?
+        .is_none()
+    {
+        return Ok(HttpResponse::NotFound().finish());
+    }
+    commit(transaction).await?;
+
+    let issue = get_issue(&pool, issue_id)
+        .await?
+        .context("The restored draft disappeared")?;
+    Ok(HttpResponse::Ok().json(issue))
+}
+
+#[derive(Deserialize)]
+pub struct SendForm {
+    /// When to start sending the issue, right away if missing
+    #[serde(default)]
+    send_at: Option<DateTime<Utc>>,
+}
+
+/// Send a draft to its subscribers, right away or at `send_at` in each subscriber's time zone.
+#[tracing::instrument(
+    name = "Send a newsletter draft",
+    skip(form, pool, request),
+    fields(user_id=tracing::field::Empty)
+)]
+pub async fn send_newsletter_draft(
+    issue_id: Path<Uuid>,
+    form: Form<SendForm>,
+    pool: Data<PgPool>,
+    request: HttpRequest,
+) -> Result<HttpResponse, AdminError> {
+    check_origin(&request)?;
+    let user_id = authenticate(request.headers(), &pool).await?;
+    tracing::Span::current().record("user_id", tracing::field::display(&user_id));
+
+    if form.send_at.is_some_and(|send_at| send_at <= Utc::now()) {
+        return Err(AdminError::ValidationError(
+            "send_at must be in the future".into(),
+        ));
+    }
+    let issue_id = issue_id.into_inner();
+    let mut transaction = begin(&pool).await?;
+    if !lock_draft(&mut transaction, issue_id).await? {
+        return Ok(HttpResponse::NotFound().finish());
+    }
+    // drafts written before merge tags existed were never checked
+    let draft = get_issue(&pool, issue_id)
+        .await?
+        .context("The locked draft disappeared")?;
+    check_merge_tags(
+        &draft.summary.title,
+        &draft.html_content,
+        &draft.text_content,
+    )?;
+    let status = send_issue(&mut transaction, issue_id, form.send_at).await?;
+    audit::record(
+        &mut transaction,
+        Actor::User(user_id),
+        "issue_sent",
+        &issue_id.to_string(),
+        serde_json::json!({ "status": status, "send_at": form.send_at }),
+    )
+    .await?;
+    commit(transaction).await?;
+
+    let issue = get_issue(&pool, issue_id)
+        .await?
+        .context("The sent issue disappeared")?;
+    Ok(HttpResponse::Accepted().json(issue.summary))
+}
+
+#[derive(Deserialize)]
+pub struct TextCheckBody {
+    html: String,
+    text: String,
+}
+
+/// Flags a text body that drifted from its HTML body, or that is a mere placeholder,
+/// before it hurts the spam score of the issue.
+#[tracing::instrument(
+    name = "Check the text body of a newsletter issue",
+    skip(body, pool, request),
+    fields(user_id=tracing::field::Empty)
+)]
+pub async fn check_newsletter_text(
+    body: Json<TextCheckBody>,
+    pool: Data<PgPool>,
+    request: HttpRequest,
+) -> Result<HttpResponse, AdminError> {
+    let user_id = authenticate(request.headers(), &pool).await?;
+    tracing::Span::current().record("user_id", tracing::field::display(&user_id));
+
+    Ok(HttpResponse::Ok().json(plain_text::check(&body.html, &body.text)))
+}
+
+/// The `{{ name }}` of test sends.
+const TEST_SUBSCRIBER_NAME: &str = "Test Subscriber";
+/// The most addresses a test send can name, so that it cannot stand in for a real send.
+const MAX_TEST_RECIPIENTS: usize = 10;
+
+#[derive(Deserialize)]
+pub struct TestSendForm {
+    /// Addresses separated by commas or whitespace, the configured seed list if missing
+    /// and `include_me` is not set
+    #[serde(default)]
+    recipients: Option<String>,
+    /// Also send to the email address of the logged-in user
+    #[serde(default)]
+    include_me: bool,
+    /// The revision to send, the current content if missing
+    #[serde(default)]
+    revision: Option<i32>,
+}
+
+/// The outcome of a test send for one recipient.
+#[derive(Serialize)]
+struct TestDelivery {
+    email: String,
+    sent: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+/// Email an issue to a few addresses, to check how it renders before the real send.
+///
+/// Test sends go straight through the email client: they are not queued, not counted
+/// in the delivery stats and leave the status of the issue alone.
+#[tracing::instrument(
+    name = "Send a test of a newsletter issue",
+    skip(form, pool, email_client, seed_list, request),
+    fields(user_id=tracing::field::Empty)
+)]
+pub async fn send_test_newsletter_issue(
+    issue_id: Path<Uuid>,
+    form: Form<TestSendForm>,
+    pool: Data<PgPool>,
+    email_client: Data<EmailClient>,
+    seed_list: Data<TestRecipients>,
+    request: HttpRequest,
+) -> Result<HttpResponse, AdminError> {
+    check_origin(&request)?;
+    let user_id = authenticate(request.headers(), &pool).await?;
+    tracing::Span::current().record("user_id", tracing::field::display(&user_id));
+
+    let mut recipients = match form.recipients.as_deref().map(str::trim) {
+        Some(recipients) if !recipients.is_empty() => recipients
+            .split(|c: char| c == ',' || c.is_whitespace())
+            .filter(|email| !email.is_empty())
+            .map(|email| SubscriberEmail::parse(email.to_string()))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(AdminError::ValidationError)?,
+        _ if form.include_me => Vec::new(),
+        _ => seed_list.0.clone(),
+    };
+    if form.include_me {
+        let email = user_email(&pool, user_id).await?.ok_or_else(|| {
+            AdminError::ValidationError("Your account has no email address".into())
+        })?;
+        recipients.push(SubscriberEmail::parse(email).map_err(AdminError::ValidationError)?);
+    }
+    if recipients.len() > MAX_TEST_RECIPIENTS {
+        return Err(AdminError::ValidationError(format!(
+            "A test send goes to at most {MAX_TEST_RECIPIENTS} recipients"
+        )));
+    }
+    if recipients.is_empty() {
+        return Err(AdminError::ValidationError(
+            "No recipients given and no test recipients configured".into(),
+        ));
+    }
+
+    let issue_id = issue_id.into_inner();
+    let Some(issue) = get_issue(&pool, issue_id).await? else {
+        return Ok(HttpResponse::NotFound().finish());
+    };
+    let revision = form.revision.unwrap_or(issue.revision);
+    let Some(revision) = get_revision(&pool, issue_id, revision).await? else {
+        return Ok(HttpResponse::NotFound().finish());
+    };
+
+    let no_attributes = serde_json::Map::new();
+    let mut deliveries = Vec::with_capacity(recipients.len());
+    for recipient in &recipients {
+        // there is no subscriber behind a test address: attributes fall back to their defaults
+        // and the links point nowhere
+        let fields = MergeFields {
+            name: TEST_SUBSCRIBER_NAME,
+            email: recipient.as_ref(),
+            unsubscribe_url: "#",
+            attributes: &no_attributes,
+        };
+        let content = personalize(
+            &revision.summary.title,
+            &revision.html_content,
+            &revision.text_content,
+            &fields,
+        )
+        .map_err(|e| AdminError::ValidationError(e.to_string()))?;
+        let subject = format!("[TEST] {}", content.subject);
+        let (html_body, text_body) = email_bodies(&content.html, &content.text, "#");
+        let sent = email_client
+            .send_email(recipient, &subject, &html_body, &text_body)
+            .await;
+        if let Err(e) = &sent {
+            tracing::warn!(error.cause_chain = ?e, "Failed to send a test email");
+        }
+        deliveries.push(TestDelivery {
+            email: recipient.as_ref().to_string(),
+            sent: sent.is_ok(),
+            error: sent.err().map(|e| e.to_string()),
+        });
+    }
+
+    let mut transaction = begin(&pool).await?;
+    audit::record(
+        &mut transaction,
+        Actor::User(user_id),
+        "issue_test_sent",
+        &issue_id.to_string(),
+        serde_json::json!({
+            "revision": revision.summary.revision,
+            "recipients": deliveries.iter().map(|d| &d.email).collect::<Vec<_>>(),
+        }),
+    )
+    .await?;
+    commit(transaction).await?;
+
+    if deliveries.iter().all(|d| d.sent) {
+        Ok(HttpResponse::Ok().json(deliveries))
+    } else {
+        Ok(HttpResponse::BadGateway().json(deliveries))
+    }
+}
+
+/// The email address of the user, if they have one.
+async fn user_email(pool: &PgPool, user_id: Uuid) -> Result<Option<String>, AdminError> {
+    let user = sqlx::query!(r#"SELECT email FROM users WHERE user_id = $1"#, user_id)
+        .fetch_one(pool)
+        .await
+        .context("Failed to fetch the email address of a user")?;
+    Ok(user.email)
+}
+
+/// Renders the bodies of the draft, when written in Markdown, and checks their merge tags.
+fn bodies(body: &DraftBody, layout: &EmailLayout) -> Result<Bodies, AdminError> {
+    let bodies = body
+        .content
+        .bodies(layout)
+        .map_err(AdminError::ValidationError)?;
+    check_merge_tags(&body.title, &bodies.html, &bodies.text)?;
+    Ok(bodies)
+}
+
+fn new_issue<'a>(title: &'a str, bodies: &'a Bodies, list_id: Option<Uuid>) -> NewIssue<'a> {
+    NewIssue {
+        title,
+        html_content: &bodies.html,
+        text_content: &bodies.text,
+        markdown_content: bodies.markdown.as_deref(),
+        list_id,
+    }
+}
+
+/// Rejects merge tag syntax errors now rather than in the middle of the send.
+fn check_merge_tags(title: &str, html: &str, text: &str) -> Result<(), AdminError> {
+    merge_tags::check(title, html, text).map_err(AdminError::ValidationError)
+}
+
+async fn list_id(pool: &PgPool, body: &DraftBody) -> Result<Option<Uuid>, AdminError> {
+    match &body.list {
+        Some(slug) => Ok(Some(list_id_by_slug(pool, slug).await?.ok_or_else(
+            || AdminError::ValidationError(format!("{slug} is not a list")),
+        )?)),
+        None => Ok(None),
+    }
+}
+
+/// Locks the issue, making sure it is still a draft. Returns `false` if it does not exist.
+async fn lock_draft(
+    tx: &mut Transaction<'_, Postgres>,
+    issue_id: Uuid,
+) -> Result<bool, AdminError> {
+    match lock_issue(tx, issue_id).await? {
+        None => Ok(false),
+        Some(IssueStatus::Draft) => Ok(true),
+        Some(_) => Err(AdminError::Conflict(
+            "Only drafts can be changed or sent".into(),
+        )),
+    }
+}
+
+async fn begin(pool: &PgPool) -> Result<Transaction<'static, Postgres>, AdminError> {
+    Ok(pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?)
+}
+
+async fn commit(transaction: Transaction<'_, Postgres>) -> Result<(), AdminError> {
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to change a newsletter draft")?;
+    Ok(())
+}
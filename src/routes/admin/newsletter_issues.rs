@@ -0,0 +1,248 @@
+use actix_web::{
+    web::{Data, Form, Path, Query},
+    HttpRequest, HttpResponse,
+};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+    audit::{self, Actor},
+    domain::DeliveryStatus,
+    newsletter_issues::{
+        cancel_issue, get_issue, list_deliveries, list_issues, replay_dead_letters,
+        reschedule_issue,
+    },
+    routes::auth::authenticate,
+};
+
+use super::{check_origin, AdminError};
+
+const DEFAULT_PAGE_SIZE: i64 = 50;
+const MAX_PAGE_SIZE: i64 = 200;
+
+#[derive(Deserialize)]
+pub struct ListParameters {
+    limit: Option<i64>,
+}
+
+/// The most recent newsletter issues, newest first, without their content.
+#[tracing::instrument(
+    name = "List newsletter issues",
+    skip(parameters, pool, request),
+    fields(user_id=tracing::field::Empty)
+)]
+pub async fn list_newsletter_issues(
+    parameters: Query<ListParameters>,
+    pool: Data<PgPool>,
+    request: HttpRequest,
+) -> Result<HttpResponse, AdminError> {
+    let user_id = authenticate(request.headers(), &pool).await?;
+    tracing::Span::current().record("user_id", tracing::field::display(&user_id));
+
+    let limit = parameters
+        .limit
+        .unwrap_or(DEFAULT_PAGE_SIZE)
+        .clamp(1, MAX_PAGE_SIZE);
+    let issues = list_issues(&pool, limit).await?;
+    Ok(HttpResponse::Ok().json(issues))
+}
+
+/// A single newsletter issue, with its content.
+#[tracing::instrument(
+    name = "Show a newsletter issue",
+    skip(pool, request),
+    fields(user_id=tracing::field::Empty)
+)]
+pub async fn newsletter_issue(
+    issue_id: Path<Uuid>,
+    pool: Data<PgPool>,
+    request: HttpRequest,
+) -> Result<HttpResponse, AdminError> {
+    let user_id = authenticate(request.headers(), &pool).await?;
+    tracing::Span::current().record("user_id", tracing::field::display(&user_id));
+
+    match get_issue(&pool, issue_id.into_inner()).await? {
+        Some(issue) => Ok(HttpResponse::Ok().json(issue)),
+        None => Ok(HttpResponse::NotFound().finish()),
+    }
+}
+
+#[derive(Deserialize)]
+pub struct DeliveriesParameters {
+    status: Option<DeliveryStatus>,
+}
+
+/// The deliveries of a newsletter issue, optionally only those in a given status.
+#[tracing::instrument(
+    name = "List the deliveries of a newsletter issue",
+    skip(parameters, pool, request),
+    fields(user_id=tracing::field::Empty)
+)]
+pub async fn newsletter_issue_deliveries(
+    issue_id: Path<Uuid>,
+    parameters: Query<DeliveriesParameters>,
+    pool: Data<PgPool>,
+    request: HttpRequest,
+) -> Result<HttpResponse, AdminError> {
+    let user_id = authenticate(request.headers(), &pool).await?;
+    tracing::Span::current().record("user_id", tracing::field::display(&user_id));
+
+    let issue_id = issue_id.into_inner();
+    if get_issue(&pool, issue_id).await?.is_none() {
+        return Ok(HttpResponse::NotFound().finish());
+    }
+    let deliveries = list_deliveries(&pool, issue_id, parameters.status).await?;
+    Ok(HttpResponse::Ok().json(deliveries))
+}
+
+/// Queue the dead-lettered deliveries of a newsletter issue again.
+#[tracing::instrument(
+    name = "Replay the dead-lettered deliveries of a newsletter issue",
+    skip(pool, request),
+    fields(user_id=tracing::field::Empty)
+)]
+pub async fn replay_newsletter_issue_deliveries(
+    issue_id: Path<Uuid>,
+    pool: Data<PgPool>,
+    request: HttpRequest,
+) -> Result<HttpResponse, AdminError> {
+    check_origin(&request)?;
+    let user_id = authenticate(request.headers(), &pool).await?;
+    tracing::Span::current().record("user_id", tracing::field::display(&user_id));
+
+    let issue_id = issue_id.into_inner();
+    if get_issue(&pool, issue_id).await?.is_none() {
+        return Ok(HttpResponse::NotFound().finish());
+    }
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    let replayed = replay_dead_letters(&mut transaction, issue_id).await?;
+    audit::record(
+        &mut transaction,
+        Actor::User(user_id),
+        "deliveries_replayed",
+        &issue_id.to_string(),
+        serde_json::json!({ "replayed": replayed }),
+    )
+    .await?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to replay deliveries")?;
+
+    Ok(HttpResponse::Ok().json(serde_json::json!({ "replayed": replayed })))
+}
+
+#[derive(Deserialize)]
+pub struct RescheduleForm {
+    send_at: DateTime<Utc>,
+}
+
+/// Move a scheduled newsletter issue to another time, before it starts sending.
+#[tracing::instrument(
+    name = "Reschedule a newsletter issue",
+    skip(form, pool, request),
+    fields(user_id=tracing::field::Empty)
+)]
+pub async fn reschedule_newsletter_issue(
+    issue_id: Path<Uuid>,
+    form: Form<RescheduleForm>,
+    pool: Data<PgPool>,
+    request: HttpRequest,
+) -> Result<HttpResponse, AdminError> {
+    check_origin(&request)?;
+    let user_id = authenticate(request.headers(), &pool).await?;
+    tracing::Span::current().record("user_id", tracing::field::display(&user_id));
+
+    let send_at = form.send_at;
+    if send_at <= Utc::now() {
+        return Err(AdminError::ValidationError(
+            "send_at must be in the future".into(),
+        ));
+    }
+    let issue_id = issue_id.into_inner();
+    if get_issue(&pool, issue_id).await?.is_none() {
+        return Ok(HttpResponse::NotFound().finish());
+    }
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    if !reschedule_issue(&mut transaction, issue_id, send_at).await? {
+        return Err(not_scheduled());
+    }
+    audit::record(
+        &mut transaction,
+        Actor::User(user_id),
+        "issue_rescheduled",
+        &issue_id.to_string(),
+        serde_json::json!({ "send_at": send_at }),
+    )
+    .await?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to reschedule an issue")?;
+
+    let issue = get_issue(&pool, issue_id)
+        .await?
+        .context("The rescheduled issue disappeared")?;
+    Ok(HttpResponse::Ok().json(issue.summary))
+}
+
+/// Cancel a scheduled newsletter issue, before it starts sending.
+#[tracing::instrument(
+    name = "Cancel a newsletter issue",
+    skip(pool, request),
+    fields(user_id=tracing::field::Empty)
+)]
+pub async fn cancel_newsletter_issue(
+    issue_id: Path<Uuid>,
+    pool: Data<PgPool>,
+    request: HttpRequest,
+) -> Result<HttpResponse, AdminError> {
+    check_origin(&request)?;
+    let user_id = authenticate(request.headers(), &pool).await?;
+    tracing::Span::current().record("user_id", tracing::field::display(&user_id));
+
+    let issue_id = issue_id.into_inner();
+    if get_issue(&pool, issue_id).await?.is_none() {
+        return Ok(HttpResponse::NotFound().finish());
+    }
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    if !cancel_issue(&mut transaction, issue_id).await? {
+        return Err(not_scheduled());
+    }
+    audit::record(
+        &mut transaction,
+        Actor::User(user_id),
+        "issue_cancelled",
+        &issue_id.to_string(),
+        serde_json::json!({}),
+    )
+    .await?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to cancel an issue")?;
+
+    let issue = get_issue(&pool, issue_id)
+        .await?
+        .context("The cancelled issue disappeared")?;
+    Ok(HttpResponse::Ok().json(issue.summary))
+}
+
+fn not_scheduled() -> AdminError {
+    AdminError::Conflict("Only issues that have not started sending can be changed".into())
+}
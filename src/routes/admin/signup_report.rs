@@ -0,0 +1,201 @@
+use actix_web::{
+    http::header::ContentType,
+    web::{Data, Query},
+    HttpRequest, HttpResponse,
+};
+use anyhow::Context;
+use chrono::{DateTime, Duration, NaiveDate, TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+
+use crate::{
+    attribution::UNKNOWN_SOURCE, domain::SubscriptionStatus, routes::auth::authenticate,
+    utils::html_escape,
+};
+
+use super::{
+    subscribers::{empty_as_none, ResponseFormat},
+    AdminError,
+};
+
+#[derive(Deserialize, Serialize, Clone, Copy, Default, PartialEq, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum ReportInterval {
+    Day,
+    #[default]
+    Week,
+    Month,
+}
+
+impl ReportInterval {
+    const ALL: [Self; 3] = [Self::Day, Self::Week, Self::Month];
+
+    /// The field `date_trunc` truncates signup times to.
+    fn as_str(&self) -> &'static str {
+        match self {
+            Self::Day => "day",
+            Self::Week => "week",
+            Self::Month => "month",
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct ReportParameters {
+    #[serde(default)]
+    format: ResponseFormat,
+    #[serde(default)]
+    interval: ReportInterval,
+    /// First signup date to include
+    #[serde(default, deserialize_with = "empty_as_none")]
+    from: Option<NaiveDate>,
+    /// Last signup date to include
+    #[serde(default, deserialize_with = "empty_as_none")]
+    to: Option<NaiveDate>,
+}
+
+#[derive(Serialize)]
+struct ReportRow {
+    /// The start of the day, week or month
+    period: DateTime<Utc>,
+    source: String,
+    signups: i64,
+    /// Signups that confirmed their address, even if they have left since
+    confirmed: i64,
+    confirmation_rate: f64,
+}
+
+#[derive(Serialize)]
+struct SignupReport {
+    interval: ReportInterval,
+    rows: Vec<ReportRow>,
+}
+
+/// Signups and confirmation rates by source and by day, week or month,
+/// as an HTML page or as JSON (`format=json`).
+///
+/// The source is the `source` given at signup, else the `utm_source`.
+#[tracing::instrument(
+    name = "Report signups by source",
+    skip(parameters, pool, request),
+    fields(user_id=tracing::field::Empty)
+)]
+pub async fn signup_report(
+    parameters: Query<ReportParameters>,
+    pool: Data<PgPool>,
+    request: HttpRequest,
+) -> Result<HttpResponse, AdminError> {
+    let user_id = authenticate(request.headers(), &pool).await?;
+    tracing::Span::current().record("user_id", tracing::field::display(&user_id));
+
+    let parameters = parameters.into_inner();
+    let report = fetch_report(&pool, &parameters).await?;
+    match parameters.format {
+        ResponseFormat::Json => Ok(HttpResponse::Ok().json(report)),
+        ResponseFormat::Html => Ok(HttpResponse::Ok()
+            .content_type(ContentType::html())
+            .body(render_report(&parameters, &report))),
+    }
+}
+
+async fn fetch_report(
+    pool: &PgPool,
+    parameters: &ReportParameters,
+) -> Result<SignupReport, AdminError> {
+    let midnight = |d: NaiveDate| Utc.from_utc_datetime(&d.and_hms_opt(0, 0, 0).unwrap());
+    let from = parameters.from.map(midnight);
+    let until = parameters.to.map(|d| midnight(d) + Duration::days(1));
+
+    let rows = sqlx::query!(
+        r#"
+        SELECT
+            date_trunc($1, subscribed_at) AS "period!",
+            COALESCE(source, utm_source, $4) AS "source!",
+            count(*) AS "signups!",
+            count(*) FILTER (WHERE EXISTS (
+                SELECT 1 FROM subscription_status_history
+                WHERE subscriber_id = subscriptions.id AND to_status = $5
+            )) AS "confirmed!"
+        FROM subscriptions
+        WHERE ($2::timestamptz IS NULL OR subscribed_at >= $2)
+            AND ($3::timestamptz IS NULL OR subscribed_at < $3)
+        GROUP BY 1, 2
+        ORDER BY 1 DESC, 3 DESC, 2
+        "#,
+        parameters.interval.as_str(),
+        from,
+        until,
+        UNKNOWN_SOURCE,
+        SubscriptionStatus::Confirmed.as_str(),
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to aggregate signups by source")?
+    .into_iter()
+    .map(|r| ReportRow {
+        period: r.period,
+        source: r.source,
+        signups: r.signups,
+        confirmed: r.confirmed,
+        confirmation_rate: r.confirmed as f64 / r.signups as f64,
+    })
+    .collect();
+
+    Ok(SignupReport {
+        interval: parameters.interval,
+        rows,
+    })
+}
+
+fn render_report(parameters: &ReportParameters, report: &SignupReport) -> String {
+    let interval_options: String = ReportInterval::ALL
+        .iter()
+        .map(|i| {
+            let selected = if parameters.interval == *i {
+                " selected"
+            } else {
+                ""
+            };
+            format!(r#"<option value="{0}"{selected}>{0}</option>"#, i.as_str())
+        })
+        .collect();
+    let rows: String = report
+        .rows
+        .iter()
+        .map(|r| {
+            format!(
+                "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{:.1}%</td></tr>",
+                r.period.date_naive(),
+                html_escape(&r.source),
+                r.signups,
+                r.confirmed,
+                r.confirmation_rate * 100.0,
+            )
+        })
+        .collect();
+
+    format!(
+        r#"<!DOCTYPE html>
+<html lang="en">
+  <head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8" />
+    <title>Signups by source</title>
+  </head>
+  <body>
+    <form action="/admin/reports/signups" method="get">
+      <select name="interval">{interval_options}</select>
+      <label>From <input type="date" name="from" value="{from}" /></label>
+      <label>To <input type="date" name="to" value="{to}" /></label>
+      <button type="submit">Show</button>
+    </form>
+    <table>
+      <thead><tr><th>Period</th><th>Source</th><th>Signups</th><th>Confirmed</th><th>Confirmation rate</th></tr></thead>
+      <tbody>{rows}</tbody>
+    </table>
+  </body>
+</html>
+"#,
+        from = parameters.from.map(|d| d.to_string()).unwrap_or_default(),
+        to = parameters.to.map(|d| d.to_string()).unwrap_or_default(),
+    )
+}
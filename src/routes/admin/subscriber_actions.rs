@@ -0,0 +1,118 @@
+use actix_web::{
+    http::header::LOCATION,
+    web::{Data, Form, Path},
+    HttpRequest, HttpResponse,
+};
+use anyhow::Context;
+use serde::Deserialize;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+    audit::{self, Actor},
+    domain::SubscriptionStatus,
+    routes::auth::authenticate,
+    subscription_lifecycle::{transition_status, TransitionError},
+};
+
+use super::{check_origin, AdminError};
+
+/// A manual correction support can make to a subscriber.
+#[derive(Deserialize, Debug, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum SubscriberAction {
+    Confirm,
+    Unsubscribe,
+    Delete,
+}
+
+impl SubscriberAction {
+    fn target_status(&self) -> SubscriptionStatus {
+        match self {
+            Self::Confirm => SubscriptionStatus::Confirmed,
+            Self::Unsubscribe => SubscriptionStatus::Unsubscribed,
+            Self::Delete => SubscriptionStatus::Deleted,
+        }
+    }
+
+    fn reason(&self) -> &'static str {
+        match self {
+            Self::Confirm => "Confirmed by staff",
+            Self::Unsubscribe => "Unsubscribed by staff",
+            Self::Delete => "Deleted by staff",
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct ActionForm {
+    /// Why the change was made, e.g. "asked by phone"
+    #[serde(default)]
+    note: Option<String>,
+}
+
+/// Confirm, unsubscribe or delete a subscriber on their behalf.
+///
+/// The change follows the same status rules as the public flows, and redirects
+/// back to the subscriber's detail page once done.
+#[tracing::instrument(
+    name = "Change the status of a subscriber as staff",
+    skip(form, pool, request),
+    fields(user_id=tracing::field::Empty)
+)]
+pub async fn change_subscriber_status(
+    path: Path<(Uuid, SubscriberAction)>,
+    form: Form<ActionForm>,
+    pool: Data<PgPool>,
+    request: HttpRequest,
+) -> Result<HttpResponse, AdminError> {
+    check_origin(&request)?;
+    let user_id = authenticate(request.headers(), &pool).await?;
+    tracing::Span::current().record("user_id", tracing::field::display(&user_id));
+
+    let (subscriber_id, action) = path.into_inner();
+    let next = action.target_status();
+    let note = form.0.note.filter(|n| !n.trim().is_empty());
+    let reason = match &note {
+        Some(note) => format!("{}: {}", action.reason(), note.trim()),
+        None => action.reason().to_string(),
+    };
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+
+    match transition_status(&mut transaction, subscriber_id, next, &reason).await {
+        Ok(true) => {}
+        // nothing changed, so there is nothing to audit either
+        Ok(false) => return Ok(see_details(subscriber_id)),
+        Err(TransitionError::NotFound) => return Ok(HttpResponse::NotFound().finish()),
+        Err(TransitionError::InvalidTransition(e)) => {
+            return Err(AdminError::Conflict(e.to_string()))
+        }
+        Err(TransitionError::UnexpectedError(e)) => return Err(e.into()),
+    }
+
+    audit::record(
+        &mut transaction,
+        Actor::User(user_id),
+        "subscriber_status_changed",
+        &subscriber_id.to_string(),
+        serde_json::json!({ "to_status": next, "note": note }),
+    )
+    .await?;
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to change the status of a subscriber")?;
+
+    Ok(see_details(subscriber_id))
+}
+
+fn see_details(subscriber_id: Uuid) -> HttpResponse {
+    HttpResponse::SeeOther()
+        .insert_header((LOCATION, format!("/admin/subscribers/{subscriber_id}")))
+        .finish()
+}
@@ -0,0 +1,64 @@
+use actix_web::{
+    web::{Data, Form, Query},
+    HttpRequest, HttpResponse,
+};
+use serde::Deserialize;
+use sqlx::PgPool;
+
+use crate::{
+    audit::Actor,
+    gdpr::{collect_subscriber_data, erase_subscribers, find_subscriber_ids},
+    routes::auth::authenticate,
+};
+
+use super::{check_origin, AdminError};
+
+#[derive(Deserialize)]
+pub struct SubscriberParameters {
+    email: String,
+}
+
+/// Export everything stored about the subscribers with the given email address, deleted
+/// ones included.
+#[tracing::instrument(
+    name = "Export subscriber data",
+    skip(parameters, pool, request),
+    fields(user_id=tracing::field::Empty)
+)]
+pub async fn export_subscriber_data(
+    parameters: Query<SubscriberParameters>,
+    pool: Data<PgPool>,
+    request: HttpRequest,
+) -> Result<HttpResponse, AdminError> {
+    let user_id = authenticate(request.headers(), &pool).await?;
+    tracing::Span::current().record("user_id", tracing::field::display(&user_id));
+
+    let subscriber_ids = find_subscriber_ids(&pool, &parameters.email).await?;
+    match collect_subscriber_data(&pool, &subscriber_ids, Actor::User(user_id)).await? {
+        Some(data) => Ok(HttpResponse::Ok().json(data)),
+        None => Ok(HttpResponse::NotFound().finish()),
+    }
+}
+
+/// Permanently delete the subscribers with the given email address, deleted ones included.
+#[tracing::instrument(
+    name = "Erase subscriber data",
+    skip(form, pool, request),
+    fields(user_id=tracing::field::Empty)
+)]
+pub async fn erase_subscriber_data(
+    form: Form<SubscriberParameters>,
+    pool: Data<PgPool>,
+    request: HttpRequest,
+) -> Result<HttpResponse, AdminError> {
+    check_origin(&request)?;
+    let user_id = authenticate(request.headers(), &pool).await?;
+    tracing::Span::current().record("user_id", tracing::field::display(&user_id));
+
+    let subscriber_ids = find_subscriber_ids(&pool, &form.email).await?;
+    if erase_subscribers(&pool, &subscriber_ids, Actor::User(user_id)).await? > 0 {
+        Ok(HttpResponse::Ok().finish())
+    } else {
+        Ok(HttpResponse::NotFound().finish())
+    }
+}
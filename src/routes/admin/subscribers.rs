@@ -0,0 +1,639 @@
+use actix_web::{
+    http::header::ContentType,
+    web::{Data, Path, Query},
+    HttpRequest, HttpResponse,
+};
+use anyhow::Context;
+use chrono::{DateTime, Duration, NaiveDate, TimeZone, Utc};
+use serde::{de::IntoDeserializer, Deserialize, Deserializer, Serialize};
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+    attribution::Attribution,
+    domain::SubscriptionStatus,
+    gdpr::{DataAccessTokenRecord, DeliveryRecord, StatusChangeRecord},
+    preferences::{all_lists, List},
+    routes::auth::authenticate,
+    utils::html_escape,
+};
+
+use super::AdminError;
+
+const DEFAULT_PAGE_SIZE: i64 = 50;
+const MAX_PAGE_SIZE: i64 = 200;
+
+#[derive(Deserialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ResponseFormat {
+    #[default]
+    Html,
+    Json,
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct BrowseParameters {
+    #[serde(default, skip_serializing)]
+    format: ResponseFormat,
+    #[serde(
+        default,
+        deserialize_with = "empty_as_none",
+        skip_serializing_if = "Option::is_none"
+    )]
+    status: Option<SubscriptionStatus>,
+    /// First signup date to include
+    #[serde(
+        default,
+        deserialize_with = "empty_as_none",
+        skip_serializing_if = "Option::is_none"
+    )]
+    from: Option<NaiveDate>,
+    /// Last signup date to include
+    #[serde(
+        default,
+        deserialize_with = "empty_as_none",
+        skip_serializing_if = "Option::is_none"
+    )]
+    to: Option<NaiveDate>,
+    /// The slug of a list the subscribers receive
+    #[serde(
+        default,
+        deserialize_with = "empty_as_none",
+        skip_serializing_if = "Option::is_none"
+    )]
+    list: Option<String>,
+    /// Matched against the email address and the name, ignoring case
+    #[serde(skip_serializing_if = "Option::is_none")]
+    q: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    limit: Option<i64>,
+    /// Where the previous page ended, as returned in `next_cursor`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    after: Option<String>,
+}
+
+/// Treats the empty values submitted by blank filter fields as missing.
+pub(super) fn empty_as_none<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    match Option::<String>::deserialize(deserializer)? {
+        Some(s) if !s.trim().is_empty() => T::deserialize(s.into_deserializer()).map(Some),
+        _ => Ok(None),
+    }
+}
+
+#[derive(Serialize)]
+struct SubscriberSummary {
+    id: Uuid,
+    email: String,
+    name: String,
+    status: String,
+    locale: String,
+    delivery_frequency: String,
+    subscribed_at: DateTime<Utc>,
+}
+
+#[derive(Serialize)]
+struct SubscriberPage {
+    subscribers: Vec<SubscriberSummary>,
+    next_cursor: Option<String>,
+}
+
+/// The position of a subscriber in the listing, newest first.
+#[derive(Debug)]
+struct Cursor {
+    subscribed_at: DateTime<Utc>,
+    id: Uuid,
+}
+
+impl Cursor {
+    fn encode(&self) -> String {
+        let raw = format!("{}:{}", self.subscribed_at.timestamp_micros(), self.id);
+        base64::encode_config(raw, base64::URL_SAFE_NO_PAD)
+    }
+
+    fn decode(s: &str) -> Result<Self, String> {
+        let invalid = || "The pagination cursor is invalid".to_string();
+        let raw = base64::decode_config(s, base64::URL_SAFE_NO_PAD).map_err(|_| invalid())?;
+        let raw = String::from_utf8(raw).map_err(|_| invalid())?;
+        let (micros, id) = raw.split_once(':').ok_or_else(invalid)?;
+        let nanos = micros
+            .parse::<i64>()
+            .ok()
+            .and_then(|micros| micros.checked_mul(1000))
+            .ok_or_else(invalid)?;
+        Ok(Self {
+            subscribed_at: Utc.timestamp_nanos(nanos),
+            id: id.parse().map_err(|_| invalid())?,
+        })
+    }
+}
+
+/// Browse subscribers, newest first, as an HTML page or as JSON (`format=json`).
+///
+/// Pages are keyset paginated: `next_cursor` is passed back as `after` to get the next page.
+#[tracing::instrument(
+    name = "Browse subscribers",
+    skip(parameters, pool, request),
+    fields(user_id=tracing::field::Empty)
+)]
+pub async fn browse_subscribers(
+    parameters: Query<BrowseParameters>,
+    pool: Data<PgPool>,
+    request: HttpRequest,
+) -> Result<HttpResponse, AdminError> {
+    let user_id = authenticate(request.headers(), &pool).await?;
+    tracing::Span::current().record("user_id", tracing::field::display(&user_id));
+
+    let parameters = parameters.into_inner();
+    let page = fetch_page(&pool, &parameters).await?;
+    match parameters.format {
+        ResponseFormat::Json => Ok(HttpResponse::Ok().json(page)),
+        ResponseFormat::Html => {
+            let lists = all_lists(&pool).await?;
+            Ok(HttpResponse::Ok()
+                .content_type(ContentType::html())
+                .body(render_page(&parameters, &page, &lists)))
+        }
+    }
+}
+
+async fn fetch_page(
+    pool: &PgPool,
+    parameters: &BrowseParameters,
+) -> Result<SubscriberPage, AdminError> {
+    let cursor = parameters
+        .after
+        .as_deref()
+        .map(Cursor::decode)
+        .transpose()
+        .map_err(AdminError::ValidationError)?;
+    let limit = parameters
+        .limit
+        .unwrap_or(DEFAULT_PAGE_SIZE)
+        .clamp(1, MAX_PAGE_SIZE);
+    let midnight = |d: NaiveDate| Utc.from_utc_datetime(&d.and_hms_opt(0, 0, 0).unwrap());
+    let from = parameters.from.map(midnight);
+    let until = parameters.to.map(|d| midnight(d) + Duration::days(1));
+    let pattern = parameters
+        .q
+        .as_deref()
+        .map(str::trim)
+        .filter(|q| !q.is_empty())
+        .map(|q| format!("%{}%", escape_like(q)));
+
+    let mut subscribers = sqlx::query_as!(
+        SubscriberSummary,
+        r#"
+        SELECT id, email, name, status, locale, delivery_frequency, subscribed_at
+        FROM subscriptions
+        WHERE ($1::text IS NULL OR status = $1)
+            AND ($2::timestamptz IS NULL OR subscribed_at >= $2)
+            AND ($3::timestamptz IS NULL OR subscribed_at < $3)
+            AND ($4::text IS NULL OR email ILIKE $4 OR name ILIKE $4)
+            AND ($5::timestamptz IS NULL OR (subscribed_at, id) < ($5, $6))
+            AND ($8::text IS NULL OR EXISTS (
+                SELECT 1 FROM subscription_lists JOIN lists ON lists.id = list_id
+                WHERE subscriber_id = subscriptions.id AND lists.slug = $8
+            ))
+        ORDER BY subscribed_at DESC, id DESC
+        LIMIT $7
+        "#,
+        parameters.status.as_ref().map(SubscriptionStatus::as_str),
+        from,
+        until,
+        pattern,
+        cursor.as_ref().map(|c| c.subscribed_at),
+        cursor.as_ref().map(|c| c.id),
+        // one more row than needed tells us whether there is a next page
+        limit + 1,
+        parameters.list,
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to fetch a page of subscribers")?;
+
+    let next_cursor = if subscribers.len() as i64 > limit {
+        subscribers.truncate(limit as usize);
+        subscribers.last().map(|s| {
+            Cursor {
+                subscribed_at: s.subscribed_at,
+                id: s.id,
+            }
+            .encode()
+        })
+    } else {
+        None
+    };
+    Ok(SubscriberPage {
+        subscribers,
+        next_cursor,
+    })
+}
+
+/// Escapes the wildcards of a `LIKE` pattern.
+fn escape_like(s: &str) -> String {
+    s.replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
+
+fn render_page(parameters: &BrowseParameters, page: &SubscriberPage, lists: &[List]) -> String {
+    let value = |v: Option<String>| html_escape(&v.unwrap_or_default());
+    let status_options: String = SubscriptionStatus::ALL
+        .iter()
+        .map(|s| {
+            let selected = if parameters.status == Some(*s) {
+                " selected"
+            } else {
+                ""
+            };
+            format!(r#"<option value="{s}"{selected}>{s}</option>"#)
+        })
+        .collect();
+    let list_options: String = lists
+        .iter()
+        .map(|l| {
+            let selected = if parameters.list.as_deref() == Some(l.slug.as_str()) {
+                " selected"
+            } else {
+                ""
+            };
+            format!(
+                r#"<option value="{}"{selected}>{}</option>"#,
+                html_escape(&l.slug),
+                html_escape(&l.name)
+            )
+        })
+        .collect();
+
+    let rows: String = page
+        .subscribers
+        .iter()
+        .map(|s| {
+            format!(
+                r#"<tr><td><a href="/admin/subscribers/{}">{}</a></td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>"#,
+                s.id,
+                html_escape(&s.email),
+                html_escape(&s.name),
+                html_escape(&s.status),
+                html_escape(&s.locale),
+                s.subscribed_at.to_rfc3339(),
+            )
+        })
+        .collect();
+
+    let next_link = match &page.next_cursor {
+        Some(cursor) => {
+            let next = BrowseParameters {
+                format: ResponseFormat::Html,
+                status: parameters.status,
+                from: parameters.from,
+                to: parameters.to,
+                list: parameters.list.clone(),
+                q: parameters.q.clone(),
+                limit: parameters.limit,
+                after: Some(cursor.clone()),
+            };
+            let query = serde_urlencoded::to_string(&next).unwrap_or_default();
+            format!(
+                r#"<p><a href="/admin/subscribers?{}">Next page</a></p>"#,
+                html_escape(&query)
+            )
+        }
+        None => String::new(),
+    };
+
+    format!(
+        r#"<!DOCTYPE html>
+<html lang="en">
+  <head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8" />
+    <title>Subscribers</title>
+  </head>
+  <body>
+    <form action="/admin/subscribers" method="get">
+      <input type="search" name="q" placeholder="Email or name" value="{q}" />
+      <select name="status"><option value="">any status</option>{status_options}</select>
+      <select name="list"><option value="">any list</option>{list_options}</select>
+      <label>From <input type="date" name="from" value="{from}" /></label>
+      <label>To <input type="date" name="to" value="{to}" /></label>
+      <button type="submit">Search</button>
+    </form>
+    <table>
+      <thead><tr><th>Email</th><th>Name</th><th>Status</th><th>Locale</th><th>Subscribed at</th></tr></thead>
+      <tbody>{rows}</tbody>
+    </table>
+    {next_link}
+  </body>
+</html>
+"#,
+        q = value(parameters.q.clone()),
+        from = value(parameters.from.map(|d| d.to_string())),
+        to = value(parameters.to.map(|d| d.to_string())),
+    )
+}
+
+#[derive(Deserialize)]
+pub struct DetailParameters {
+    #[serde(default)]
+    format: ResponseFormat,
+}
+
+#[derive(Serialize)]
+struct SubscriberDetails {
+    subscriber: SubscriberSummary,
+    /// The names of the lists the subscriber receives
+    lists: Vec<String>,
+    attribution: Attribution,
+    subscription_tokens: Vec<String>,
+    data_access_tokens: Vec<DataAccessTokenRecord>,
+    status_history: Vec<StatusChangeRecord>,
+    deliveries: Vec<DeliveryRecord>,
+}
+
+/// Everything about a single subscriber, as an HTML page or as JSON (`format=json`).
+#[tracing::instrument(
+    name = "Show subscriber details",
+    skip(parameters, pool, request),
+    fields(user_id=tracing::field::Empty)
+)]
+pub async fn subscriber_details(
+    subscriber_id: Path<Uuid>,
+    parameters: Query<DetailParameters>,
+    pool: Data<PgPool>,
+    request: HttpRequest,
+) -> Result<HttpResponse, AdminError> {
+    let user_id = authenticate(request.headers(), &pool).await?;
+    tracing::Span::current().record("user_id", tracing::field::display(&user_id));
+
+    let Some(details) = fetch_details(&pool, subscriber_id.into_inner()).await? else {
+        return Ok(HttpResponse::NotFound().finish());
+    };
+    match parameters.format {
+        ResponseFormat::Json => Ok(HttpResponse::Ok().json(details)),
+        ResponseFormat::Html => Ok(HttpResponse::Ok()
+            .content_type(ContentType::html())
+            .body(render_details(&details))),
+    }
+}
+
+async fn fetch_details(
+    pool: &PgPool,
+    subscriber_id: Uuid,
+) -> Result<Option<SubscriberDetails>, AdminError> {
+    let subscriber = sqlx::query_as!(
+        SubscriberSummary,
+        r#"
+        SELECT id, email, name, status, locale, delivery_frequency, subscribed_at
+        FROM subscriptions WHERE id = $1
+        "#,
+        subscriber_id
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to fetch the subscriber")?;
+    let Some(subscriber) = subscriber else {
+        return Ok(None);
+    };
+
+    let lists = sqlx::query!(
+        r#"
+        SELECT name FROM lists JOIN subscription_lists ON list_id = lists.id
+        WHERE subscriber_id = $1 ORDER BY name
+        "#,
+        subscriber_id
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to fetch the lists of the subscriber")?
+    .into_iter()
+    .map(|r| r.name)
+    .collect();
+
+    let attribution = sqlx::query_as!(
+        Attribution,
+        r#"
+        SELECT source, utm_source, utm_medium, utm_campaign, utm_term, utm_content, referrer, landing_url
+        FROM subscriptions WHERE id = $1
+        "#,
+        subscriber_id
+    )
+    .fetch_one(pool)
+    .await
+    .context("Failed to fetch the attribution of the subscriber")?;
+
+    let subscription_tokens = sqlx::query!(
+        r#"SELECT subscription_token FROM subscription_tokens WHERE subscriber_id = $1"#,
+        subscriber_id
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to fetch the subscription tokens")?
+    .into_iter()
+    .map(|r| r.subscription_token)
+    .collect();
+
+    let data_access_tokens = sqlx::query_as!(
+        DataAccessTokenRecord,
+        r#"
+        SELECT created_at, expires_at FROM data_access_tokens
+        WHERE subscriber_id = $1 ORDER BY created_at
+        "#,
+        subscriber_id
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to fetch the data access tokens")?;
+
+    let status_history = sqlx::query_as!(
+        StatusChangeRecord,
+        r#"
+        SELECT from_status, to_status, reason, changed_at FROM subscription_status_history
+        WHERE subscriber_id = $1 ORDER BY changed_at
+        "#,
+        subscriber_id
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to fetch the status history")?;
+
+    let deliveries = sqlx::query_as!(
+        DeliveryRecord,
+        r#"
+        SELECT d.issue_id, i.title, d.status, d.created_at, d.processed_at
+        FROM newsletter_deliveries d JOIN newsletter_issues i ON i.id = d.issue_id
+        WHERE d.subscriber_id = $1 ORDER BY d.created_at
+        "#,
+        subscriber_id
+    )
+    .fetch_all(pool)
+    .await
+    .context("Failed to fetch the deliveries")?;
+
+    Ok(Some(SubscriberDetails {
+        subscriber,
+        lists,
+        attribution,
+        subscription_tokens,
+        data_access_tokens,
+        status_history,
+        deliveries,
+    }))
+}
+
+fn render_details(details: &SubscriberDetails) -> String {
+    let s = &details.subscriber;
+    let tokens: String = details
+        .subscription_tokens
+        .iter()
+        .map(|t| format!("<li><code>{}</code></li>", html_escape(t)))
+        .collect();
+    let data_access_tokens: String = details
+        .data_access_tokens
+        .iter()
+        .map(|t| {
+            format!(
+                "<li>Issued {}, expires {}</li>",
+                t.created_at.to_rfc3339(),
+                t.expires_at.to_rfc3339()
+            )
+        })
+        .collect();
+    let history: String = details
+        .status_history
+        .iter()
+        .map(|h| {
+            format!(
+                "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
+                h.changed_at.to_rfc3339(),
+                html_escape(h.from_status.as_deref().unwrap_or("")),
+                html_escape(&h.to_status),
+                html_escape(&h.reason),
+            )
+        })
+        .collect();
+    let deliveries: String = details
+        .deliveries
+        .iter()
+        .map(|d| {
+            format!(
+                r#"<tr><td>{}</td><td><a href="/admin/newsletters/{}">{}</a></td><td>{}</td><td>{}</td></tr>"#,
+                d.created_at.to_rfc3339(),
+                d.issue_id,
+                html_escape(&d.title),
+                html_escape(&d.status),
+                d.processed_at.map(|t| t.to_rfc3339()).unwrap_or_default(),
+            )
+        })
+        .collect();
+    let attribution: String = details
+        .attribution
+        .fields()
+        .into_iter()
+        .map(|(name, value)| format!("<dt>{name}</dt><dd>{}</dd>", html_escape(value)))
+        .collect();
+    // only offer the transitions the status rules allow
+    let current = SubscriptionStatus::try_from(s.status.clone()).ok();
+    let actions: String = [
+        ("confirm", "Confirm", SubscriptionStatus::Confirmed),
+        (
+            "unsubscribe",
+            "Unsubscribe",
+            SubscriptionStatus::Unsubscribed,
+        ),
+        ("delete", "Delete", SubscriptionStatus::Deleted),
+    ]
+    .into_iter()
+    .filter(|(_, _, next)| current.is_some_and(|c| c.can_transition_to(*next)))
+    .map(|(action, label, _)| {
+        format!(
+            r#"<form method="post" action="/admin/subscribers/{id}/{action}">
+      <input type="text" name="note" placeholder="Note" />
+      <button type="submit">{label}</button>
+    </form>
+    "#,
+            id = s.id,
+        )
+    })
+    .collect();
+
+    format!(
+        r#"<!DOCTYPE html>
+<html lang="en">
+  <head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8" />
+    <title>{email}</title>
+  </head>
+  <body>
+    <p><a href="/admin/subscribers">All subscribers</a></p>
+    <h1>{email}</h1>
+    <dl>
+      <dt>Name</dt><dd>{name}</dd>
+      <dt>Status</dt><dd>{status}</dd>
+      <dt>Locale</dt><dd>{locale}</dd>
+      <dt>Lists</dt><dd>{lists}</dd>
+      <dt>Frequency</dt><dd>{frequency}</dd>
+      <dt>Subscribed at</dt><dd>{subscribed_at}</dd>
+    </dl>
+    <h2>Attribution</h2>
+    <dl>{attribution}</dl>
+    <h2>Actions</h2>
+    {actions}<h2>Confirmation tokens</h2>
+    <ul>{tokens}</ul>
+    <h2>Data access links</h2>
+    <ul>{data_access_tokens}</ul>
+    <h2>Status history</h2>
+    <table>
+      <thead><tr><th>Changed at</th><th>From</th><th>To</th><th>Reason</th></tr></thead>
+      <tbody>{history}</tbody>
+    </table>
+    <h2>Deliveries</h2>
+    <table>
+      <thead><tr><th>Queued at</th><th>Issue</th><th>Status</th><th>Processed at</th></tr></thead>
+      <tbody>{deliveries}</tbody>
+    </table>
+  </body>
+</html>
+"#,
+        email = html_escape(&s.email),
+        name = html_escape(&s.name),
+        status = html_escape(&s.status),
+        locale = html_escape(&s.locale),
+        lists = html_escape(&details.lists.join(", ")),
+        frequency = html_escape(&s.delivery_frequency),
+        subscribed_at = s.subscribed_at.to_rfc3339(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{escape_like, Cursor};
+    use chrono::{TimeZone, Utc};
+    use claims::assert_err;
+
+    #[test]
+    fn cursors_round_trip() {
+        let cursor = Cursor {
+            subscribed_at: Utc.timestamp_nanos(1_700_000_000_123_456_000),
+            id: uuid::Uuid::new_v4(),
+        };
+        let decoded = Cursor::decode(&cursor.encode()).unwrap();
+        assert_eq!(decoded.subscribed_at, cursor.subscribed_at);
+        assert_eq!(decoded.id, cursor.id);
+        assert_err!(Cursor::decode("not-a-cursor"));
+
+        let out_of_range = format!("{}:{}", i64::MAX, cursor.id);
+        assert_err!(Cursor::decode(&base64::encode_config(
+            out_of_range,
+            base64::URL_SAFE_NO_PAD
+        )));
+    }
+
+    #[test]
+    fn like_wildcards_are_escaped() {
+        assert_eq!(escape_like(r"50%_off\"), r"50\%\_off\\");
+    }
+}
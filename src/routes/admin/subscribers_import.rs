@@ -0,0 +1,85 @@
+use actix_web::{
+    web::{Bytes, Data, Payload, Query},
+    HttpRequest, HttpResponse,
+};
+use futures::{channel::mpsc, SinkExt, StreamExt, TryStreamExt};
+use serde::Deserialize;
+use sqlx::PgPool;
+
+use crate::{
+    email_client::EmailClient,
+    i18n::Catalogs,
+    routes::auth::authenticate,
+    signup_policy::SignupPolicy,
+    startup::ApplicationBaseUrl,
+    subscriber_import::{import_from_csv, ImportError, ImportOptions},
+};
+
+use super::{check_origin, AdminError};
+
+#[derive(Deserialize)]
+pub struct ImportParameters {
+    /// Skip double opt-in and store the imported subscribers as confirmed.
+    #[serde(default)]
+    confirmed: bool,
+}
+
+/// Bulk import subscribers from a CSV file sent as the request body.
+///
+/// The body is streamed through the importer, so the file is never held in memory as a whole.
+#[tracing::instrument(
+    name = "Import subscribers",
+    skip(parameters, payload, pool, email_client, signup_policy, catalogs, base_url, request),
+    fields(user_id=tracing::field::Empty, confirmed=%parameters.confirmed)
+)]
+#[allow(clippy::too_many_arguments)]
+pub async fn import_subscribers(
+    parameters: Query<ImportParameters>,
+    payload: Payload,
+    pool: Data<PgPool>,
+    email_client: Data<EmailClient>,
+    signup_policy: Data<SignupPolicy>,
+    catalogs: Data<Catalogs>,
+    base_url: Data<ApplicationBaseUrl>,
+    request: HttpRequest,
+) -> Result<HttpResponse, AdminError> {
+    check_origin(&request)?;
+    let user_id = authenticate(request.headers(), &pool).await?;
+    tracing::Span::current().record("user_id", tracing::field::display(&user_id));
+
+    // The request payload is not `Send`, so its chunks are forwarded through a bounded
+    // channel to the importer. Both halves are driven by this task and the bound provides
+    // backpressure on the upload.
+    let (sender, receiver) = mpsc::channel::<std::io::Result<Bytes>>(16);
+    let forward = forward_payload(payload, sender);
+    let options = ImportOptions {
+        confirmed: parameters.confirmed,
+    };
+    let import = import_from_csv(
+        receiver.into_async_read(),
+        &pool,
+        &email_client,
+        &base_url.0,
+        &signup_policy,
+        &catalogs,
+        options,
+    );
+
+    let (_, report) = futures::join!(forward, import);
+    let report = report.map_err(|e| match e {
+        ImportError::InvalidCsv(message) => AdminError::ValidationError(message),
+        ImportError::UnexpectedError(e) => AdminError::UnexpectedError(e),
+    })?;
+
+    Ok(HttpResponse::Ok().json(report))
+}
+
+async fn forward_payload(mut payload: Payload, mut sender: mpsc::Sender<std::io::Result<Bytes>>) {
+    while let Some(chunk) = payload.next().await {
+        let chunk = chunk.map_err(|e| std::io::Error::other(e.to_string()));
+        // the importer has stopped reading, e.g. because the CSV header was invalid
+        if sender.send(chunk).await.is_err() {
+            break;
+        }
+    }
+}
@@ -52,6 +52,13 @@ pub fn basic_authentication(headers: &HeaderMap) -> Result<Credentials, anyhow::
     })
 }
 
+/// Extracts the basic auth credentials from the request headers and validates them,
+/// returning the id of the authenticated user.
+pub async fn authenticate(headers: &HeaderMap, pool: &PgPool) -> Result<Uuid, AuthError> {
+    let credentials = basic_authentication(headers).map_err(AuthError::InvalidCredentials)?;
+    validate_credentials(&credentials, pool).await
+}
+
 pub async fn validate_credentials(creds: &Credentials, pool: &PgPool) -> Result<Uuid, AuthError> {
     let row: Option<_> = sqlx::query!(
         r#"SELECT user_id, password_hash FROM users WHERE username = $1"#,
@@ -64,7 +71,7 @@ pub async fn validate_credentials(creds: &Credentials, pool: &PgPool) -> Result<
 
     let (expected_hash, user_id) = match row {
         Some(row) => (row.password_hash, row.user_id),
-        None => return Err(AuthError::InvalidCredentials(anyhow!("Unknown username")).into()),
+        None => return Err(AuthError::InvalidCredentials(anyhow!("Unknown username"))),
     };
 
     verify_password(creds.password.expose_secret().to_string(), expected_hash).await?;
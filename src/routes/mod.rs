@@ -1,3 +1,4 @@
+mod admin;
 pub mod auth;
 mod health_check;
 mod home;
@@ -5,10 +6,33 @@ mod login;
 mod newsletters;
 mod subscriptions;
 mod subscriptions_confirm;
+mod subscriptions_data;
+mod subscriptions_email;
+mod subscriptions_form;
+mod subscriptions_preferences;
 
+pub use admin::{
+    browse_subscribers, cancel_newsletter_issue, change_subscriber_status, check_newsletter_text,
+    create_newsletter_draft, diff_newsletter_issue_revisions, erase_subscriber_data,
+    export_subscriber_data, export_subscribers, import_subscribers, list_newsletter_issues,
+    newsletter_issue, newsletter_issue_deliveries, newsletter_issue_revision,
+    newsletter_issue_revisions, preview_newsletter_issue, replay_newsletter_issue_deliveries,
+    reschedule_newsletter_issue, restore_newsletter_issue_revision, send_newsletter_draft,
+    send_test_newsletter_issue, signup_report, subscriber_details, update_newsletter_draft,
+};
 pub use health_check::health_check;
 pub use home::home;
 pub use login::{get as login_get, post as login_post};
 pub use newsletters::publish_newsletter;
-pub use subscriptions::{subscribe, FormData};
+pub use subscriptions::{
+    generate_subscription_token, send_confirmation_email, subscribe, FormData,
+};
 pub use subscriptions_confirm::confirm;
+pub use subscriptions_data::{
+    data_access_page, erase_own_data, export_own_data, request_data_access,
+};
+pub use subscriptions_email::{confirm_email_change, request_email_change};
+pub use subscriptions_form::subscribe_form;
+pub use subscriptions_preferences::{
+    preferences_page, unsubscribe_from_preferences, update_preferences,
+};
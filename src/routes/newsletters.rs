@@ -1,41 +1,64 @@
-use crate::{domain::SubscriberEmail, email_client::EmailClient, utils};
+use crate::{
+    authoring::{Content, EmailLayout},
+    configuration::IdempotencySettings,
+    domain::IssueStatus,
+    idempotency::{
+        request_hash, save_response, try_processing, IdempotencyKey, NextAction,
+        IDEMPOTENCY_KEY_HEADER,
+    },
+    merge_tags,
+    newsletter_issues::{create_issue, send_issue, NewIssue},
+    preferences::list_id_by_slug,
+    utils,
+};
 use actix_web::{
     http::header,
     web::{Data, Json},
     HttpRequest, HttpResponse, ResponseError,
 };
-use anyhow::anyhow;
-use futures::{stream::FuturesUnordered, StreamExt};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
 use sqlx::PgPool;
 
 use super::auth::{basic_authentication, validate_credentials, AuthError};
 
-#[derive(serde::Deserialize)]
+#[derive(serde::Deserialize, serde::Serialize)]
 pub struct BodyData {
     title: String,
     content: Content,
+    /// The slug of the list to send the issue to, every confirmed subscriber if missing
+    #[serde(default)]
+    list: Option<String>,
+    /// Opts in to sending the issue on creation, rather than leaving it as a draft
+    #[serde(default)]
+    send: bool,
+    /// When to start sending the issue, right away if missing; requires `send`
+    #[serde(default)]
+    send_at: Option<DateTime<Utc>>,
 }
 
-#[derive(serde::Deserialize)]
-pub struct Content {
-    html: String,
-    text: String,
-}
-
+/// Stores the issue as a draft and returns `201 Created`; it is sent with
+/// `POST /admin/newsletters/{issue_id}/send` once reviewed.
+///
+/// With `send` set, the issue is queued for every confirmed subscriber instead, and
+/// `202 Accepted` returned right away; the delivery worker sends the emails in the background.
+/// With a `send_at` time as well, the issue is scheduled, and each subscriber gets it once
+/// that time of day comes in their time zone.
 #[tracing::instrument(
-    name = "Publish Newsletter", 
-    skip(body, pool, email_client),
-    fields(username=tracing::field::Empty, user_id=tracing::field::Empty))
+    name = "Publish Newsletter",
+    skip(body, pool, idempotency, layout),
+    fields(username=tracing::field::Empty, user_id=tracing::field::Empty, issue_id=tracing::field::Empty))
 ]
 pub async fn publish_newsletter(
     body: Json<BodyData>,
     pool: Data<PgPool>,
-    email_client: Data<EmailClient>,
+    idempotency: Data<IdempotencySettings>,
+    layout: Data<EmailLayout>,
     request: HttpRequest,
 ) -> Result<HttpResponse, PublishError> {
     // extract credentials
     let credentials = basic_authentication(request.headers()).map_err(PublishError::AuthError)?;
-    tracing::Span::current().record("username", &tracing::field::display(&credentials.username));
+    tracing::Span::current().record("username", tracing::field::display(&credentials.username));
 
     // validate credentials
     let user_id = validate_credentials(&credentials, &pool)
@@ -44,112 +67,126 @@ pub async fn publish_newsletter(
             AuthError::InvalidCredentials(_) => PublishError::AuthError(e.into()),
             AuthError::UnexpectedError(_) => PublishError::UnexpectedError(e.into()),
         })?;
-    tracing::Span::current().record("user_id", &tracing::field::display(&user_id));
-
-    // process subscribers
-    let subscribers = get_confirmed_subscribers(&pool).await?;
-    process_all_subscribers(subscribers, Data::new(body.0), email_client).await;
-
-    Ok(HttpResponse::Ok().finish())
-}
-
-/// Takes the subscribers, create and process the subscribers in chunks.
-async fn process_all_subscribers(
-    subscribers: Vec<anyhow::Result<ConfirmedSubscriber>>,
-    body: Data<BodyData>,
-    email_client: Data<EmailClient>,
-) {
-    let mut iter = subscribers.into_iter();
-    let mut num_processed = 0;
-    let num_subscribers = iter.len();
-    let chunk_size = 20;
-
-    while num_processed <= num_subscribers {
-        let chunk = iter.by_ref().take(chunk_size).collect::<Vec<_>>();
-
-        // process the chunk in parallel
-        if let Err(e) = tokio::spawn(process_subscriber_chunk(
-            chunk,
-            body.clone(),
-            email_client.clone(),
-        ))
-        .await
-        {
-            tracing::warn!("Failed to execute newsletter sending task: {e}");
-        };
-
-        // timeout to not overwhelm the email server
-        tokio::time::sleep(std::time::Duration::from_millis(500)).await;
-
-        num_processed += chunk_size;
-    }
-}
-
-/// Sends newsletters to each subscriber in parallel
-async fn process_subscriber_chunk(
-    chunk: Vec<anyhow::Result<ConfirmedSubscriber>>,
-    body: Data<BodyData>,
-    email_client: Data<EmailClient>,
-) {
-    let mut futures = FuturesUnordered::new();
-    for subscriber in chunk {
-        futures.push(async {
-            match subscriber {
-                Ok(subscriber) => {
-                    email_client
-                        .send_email(
-                            &subscriber.email,
-                            &body.title,
-                            &body.content.html,
-                            &body.content.text,
-                        )
-                        .await
-                }
-                Err(error) => {
-                    tracing::warn!(
-                        error.cause_chain = ?error,
-                        "Skipping a confirmed subscriber. \
-                    Their stored contact details are invalid",
-                    );
-                    Ok(())
-                }
+    tracing::Span::current().record("user_id", tracing::field::display(&user_id));
+
+    // a retried request gets the response to the first one instead of storing the issue again
+    let idempotency_key = idempotency_key(&request, idempotency.required)?;
+    let mut transaction = match &idempotency_key {
+        Some(key) => {
+            let ttl = chrono::Duration::hours(idempotency.ttl_hours);
+            let request_hash = request_hash(&*body)?;
+            match try_processing(&pool, key, user_id, &request_hash, ttl).await? {
+                NextAction::StartProcessing(transaction) => transaction,
+                NextAction::ReturnSavedResponse(saved_response) => return Ok(saved_response),
+                NextAction::RejectReusedKey => return Err(PublishError::ReusedIdempotencyKey),
             }
-        });
+        }
+        None => pool
+            .begin()
+            .await
+            .context("Failed to acquire a Postgres connection from the pool")?,
+    };
+
+    if body.send_at.is_some() && !body.send {
+        return Err(PublishError::ValidationError(
+            "send_at requires send to be true".into(),
+        ));
     }
-
-    while let Some(result) = futures.next().await {
-        if result.is_err() {
-            tracing::warn!("Failed to send newsletter issue");
+    if body.send_at.is_some_and(|send_at| send_at <= Utc::now()) {
+        return Err(PublishError::ValidationError(
+            "send_at must be in the future".into(),
+        ));
+    }
+    let bodies = body
+        .content
+        .bodies(&layout)
+        .map_err(PublishError::ValidationError)?;
+    merge_tags::check(&body.title, &bodies.html, &bodies.text)
+        .map_err(PublishError::ValidationError)?;
+    let list_id = match &body.list {
+        Some(slug) => Some(
+            list_id_by_slug(&pool, slug)
+                .await?
+                .ok_or_else(|| PublishError::ValidationError(format!("{slug} is not a list")))?,
+        ),
+        None => None,
+    };
+    // the issue and its deliveries are stored together, so an issue is never half queued
+    let issue_id = create_issue(
+        &mut transaction,
+        user_id,
+        &NewIssue {
+            title: &body.title,
+            html_content: &bodies.html,
+            text_content: &bodies.text,
+            markdown_content: bodies.markdown.as_deref(),
+            list_id,
+        },
+    )
+    .await?;
+    tracing::Span::current().record("issue_id", tracing::field::display(&issue_id));
+    let response = if body.send {
+        let status = send_issue(&mut transaction, issue_id, body.send_at).await?;
+        HttpResponse::Accepted().json(serde_json::json!({
+            "issue_id": issue_id,
+            "status": status,
+            "send_at": body.send_at,
+        }))
+    } else {
+        HttpResponse::Created().json(serde_json::json!({
+            "issue_id": issue_id,
+            "status": IssueStatus::Draft,
+            "send_at": null,
+        }))
+    };
+    match idempotency_key {
+        Some(key) => Ok(save_response(transaction, &key, user_id, response).await?),
+        None => {
+            transaction
+                .commit()
+                .await
+                .context("Failed to commit SQL transaction to publish a newsletter issue")?;
+            Ok(response)
         }
     }
 }
 
-struct ConfirmedSubscriber {
-    email: SubscriberEmail,
-}
-
-#[tracing::instrument(name = "Get confirmed subscribers", skip(pool))]
-async fn get_confirmed_subscribers(
-    pool: &PgPool,
-) -> anyhow::Result<Vec<anyhow::Result<ConfirmedSubscriber>>> {
-    let out = sqlx::query!(r#"SELECT email FROM subscriptions WHERE status = 'confirmed'"#,)
-        .fetch_all(pool)
-        .await?
-        .into_iter()
-        .map(|r| match SubscriberEmail::parse(r.email) {
-            Ok(email) => Ok(ConfirmedSubscriber { email }),
-            Err(error) => Err(anyhow!(error)),
-        })
-        .collect();
-
-    Ok(out)
+/// The `Idempotency-Key` header of the request, which may be required by the configuration.
+fn idempotency_key(
+    request: &HttpRequest,
+    required: bool,
+) -> Result<Option<IdempotencyKey>, PublishError> {
+    let Some(value) = request.headers().get(IDEMPOTENCY_KEY_HEADER) else {
+        return if required {
+            Err(PublishError::ValidationError(format!(
+                "The {IDEMPOTENCY_KEY_HEADER} header is required"
+            )))
+        } else {
+            Ok(None)
+        };
+    };
+    let value = value.to_str().map_err(|_| {
+        PublishError::ValidationError(format!(
+            "The {IDEMPOTENCY_KEY_HEADER} header must be valid ASCII"
+        ))
+    })?;
+    IdempotencyKey::try_from(value.to_string())
+        .map(Some)
+        .map_err(PublishError::ValidationError)
 }
 
+#[allow(clippy::enum_variant_names)]
 #[derive(thiserror::Error)]
 pub enum PublishError {
     #[error("Authentication failed")]
     AuthError(#[source] anyhow::Error),
 
+    #[error("{0}")]
+    ValidationError(String),
+
+    #[error("The {IDEMPOTENCY_KEY_HEADER} was already used for a different request")]
+    ReusedIdempotencyKey,
+
     #[error(transparent)]
     UnexpectedError(#[from] anyhow::Error),
 }
@@ -166,6 +203,10 @@ impl ResponseError for PublishError {
 
         match self {
             Self::UnexpectedError(_) => HttpResponse::new(StatusCode::INTERNAL_SERVER_ERROR),
+            Self::ValidationError(message) => HttpResponse::BadRequest().body(message.clone()),
+            Self::ReusedIdempotencyKey => {
+                HttpResponse::UnprocessableEntity().body(self.to_string())
+            }
             Self::AuthError(_) => {
                 let mut response = HttpResponse::new(StatusCode::UNAUTHORIZED);
                 let header_value = HeaderValue::from_str(r#"Basic realm="publish""#).unwrap();
@@ -1,5 +1,6 @@
+use actix_web::http::header::{ACCEPT_LANGUAGE, REFERER};
 use actix_web::web::{Data, Form};
-use actix_web::HttpResponse;
+use actix_web::{HttpMessage, HttpRequest, HttpResponse};
 use anyhow::Context;
 use chrono::Utc;
 use rand::distributions::Alphanumeric;
@@ -8,7 +9,15 @@ use serde::Deserialize;
 use sqlx::{PgPool, Postgres, Transaction};
 use uuid::Uuid;
 
+use crate::attribution::Attribution;
+use crate::bot_protection::BotProtection;
+use crate::domain::SubscriptionStatus;
+use crate::i18n::{self, Catalogs, EmailMessages};
+use crate::preferences::join_all_lists;
+use crate::rate_limit::ClientIp;
+use crate::signup_policy::SignupPolicy;
 use crate::startup::ApplicationBaseUrl;
+use crate::subscription_lifecycle::{record_initial_status, transition_status};
 use crate::utils;
 use crate::{domain::NewSubscriber, email_client::EmailClient};
 
@@ -16,6 +25,18 @@ use crate::{domain::NewSubscriber, email_client::EmailClient};
 pub struct FormData {
     pub email: String,
     pub name: String,
+    /// Honeypot, hidden from humans by the subscribe form: only bots fill it in
+    #[serde(default)]
+    pub website: String,
+    /// The signed timestamp and nonce issued when the subscribe form was rendered
+    pub form_token: Option<String>,
+    /// The response to the challenge, if one is configured
+    pub challenge_response: Option<String>,
+    /// The locale to email the subscriber in, overriding `Accept-Language`
+    pub locale: Option<String>,
+    /// Where the subscriber came from
+    #[serde(flatten)]
+    pub attribution: Attribution,
 }
 
 /// Subscribe an email to the newsletter.
@@ -24,22 +45,103 @@ pub struct FormData {
 /// This handler get called only if content type is *x-www-form-urlencoded*
 /// and content of the request could be deserialized to a `FormData` struct.
 #[tracing::instrument(
-    name = "Adding a new subscriber", skip(form, pool, email_client, base_url),
+    name = "Adding a new subscriber",
+    skip(form, pool, email_client, signup_policy, base_url, bot_protection, catalogs, request),
     fields(subscriber_email = %form.email, subscriber_name= %form.name)
 )]
+#[allow(clippy::too_many_arguments)]
 pub async fn subscribe(
     form: Form<FormData>,
     pool: Data<PgPool>,
     email_client: Data<EmailClient>,
+    signup_policy: Data<SignupPolicy>,
     base_url: Data<ApplicationBaseUrl>,
+    bot_protection: Data<BotProtection>,
+    catalogs: Data<Catalogs>,
+    request: HttpRequest,
 ) -> Result<HttpResponse, SubscribeError> {
-    let new_subscriber = form.0.try_into().map_err(SubscribeError::ValidationError)?;
+    if !form.website.is_empty() {
+        // don't let the bot know it was caught
+        tracing::warn!("The honeypot field was filled in, ignoring the submission");
+        return Ok(HttpResponse::Ok().finish());
+    }
+    let form_token = bot_protection
+        .check_form_token(form.form_token.as_deref())
+        .map_err(SubscribeError::ValidationError)?;
+
+    let remote_ip = request
+        .extensions()
+        .get::<ClientIp>()
+        .map(|ip| ip.0.to_string());
+    let solved = bot_protection
+        .verify_challenge(form.challenge_response.as_deref(), remote_ip.as_deref())
+        .await
+        .context("Failed to verify the challenge response")?;
+    if !solved {
+        return Err(SubscribeError::ValidationError(
+            "The challenge was not solved, please try again".into(),
+        ));
+    }
+
+    let accept_language = request
+        .headers()
+        .get(ACCEPT_LANGUAGE)
+        .and_then(|h| h.to_str().ok());
+    let locale = catalogs.negotiate(form.locale.as_deref(), accept_language);
+
+    // forms embedded in other sites post straight here: the page they are on is the landing page
+    let mut attribution = form.attribution.clone();
+    if attribution.landing_url.is_none() {
+        attribution.landing_url = request
+            .headers()
+            .get(REFERER)
+            .and_then(|h| h.to_str().ok())
+            .map(str::to_string);
+    }
+    let attribution = attribution.sanitized();
+
+    let new_subscriber: NewSubscriber =
+        form.0.try_into().map_err(SubscribeError::ValidationError)?;
+    signup_policy
+        .check(&new_subscriber)
+        .map_err(SubscribeError::ValidationError)?;
     let mut transaction = pool
         .begin()
         .await
         .context("Failed to acquire a Postgres connection from the pool")?;
 
-    let subscriber_id = insert_subscriber(&mut transaction, &new_subscriber).await?;
+    if let Some(form_token) = form_token {
+        let spent = form_token
+            .spend(&mut transaction)
+            .await
+            .context("Failed to spend the form token")?;
+        if !spent {
+            return Err(SubscribeError::ValidationError(
+                "The form was already submitted, please reload the page and try again".into(),
+            ));
+        }
+    }
+
+    let subscriber_id = match existing_subscriber(&mut transaction, &new_subscriber).await? {
+        None => insert_subscriber(&mut transaction, &new_subscriber, &locale, &attribution).await?,
+        // the first confirmation email may have been lost: send a new one
+        Some((subscriber_id, SubscriptionStatus::PendingConfirmation)) => subscriber_id,
+        // coming back after leaving takes a new confirmation
+        Some((subscriber_id, status))
+            if status.can_transition_to(SubscriptionStatus::PendingConfirmation) =>
+        {
+            transition_status(
+                &mut transaction,
+                subscriber_id,
+                SubscriptionStatus::PendingConfirmation,
+                "Subscribed again via the subscribe form",
+            )
+            .await
+            .context("Failed to move a returning subscriber back to pending confirmation")?;
+            subscriber_id
+        }
+        Some(_) => return Err(SubscribeError::AlreadySubscribed),
+    };
 
     let sub_token = generate_subscription_token();
     store_token(&mut transaction, subscriber_id, &sub_token).await?;
@@ -49,56 +151,118 @@ pub async fn subscribe(
         .await
         .context("Failed to commit SQL transaction to store a new subscriber")?;
 
-    send_confirmation_email(&email_client, new_subscriber, &base_url.0, &sub_token).await?;
+    send_confirmation_email(
+        &email_client,
+        &new_subscriber,
+        &base_url.0,
+        &sub_token,
+        &catalogs.get(&locale).confirmation_email,
+    )
+    .await?;
 
     Ok(HttpResponse::Ok().finish())
 }
 
-#[tracing::instrument(name = "Saving new subscriber details in the database", skip(s, tx))]
+/// The subscriber with the same address, whatever its case, and their status.
+#[tracing::instrument(name = "Look up an existing subscriber", skip(tx, s))]
+async fn existing_subscriber(
+    tx: &mut Transaction<'_, Postgres>,
+    s: &NewSubscriber,
+) -> Result<Option<(Uuid, SubscriptionStatus)>, SubscribeError> {
+    let row = sqlx::query!(
+        r#"
+        SELECT id, status FROM subscriptions
+        WHERE lower(email_normalized) = lower($1)
+        FOR UPDATE
+        "#,
+        s.email.normalized(),
+    )
+    .fetch_optional(&mut *tx)
+    .await
+    .context("Failed to look up an existing subscriber")?;
+    row.map(|row| {
+        let status = SubscriptionStatus::try_from(row.status).map_err(|e| anyhow::anyhow!(e))?;
+        Ok((row.id, status))
+    })
+    .transpose()
+}
+
+#[tracing::instrument(
+    name = "Saving new subscriber details in the database",
+    skip(s, tx, attribution)
+)]
 async fn insert_subscriber(
     tx: &mut Transaction<'_, Postgres>,
     s: &NewSubscriber,
+    locale: &str,
+    attribution: &Attribution,
 ) -> Result<Uuid, SubscribeError> {
     let subscriber_id = Uuid::new_v4();
+    let status = SubscriptionStatus::PendingConfirmation;
 
-    sqlx::query!(
-        r#"INSERT INTO subscriptions (id, email, name, subscribed_at, status) VALUES ($1, $2, $3, $4, 'pending_confirmation')"#,
+    let inserted = sqlx::query!(
+        r#"
+        INSERT INTO subscriptions (
+            id, email, email_normalized, name, subscribed_at, status, status_changed_at, locale,
+            source, utm_source, utm_medium, utm_campaign, utm_term, utm_content, referrer, landing_url
+        )
+        VALUES ($1, $2, $3, $4, $5, $6, $5, $7, $8, $9, $10, $11, $12, $13, $14, $15)
+        "#,
         subscriber_id,
         s.email.as_ref(),
+        s.email.normalized(),
         s.name.as_ref(),
         Utc::now(),
+        status.as_str(),
+        locale,
+        attribution.source,
+        attribution.utm_source,
+        attribution.utm_medium,
+        attribution.utm_campaign,
+        attribution.utm_term,
+        attribution.utm_content,
+        attribution.referrer,
+        attribution.landing_url,
     )
-    .execute(tx)
-    .await
-    .context("Failed to store the confirmation token for a new subscriber")?;
+    .execute(&mut *tx)
+    .await;
+    match inserted {
+        // the address was taken by a concurrent signup since we looked it up
+        Err(sqlx::Error::Database(e)) if e.code().as_deref() == Some(utils::UNIQUE_VIOLATION) => {
+            return Err(SubscribeError::AlreadySubscribed)
+        }
+        inserted => inserted.context("Failed to store a new subscriber")?,
+    };
+    record_initial_status(
+        tx,
+        &[subscriber_id],
+        status,
+        "Subscribed via the subscribe form",
+    )
+    .await?;
+    join_all_lists(tx, &[subscriber_id]).await?;
 
     Ok(subscriber_id)
 }
 
 #[tracing::instrument(
     name = "Send a confirmation email to a new subscriber",
-    skip(client, subscriber, base_url, token)
+    skip(client, subscriber, base_url, token, messages)
 )]
-async fn send_confirmation_email(
+pub async fn send_confirmation_email(
     client: &EmailClient,
-    subscriber: NewSubscriber,
+    subscriber: &NewSubscriber,
     base_url: &str,
     token: &str,
+    messages: &EmailMessages,
 ) -> Result<(), SubscribeError> {
     let confirmation_link = format!("{base_url}/subscriptions/confirm?subscription_token={token}");
     client
         .send_email(
             &subscriber.email,
-            "Welcome!",
-            &format!(
-                "Welcome to our newsletter!<br />\
-                Click <a href=\"{}\">here</a> to confirm your subscription.",
-                confirmation_link
-            ),
-            &format!(
-                "Welcome to our newsletter!\nVisit {} to confirm your subscription.",
-                confirmation_link
-            ),
+            &messages.subject,
+            &i18n::with_link(&messages.html, &confirmation_link),
+            &i18n::with_link(&messages.text, &confirmation_link),
         )
         .await
         .context("Failed to send a confirmation email")?;
@@ -110,6 +274,9 @@ pub enum SubscribeError {
     #[error("{0}")]
     ValidationError(String),
 
+    #[error("This email address is already subscribed")]
+    AlreadySubscribed,
+
     #[error(transparent)]
     UnexpectedError(#[from] anyhow::Error),
 }
@@ -124,6 +291,7 @@ impl actix_web::ResponseError for SubscribeError {
     fn status_code(&self) -> reqwest::StatusCode {
         match self {
             Self::ValidationError(_) => reqwest::StatusCode::BAD_REQUEST,
+            Self::AlreadySubscribed => reqwest::StatusCode::CONFLICT,
             Self::UnexpectedError(_) => reqwest::StatusCode::INTERNAL_SERVER_ERROR,
         }
     }
@@ -150,7 +318,7 @@ async fn store_token(
 }
 
 /// Generate a random 25-characters-long case-sensitive subscription token.
-fn generate_subscription_token() -> String {
+pub fn generate_subscription_token() -> String {
     let mut rng = thread_rng();
     std::iter::repeat_with(|| rng.sample(Alphanumeric))
         .map(char::from)
@@ -1,40 +1,112 @@
 use actix_web::{
+    http::{
+        header::{ContentType, ACCEPT_LANGUAGE},
+        StatusCode,
+    },
     web::{Data, Query},
-    HttpResponse,
+    HttpRequest, HttpResponse,
 };
+use anyhow::Context;
 use serde::Deserialize;
 use sqlx::PgPool;
 use uuid::Uuid;
 
+use crate::domain::SubscriptionStatus;
+use crate::i18n::{Catalogs, ConfirmationPageMessages};
+use crate::subscription_lifecycle::{transition_status, TransitionError};
+
 #[derive(Deserialize)]
 pub struct Parameters {
     subscription_token: String,
 }
 
-#[tracing::instrument(name = "Confirm a pending subscriber", skip(parameters, pool))]
-pub async fn confirm(parameters: Query<Parameters>, pool: Data<PgPool>) -> HttpResponse {
-    let Ok(id) = get_subscriber_id_from_token(&pool, &parameters.subscription_token).await else {
-        return HttpResponse::InternalServerError().finish();
+#[tracing::instrument(
+    name = "Confirm a pending subscriber",
+    skip(parameters, pool, catalogs, request)
+)]
+pub async fn confirm(
+    parameters: Query<Parameters>,
+    pool: Data<PgPool>,
+    catalogs: Data<Catalogs>,
+    request: HttpRequest,
+) -> HttpResponse {
+    let accept_language = request
+        .headers()
+        .get(ACCEPT_LANGUAGE)
+        .and_then(|h| h.to_str().ok());
+    let fallback_locale = catalogs.negotiate(None, accept_language);
+
+    let subscriber = get_subscriber_from_token(&pool, &parameters.subscription_token).await;
+    let (outcome, locale) = match subscriber {
+        Err(_) => (Outcome::Error, fallback_locale),
+        Ok(None) => (Outcome::InvalidLink, fallback_locale),
+        Ok(Some((sub_id, locale))) => match confirm_subscriber(&pool, sub_id).await {
+            Ok(()) => (Outcome::Confirmed, locale),
+            // e.g. the subscriber unsubscribed since the link was sent
+            Err(TransitionError::InvalidTransition(_)) => (Outcome::CannotConfirm, locale),
+            Err(TransitionError::NotFound) => (Outcome::InvalidLink, locale),
+            Err(TransitionError::UnexpectedError(_)) => (Outcome::Error, locale),
+        },
     };
 
-    match id {
-        None => HttpResponse::Unauthorized().finish(),
-        Some(sub_id) => {
-            if confirm_subscriber(&pool, sub_id).await.is_err() {
-                return HttpResponse::InternalServerError().finish();
-            };
-            HttpResponse::Ok().finish()
+    let messages = &catalogs.get(&locale).confirmation_page;
+    HttpResponse::build(outcome.status_code())
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="{locale}">
+  <head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8" />
+    <title>{}</title>
+  </head>
+  <body>
+    <p>{}</p>
+  </body>
+</html>
+"#,
+            messages.title,
+            outcome.message(messages)
+        ))
+}
+
+enum Outcome {
+    Confirmed,
+    InvalidLink,
+    CannotConfirm,
+    Error,
+}
+
+impl Outcome {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            Self::Confirmed => StatusCode::OK,
+            Self::InvalidLink => StatusCode::UNAUTHORIZED,
+            Self::CannotConfirm => StatusCode::CONFLICT,
+            Self::Error => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn message<'a>(&self, messages: &'a ConfirmationPageMessages) -> &'a str {
+        match self {
+            Self::Confirmed => &messages.confirmed,
+            Self::InvalidLink => &messages.invalid_link,
+            Self::CannotConfirm => &messages.cannot_confirm,
+            Self::Error => &messages.error,
         }
     }
 }
 
 #[tracing::instrument(name = "Get subscriber_id from token", skip(pool, sub_token))]
-async fn get_subscriber_id_from_token(
+async fn get_subscriber_from_token(
     pool: &PgPool,
     sub_token: &str,
-) -> Result<Option<Uuid>, sqlx::Error> {
+) -> Result<Option<(Uuid, String)>, sqlx::Error> {
     let result = sqlx::query!(
-        r#"SELECT subscriber_id FROM subscription_tokens WHERE subscription_token = $1"#,
+        r#"
+        SELECT t.subscriber_id, s.locale FROM subscription_tokens t
+        JOIN subscriptions s ON s.id = t.subscriber_id
+        WHERE t.subscription_token = $1
+        "#,
         sub_token
     )
     .fetch_optional(pool)
@@ -44,20 +116,29 @@ async fn get_subscriber_id_from_token(
         e
     })?;
 
-    Ok(result.map(|r| r.subscriber_id))
+    Ok(result.map(|r| (r.subscriber_id, r.locale)))
 }
 
 #[tracing::instrument(name = "Mark subscriber as confirmed", skip(pool, sub_id))]
-async fn confirm_subscriber(pool: &PgPool, sub_id: Uuid) -> Result<(), sqlx::Error> {
-    sqlx::query!(
-        r#"UPDATE subscriptions SET status='confirmed' WHERE id=$1"#,
-        sub_id
+async fn confirm_subscriber(pool: &PgPool, sub_id: Uuid) -> Result<(), TransitionError> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    transition_status(
+        &mut transaction,
+        sub_id,
+        SubscriptionStatus::Confirmed,
+        "Confirmation link clicked",
     )
-    .execute(pool)
     .await
     .map_err(|e| {
-        tracing::error!("Failed to execute query: {:?}", e);
+        tracing::error!("Failed to confirm the subscriber: {:?}", e);
         e
     })?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to confirm a subscriber")?;
     Ok(())
 }
@@ -0,0 +1,184 @@
+use actix_web::{
+    http::header::ContentType,
+    web::{Data, Form, Query},
+    HttpResponse,
+};
+use anyhow::{anyhow, Context};
+use serde::Deserialize;
+use sqlx::PgPool;
+
+use crate::{
+    audit::Actor,
+    domain::SubscriberEmail,
+    email_client::EmailClient,
+    gdpr::{
+        collect_subscriber_data, erase_subscribers, find_subscriber_ids, issue_data_access_token,
+        subscriber_id_from_data_access_token, subscriber_ids_sharing_address,
+    },
+    startup::ApplicationBaseUrl,
+    utils,
+};
+
+#[derive(Deserialize)]
+pub struct DataRequestForm {
+    email: String,
+}
+
+#[derive(Deserialize)]
+pub struct TokenParameters {
+    token: String,
+}
+
+/// Email a magic link to the data access page to the given address.
+///
+/// The response is the same whether or not the address is subscribed,
+/// so that the endpoint can't be used to find out who our subscribers are.
+#[tracing::instrument(
+    name = "Request access to subscriber data",
+    skip(form, pool, email_client, base_url)
+)]
+pub async fn request_data_access(
+    form: Form<DataRequestForm>,
+    pool: Data<PgPool>,
+    email_client: Data<EmailClient>,
+    base_url: Data<ApplicationBaseUrl>,
+) -> Result<HttpResponse, DataAccessError> {
+    // the current subscriber comes first, the link covers the deleted ones too
+    if let Some(&subscriber_id) = find_subscriber_ids(&pool, &form.email).await?.first() {
+        let recipient = SubscriberEmail::parse(form.0.email).map_err(|e| anyhow!(e))?;
+        let token = issue_data_access_token(&pool, subscriber_id).await?;
+        send_data_access_email(&email_client, &recipient, &base_url.0, &token).await?;
+    }
+    Ok(HttpResponse::Ok().finish())
+}
+
+/// The manage subscription page a data access magic link points to.
+#[tracing::instrument(name = "Show the data access page", skip(parameters, pool))]
+pub async fn data_access_page(
+    parameters: Query<TokenParameters>,
+    pool: Data<PgPool>,
+) -> Result<HttpResponse, DataAccessError> {
+    let token = &parameters.token;
+    if subscriber_id_from_data_access_token(&pool, token)
+        .await?
+        .is_none()
+    {
+        return Err(DataAccessError::InvalidToken);
+    }
+
+    let html = format!(
+        r#"<!DOCTYPE html>
+<html lang="en">
+  <head>
+    <meta charset="UTF-8" />
+    <title>Manage your subscription</title>
+  </head>
+  <body>
+    <form action="/subscriptions/email/change" method="post">
+      <input type="hidden" name="token" value="{token}" />
+      <label>New email address <input type="email" name="email" /></label>
+      <button type="submit">Change my email address</button>
+    </form>
+    <p><a href="/subscriptions/data/export?token={token}">Download</a> everything we store about you.</p>
+    <form action="/subscriptions/data/erase" method="post">
+      <input type="hidden" name="token" value="{token}" />
+      <p>Erasing your data unsubscribes you and cannot be undone.</p>
+      <button type="submit">Erase my data</button>
+    </form>
+  </body>
+</html>
+"#
+    );
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(html))
+}
+
+/// Download everything stored about the subscriber the token was issued for, and about the
+/// deleted subscriptions of the same address.
+#[tracing::instrument(name = "Export subscriber data via magic link", skip(parameters, pool))]
+pub async fn export_own_data(
+    parameters: Query<TokenParameters>,
+    pool: Data<PgPool>,
+) -> Result<HttpResponse, DataAccessError> {
+    let subscriber_id = subscriber_id_from_data_access_token(&pool, &parameters.token)
+        .await?
+        .ok_or(DataAccessError::InvalidToken)?;
+
+    let subscriber_ids = subscriber_ids_sharing_address(&pool, subscriber_id).await?;
+    let data = collect_subscriber_data(&pool, &subscriber_ids, Actor::Subscriber)
+        .await?
+        .ok_or(DataAccessError::InvalidToken)?;
+    Ok(HttpResponse::Ok().json(data))
+}
+
+/// Permanently delete the subscriber the token was issued for, with the deleted subscriptions
+/// of the same address.
+#[tracing::instrument(name = "Erase subscriber data via magic link", skip(form, pool))]
+pub async fn erase_own_data(
+    form: Form<TokenParameters>,
+    pool: Data<PgPool>,
+) -> Result<HttpResponse, DataAccessError> {
+    let subscriber_id = subscriber_id_from_data_access_token(&pool, &form.token)
+        .await?
+        .ok_or(DataAccessError::InvalidToken)?;
+
+    let subscriber_ids = subscriber_ids_sharing_address(&pool, subscriber_id).await?;
+    erase_subscribers(&pool, &subscriber_ids, Actor::Subscriber).await?;
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body("<p>Your data has been erased.</p>"))
+}
+
+#[tracing::instrument(
+    name = "Send a data access email",
+    skip(client, recipient, base_url, token)
+)]
+async fn send_data_access_email(
+    client: &EmailClient,
+    recipient: &SubscriberEmail,
+    base_url: &str,
+    token: &str,
+) -> Result<(), DataAccessError> {
+    let link = format!("{base_url}/subscriptions/data?token={token}");
+    client
+        .send_email(
+            recipient,
+            "Your data",
+            &format!(
+                "Click <a href=\"{link}\">here</a> to manage your subscription, or to download or erase the data we store about you.<br />\
+                The link expires in 24 hours."
+            ),
+            &format!(
+                "Visit {link} to manage your subscription, or to download or erase the data we store about you.\n\
+                The link expires in 24 hours."
+            ),
+        )
+        .await
+        .context("Failed to send a data access email")?;
+    Ok(())
+}
+
+#[derive(thiserror::Error)]
+pub enum DataAccessError {
+    #[error("The link is invalid or has expired")]
+    InvalidToken,
+
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for DataAccessError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        utils::error_chain_fmt(self, f)
+    }
+}
+
+impl actix_web::ResponseError for DataAccessError {
+    fn status_code(&self) -> reqwest::StatusCode {
+        match self {
+            Self::InvalidToken => reqwest::StatusCode::UNAUTHORIZED,
+            Self::UnexpectedError(_) => reqwest::StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
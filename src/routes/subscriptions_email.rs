@@ -0,0 +1,290 @@
+use actix_web::{
+    http::header::ContentType,
+    web::{Data, Form, Query},
+    HttpResponse,
+};
+use anyhow::{anyhow, Context};
+use chrono::{Duration, Utc};
+use serde::Deserialize;
+use sqlx::{PgPool, Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::{
+    audit::{self, Actor},
+    domain::SubscriberEmail,
+    email_client::EmailClient,
+    gdpr::subscriber_id_from_data_access_token,
+    routes::generate_subscription_token,
+    signup_policy::SignupPolicy,
+    startup::ApplicationBaseUrl,
+    utils::{self, html_escape},
+};
+
+/// How long the link confirming a new email address stays valid.
+const EMAIL_CHANGE_TOKEN_TTL_HOURS: i64 = 24;
+
+#[derive(Deserialize)]
+pub struct EmailChangeForm {
+    /// The data access token of the manage subscription page
+    token: String,
+    email: String,
+}
+
+#[derive(Deserialize)]
+pub struct TokenParameters {
+    token: String,
+}
+
+/// Request a change of the email address of the subscriber the data access token was issued for.
+///
+/// The new address is sent a confirmation link and the current one a notice;
+/// the subscription keeps its current address until the link is clicked.
+#[tracing::instrument(
+    name = "Request an email address change",
+    skip(form, pool, email_client, signup_policy, base_url)
+)]
+pub async fn request_email_change(
+    form: Form<EmailChangeForm>,
+    pool: Data<PgPool>,
+    email_client: Data<EmailClient>,
+    signup_policy: Data<SignupPolicy>,
+    base_url: Data<ApplicationBaseUrl>,
+) -> Result<HttpResponse, EmailChangeError> {
+    let subscriber_id = subscriber_id_from_data_access_token(&pool, &form.token)
+        .await?
+        .ok_or(EmailChangeError::InvalidToken)?;
+    let new_email =
+        SubscriberEmail::parse(form.0.email).map_err(EmailChangeError::ValidationError)?;
+    signup_policy
+        .check_email(&new_email)
+        .map_err(EmailChangeError::ValidationError)?;
+
+    let current = sqlx::query!(
+        r#"SELECT email, email_normalized FROM subscriptions WHERE id = $1"#,
+        subscriber_id
+    )
+    .fetch_optional(pool.get_ref())
+    .await
+    .context("Failed to fetch the current email address of the subscriber")?
+    .ok_or(EmailChangeError::InvalidToken)?;
+    if current.email_normalized.to_lowercase() == new_email.normalized().to_lowercase() {
+        return Err(EmailChangeError::ValidationError(
+            "This is already your email address".into(),
+        ));
+    }
+    let current_email = SubscriberEmail::parse(current.email).map_err(|e| anyhow!(e))?;
+
+    let token = generate_subscription_token();
+    let now = Utc::now();
+    sqlx::query!(
+        r#"
+        INSERT INTO email_change_requests
+            (token, subscriber_id, new_email, new_email_normalized, created_at, expires_at)
+        VALUES ($1, $2, $3, $4, $5, $6)
+        "#,
+        token,
+        subscriber_id,
+        new_email.as_ref(),
+        new_email.normalized(),
+        now,
+        now + Duration::hours(EMAIL_CHANGE_TOKEN_TTL_HOURS),
+    )
+    .execute(pool.get_ref())
+    .await
+    .context("Failed to store an email change request")?;
+
+    send_email_change_emails(
+        &email_client,
+        &current_email,
+        &new_email,
+        &base_url.0,
+        &token,
+    )
+    .await?;
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            "<p>We sent a confirmation link to {}. Your address will change once you click it.</p>",
+            html_escape(new_email.as_ref())
+        )))
+}
+
+/// Switch the subscription to the new address, now that it has been confirmed.
+#[tracing::instrument(name = "Confirm an email address change", skip(parameters, pool))]
+pub async fn confirm_email_change(
+    parameters: Query<TokenParameters>,
+    pool: Data<PgPool>,
+) -> Result<HttpResponse, EmailChangeError> {
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+
+    let request = sqlx::query!(
+        r#"
+        SELECT subscriber_id, new_email, new_email_normalized FROM email_change_requests
+        WHERE token = $1 AND expires_at > $2
+        FOR UPDATE
+        "#,
+        parameters.token,
+        Utc::now(),
+    )
+    .fetch_optional(&mut transaction)
+    .await
+    .context("Failed to look up an email change request")?
+    .ok_or(EmailChangeError::InvalidToken)?;
+
+    if address_taken(
+        &mut transaction,
+        request.subscriber_id,
+        &request.new_email_normalized,
+    )
+    .await?
+    {
+        return Err(EmailChangeError::AddressTaken);
+    }
+
+    let updated = sqlx::query!(
+        r#"UPDATE subscriptions SET email = $1, email_normalized = $2 WHERE id = $3"#,
+        request.new_email,
+        request.new_email_normalized,
+        request.subscriber_id,
+    )
+    .execute(&mut transaction)
+    .await;
+    match updated {
+        // the address was taken by a concurrent signup since we checked
+        Err(sqlx::Error::Database(e)) if e.code().as_deref() == Some(utils::UNIQUE_VIOLATION) => {
+            return Err(EmailChangeError::AddressTaken)
+        }
+        updated => updated.context("Failed to update the email address of the subscriber")?,
+    };
+
+    // any other pending change is superseded by this one
+    sqlx::query!(
+        r#"DELETE FROM email_change_requests WHERE subscriber_id = $1"#,
+        request.subscriber_id
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to delete the email change requests of the subscriber")?;
+
+    audit::record(
+        &mut transaction,
+        Actor::Subscriber,
+        "subscriber_email_changed",
+        &request.subscriber_id.to_string(),
+        serde_json::json!({}),
+    )
+    .await?;
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to change an email address")?;
+
+    Ok(HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body("<p>Your email address has been changed.</p>"))
+}
+
+/// Whether another subscriber already uses the given address.
+async fn address_taken(
+    tx: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    email_normalized: &str,
+) -> anyhow::Result<bool> {
+    let row = sqlx::query!(
+        r#"
+        SELECT id FROM subscriptions
+        WHERE lower(email_normalized) = lower($1) AND id <> $2
+        "#,
+        email_normalized,
+        subscriber_id,
+    )
+    .fetch_optional(&mut *tx)
+    .await
+    .context("Failed to look up a subscriber by email")?;
+    Ok(row.is_some())
+}
+
+#[tracing::instrument(
+    name = "Send the email address change emails",
+    skip(client, current_email, new_email, base_url, token)
+)]
+async fn send_email_change_emails(
+    client: &EmailClient,
+    current_email: &SubscriberEmail,
+    new_email: &SubscriberEmail,
+    base_url: &str,
+    token: &str,
+) -> Result<(), EmailChangeError> {
+    let link = format!("{base_url}/subscriptions/email/confirm?token={token}");
+    client
+        .send_email(
+            new_email,
+            "Confirm your new email address",
+            &format!(
+                "Click <a href=\"{link}\">here</a> to receive our newsletter at this address.<br />\
+                The link expires in 24 hours."
+            ),
+            &format!(
+                "Visit {link} to receive our newsletter at this address.\n\
+                The link expires in 24 hours."
+            ),
+        )
+        .await
+        .context("Failed to send an email change confirmation email")?;
+
+    let new_address = new_email.as_ref();
+    client
+        .send_email(
+            current_email,
+            "Your email address is being changed",
+            &format!(
+                "A change of the address you receive our newsletter at to {} was requested.<br />\
+                If this wasn't you, ignore the confirmation link and nothing will change.",
+                html_escape(new_address)
+            ),
+            &format!(
+                "A change of the address you receive our newsletter at to {new_address} was requested.\n\
+                If this wasn't you, ignore the confirmation link and nothing will change."
+            ),
+        )
+        .await
+        .context("Failed to send an email change notice")?;
+    Ok(())
+}
+
+#[derive(thiserror::Error)]
+pub enum EmailChangeError {
+    #[error("The link is invalid or has expired")]
+    InvalidToken,
+
+    #[error("{0}")]
+    ValidationError(String),
+
+    #[error("Another subscriber already uses this email address")]
+    AddressTaken,
+
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for EmailChangeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        utils::error_chain_fmt(self, f)
+    }
+}
+
+impl actix_web::ResponseError for EmailChangeError {
+    fn status_code(&self) -> reqwest::StatusCode {
+        match self {
+            Self::InvalidToken => reqwest::StatusCode::UNAUTHORIZED,
+            Self::ValidationError(_) => reqwest::StatusCode::BAD_REQUEST,
+            Self::AddressTaken => reqwest::StatusCode::CONFLICT,
+            Self::UnexpectedError(_) => reqwest::StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
@@ -0,0 +1,76 @@
+use actix_web::{
+    http::header::{ContentType, REFERER},
+    web::{Data, Query},
+    HttpRequest, HttpResponse,
+};
+
+use crate::attribution::Attribution;
+use crate::bot_protection::BotProtection;
+use crate::startup::ApplicationBaseUrl;
+use crate::utils::html_escape;
+
+/// The public subscribe form, carrying a freshly signed form token.
+///
+/// The attribution in the query string (`source`, `utm_*`), the page linking to the form
+/// and the form address itself are passed along with the submission.
+pub async fn subscribe_form(
+    attribution: Query<Attribution>,
+    bot_protection: Data<BotProtection>,
+    base_url: Data<ApplicationBaseUrl>,
+    request: HttpRequest,
+) -> HttpResponse {
+    let mut attribution = attribution.into_inner();
+    if attribution.referrer.is_none() {
+        attribution.referrer = request
+            .headers()
+            .get(REFERER)
+            .and_then(|h| h.to_str().ok())
+            .map(str::to_string);
+    }
+    if attribution.landing_url.is_none() {
+        attribution.landing_url = Some(format!("{}{}", base_url.0, request.uri()));
+    }
+    let attribution_inputs: String = attribution
+        .sanitized()
+        .fields()
+        .into_iter()
+        .map(|(name, value)| {
+            format!(
+                r#"<input type="hidden" name="{name}" value="{}" />
+      "#,
+                html_escape(value)
+            )
+        })
+        .collect();
+
+    let form_token = bot_protection.issue_form_token();
+    HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(format!(
+            r#"<!DOCTYPE html>
+<html lang="en">
+  <head>
+    <meta http-equiv="content-type" content="text/html; charset=utf-8" />
+    <title>Subscribe</title>
+  </head>
+  <body>
+    <form action="/subscriptions" method="post">
+      <label>Name
+        <input type="text" placeholder="Enter your name" name="name" />
+      </label>
+      <label>Email
+        <input type="email" placeholder="Enter your email" name="email" />
+      </label>
+      <div style="position: absolute; left: -10000px;" aria-hidden="true">
+        <label>Leave this field empty
+          <input type="text" name="website" tabindex="-1" autocomplete="off" />
+        </label>
+      </div>
+      <input type="hidden" name="form_token" value="{form_token}" />
+      {attribution_inputs}<button type="submit">Subscribe</button>
+    </form>
+  </body>
+</html>
+"#
+        ))
+}
@@ -0,0 +1,316 @@
+use actix_web::{
+    http::header::ContentType,
+    web::{Data, Form, Query},
+    HttpResponse,
+};
+use anyhow::{anyhow, Context};
+use serde::Deserialize;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+    domain::{DeliveryFrequency, SubscriberName, SubscriptionStatus},
+    preferences::{all_lists, set_lists, subscriber_list_ids, List, PreferencesToken},
+    startup::HmacSecret,
+    subscription_lifecycle::{transition_status, TransitionError},
+    utils::{self, html_escape},
+};
+
+#[derive(Deserialize)]
+pub struct TokenParameters {
+    token: String,
+}
+
+struct Preferences {
+    name: String,
+    status: SubscriptionStatus,
+    frequency: DeliveryFrequency,
+    /// The IANA name of the time zone scheduled issues are sent in
+    time_zone: String,
+    list_ids: Vec<Uuid>,
+}
+
+/// The preference center linked from every email.
+#[tracing::instrument(
+    name = "Show the preference center",
+    skip(parameters, pool, hmac_secret)
+)]
+pub async fn preferences_page(
+    parameters: Query<TokenParameters>,
+    pool: Data<PgPool>,
+    hmac_secret: Data<HmacSecret>,
+) -> Result<HttpResponse, PreferencesError> {
+    let subscriber_id = subscriber_id(&parameters.token, &hmac_secret)?;
+    let preferences = fetch_preferences(&pool, subscriber_id).await?;
+    let lists = all_lists(&pool).await?;
+    Ok(render(&parameters.token, &preferences, &lists, None))
+}
+
+/// Save the name, lists, delivery frequency and time zone picked in the preference center.
+///
+/// The form is read as key-value pairs since every ticked list is submitted as its own `list` field.
+#[tracing::instrument(name = "Update subscriber preferences", skip(form, pool, hmac_secret))]
+pub async fn update_preferences(
+    form: Form<Vec<(String, String)>>,
+    pool: Data<PgPool>,
+    hmac_secret: Data<HmacSecret>,
+) -> Result<HttpResponse, PreferencesError> {
+    let field = |key: &str| form.iter().find(|(k, _)| k == key).map(|(_, v)| v.clone());
+    let token = field("token").ok_or(PreferencesError::InvalidToken)?;
+    let subscriber_id = subscriber_id(&token, &hmac_secret)?;
+    // make sure the subscriber still exists before validating anything
+    fetch_preferences(&pool, subscriber_id).await?;
+
+    let name = SubscriberName::parse(field("name").unwrap_or_default())
+        .map_err(PreferencesError::ValidationError)?;
+    let frequency = DeliveryFrequency::try_from(field("frequency").unwrap_or_default())
+        .map_err(PreferencesError::ValidationError)?;
+    let list_ids = form
+        .iter()
+        .filter(|(k, _)| k == "list")
+        .map(|(_, v)| {
+            v.parse::<Uuid>()
+                .map_err(|_| PreferencesError::ValidationError(format!("{v} is not a valid list")))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    // older forms had no time zone: leave it as it was
+    let time_zone = field("time_zone").map(|t| t.trim().to_string());
+    if let Some(time_zone) = &time_zone {
+        if !is_time_zone(&pool, time_zone).await? {
+            return Err(PreferencesError::ValidationError(format!(
+                "{time_zone} is not a known time zone"
+            )));
+        }
+    }
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    sqlx::query!(
+        r#"
+        UPDATE subscriptions
+        SET name = $1, delivery_frequency = $2, time_zone = COALESCE($3, time_zone)
+        WHERE id = $4
+        "#,
+        name.as_ref(),
+        frequency.as_str(),
+        time_zone,
+        subscriber_id,
+    )
+    .execute(&mut transaction)
+    .await
+    .context("Failed to update the preferences of a subscriber")?;
+    set_lists(&mut transaction, subscriber_id, &list_ids).await?;
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to update subscriber preferences")?;
+
+    let preferences = fetch_preferences(&pool, subscriber_id).await?;
+    let lists = all_lists(&pool).await?;
+    Ok(render(
+        &token,
+        &preferences,
+        &lists,
+        Some("Your preferences have been saved."),
+    ))
+}
+
+/// Unsubscribe from everything, from the preference center.
+#[tracing::instrument(
+    name = "Unsubscribe from the preference center",
+    skip(form, pool, hmac_secret)
+)]
+pub async fn unsubscribe_from_preferences(
+    form: Form<TokenParameters>,
+    pool: Data<PgPool>,
+    hmac_secret: Data<HmacSecret>,
+) -> Result<HttpResponse, PreferencesError> {
+    let subscriber_id = subscriber_id(&form.token, &hmac_secret)?;
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+    match transition_status(
+        &mut transaction,
+        subscriber_id,
+        SubscriptionStatus::Unsubscribed,
+        "Unsubscribed from the preference center",
+    )
+    .await
+    {
+        Ok(_) => {}
+        Err(TransitionError::NotFound) => return Err(PreferencesError::InvalidToken),
+        Err(TransitionError::InvalidTransition(e)) => {
+            return Err(PreferencesError::ValidationError(e.to_string()))
+        }
+        Err(TransitionError::UnexpectedError(e)) => return Err(e.into()),
+    }
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to unsubscribe a subscriber")?;
+
+    let preferences = fetch_preferences(&pool, subscriber_id).await?;
+    let lists = all_lists(&pool).await?;
+    Ok(render(
+        &form.token,
+        &preferences,
+        &lists,
+        Some("You have been unsubscribed, you won't receive any more emails from us."),
+    ))
+}
+
+fn subscriber_id(token: &str, hmac_secret: &HmacSecret) -> Result<Uuid, PreferencesError> {
+    PreferencesToken::verify(token, &hmac_secret.0).ok_or(PreferencesError::InvalidToken)
+}
+
+/// Whether Postgres knows `name` as a time zone, so that deliveries can be scheduled in it.
+async fn is_time_zone(pool: &PgPool, name: &str) -> anyhow::Result<bool> {
+    let known = sqlx::query_scalar!(
+        r#"SELECT EXISTS (SELECT 1 FROM pg_timezone_names WHERE name = $1) AS "known!""#,
+        name
+    )
+    .fetch_one(pool)
+    .await
+    .context("Failed to look up a time zone")?;
+    Ok(known)
+}
+
+/// Deleted subscribers are treated as if they did not exist.
+async fn fetch_preferences(
+    pool: &PgPool,
+    subscriber_id: Uuid,
+) -> Result<Preferences, PreferencesError> {
+    let row = sqlx::query!(
+        r#"SELECT name, status, delivery_frequency, time_zone FROM subscriptions WHERE id = $1"#,
+        subscriber_id
+    )
+    .fetch_optional(pool)
+    .await
+    .context("Failed to fetch the preferences of a subscriber")?
+    .ok_or(PreferencesError::InvalidToken)?;
+
+    let status = SubscriptionStatus::try_from(row.status).map_err(|e| anyhow!(e))?;
+    if status == SubscriptionStatus::Deleted {
+        return Err(PreferencesError::InvalidToken);
+    }
+    Ok(Preferences {
+        name: row.name,
+        status,
+        frequency: DeliveryFrequency::try_from(row.delivery_frequency).map_err(|e| anyhow!(e))?,
+        time_zone: row.time_zone,
+        list_ids: subscriber_list_ids(pool, subscriber_id).await?,
+    })
+}
+
+fn render(
+    token: &str,
+    preferences: &Preferences,
+    lists: &[List],
+    notice: Option<&str>,
+) -> HttpResponse {
+    let token = html_escape(token);
+    let notice = notice
+        .map(|n| format!("<p><strong>{}</strong></p>", html_escape(n)))
+        .unwrap_or_default();
+    let list_options: String = lists
+        .iter()
+        .map(|l| {
+            let checked = if preferences.list_ids.contains(&l.id) {
+                " checked"
+            } else {
+                ""
+            };
+            format!(
+                r#"<label><input type="checkbox" name="list" value="{}"{checked} /> {}</label><br />"#,
+                l.id,
+                html_escape(&l.name)
+            )
+        })
+        .collect();
+    let frequency_options: String = DeliveryFrequency::ALL
+        .iter()
+        .map(|f| {
+            let checked = if preferences.frequency == *f {
+                " checked"
+            } else {
+                ""
+            };
+            format!(
+                r#"<label><input type="radio" name="frequency" value="{f}"{checked} /> {}</label><br />"#,
+                f.label()
+            )
+        })
+        .collect();
+    let unsubscribe = if preferences.status == SubscriptionStatus::Unsubscribed {
+        "<p>You are unsubscribed from all our emails.</p>".to_string()
+    } else {
+        format!(
+            r#"<form action="/subscriptions/preferences/unsubscribe" method="post">
+      <input type="hidden" name="token" value="{token}" />
+      <button type="submit">Unsubscribe from everything</button>
+    </form>"#
+        )
+    };
+
+    let html = format!(
+        r#"<!DOCTYPE html>
+<html lang="en">
+  <head>
+    <meta charset="UTF-8" />
+    <title>Your preferences</title>
+  </head>
+  <body>
+    {notice}
+    <form action="/subscriptions/preferences" method="post">
+      <input type="hidden" name="token" value="{token}" />
+      <label>Name <input type="text" name="name" value="{name}" /></label>
+      <h2>What you receive</h2>
+      {list_options}
+      <h2>How often</h2>
+      {frequency_options}
+      <label>Time zone <input type="text" name="time_zone" value="{time_zone}" /></label>
+      <button type="submit">Save</button>
+    </form>
+    {unsubscribe}
+  </body>
+</html>
+"#,
+        name = html_escape(&preferences.name),
+        time_zone = html_escape(&preferences.time_zone),
+    );
+    HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(html)
+}
+
+#[derive(thiserror::Error)]
+pub enum PreferencesError {
+    #[error("The link is invalid")]
+    InvalidToken,
+
+    #[error("{0}")]
+    ValidationError(String),
+
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for PreferencesError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        utils::error_chain_fmt(self, f)
+    }
+}
+
+impl actix_web::ResponseError for PreferencesError {
+    fn status_code(&self) -> reqwest::StatusCode {
+        match self {
+            Self::InvalidToken => reqwest::StatusCode::UNAUTHORIZED,
+            Self::ValidationError(_) => reqwest::StatusCode::BAD_REQUEST,
+            Self::UnexpectedError(_) => reqwest::StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
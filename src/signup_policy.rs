@@ -0,0 +1,221 @@
+use std::collections::HashSet;
+use std::path::PathBuf;
+use std::sync::RwLock;
+use std::time::SystemTime;
+
+use crate::domain::{NewSubscriber, SubscriberEmail};
+
+/// Domains and local-part patterns that are not accepted at signup.
+///
+/// A blocklist line is either a domain, blocking that domain and all its subdomains,
+/// or a local-part pattern ending with `@`, where `*` matches any run of characters.
+/// Blank lines and lines starting with `#` are ignored.
+#[derive(Debug, Default)]
+pub struct Blocklist {
+    domains: HashSet<String>,
+    local_parts: Vec<String>,
+}
+
+impl Blocklist {
+    pub fn parse(contents: &str) -> Self {
+        let mut blocklist = Self::default();
+        for line in contents.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            match line.strip_suffix('@') {
+                Some(pattern) => blocklist.local_parts.push(pattern.to_lowercase()),
+                None => {
+                    let domain =
+                        idna::domain_to_ascii(line).unwrap_or_else(|_| line.to_lowercase());
+                    blocklist.domains.insert(domain);
+                }
+            }
+        }
+        blocklist
+    }
+
+    pub fn check(&self, email: &SubscriberEmail) -> Result<(), String> {
+        let (local, domain) = email
+            .normalized()
+            .rsplit_once('@')
+            .expect("a parsed email always contains an @");
+
+        // `mail.mailinator.com` is checked against `mail.mailinator.com`, `mailinator.com` and `com`
+        let mut suffix = domain;
+        loop {
+            if self.domains.contains(suffix) {
+                return Err(format!(
+                    "Email addresses from {domain} are not accepted, please use a permanent address"
+                ));
+            }
+            match suffix.split_once('.') {
+                Some((_, parent)) => suffix = parent,
+                None => break,
+            }
+        }
+
+        let local = local.to_lowercase();
+        if self.local_parts.iter().any(|p| wildcard_match(p, &local)) {
+            return Err(format!(
+                "Role addresses such as {local}@ are not accepted, please use a personal address"
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+/// Matches `value` against a pattern where `*` stands for any (possibly empty) run of characters.
+fn wildcard_match(pattern: &str, value: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    let Some(mut rest) = value.strip_prefix(first) else {
+        return false;
+    };
+
+    let parts: Vec<_> = parts.collect();
+    let Some((last, middle)) = parts.split_last() else {
+        // no wildcard in the pattern
+        return rest.is_empty();
+    };
+
+    for part in middle {
+        match rest.find(part) {
+            Some(index) => rest = &rest[index + part.len()..],
+            None => return false,
+        }
+    }
+    rest.ends_with(last)
+}
+
+/// The signup policy applied on top of the validation done by [`NewSubscriber::try_from`].
+///
+/// The blocklist is read from a file, which is reloaded as soon as it changes.
+pub struct SignupPolicy {
+    path: PathBuf,
+    state: RwLock<LoadedBlocklist>,
+}
+
+struct LoadedBlocklist {
+    /// Modification time and length of the file the blocklist was loaded from
+    fingerprint: Option<(SystemTime, u64)>,
+    blocklist: Blocklist,
+}
+
+impl SignupPolicy {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        let policy = Self {
+            path: path.into(),
+            state: RwLock::new(LoadedBlocklist {
+                fingerprint: None,
+                blocklist: Blocklist::default(),
+            }),
+        };
+        policy.reload_if_changed();
+        policy
+    }
+
+    pub fn check(&self, subscriber: &NewSubscriber) -> Result<(), String> {
+        self.check_email(&subscriber.email)
+    }
+
+    /// Checks an address on its own, e.g. one an existing subscriber is moving to.
+    pub fn check_email(&self, email: &SubscriberEmail) -> Result<(), String> {
+        self.reload_if_changed();
+        let state = self.state.read().expect("the blocklist lock is poisoned");
+        state.blocklist.check(email)
+    }
+
+    fn fingerprint(&self) -> Option<(SystemTime, u64)> {
+        let metadata = std::fs::metadata(&self.path).ok()?;
+        Some((metadata.modified().ok()?, metadata.len()))
+    }
+
+    fn reload_if_changed(&self) {
+        let fingerprint = self.fingerprint();
+        if self
+            .state
+            .read()
+            .expect("the blocklist lock is poisoned")
+            .fingerprint
+            == fingerprint
+        {
+            return;
+        }
+
+        let blocklist = match std::fs::read_to_string(&self.path) {
+            Ok(contents) => {
+                tracing::info!(path = %self.path.display(), "Loaded the signup blocklist");
+                Blocklist::parse(&contents)
+            }
+            Err(e) => {
+                tracing::warn!(
+                    error.cause_chain = ?e,
+                    path = %self.path.display(),
+                    "Failed to read the signup blocklist, no address will be blocked"
+                );
+                Blocklist::default()
+            }
+        };
+
+        let mut state = self.state.write().expect("the blocklist lock is poisoned");
+        *state = LoadedBlocklist {
+            fingerprint,
+            blocklist,
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{wildcard_match, Blocklist, SignupPolicy};
+    use crate::domain::{NewSubscriber, SubscriberEmail, SubscriberName};
+    use claims::{assert_err, assert_ok};
+
+    fn email(s: &str) -> SubscriberEmail {
+        SubscriberEmail::parse(s.to_string()).unwrap()
+    }
+
+    #[test]
+    fn blocked_domains_and_their_subdomains_are_rejected() {
+        let blocklist = Blocklist::parse("# disposable\nmailinator.com\n");
+        assert_err!(blocklist.check(&email("ursula@mailinator.com")));
+        assert_err!(blocklist.check(&email("ursula@Mail.Mailinator.com")));
+        assert_ok!(blocklist.check(&email("ursula@notmailinator.com")));
+    }
+
+    #[test]
+    fn blocked_local_parts_are_rejected_regardless_of_case() {
+        let blocklist = Blocklist::parse("noreply@\nbounce*@\n");
+        assert_err!(blocklist.check(&email("NoReply@example.com")));
+        assert_err!(blocklist.check(&email("bounces-42@example.com")));
+        assert_ok!(blocklist.check(&email("noreplyanne@example.com")));
+    }
+
+    #[test]
+    fn wildcards_match_any_run_of_characters() {
+        assert!(wildcard_match("no*reply", "no-reply"));
+        assert!(wildcard_match("no*reply", "noreply"));
+        assert!(wildcard_match("*admin*", "sysadmins"));
+        assert!(!wildcard_match("no*reply", "no-replies"));
+        assert!(!wildcard_match("admin", "admins"));
+    }
+
+    #[test]
+    fn the_blocklist_is_reloaded_when_the_file_changes() {
+        let path = std::env::temp_dir().join(format!("blocklist-{}.txt", uuid::Uuid::new_v4()));
+        std::fs::write(&path, "mailinator.com\n").unwrap();
+        let policy = SignupPolicy::new(&path);
+        let subscriber = || NewSubscriber {
+            email: email("ursula@yopmail.com"),
+            name: SubscriberName::parse("Ursula".into()).unwrap(),
+        };
+        assert_ok!(policy.check(&subscriber()));
+
+        std::fs::write(&path, "mailinator.com\nyopmail.com\n").unwrap();
+        assert_err!(policy.check(&subscriber()));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -1,5 +1,6 @@
 use actix_web::dev::Server;
 use actix_web::{web, App, HttpServer};
+use secrecy::Secret;
 use sqlx::postgres::PgPoolOptions;
 use sqlx::{PgPool, Pool, Postgres};
 use std::net::{self, TcpListener};
@@ -7,9 +8,20 @@ use tracing::info;
 use tracing_actix_web::TracingLogger;
 
 use crate::configuration::{DatabaseSettings, Environment, Settings};
+use crate::domain::SubscriberEmail;
 use crate::email_client::EmailClient;
 use crate::routes::{
-    confirm, health_check, home, login_get, login_post, publish_newsletter, subscribe,
+    browse_subscribers, cancel_newsletter_issue, change_subscriber_status, check_newsletter_text,
+    confirm, confirm_email_change, create_newsletter_draft, data_access_page,
+    diff_newsletter_issue_revisions, erase_own_data, erase_subscriber_data, export_own_data,
+    export_subscriber_data, export_subscribers, health_check, home, import_subscribers,
+    list_newsletter_issues, login_get, login_post, newsletter_issue, newsletter_issue_deliveries,
+    newsletter_issue_revision, newsletter_issue_revisions, preferences_page,
+    preview_newsletter_issue, publish_newsletter, replay_newsletter_issue_deliveries,
+    request_data_access, request_email_change, reschedule_newsletter_issue,
+    restore_newsletter_issue_revision, send_newsletter_draft, send_test_newsletter_issue,
+    signup_report, subscribe, subscribe_form, subscriber_details, unsubscribe_from_preferences,
+    update_newsletter_draft, update_preferences,
 };
 
 pub struct Application {
@@ -21,7 +33,7 @@ impl Application {
     /// Builds the components needed to run the app
     pub async fn build(config: Settings) -> Result<Self, std::io::Error> {
         let conn_pool = get_connection_pool(&config.database);
-        let email_client = config.email_client.client();
+        let email_client = config.email_client.clone().client();
 
         let address = format!("{}:{}", config.application.host, config.application.port);
         info!("App running on {:?} env with address {address}", config.env);
@@ -31,10 +43,10 @@ impl Application {
 
         let base_url = match config.env {
             Environment::Testing => format!("http://127.0.0.1:{}", port),
-            _ => config.application.base_url,
+            _ => config.application.base_url.clone(),
         };
 
-        let server = run(listener, conn_pool, email_client, base_url).await?;
+        let server = run(listener, conn_pool, email_client, base_url, &config).await?;
 
         Ok(Self { port, server })
     }
@@ -55,29 +67,175 @@ pub fn get_connection_pool(configuration: &DatabaseSettings) -> PgPool {
 }
 
 /// Creates and returns the server (which implements Future trait)
+///
+/// The remaining components shared by the handlers are built from `config`.
 async fn run(
     listener: TcpListener,
     conn_pool: Pool<Postgres>,
     email_client: EmailClient,
     base_url: String,
+    config: &Settings,
 ) -> Result<Server, std::io::Error> {
+    let invalid_config = |e| std::io::Error::new(std::io::ErrorKind::InvalidInput, e);
+    let rate_limiter = config.rate_limit.rate_limiter().map_err(invalid_config)?;
+    let catalogs = config
+        .i18n
+        .catalogs()
+        .map_err(|e| invalid_config(e.to_string()))?;
+    let bot_protection = config
+        .bot_protection
+        .bot_protection(config.application.hmac_secret.clone());
+
     let db_conn = web::Data::new(conn_pool);
     let email_client = web::Data::new(email_client);
+    let signup_policy = web::Data::new(config.signup_policy.policy());
+    let bot_protection = web::Data::new(bot_protection);
+    let catalogs = web::Data::new(catalogs);
     let base_url = web::Data::new(ApplicationBaseUrl(base_url));
+    let hmac_secret = web::Data::new(HmacSecret(config.application.hmac_secret.clone()));
+    let idempotency = web::Data::new(config.idempotency.clone());
+    let email_layout = web::Data::new(config.authoring.layout().map_err(invalid_config)?);
+    let test_recipients = web::Data::new(TestRecipients(
+        config.delivery.test_recipients().map_err(invalid_config)?,
+    ));
 
     let server = HttpServer::new(move || {
         App::new()
+            .wrap(rate_limiter.clone())
             .wrap(TracingLogger::default())
             .route("/health_check", web::get().to(health_check))
+            .route("/subscriptions", web::get().to(subscribe_form))
             .route("/subscriptions", web::post().to(subscribe))
             .route("/subscriptions/confirm", web::get().to(confirm))
+            .route(
+                "/subscriptions/data/request",
+                web::post().to(request_data_access),
+            )
+            .route("/subscriptions/data", web::get().to(data_access_page))
+            .route("/subscriptions/data/export", web::get().to(export_own_data))
+            .route("/subscriptions/data/erase", web::post().to(erase_own_data))
+            .route(
+                "/subscriptions/preferences",
+                web::get().to(preferences_page),
+            )
+            .route(
+                "/subscriptions/preferences",
+                web::post().to(update_preferences),
+            )
+            .route(
+                "/subscriptions/preferences/unsubscribe",
+                web::post().to(unsubscribe_from_preferences),
+            )
+            .route(
+                "/subscriptions/email/change",
+                web::post().to(request_email_change),
+            )
+            .route(
+                "/subscriptions/email/confirm",
+                web::get().to(confirm_email_change),
+            )
             .route("/newsletters", web::post().to(publish_newsletter))
+            .route("/admin/newsletters", web::get().to(list_newsletter_issues))
+            .route(
+                "/admin/newsletters",
+                web::post().to(create_newsletter_draft),
+            )
+            .route(
+                "/admin/newsletters/text-check",
+                web::post().to(check_newsletter_text),
+            )
+            .route(
+                "/admin/newsletters/{issue_id}",
+                web::get().to(newsletter_issue),
+            )
+            .route(
+                "/admin/newsletters/{issue_id}",
+                web::put().to(update_newsletter_draft),
+            )
+            .route(
+                "/admin/newsletters/{issue_id}/preview",
+                web::get().to(preview_newsletter_issue),
+            )
+            .route(
+                "/admin/newsletters/{issue_id}/revisions",
+                web::get().to(newsletter_issue_revisions),
+            )
+            .route(
+                "/admin/newsletters/{issue_id}/revisions/{revision}",
+                web::get().to(newsletter_issue_revision),
+            )
+            .route(
+                "/admin/newsletters/{issue_id}/revisions/{revision}/restore",
+                web::post().to(restore_newsletter_issue_revision),
+            )
+            .route(
+                "/admin/newsletters/{issue_id}/diff",
+                web::get().to(diff_newsletter_issue_revisions),
+            )
+            .route(
+                "/admin/newsletters/{issue_id}/send",
+                web::post().to(send_newsletter_draft),
+            )
+            .route(
+                "/admin/newsletters/{issue_id}/test",
+                web::post().to(send_test_newsletter_issue),
+            )
+            .route(
+                "/admin/newsletters/{issue_id}/deliveries",
+                web::get().to(newsletter_issue_deliveries),
+            )
+            .route(
+                "/admin/newsletters/{issue_id}/replay",
+                web::post().to(replay_newsletter_issue_deliveries),
+            )
+            .route(
+                "/admin/newsletters/{issue_id}/reschedule",
+                web::post().to(reschedule_newsletter_issue),
+            )
+            .route(
+                "/admin/newsletters/{issue_id}/cancel",
+                web::post().to(cancel_newsletter_issue),
+            )
             .route("/", web::get().to(home))
             .route("/login", web::get().to(login_get))
             .route("/login", web::post().to(login_post))
+            .route(
+                "/admin/subscribers/import",
+                web::post().to(import_subscribers),
+            )
+            .route(
+                "/admin/subscribers/export",
+                web::get().to(export_subscribers),
+            )
+            .route(
+                "/admin/subscribers/data",
+                web::get().to(export_subscriber_data),
+            )
+            .route(
+                "/admin/subscribers/erase",
+                web::post().to(erase_subscriber_data),
+            )
+            .route("/admin/reports/signups", web::get().to(signup_report))
+            .route("/admin/subscribers", web::get().to(browse_subscribers))
+            // registered after the other `/admin/subscribers/...` routes it would shadow
+            .route(
+                "/admin/subscribers/{subscriber_id}",
+                web::get().to(subscriber_details),
+            )
+            .route(
+                "/admin/subscribers/{subscriber_id}/{action}",
+                web::post().to(change_subscriber_status),
+            )
             .app_data(db_conn.clone())
             .app_data(email_client.clone())
+            .app_data(signup_policy.clone())
+            .app_data(bot_protection.clone())
+            .app_data(catalogs.clone())
             .app_data(base_url.clone())
+            .app_data(hmac_secret.clone())
+            .app_data(idempotency.clone())
+            .app_data(test_recipients.clone())
+            .app_data(email_layout.clone())
     })
     .listen(listener)?
     .run();
@@ -89,3 +247,9 @@ async fn run(
 // Retrieval from the context, in actix-web, is type-based: using
 // a raw `String` would expose us to conflicts.
 pub struct ApplicationBaseUrl(pub String);
+
+/// The key signing the links that need no login, such as the preference center one.
+pub struct HmacSecret(pub Secret<String>);
+
+/// The addresses a test send of an issue goes to when the request names none.
+pub struct TestRecipients(pub Vec<SubscriberEmail>);
@@ -0,0 +1,410 @@
+use std::collections::HashSet;
+
+use anyhow::Context;
+use chrono::Utc;
+use csv_async::{AsyncReaderBuilder, StringRecord, Trim};
+use futures::{AsyncRead, StreamExt};
+use serde::Serialize;
+use sqlx::PgPool;
+use uuid::Uuid;
+
+use crate::{
+    domain::{NewSubscriber, SubscriberEmail, SubscriberName, SubscriptionStatus},
+    email_client::EmailClient,
+    gdpr::{email_hash, suppressed_hashes},
+    i18n::Catalogs,
+    preferences::join_all_lists,
+    routes::{generate_subscription_token, send_confirmation_email},
+    signup_policy::SignupPolicy,
+    subscription_lifecycle::record_initial_status,
+    utils,
+};
+
+/// Number of valid rows written to the database in a single transaction.
+const BATCH_SIZE: usize = 500;
+
+/// The attribution `source` of imported subscribers.
+const IMPORT_SOURCE: &str = "import";
+
+/// Number of confirmation emails sent concurrently after a batch has been stored.
+const EMAIL_CONCURRENCY: usize = 10;
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ImportOptions {
+    /// Store the imported subscribers as `confirmed` instead of sending them
+    /// through the double opt-in flow.
+    pub confirmed: bool,
+}
+
+/// The outcome of an import, with the line number of every row that was not imported.
+#[derive(Debug, Default, Serialize)]
+pub struct ImportReport {
+    pub imported: usize,
+    pub duplicates: Vec<RowIssue>,
+    pub invalid: Vec<RowIssue>,
+    /// Rows whose address was erased on request and must not be imported again.
+    pub suppressed: Vec<RowIssue>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RowIssue {
+    pub line: u64,
+    pub reason: String,
+}
+
+#[derive(thiserror::Error)]
+pub enum ImportError {
+    #[error("{0}")]
+    InvalidCsv(String),
+
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for ImportError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        utils::error_chain_fmt(self, f)
+    }
+}
+
+struct ValidRow {
+    line: u64,
+    subscriber: NewSubscriber,
+    locale: String,
+    attributes: serde_json::Value,
+}
+
+/// Column positions of the fields we care about, resolved from the CSV header.
+struct Columns {
+    email: usize,
+    name: usize,
+    locale: Option<usize>,
+    /// Every other column is a custom attribute, keyed by its lowercased header
+    attributes: Vec<(usize, String)>,
+}
+
+impl Columns {
+    fn from_headers(headers: &StringRecord) -> Result<Self, ImportError> {
+        let find = |column: &str| headers.iter().position(|h| h.eq_ignore_ascii_case(column));
+        let position = |column: &str| {
+            find(column).ok_or_else(|| {
+                ImportError::InvalidCsv(format!("The CSV header is missing the `{column}` column"))
+            })
+        };
+
+        let email = position("email")?;
+        let name = position("name")?;
+        let locale = find("locale");
+        let attributes = headers
+            .iter()
+            .enumerate()
+            .filter(|(i, header)| {
+                ![Some(email), Some(name), locale].contains(&Some(*i)) && !header.is_empty()
+            })
+            .map(|(i, header)| (i, header.to_lowercase()))
+            .collect();
+        Ok(Self {
+            email,
+            name,
+            locale,
+            attributes,
+        })
+    }
+
+    /// The non-empty custom attributes of the row, as a JSON object.
+    fn attributes(&self, record: &StringRecord) -> serde_json::Value {
+        self.attributes
+            .iter()
+            .filter_map(|(i, key)| {
+                let value = record.get(*i).filter(|v| !v.is_empty())?;
+                Some((key.clone(), serde_json::Value::from(value)))
+            })
+            .collect::<serde_json::Map<_, _>>()
+            .into()
+    }
+
+    fn locale<'a>(&self, record: &'a StringRecord) -> Option<&'a str> {
+        self.locale
+            .and_then(|i| record.get(i))
+            .filter(|l| !l.is_empty())
+    }
+
+    fn parse(&self, record: &StringRecord) -> Result<NewSubscriber, String> {
+        let email = record.get(self.email).unwrap_or_default().to_string();
+        let name = record.get(self.name).unwrap_or_default().to_string();
+        Ok(NewSubscriber {
+            email: SubscriberEmail::parse(email)?,
+            name: SubscriberName::parse(name)?,
+        })
+    }
+}
+
+/// Streams subscribers out of a CSV source and stores them in batches.
+///
+/// The source must start with a header row containing (at least) the `email` and `name`
+/// columns, and optionally a `locale` column. Any other column is stored as a custom
+/// attribute of the subscriber, available as a merge tag in newsletter issues.
+/// Rows failing validation are reported as invalid, while addresses that are already
+/// subscribed (or appear earlier in the same file) are reported as duplicates.
+/// Rows rejected by the signup policy are reported as invalid too,
+/// and addresses in the suppression list are never imported.
+#[tracing::instrument(name = "Import subscribers from CSV", skip_all)]
+pub async fn import_from_csv<R>(
+    source: R,
+    pool: &PgPool,
+    email_client: &EmailClient,
+    base_url: &str,
+    signup_policy: &SignupPolicy,
+    catalogs: &Catalogs,
+    options: ImportOptions,
+) -> Result<ImportReport, ImportError>
+where
+    R: AsyncRead + Unpin + Send,
+{
+    let mut reader = AsyncReaderBuilder::new()
+        .flexible(true)
+        .trim(Trim::All)
+        .create_reader(source);
+
+    let headers = reader
+        .headers()
+        .await
+        .map_err(|e| ImportError::InvalidCsv(format!("Failed to read the CSV header: {e}")))?;
+    let columns = Columns::from_headers(headers)?;
+
+    let mut report = ImportReport::default();
+    let mut seen = HashSet::new();
+    let mut batch = Vec::with_capacity(BATCH_SIZE);
+
+    let mut records = reader.records();
+    while let Some(record) = records.next().await {
+        let record = match record {
+            Ok(record) => record,
+            Err(e) if e.is_io_error() => {
+                return Err(anyhow::anyhow!(e)
+                    .context("Failed to read the CSV source")
+                    .into())
+            }
+            Err(e) => {
+                let line = e.position().map(|p| p.line()).unwrap_or_default();
+                report.invalid.push(RowIssue {
+                    line,
+                    reason: e.to_string(),
+                });
+                continue;
+            }
+        };
+        let line = record.position().map(|p| p.line()).unwrap_or_default();
+
+        let subscriber = match columns
+            .parse(&record)
+            .and_then(|s| signup_policy.check(&s).map(|_| s))
+        {
+            Ok(subscriber) => subscriber,
+            Err(reason) => {
+                report.invalid.push(RowIssue { line, reason });
+                continue;
+            }
+        };
+
+        if !seen.insert(subscriber.email.normalized().to_lowercase()) {
+            report.duplicates.push(RowIssue {
+                line,
+                reason: "The email address appears earlier in the file".into(),
+            });
+            continue;
+        }
+
+        let locale = catalogs.negotiate(columns.locale(&record), None);
+        batch.push(ValidRow {
+            line,
+            subscriber,
+            locale,
+            attributes: columns.attributes(&record),
+        });
+        if batch.len() == BATCH_SIZE {
+            store_batch(
+                &mut batch,
+                pool,
+                email_client,
+                base_url,
+                catalogs,
+                options,
+                &mut report,
+            )
+            .await?;
+        }
+    }
+
+    if !batch.is_empty() {
+        store_batch(
+            &mut batch,
+            pool,
+            email_client,
+            base_url,
+            catalogs,
+            options,
+            &mut report,
+        )
+        .await?;
+    }
+
+    Ok(report)
+}
+
+/// Filters out, and reports, the rows whose address is in the suppression list.
+async fn remove_suppressed(
+    rows: Vec<ValidRow>,
+    pool: &PgPool,
+    report: &mut ImportReport,
+) -> Result<Vec<ValidRow>, ImportError> {
+    let hashes: Vec<String> = rows
+        .iter()
+        .map(|r| email_hash(r.subscriber.email.normalized()))
+        .collect();
+    let suppressed = suppressed_hashes(pool, &hashes).await?;
+
+    let mut out = Vec::with_capacity(rows.len());
+    for (row, hash) in rows.into_iter().zip(hashes) {
+        if suppressed.contains(&hash) {
+            report.suppressed.push(RowIssue {
+                line: row.line,
+                reason: "The email address was erased on request".into(),
+            });
+        } else {
+            out.push(row);
+        }
+    }
+    Ok(out)
+}
+
+/// Stores a batch of valid rows in a single transaction, then sends the confirmation
+/// emails when the rows are going through double opt-in.
+#[tracing::instrument(name = "Store a batch of imported subscribers", skip_all, fields(batch_size = batch.len()))]
+async fn store_batch(
+    batch: &mut Vec<ValidRow>,
+    pool: &PgPool,
+    email_client: &EmailClient,
+    base_url: &str,
+    catalogs: &Catalogs,
+    options: ImportOptions,
+    report: &mut ImportReport,
+) -> Result<(), ImportError> {
+    let rows = remove_suppressed(std::mem::take(batch), pool, report).await?;
+    let ids: Vec<Uuid> = rows.iter().map(|_| Uuid::new_v4()).collect();
+    let emails: Vec<String> = rows
+        .iter()
+        .map(|r| r.subscriber.email.as_ref().to_string())
+        .collect();
+    let normalized_emails: Vec<String> = rows
+        .iter()
+        .map(|r| r.subscriber.email.normalized().to_string())
+        .collect();
+    let names: Vec<String> = rows
+        .iter()
+        .map(|r| r.subscriber.name.as_ref().to_string())
+        .collect();
+    let locales: Vec<String> = rows.iter().map(|r| r.locale.clone()).collect();
+    let attributes: Vec<serde_json::Value> = rows.iter().map(|r| r.attributes.clone()).collect();
+    let status = if options.confirmed {
+        SubscriptionStatus::Confirmed
+    } else {
+        SubscriptionStatus::PendingConfirmation
+    };
+
+    let mut transaction = pool
+        .begin()
+        .await
+        .context("Failed to acquire a Postgres connection from the pool")?;
+
+    let inserted: HashSet<Uuid> = sqlx::query!(
+        r#"
+        INSERT INTO subscriptions (id, email, email_normalized, name, subscribed_at, status, status_changed_at, locale, source, attributes)
+        SELECT id, email, email_normalized, name, $5, $6, $5, locale, $8, attributes
+        FROM UNNEST($1::uuid[], $2::text[], $3::text[], $4::text[], $7::text[], $9::jsonb[])
+            AS t(id, email, email_normalized, name, locale, attributes)
+        ON CONFLICT ((lower(email_normalized))) DO NOTHING
+        RETURNING id
+        "#,
+        &ids[..],
+        &emails[..],
+        &normalized_emails[..],
+        &names[..],
+        Utc::now(),
+        status.as_str(),
+        &locales[..],
+        IMPORT_SOURCE,
+        &attributes[..],
+    )
+    .fetch_all(&mut transaction)
+    .await
+    .context("Failed to store a batch of imported subscribers")?
+    .into_iter()
+    .map(|r| r.id)
+    .collect();
+
+    let inserted_ids: Vec<Uuid> = inserted.iter().copied().collect();
+    record_initial_status(
+        &mut transaction,
+        &inserted_ids,
+        status,
+        "Imported from a CSV file",
+    )
+    .await?;
+    join_all_lists(&mut transaction, &inserted_ids).await?;
+
+    let mut pending = Vec::new();
+    for (row, id) in rows.into_iter().zip(ids) {
+        if !inserted.contains(&id) {
+            report.duplicates.push(RowIssue {
+                line: row.line,
+                reason: "The email address is already subscribed".into(),
+            });
+            continue;
+        }
+        report.imported += 1;
+        if !options.confirmed {
+            pending.push((id, row, generate_subscription_token()));
+        }
+    }
+
+    if !pending.is_empty() {
+        let (ids, tokens): (Vec<Uuid>, Vec<String>) = pending
+            .iter()
+            .map(|(id, _, token)| (*id, token.clone()))
+            .unzip();
+        sqlx::query!(
+            r#"
+            INSERT INTO subscription_tokens (subscriber_id, subscription_token)
+            SELECT * FROM UNNEST($1::uuid[], $2::text[])
+            "#,
+            &ids[..],
+            &tokens[..],
+        )
+        .execute(&mut transaction)
+        .await
+        .context("Failed to store the confirmation tokens for imported subscribers")?;
+    }
+
+    transaction
+        .commit()
+        .await
+        .context("Failed to commit SQL transaction to store imported subscribers")?;
+
+    futures::stream::iter(pending)
+        .for_each_concurrent(EMAIL_CONCURRENCY, |(_, row, token)| async move {
+            let messages = &catalogs.get(&row.locale).confirmation_email;
+            if let Err(e) =
+                send_confirmation_email(email_client, &row.subscriber, base_url, &token, messages)
+                    .await
+            {
+                tracing::warn!(
+                    error.cause_chain = ?e,
+                    "Failed to send a confirmation email to an imported subscriber"
+                );
+            }
+        })
+        .await;
+
+    Ok(())
+}
@@ -0,0 +1,126 @@
+//! Persisting subscription status changes together with their history.
+use anyhow::Context;
+use chrono::Utc;
+use sqlx::{Postgres, Transaction};
+use uuid::Uuid;
+
+use crate::domain::{InvalidTransition, SubscriptionStatus};
+use crate::utils;
+
+/// Records the status new subscribers were stored with.
+#[tracing::instrument(
+    name = "Record the initial status of subscribers",
+    skip(tx, subscriber_ids)
+)]
+pub async fn record_initial_status(
+    tx: &mut Transaction<'_, Postgres>,
+    subscriber_ids: &[Uuid],
+    status: SubscriptionStatus,
+    reason: &str,
+) -> anyhow::Result<()> {
+    let ids: Vec<Uuid> = subscriber_ids.iter().map(|_| Uuid::new_v4()).collect();
+    sqlx::query!(
+        r#"
+        INSERT INTO subscription_status_history (id, subscriber_id, from_status, to_status, reason, changed_at)
+        SELECT id, subscriber_id, NULL, $3, $4, $5
+        FROM UNNEST($1::uuid[], $2::uuid[]) AS t(id, subscriber_id)
+        "#,
+        &ids[..],
+        subscriber_ids,
+        status.as_str(),
+        reason,
+        Utc::now(),
+    )
+    .execute(&mut *tx)
+    .await
+    .context("Failed to record the initial status of subscribers")?;
+    Ok(())
+}
+
+/// Moves a subscriber to `next`, if the transition is legal, and records why it happened.
+///
+/// Moving a subscriber to the status they already have is a no-op that returns `Ok(false)`.
+/// Deleted subscribers release their address, which can then subscribe again from scratch;
+/// it is kept in `deleted_email_normalized` for data access and erasure requests.
+#[tracing::instrument(name = "Change the status of a subscriber", skip(tx))]
+pub async fn transition_status(
+    tx: &mut Transaction<'_, Postgres>,
+    subscriber_id: Uuid,
+    next: SubscriptionStatus,
+    reason: &str,
+) -> Result<bool, TransitionError> {
+    let row = sqlx::query!(
+        r#"SELECT status FROM subscriptions WHERE id = $1 FOR UPDATE"#,
+        subscriber_id
+    )
+    .fetch_optional(&mut *tx)
+    .await
+    .context("Failed to fetch the status of the subscriber")?
+    .ok_or(TransitionError::NotFound)?;
+
+    let current = SubscriptionStatus::try_from(row.status).map_err(|e| anyhow::anyhow!(e))?;
+    if current == next {
+        return Ok(false);
+    }
+    current.transition_to(next)?;
+
+    let now = Utc::now();
+    sqlx::query!(
+        r#"UPDATE subscriptions SET status = $1, status_changed_at = $2 WHERE id = $3"#,
+        next.as_str(),
+        now,
+        subscriber_id,
+    )
+    .execute(&mut *tx)
+    .await
+    .context("Failed to update the status of the subscriber")?;
+
+    if next == SubscriptionStatus::Deleted {
+        sqlx::query!(
+            r#"
+            UPDATE subscriptions
+            SET deleted_email_normalized = email_normalized, email_normalized = 'deleted:' || id::text
+            WHERE id = $1
+            "#,
+            subscriber_id,
+        )
+        .execute(&mut *tx)
+        .await
+        .context("Failed to release the address of the deleted subscriber")?;
+    }
+
+    sqlx::query!(
+        r#"
+        INSERT INTO subscription_status_history (id, subscriber_id, from_status, to_status, reason, changed_at)
+        VALUES ($1, $2, $3, $4, $5, $6)
+        "#,
+        Uuid::new_v4(),
+        subscriber_id,
+        current.as_str(),
+        next.as_str(),
+        reason,
+        now,
+    )
+    .execute(&mut *tx)
+    .await
+    .context("Failed to record the status change of the subscriber")?;
+    Ok(true)
+}
+
+#[derive(thiserror::Error)]
+pub enum TransitionError {
+    #[error("The subscriber does not exist")]
+    NotFound,
+
+    #[error(transparent)]
+    InvalidTransition(#[from] InvalidTransition),
+
+    #[error(transparent)]
+    UnexpectedError(#[from] anyhow::Error),
+}
+
+impl std::fmt::Debug for TransitionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        utils::error_chain_fmt(self, f)
+    }
+}
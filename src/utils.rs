@@ -1,3 +1,6 @@
+/// The Postgres error code of a unique constraint violation.
+pub const UNIQUE_VIOLATION: &str = "23505";
+
 pub fn error_chain_fmt(
     e: impl std::error::Error,
     f: &mut std::fmt::Formatter<'_>,
@@ -12,3 +15,33 @@ pub fn error_chain_fmt(
 
     Ok(())
 }
+
+/// Escapes text to be embedded in HTML, in element content or in a quoted attribute value.
+pub fn html_escape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#x27;"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::html_escape;
+
+    #[test]
+    fn html_special_characters_are_escaped() {
+        assert_eq!(
+            html_escape(r#"<a href="x">Tom & 'Jerry'</a>"#),
+            "&lt;a href=&quot;x&quot;&gt;Tom &amp; &#x27;Jerry&#x27;&lt;/a&gt;"
+        );
+        assert_eq!(html_escape("plain"), "plain");
+    }
+}
@@ -0,0 +1,503 @@
+use crate::helpers::{spawn_app, TestApp};
+use serde_json::Value;
+use wiremock::{matchers::path, Mock, ResponseTemplate};
+
+async fn import_fixture(app: &TestApp) {
+    let csv = "\
+email,name
+octavia@example.com,Octavia Butler
+iain@example.com,Iain Banks
+ursula@example.com,Ursula Le Guin
+ted@example.com,Ted Chiang
+nk@example.com,N. K. Jemisin
+";
+    app.post_subscribers_import(csv, true)
+        .await
+        .error_for_status()
+        .unwrap();
+}
+
+async fn get_json(app: &TestApp, path: &str, query: &[(&str, &str)]) -> Value {
+    let mut query = query.to_vec();
+    query.push(("format", "json"));
+    app.get_admin(path, &query)
+        .await
+        .error_for_status()
+        .unwrap()
+        .json()
+        .await
+        .unwrap()
+}
+
+fn emails(page: &Value) -> Vec<String> {
+    page["subscribers"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|s| s["email"].as_str().unwrap().to_string())
+        .collect()
+}
+
+#[tokio::test]
+async fn subscribers_are_paginated_with_a_cursor() {
+    let app = spawn_app().await;
+    import_fixture(&app).await;
+
+    let mut seen = Vec::new();
+    let mut query = vec![("limit", "2".to_string())];
+    let mut pages = 0;
+    loop {
+        let q: Vec<(&str, &str)> = query.iter().map(|(k, v)| (*k, v.as_str())).collect();
+        let page = get_json(&app, "/admin/subscribers", &q).await;
+        pages += 1;
+        seen.extend(emails(&page));
+        match page["next_cursor"].as_str() {
+            Some(cursor) => query = vec![("limit", "2".into()), ("after", cursor.into())],
+            None => break,
+        }
+    }
+
+    assert_eq!(pages, 3);
+    seen.sort();
+    seen.dedup();
+    assert_eq!(seen.len(), 5);
+}
+
+#[tokio::test]
+async fn invalid_cursors_are_rejected() {
+    let app = spawn_app().await;
+
+    let out_of_range = base64::encode_config(
+        format!("{}:{}", i64::MAX, uuid::Uuid::new_v4()),
+        base64::URL_SAFE_NO_PAD,
+    );
+    for cursor in ["not-a-cursor", out_of_range.as_str()] {
+        let response = app
+            .get_admin(
+                "/admin/subscribers",
+                &[("after", cursor), ("format", "json")],
+            )
+            .await;
+        assert_eq!(response.status().as_u16(), 400);
+    }
+}
+
+#[tokio::test]
+async fn subscribers_can_be_searched_and_filtered() {
+    let app = spawn_app().await;
+    import_fixture(&app).await;
+    app.post_subscribers_import("email,name\npending@example.com,Pending Person\n", false)
+        .await;
+
+    let page = get_json(&app, "/admin/subscribers", &[("q", "LE GUIN")]).await;
+    assert_eq!(emails(&page), vec!["ursula@example.com"]);
+
+    let page = get_json(&app, "/admin/subscribers", &[("q", "iain@")]).await;
+    assert_eq!(emails(&page), vec!["iain@example.com"]);
+
+    let page = get_json(
+        &app,
+        "/admin/subscribers",
+        &[("status", "pending_confirmation")],
+    )
+    .await;
+    assert_eq!(emails(&page), vec!["pending@example.com"]);
+
+    let today = chrono::Utc::now().date_naive().to_string();
+    let page = get_json(
+        &app,
+        "/admin/subscribers",
+        &[("from", &today), ("to", &today)],
+    )
+    .await;
+    assert_eq!(emails(&page).len(), 6);
+    let page = get_json(&app, "/admin/subscribers", &[("to", "2020-01-01")]).await;
+    assert!(emails(&page).is_empty());
+
+    // blank fields of the HTML filter form are ignored
+    let response = app
+        .get_admin(
+            "/admin/subscribers",
+            &[("q", ""), ("status", ""), ("from", ""), ("to", "")],
+        )
+        .await;
+    assert_eq!(response.status(), 200);
+    let html = response.text().await.unwrap();
+    assert!(html.contains("octavia@example.com"));
+}
+
+#[tokio::test]
+async fn invalid_filters_are_rejected_with_a_400() {
+    let app = spawn_app().await;
+
+    for query in [("status", "pending"), ("from", "yesterday"), ("after", "x")] {
+        let response = app.get_admin("/admin/subscribers", &[query]).await;
+        assert_eq!(response.status(), 400, "{query:?} was not rejected");
+    }
+}
+
+#[tokio::test]
+async fn the_detail_view_shows_tokens_and_status_history() {
+    let app = spawn_app().await;
+    let link = app
+        .create_unconfirmed_subscriber("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+    reqwest::get(link)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    let page = get_json(&app, "/admin/subscribers", &[]).await;
+    let id = page["subscribers"][0]["id"].as_str().unwrap();
+
+    let details = get_json(&app, &format!("/admin/subscribers/{id}"), &[]).await;
+    assert_eq!(details["subscriber"]["status"], "confirmed");
+    assert_eq!(details["subscription_tokens"].as_array().unwrap().len(), 1);
+    assert_eq!(details["status_history"].as_array().unwrap().len(), 2);
+
+    let html = app
+        .get_admin(&format!("/admin/subscribers/{id}"), &[])
+        .await
+        .text()
+        .await
+        .unwrap();
+    assert!(html.contains("Confirmation link clicked"));
+
+    let response = app
+        .get_admin(&format!("/admin/subscribers/{}", uuid::Uuid::new_v4()), &[])
+        .await;
+    assert_eq!(response.status(), 404);
+}
+
+#[tokio::test]
+async fn subscribers_can_be_filtered_by_list() {
+    let app = spawn_app().await;
+    import_fixture(&app).await;
+    sqlx::query!(
+        r#"
+        WITH events AS (
+            INSERT INTO lists (id, slug, name, created_at)
+            VALUES ('5d4bf1c0-6a3c-4b34-9d3f-1b8e7f4c2a10', 'events', 'Events', now())
+            RETURNING id
+        )
+        INSERT INTO subscription_lists (subscriber_id, list_id)
+        SELECT subscriptions.id, events.id FROM subscriptions, events
+        WHERE email IN ('ted@example.com', 'iain@example.com')
+        "#
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    let mut found = emails(&get_json(&app, "/admin/subscribers", &[("list", "events")]).await);
+    found.sort();
+    assert_eq!(found, vec!["iain@example.com", "ted@example.com"]);
+
+    // imported subscribers receive every list that existed when they signed up
+    let all = get_json(&app, "/admin/subscribers", &[("list", "newsletter")]).await;
+    assert_eq!(emails(&all).len(), 5);
+
+    let id = all["subscribers"][0]["id"].as_str().unwrap();
+    let details = get_json(&app, &format!("/admin/subscribers/{id}"), &[]).await;
+    assert!(details["lists"]
+        .as_array()
+        .unwrap()
+        .contains(&"Newsletter".into()));
+    assert_eq!(details["subscriber"]["delivery_frequency"], "every_issue");
+}
+
+#[tokio::test]
+async fn subscriber_details_are_html_escaped() {
+    let app = spawn_app().await;
+    app.post_subscribers_import("email,name\no'brien@example.com,Flann & co\n", true)
+        .await;
+
+    let html = app
+        .get_admin("/admin/subscribers", &[])
+        .await
+        .text()
+        .await
+        .unwrap();
+
+    assert!(html.contains("o&#x27;brien@example.com"));
+    assert!(html.contains("Flann &amp; co"));
+}
+
+#[tokio::test]
+async fn the_subscriber_browser_requires_authentication() {
+    let app = spawn_app().await;
+
+    let response = reqwest::get(format!("{}/admin/subscribers", app.address))
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), 401);
+}
+
+async fn first_subscriber_id(app: &TestApp) -> String {
+    let page = get_json(app, "/admin/subscribers", &[]).await;
+    page["subscribers"][0]["id"].as_str().unwrap().to_string()
+}
+
+#[tokio::test]
+async fn staff_can_confirm_a_pending_subscriber() {
+    let app = spawn_app().await;
+    app.create_unconfirmed_subscriber("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+    let id = first_subscriber_id(&app).await;
+
+    let response = app
+        .post_admin(
+            &format!("/admin/subscribers/{id}/confirm"),
+            &[("note", "email never arrived")],
+        )
+        .await;
+
+    assert_eq!(response.status(), 303);
+    assert_eq!(
+        response.headers()["Location"],
+        format!("/admin/subscribers/{id}").as_str()
+    );
+    let details = get_json(&app, &format!("/admin/subscribers/{id}"), &[]).await;
+    assert_eq!(details["subscriber"]["status"], "confirmed");
+    assert_eq!(
+        details["status_history"][1]["reason"],
+        "Confirmed by staff: email never arrived"
+    );
+
+    let audit = sqlx::query!("SELECT actor_user_id, subject, details FROM audit_log WHERE action = 'subscriber_status_changed'")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(audit.actor_user_id, Some(app.test_user.user_id));
+    assert_eq!(audit.subject, id);
+    assert_eq!(audit.details["to_status"], "confirmed");
+
+    // only the transitions that are still legal are offered
+    let html = app
+        .get_admin(&format!("/admin/subscribers/{id}"), &[])
+        .await
+        .text()
+        .await
+        .unwrap();
+    assert!(!html.contains(&format!("/admin/subscribers/{id}/confirm")));
+    assert!(html.contains(&format!("/admin/subscribers/{id}/unsubscribe")));
+}
+
+#[tokio::test]
+async fn staff_actions_follow_the_status_rules() {
+    let app = spawn_app().await;
+    import_fixture(&app).await;
+    let id = first_subscriber_id(&app).await;
+
+    let response = app
+        .post_admin(&format!("/admin/subscribers/{id}/unsubscribe"), &[])
+        .await;
+    assert_eq!(response.status(), 303);
+    let response = app
+        .post_admin(&format!("/admin/subscribers/{id}/delete"), &[])
+        .await;
+    assert_eq!(response.status(), 303);
+
+    // deleted is terminal
+    let response = app
+        .post_admin(&format!("/admin/subscribers/{id}/confirm"), &[])
+        .await;
+    assert_eq!(response.status(), 409);
+
+    let details = get_json(&app, &format!("/admin/subscribers/{id}"), &[]).await;
+    assert_eq!(details["subscriber"]["status"], "deleted");
+    let audited = sqlx::query!(
+        r#"SELECT count(*) as "count!" FROM audit_log WHERE action = 'subscriber_status_changed'"#
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(audited.count, 2);
+}
+
+#[tokio::test]
+async fn a_deleted_address_can_subscribe_again() {
+    let app = spawn_app().await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+    app.create_unconfirmed_subscriber(body.into()).await;
+    let id = first_subscriber_id(&app).await;
+    let response = app
+        .post_admin(&format!("/admin/subscribers/{id}/delete"), &[])
+        .await;
+    assert_eq!(response.status(), 303);
+
+    app.create_unconfirmed_subscriber(body.into()).await;
+
+    let statuses: Vec<String> = sqlx::query!(
+        "SELECT status FROM subscriptions WHERE email = 'ursula_le_guin@gmail.com' ORDER BY subscribed_at"
+    )
+    .fetch_all(&app.db_pool)
+    .await
+    .unwrap()
+    .into_iter()
+    .map(|r| r.status)
+    .collect();
+    assert_eq!(statuses, vec!["deleted", "pending_confirmation"]);
+}
+
+#[tokio::test]
+async fn the_data_of_deleted_subscribers_can_still_be_exported_and_erased() {
+    let app = spawn_app().await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+    app.create_unconfirmed_subscriber(body.into()).await;
+    let id = first_subscriber_id(&app).await;
+    app.post_admin(&format!("/admin/subscribers/{id}/delete"), &[])
+        .await;
+
+    let data = get_json(
+        &app,
+        "/admin/subscribers/data",
+        &[("email", "ursula_le_guin@gmail.com")],
+    )
+    .await;
+    assert_eq!(data["subscription"]["status"], "deleted");
+
+    app.create_unconfirmed_subscriber(body.into()).await;
+    let data = get_json(
+        &app,
+        "/admin/subscribers/data",
+        &[("email", "ursula_le_guin@gmail.com")],
+    )
+    .await;
+    assert_eq!(data["subscription"]["status"], "pending_confirmation");
+    assert_eq!(
+        data["previous_subscriptions"][0]["subscription"]["status"],
+        "deleted"
+    );
+
+    let response = app
+        .post_admin(
+            "/admin/subscribers/erase",
+            &[("email", "ursula_le_guin@gmail.com")],
+        )
+        .await;
+    assert_eq!(response.status(), 200);
+    let left = sqlx::query!("SELECT count(*) AS \"count!\" FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(left.count, 0);
+}
+
+#[tokio::test]
+async fn staff_actions_sent_from_another_site_are_forbidden() {
+    let app = spawn_app().await;
+    import_fixture(&app).await;
+    let id = first_subscriber_id(&app).await;
+    let post_from = |origin: String, path: String| {
+        reqwest::Client::builder()
+            .redirect(reqwest::redirect::Policy::none())
+            .build()
+            .unwrap()
+            .post(format!("{}{}", app.address, path))
+            .header("Origin", origin)
+            .form(&[("email", "octavia@example.com")])
+            .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+            .send()
+    };
+
+    let forged = [
+        format!("/admin/subscribers/{id}/unsubscribe"),
+        "/admin/subscribers/erase".to_string(),
+    ];
+    for path in forged {
+        let response = post_from("https://evil.example".into(), path)
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 403);
+    }
+    let details = get_json(&app, &format!("/admin/subscribers/{id}"), &[]).await;
+    assert_eq!(details["subscriber"]["status"], "confirmed");
+    let left = sqlx::query!("SELECT count(*) AS \"count!\" FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(left.count, 5);
+
+    // the base URL the app runs with in tests
+    let response = post_from(
+        format!("http://127.0.0.1:{}", app.port),
+        format!("/admin/subscribers/{id}/unsubscribe"),
+    )
+    .await
+    .unwrap();
+    assert_eq!(response.status(), 303);
+}
+
+#[tokio::test]
+async fn staff_actions_on_unknown_subscribers_or_actions_are_not_found() {
+    let app = spawn_app().await;
+    import_fixture(&app).await;
+    let id = first_subscriber_id(&app).await;
+
+    let response = app
+        .post_admin(
+            &format!("/admin/subscribers/{}/confirm", uuid::Uuid::new_v4()),
+            &[],
+        )
+        .await;
+    assert_eq!(response.status(), 404);
+
+    let response = app
+        .post_admin(&format!("/admin/subscribers/{id}/bounce"), &[])
+        .await;
+    assert_eq!(response.status(), 404);
+}
+
+#[tokio::test]
+async fn staff_actions_require_authentication() {
+    let app = spawn_app().await;
+    import_fixture(&app).await;
+    let id = first_subscriber_id(&app).await;
+
+    let response = reqwest::Client::new()
+        .post(format!("{}/admin/subscribers/{id}/delete", app.address))
+        .form(&[("note", "")])
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), 401);
+}
+
+#[tokio::test]
+async fn the_detail_view_lists_the_deliveries_of_the_subscriber() {
+    let app = spawn_app().await;
+    import_fixture(&app).await;
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    app.post_newsletters(&serde_json::json!({
+        "title": "Issue #1",
+        "content": {"text": "Plain text", "html": "<p>HTML</p>"},
+        "send": true,
+    }))
+    .await
+    .error_for_status()
+    .unwrap();
+
+    let page = get_json(&app, "/admin/subscribers", &[("q", "octavia")]).await;
+    let id = page["subscribers"][0]["id"].as_str().unwrap();
+    let details = get_json(&app, &format!("/admin/subscribers/{id}"), &[]).await;
+    assert_eq!(details["deliveries"][0]["title"], "Issue #1");
+    assert_eq!(details["deliveries"][0]["status"], "pending");
+
+    app.dispatch_all_pending_emails().await;
+    let details = get_json(&app, &format!("/admin/subscribers/{id}"), &[]).await;
+    assert_eq!(details["deliveries"][0]["status"], "sent");
+    let html = app
+        .get_admin(&format!("/admin/subscribers/{id}"), &[])
+        .await
+        .text()
+        .await
+        .unwrap();
+    assert!(html.contains("Issue #1"));
+}
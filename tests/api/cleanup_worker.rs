@@ -0,0 +1,89 @@
+use crate::helpers::{new_sub_request_body, spawn_app, TestApp};
+use zero2prod::cleanup_worker::{purge_stale_subscriptions, CleanupOptions};
+
+fn options(dry_run: bool) -> CleanupOptions {
+    CleanupOptions {
+        max_pending_age: chrono::Duration::days(7),
+        batch_size: 2,
+        dry_run,
+    }
+}
+
+/// Moves every subscriber's last status change `days` into the past.
+async fn age_subscribers(app: &TestApp, days: i32) {
+    sqlx::query!(
+        "UPDATE subscriptions SET status_changed_at = status_changed_at - make_interval(days => $1)",
+        days
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+}
+
+async fn count_rows(app: &TestApp) -> (i64, i64) {
+    let subscriptions = sqlx::query!("SELECT count(*) as \"count!\" FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    let tokens = sqlx::query!("SELECT count(*) as \"count!\" FROM subscription_tokens")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .count;
+    (subscriptions, tokens)
+}
+
+#[tokio::test]
+async fn stale_unconfirmed_subscriptions_are_purged_in_batches() {
+    let app = spawn_app().await;
+    for _ in 0..3 {
+        app.create_unconfirmed_subscriber(new_sub_request_body())
+            .await;
+    }
+    app.create_confirmed_subscriber(new_sub_request_body())
+        .await;
+    age_subscribers(&app, 8).await;
+    // recent enough to still confirm
+    app.create_unconfirmed_subscriber(new_sub_request_body())
+        .await;
+
+    let report = purge_stale_subscriptions(&app.db_pool, options(false))
+        .await
+        .unwrap();
+
+    assert_eq!(report.purged, 3);
+    assert_eq!(report.batches, 2);
+    assert_eq!(count_rows(&app).await, (2, 2));
+}
+
+#[tokio::test]
+async fn a_dry_run_only_counts_the_stale_subscriptions() {
+    let app = spawn_app().await;
+    app.create_unconfirmed_subscriber(new_sub_request_body())
+        .await;
+    age_subscribers(&app, 8).await;
+
+    let report = purge_stale_subscriptions(&app.db_pool, options(true))
+        .await
+        .unwrap();
+
+    assert!(report.dry_run);
+    assert_eq!(report.purged, 1);
+    assert_eq!(count_rows(&app).await, (1, 1));
+}
+
+#[tokio::test]
+async fn a_purged_address_can_subscribe_again() {
+    let app = spawn_app().await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+    app.create_unconfirmed_subscriber(body.into()).await;
+    age_subscribers(&app, 8).await;
+
+    purge_stale_subscriptions(&app.db_pool, options(false))
+        .await
+        .unwrap();
+
+    app.create_unconfirmed_subscriber(body.into()).await;
+    assert_eq!(count_rows(&app).await, (1, 1));
+}
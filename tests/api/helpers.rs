@@ -1,5 +1,6 @@
 use once_cell::sync::Lazy;
 use reqwest::{Response, Url};
+use secrecy::Secret;
 use sqlx::{Connection, Executor, PgConnection, PgPool};
 use tokio::task::AbortHandle;
 use uuid::Uuid;
@@ -8,7 +9,11 @@ use wiremock::{
     Mock, MockServer, Request, ResponseTemplate,
 };
 use zero2prod::{
-    configuration::{get_configuration, DatabaseSettings, Environment},
+    configuration::{get_configuration, DatabaseSettings, Environment, Settings},
+    delivery_worker::{
+        send_due_digests, try_execute_task, ExecutionOutcome, PreferencesLinks, RetryPolicy,
+    },
+    email_client::EmailClient,
     routes::auth,
     startup::{get_connection_pool, Application},
     telemetry,
@@ -64,8 +69,10 @@ pub struct TestApp {
     pub email_server: MockServer,
     pub app_abort_handler: AbortHandle,
     pub test_user: TestUser,
+    pub hmac_secret: Secret<String>,
     // pub api_client: reqwest::Client,
-    // pub email_client: EmailClient,
+    pub email_client: EmailClient,
+    pub retry_policy: RetryPolicy,
 }
 
 impl TestApp {
@@ -73,7 +80,7 @@ impl TestApp {
     pub async fn post_subscriptions(&self, body: String) -> Response {
         let client = reqwest::Client::new();
         client
-            .post(&format!("{}/subscriptions", self.address))
+            .post(format!("{}/subscriptions", self.address))
             .header("Content-Type", "application/x-www-form-urlencoded")
             .body(body)
             .send()
@@ -93,12 +100,12 @@ impl TestApp {
             assert_eq!(links.len(), 1);
 
             let raw_link = links[0].as_str().to_owned();
-            let confirmation_link = Url::parse(&raw_link).unwrap();
-            confirmation_link
+
+            Url::parse(&raw_link).unwrap()
         };
 
-        let html = get_link(&body["HtmlBody"].as_str().unwrap());
-        let plain_text = get_link(&body["TextBody"].as_str().unwrap());
+        let html = get_link(body["HtmlBody"].as_str().unwrap());
+        let plain_text = get_link(body["TextBody"].as_str().unwrap());
 
         (html, plain_text)
     }
@@ -142,17 +149,184 @@ impl TestApp {
     /// post a newsletter
     pub async fn post_newsletters(&self, body: &serde_json::Value) -> reqwest::Response {
         reqwest::Client::new()
-            .post(&format!("{}/newsletters", &self.address))
+            .post(format!("{}/newsletters", &self.address))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .json(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    /// run the delivery worker until no delivery is due
+    pub async fn dispatch_all_pending_emails(&self) {
+        let links = PreferencesLinks {
+            base_url: format!("http://127.0.0.1:{}", self.port),
+            hmac_secret: self.hmac_secret.clone(),
+        };
+        loop {
+            if let ExecutionOutcome::EmptyQueue = try_execute_task(
+                &self.db_pool,
+                &self.email_client,
+                &links,
+                &self.retry_policy,
+            )
+            .await
+            .unwrap()
+            {
+                break;
+            }
+        }
+    }
+
+    /// send the weekly digests due, returning how many were sent
+    pub async fn send_due_digests(&self) -> u64 {
+        let links = PreferencesLinks {
+            base_url: format!("http://127.0.0.1:{}", self.port),
+            hmac_secret: self.hmac_secret.clone(),
+        };
+        send_due_digests(
+            &self.db_pool,
+            &self.email_client,
+            &links,
+            &self.retry_policy,
+        )
+        .await
+        .unwrap()
+    }
+
+    /// publish a newsletter with an `Idempotency-Key` header
+    pub async fn post_newsletters_with_key(
+        &self,
+        body: &serde_json::Value,
+        idempotency_key: &str,
+    ) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!("{}/newsletters", &self.address))
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .header("Idempotency-Key", idempotency_key)
+            .json(body)
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    /// import subscribers from a CSV document
+    pub async fn post_subscribers_import(&self, csv: &str, confirmed: bool) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!("{}/admin/subscribers/import", &self.address))
+            .query(&[("confirmed", confirmed)])
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .header("Content-Type", "text/csv")
+            .body(csv.to_string())
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    /// export subscribers, `query` being the export filters
+    pub async fn get_subscribers_export(&self, query: &[(&str, &str)]) -> reqwest::Response {
+        reqwest::Client::new()
+            .get(format!("{}/admin/subscribers/export", &self.address))
+            .query(query)
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    /// GET an admin page, e.g. `/admin/subscribers`, as the test user
+    pub async fn get_admin(&self, path: &str, query: &[(&str, &str)]) -> reqwest::Response {
+        reqwest::Client::new()
+            .get(format!("{}{}", &self.address, path))
+            .query(query)
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    /// POST a form to an admin endpoint as the test user, without following redirects
+    pub async fn post_admin(&self, path: &str, form: &[(&str, &str)]) -> reqwest::Response {
+        reqwest::Client::builder()
+            .redirect(reqwest::redirect::Policy::none())
+            .build()
+            .unwrap()
+            .post(format!("{}{}", &self.address, path))
+            .form(form)
+            .basic_auth(&self.test_user.username, Some(&self.test_user.password))
+            .send()
+            .await
+            .expect("Failed to execute request.")
+    }
+
+    /// send JSON to an admin endpoint as the test user, e.g. `PUT /admin/newsletters/{id}`
+    pub async fn json_admin(
+        &self,
+        method: reqwest::Method,
+        path: &str,
+        body: &serde_json::Value,
+    ) -> reqwest::Response {
+        reqwest::Client::new()
+            .request(method, format!("{}{}", &self.address, path))
             .basic_auth(&self.test_user.username, Some(&self.test_user.password))
             .json(body)
             .send()
             .await
             .expect("Failed to execute request.")
     }
+
+    /// request a data access email for the given address
+    pub async fn post_data_request(&self, email: &str) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!("{}/subscriptions/data/request", self.address))
+            .form(&[("email", email)])
+            .send()
+            .await
+            .expect("failed to execute request.")
+    }
+
+    /// request a change of email address from the manage subscription page
+    pub async fn post_email_change(&self, token: &str, email: &str) -> reqwest::Response {
+        reqwest::Client::new()
+            .post(format!("{}/subscriptions/email/change", self.address))
+            .form(&[("token", token), ("email", email)])
+            .send()
+            .await
+            .expect("failed to execute request.")
+    }
+
+    /// Requests a data access email and returns the magic link it contains
+    pub async fn get_data_access_link(&self, email: &str) -> Url {
+        let _guard = Mock::given(path("/email"))
+            .and(method("POST"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount_as_scoped(&self.email_server)
+            .await;
+
+        self.post_data_request(email)
+            .await
+            .error_for_status()
+            .unwrap();
+
+        let email_request = self
+            .email_server
+            .received_requests()
+            .await
+            .unwrap()
+            .pop()
+            .unwrap();
+        self.get_confirmation_links(&email_request).0
+    }
 }
 
 /// Spawns a new test app
 pub async fn spawn_app() -> TestApp {
+    spawn_app_with(|_| {}).await
+}
+
+/// Spawns a new test app, letting the caller tweak the configuration first
+pub async fn spawn_app_with(configure: impl FnOnce(&mut Settings)) -> TestApp {
     // The first time `initialize` is invoked the code in `TRACING` is executed.
     // All other invocations will instead skip execution.
     Lazy::force(&TRACING);
@@ -166,6 +340,9 @@ pub async fn spawn_app() -> TestApp {
         c.application.port = 0;
         c.email_client.base_url = email_server.uri();
         c.env = Environment::Testing;
+        // tests seed data through the public API, the rate limiting tests set their own limits
+        c.rate_limit.routes = vec![];
+        configure(&mut c);
         c
     };
 
@@ -194,6 +371,9 @@ pub async fn spawn_app() -> TestApp {
         email_server,
         app_abort_handler: t.abort_handle(),
         test_user,
+        hmac_secret: config.application.hmac_secret,
+        email_client: config.email_client.clone().client(),
+        retry_policy: RetryPolicy::from(&config.delivery),
     };
 
     test_app
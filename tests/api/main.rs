@@ -7,3 +7,33 @@ mod subscriptions;
 mod subscriptions_confirm;
 
 mod newsletter;
+
+mod subscribers_import;
+
+mod subscribers_export;
+
+mod subscriptions_data;
+
+mod subscriptions_email;
+
+mod subscriptions_preferences;
+
+mod rate_limit;
+
+mod cleanup_worker;
+
+mod admin_subscribers;
+
+mod signup_report;
+
+mod newsletter_schedule;
+
+mod newsletter_drafts;
+
+mod newsletter_test_send;
+
+mod newsletter_merge_tags;
+
+mod newsletter_markdown;
+
+mod newsletter_plain_text;
@@ -1,6 +1,6 @@
 use std::assert_eq;
 
-use crate::helpers::{new_sub_request_body, spawn_app};
+use crate::helpers::{new_sub_request_body, spawn_app, spawn_app_with, TestApp};
 use once_cell::sync::Lazy;
 use uuid::Uuid;
 use wiremock::{
@@ -14,7 +14,8 @@ static NEWSLETTER_CORRECT_BODY: Lazy<serde_json::Value> = Lazy::new(|| {
         "content": {
             "text": "Newsletter body as plain text",
             "html": "<p>Newsletter body as HTML</p>",
-        }
+        },
+        "send": true,
     })
 });
 
@@ -30,8 +31,9 @@ async fn newsletters_are_not_delivered_to_unconfirmed_subscribers() {
         .mount(&app.email_server)
         .await;
 
-    let response = app.post_newsletters(&*NEWSLETTER_CORRECT_BODY).await;
-    assert_eq!(response.status(), 200);
+    let response = app.post_newsletters(&NEWSLETTER_CORRECT_BODY).await;
+    assert_eq!(response.status(), 202);
+    app.dispatch_all_pending_emails().await;
 
     // Mock verifies on Drop that we haven't sent the newsletter email
 }
@@ -49,12 +51,61 @@ async fn newsletters_are_delivered_to_confirmed_subscribers() {
         .mount(&app.email_server)
         .await;
 
-    let response = app.post_newsletters(&*NEWSLETTER_CORRECT_BODY).await;
-    assert_eq!(response.status(), 200);
+    let response = app.post_newsletters(&NEWSLETTER_CORRECT_BODY).await;
+    assert_eq!(response.status(), 202);
+    app.dispatch_all_pending_emails().await;
 
     // Mock verifies on Drop that we haven't sent the newsletter email
 }
 
+#[tokio::test]
+async fn newsletters_are_stored_as_drafts_unless_sending_is_asked_for() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber(new_sub_request_body())
+        .await;
+    let mock = Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .named("Nothing is sent for a draft")
+        .mount_as_scoped(&app.email_server)
+        .await;
+
+    let mut draft = NEWSLETTER_CORRECT_BODY.clone();
+    draft["send"] = false.into();
+    let response = app.post_newsletters(&draft).await;
+    assert_eq!(response.status(), 201);
+    let created: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(created["status"], "draft");
+    app.dispatch_all_pending_emails().await;
+    drop(mock);
+
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    let issue_id = created["issue_id"].as_str().unwrap();
+    let response = app
+        .post_admin(&format!("/admin/newsletters/{issue_id}/send"), &[])
+        .await;
+    assert_eq!(response.status(), 202);
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn a_send_time_requires_asking_for_sending() {
+    let app = spawn_app().await;
+
+    let mut draft = NEWSLETTER_CORRECT_BODY.clone();
+    draft["send"] = false.into();
+    draft["send_at"] = (chrono::Utc::now() + chrono::Duration::hours(1))
+        .to_rfc3339()
+        .into();
+    let response = app.post_newsletters(&draft).await;
+
+    assert_eq!(response.status(), 400);
+}
+
 #[tokio::test]
 async fn newsletters_returns_400_for_invalid_data() {
     let app = spawn_app().await;
@@ -105,7 +156,8 @@ async fn newsletters_are_delivered_to_multiple_subscribers() {
         .mount(&app.email_server)
         .await;
 
-    app.post_newsletters(&*NEWSLETTER_CORRECT_BODY).await;
+    app.post_newsletters(&NEWSLETTER_CORRECT_BODY).await;
+    app.dispatch_all_pending_emails().await;
     // Mock verifies on Drop that we have sent the newsletter email to each subscriber
 }
 
@@ -113,7 +165,7 @@ async fn newsletters_are_delivered_to_multiple_subscribers() {
 async fn requests_missing_authorization_are_rejected() {
     let app = spawn_app().await;
     let response = reqwest::Client::new()
-        .post(&format!("{}/newsletters", &app.address))
+        .post(format!("{}/newsletters", &app.address))
         .json(&serde_json::json!({
             "title": "Newsletter title",
             "content": {
@@ -138,7 +190,7 @@ async fn invalid_auth_is_rejected() {
 
     // assert 401 for non-existing user
     let response = reqwest::Client::new()
-        .post(&format!("{}/newsletters", &app.address))
+        .post(format!("{}/newsletters", &app.address))
         .basic_auth(Uuid::new_v4().to_string(), Some(Uuid::new_v4().to_string()))
         .json(&*NEWSLETTER_CORRECT_BODY)
         .send()
@@ -153,7 +205,7 @@ async fn invalid_auth_is_rejected() {
 
     // assert 401 for incorrect password
     let response = reqwest::Client::new()
-        .post(&format!("{}/newsletters", &app.address))
+        .post(format!("{}/newsletters", &app.address))
         .basic_auth(&app.test_user.username, Some(Uuid::new_v4().to_string()))
         .json(&*NEWSLETTER_CORRECT_BODY)
         .send()
@@ -162,3 +214,550 @@ async fn invalid_auth_is_rejected() {
 
     assert_eq!(401, response.status());
 }
+
+#[tokio::test]
+async fn published_issues_are_stored_and_can_be_fetched() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber(new_sub_request_body())
+        .await;
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    let published: serde_json::Value = app
+        .post_newsletters(&NEWSLETTER_CORRECT_BODY)
+        .await
+        .error_for_status()
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    let issue_id = published["issue_id"].as_str().unwrap();
+    app.dispatch_all_pending_emails().await;
+
+    let issues: serde_json::Value = app
+        .get_admin("/admin/newsletters", &[])
+        .await
+        .error_for_status()
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(issues.as_array().unwrap().len(), 1);
+    assert_eq!(issues[0]["id"], issue_id);
+    assert_eq!(issues[0]["title"], "Newsletter title");
+    assert_eq!(issues[0]["status"], "sent");
+    assert_eq!(issues[0]["author"], app.test_user.username.as_str());
+    assert!(issues[0].get("html_content").is_none());
+
+    let issue: serde_json::Value = app
+        .get_admin(&format!("/admin/newsletters/{issue_id}"), &[])
+        .await
+        .error_for_status()
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(issue["html_content"], "<p>Newsletter body as HTML</p>");
+    assert_eq!(issue["text_content"], "Newsletter body as plain text");
+    assert!(issue["sent_at"].is_string());
+    assert_eq!(issue["deliveries"]["sent"], 1);
+    assert_eq!(issue["deliveries"]["pending"], 0);
+
+    let response = app
+        .get_admin(&format!("/admin/newsletters/{}", Uuid::new_v4()), &[])
+        .await;
+    assert_eq!(response.status(), 404);
+}
+
+#[tokio::test]
+async fn listing_issues_requires_authentication() {
+    let app = spawn_app().await;
+
+    let response = reqwest::get(format!("{}/admin/newsletters", app.address))
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), 401);
+}
+
+#[tokio::test]
+async fn retrying_with_the_same_idempotency_key_replays_the_first_response() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber(new_sub_request_body())
+        .await;
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let first = app
+        .post_newsletters_with_key(&NEWSLETTER_CORRECT_BODY, "retry-me")
+        .await;
+    assert_eq!(first.status(), 202);
+    let first: serde_json::Value = first.json().await.unwrap();
+
+    let retry = app
+        .post_newsletters_with_key(&NEWSLETTER_CORRECT_BODY, "retry-me")
+        .await;
+    assert_eq!(retry.status(), 202);
+    assert_eq!(
+        retry.headers()["content-type"].to_str().unwrap(),
+        "application/json"
+    );
+    let retry: serde_json::Value = retry.json().await.unwrap();
+    assert_eq!(retry, first);
+    app.dispatch_all_pending_emails().await;
+
+    // Mock verifies on Drop that the newsletter was sent once
+}
+
+#[tokio::test]
+async fn a_new_idempotency_key_publishes_again() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber(new_sub_request_body())
+        .await;
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+
+    for key in ["first-key", "second-key"] {
+        let response = app
+            .post_newsletters_with_key(&NEWSLETTER_CORRECT_BODY, key)
+            .await;
+        assert_eq!(response.status(), 202);
+    }
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn concurrent_requests_with_the_same_idempotency_key_send_once() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber(new_sub_request_body())
+        .await;
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let (first, second) = tokio::join!(
+        app.post_newsletters_with_key(&NEWSLETTER_CORRECT_BODY, "concurrent"),
+        app.post_newsletters_with_key(&NEWSLETTER_CORRECT_BODY, "concurrent"),
+    );
+
+    assert_eq!(first.status(), 202);
+    assert_eq!(second.status(), 202);
+    assert_eq!(first.text().await.unwrap(), second.text().await.unwrap());
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn reusing_an_idempotency_key_for_another_request_is_rejected_with_a_422() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber(new_sub_request_body())
+        .await;
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let first = app
+        .post_newsletters_with_key(&NEWSLETTER_CORRECT_BODY, "reused")
+        .await;
+    assert_eq!(first.status(), 202);
+    let mut other = NEWSLETTER_CORRECT_BODY.clone();
+    other["title"] = "Another title".into();
+    let response = app.post_newsletters_with_key(&other, "reused").await;
+
+    assert_eq!(response.status(), 422);
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn an_expired_idempotency_key_can_be_used_for_another_request() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber(new_sub_request_body())
+        .await;
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+
+    let first = app
+        .post_newsletters_with_key(&NEWSLETTER_CORRECT_BODY, "expiring")
+        .await;
+    assert_eq!(first.status(), 202);
+    sqlx::query!("UPDATE idempotency SET created_at = now() - interval '30 days'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    let mut other = NEWSLETTER_CORRECT_BODY.clone();
+    other["title"] = "Another title".into();
+    let response = app.post_newsletters_with_key(&other, "expiring").await;
+
+    assert_eq!(response.status(), 202);
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn invalid_idempotency_keys_are_rejected() {
+    let app = spawn_app().await;
+
+    for key in ["", &"a".repeat(50)] {
+        let response = app
+            .post_newsletters_with_key(&NEWSLETTER_CORRECT_BODY, key)
+            .await;
+        assert_eq!(response.status(), 400);
+    }
+}
+
+#[tokio::test]
+async fn the_idempotency_key_can_be_required() {
+    let app = spawn_app_with(|c| c.idempotency.required = true).await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app.post_newsletters(&NEWSLETTER_CORRECT_BODY).await;
+
+    assert_eq!(response.status(), 400);
+}
+
+#[tokio::test]
+async fn publishing_queues_the_issue_until_the_worker_delivers_it() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber(new_sub_request_body())
+        .await;
+    let mock = Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .named("Nothing is sent while publishing")
+        .mount_as_scoped(&app.email_server)
+        .await;
+
+    let response = app.post_newsletters(&NEWSLETTER_CORRECT_BODY).await;
+    assert_eq!(response.status(), 202);
+    let issue_id: Uuid = response.json::<serde_json::Value>().await.unwrap()["issue_id"]
+        .as_str()
+        .unwrap()
+        .parse()
+        .unwrap();
+    drop(mock);
+
+    let pending = sqlx::query!(
+        "SELECT status FROM newsletter_deliveries WHERE issue_id = $1",
+        issue_id
+    )
+    .fetch_all(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(pending.len(), 1);
+    assert_eq!(pending[0].status, "pending");
+
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    app.dispatch_all_pending_emails().await;
+
+    let delivered = sqlx::query!(
+        "SELECT status, processed_at FROM newsletter_deliveries WHERE issue_id = $1",
+        issue_id
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(delivered.status, "sent");
+    assert!(delivered.processed_at.is_some());
+    let issue = sqlx::query!(
+        "SELECT status FROM newsletter_issues WHERE id = $1",
+        issue_id
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(issue.status, "sent");
+}
+
+#[tokio::test]
+async fn subscribers_who_leave_before_delivery_are_skipped() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber(new_sub_request_body())
+        .await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    app.post_newsletters(&NEWSLETTER_CORRECT_BODY)
+        .await
+        .error_for_status()
+        .unwrap();
+    sqlx::query!("UPDATE subscriptions SET status = 'unsubscribed'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    app.dispatch_all_pending_emails().await;
+
+    let delivery = sqlx::query!("SELECT status FROM newsletter_deliveries")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(delivery.status, "skipped");
+}
+
+/// Publishes the standard issue and returns its id
+async fn publish(app: &TestApp) -> Uuid {
+    let published: serde_json::Value = app
+        .post_newsletters(&NEWSLETTER_CORRECT_BODY)
+        .await
+        .error_for_status()
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    published["issue_id"].as_str().unwrap().parse().unwrap()
+}
+
+/// Retries are due immediately, so a single dispatch goes through every attempt
+async fn spawn_app_with_immediate_retries() -> TestApp {
+    spawn_app_with(|c| {
+        c.delivery.max_attempts = 3;
+        c.delivery.base_backoff_seconds = 0;
+        c.delivery.max_backoff_seconds = 0;
+    })
+    .await
+}
+
+#[tokio::test]
+async fn transient_failures_are_retried_later() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber(new_sub_request_body())
+        .await;
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(500))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let issue_id = publish(&app).await;
+    app.dispatch_all_pending_emails().await;
+
+    let delivery = sqlx::query!(
+        "SELECT status, attempts, next_attempt_at FROM newsletter_deliveries WHERE issue_id = $1",
+        issue_id
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(delivery.status, "pending");
+    assert_eq!(delivery.attempts, 1);
+    assert!(delivery.next_attempt_at > chrono::Utc::now());
+
+    let attempt = sqlx::query!("SELECT attempt, error FROM delivery_attempts")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(attempt.attempt, 1);
+    assert!(attempt.error.unwrap().contains("500"));
+}
+
+#[tokio::test]
+async fn transient_failures_are_retried_until_the_delivery_succeeds() {
+    let app = spawn_app_with_immediate_retries().await;
+    app.create_confirmed_subscriber(new_sub_request_body())
+        .await;
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(429))
+        .up_to_n_times(2)
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    publish(&app).await;
+    app.dispatch_all_pending_emails().await;
+
+    let delivery = sqlx::query!("SELECT status, attempts FROM newsletter_deliveries")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(delivery.status, "sent");
+    assert_eq!(delivery.attempts, 3);
+    let errors: Vec<_> = sqlx::query!("SELECT error FROM delivery_attempts ORDER BY attempt")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|r| r.error.is_some())
+        .collect();
+    assert_eq!(errors, vec![true, true, false]);
+}
+
+#[tokio::test]
+async fn deliveries_are_dead_lettered_once_out_of_attempts() {
+    let app = spawn_app_with_immediate_retries().await;
+    app.create_confirmed_subscriber(new_sub_request_body())
+        .await;
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(503))
+        .expect(3)
+        .mount(&app.email_server)
+        .await;
+
+    let issue_id = publish(&app).await;
+    app.dispatch_all_pending_emails().await;
+
+    let delivery = sqlx::query!("SELECT status, attempts FROM newsletter_deliveries")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(delivery.status, "dead_letter");
+    assert_eq!(delivery.attempts, 3);
+    // dead letters do not hold the issue back
+    let issue: serde_json::Value = app
+        .get_admin(&format!("/admin/newsletters/{issue_id}"), &[])
+        .await
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(issue["status"], "sent");
+    assert_eq!(issue["deliveries"]["dead_letter"], 1);
+}
+
+#[tokio::test]
+async fn permanent_failures_are_dead_lettered_without_retrying() {
+    let app = spawn_app_with_immediate_retries().await;
+    app.create_confirmed_subscriber(new_sub_request_body())
+        .await;
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(422))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    publish(&app).await;
+    app.dispatch_all_pending_emails().await;
+
+    let delivery = sqlx::query!("SELECT status, attempts FROM newsletter_deliveries")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(delivery.status, "dead_letter");
+    assert_eq!(delivery.attempts, 1);
+}
+
+#[tokio::test]
+async fn dead_lettered_deliveries_can_be_replayed() {
+    let app = spawn_app_with_immediate_retries().await;
+    app.create_confirmed_subscriber(new_sub_request_body())
+        .await;
+    let failing = Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(422))
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+    let issue_id = publish(&app).await;
+    app.dispatch_all_pending_emails().await;
+    drop(failing);
+
+    let dead_letters: serde_json::Value = app
+        .get_admin(
+            &format!("/admin/newsletters/{issue_id}/deliveries"),
+            &[("status", "dead_letter")],
+        )
+        .await
+        .error_for_status()
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(dead_letters.as_array().unwrap().len(), 1);
+    assert!(dead_letters[0]["last_error"]
+        .as_str()
+        .unwrap()
+        .contains("422"));
+
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    let replayed: serde_json::Value = app
+        .post_admin(&format!("/admin/newsletters/{issue_id}/replay"), &[])
+        .await
+        .error_for_status()
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(replayed["replayed"], 1);
+    let issue = sqlx::query!("SELECT status FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(issue.status, "sending");
+
+    app.dispatch_all_pending_emails().await;
+
+    let deliveries: serde_json::Value = app
+        .get_admin(&format!("/admin/newsletters/{issue_id}/deliveries"), &[])
+        .await
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(deliveries[0]["status"], "sent");
+    assert!(deliveries[0]["last_error"].is_null());
+    let attempts = sqlx::query!("SELECT attempt FROM delivery_attempts ORDER BY attempt")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(attempts.len(), 2);
+    let audit = sqlx::query!(
+        "SELECT action FROM audit_log WHERE subject = $1",
+        issue_id.to_string()
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(audit.action, "deliveries_replayed");
+}
+
+#[tokio::test]
+async fn replaying_requires_authentication_and_an_existing_issue() {
+    let app = spawn_app().await;
+
+    let response = reqwest::Client::new()
+        .post(format!(
+            "{}/admin/newsletters/{}/replay",
+            app.address,
+            Uuid::new_v4()
+        ))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 401);
+
+    let response = app
+        .post_admin(
+            &format!("/admin/newsletters/{}/replay", Uuid::new_v4()),
+            &[],
+        )
+        .await;
+    assert_eq!(response.status(), 404);
+}
@@ -0,0 +1,225 @@
+use crate::helpers::{new_sub_request_body, spawn_app, TestApp};
+use reqwest::Method;
+use uuid::Uuid;
+use wiremock::{
+    matchers::{any, path},
+    Mock, ResponseTemplate,
+};
+
+fn draft_body(title: &str, text: &str) -> serde_json::Value {
+    serde_json::json!({
+        "title": title,
+        "content": {
+            "text": text,
+            "html": format!("<p>{text}</p>"),
+        },
+    })
+}
+
+/// Creates a draft and returns its id
+async fn create_draft(app: &TestApp, title: &str, text: &str) -> Uuid {
+    let response = app
+        .json_admin(Method::POST, "/admin/newsletters", &draft_body(title, text))
+        .await;
+    assert_eq!(response.status(), 201);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["status"], "draft");
+    assert_eq!(body["revision"], 1);
+    body["id"].as_str().unwrap().parse().unwrap()
+}
+
+async fn edit_draft(app: &TestApp, issue_id: Uuid, title: &str, text: &str) -> reqwest::Response {
+    app.json_admin(
+        Method::PUT,
+        &format!("/admin/newsletters/{issue_id}"),
+        &draft_body(title, text),
+    )
+    .await
+}
+
+#[tokio::test]
+async fn drafts_are_not_sent_until_the_send_action() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber(new_sub_request_body())
+        .await;
+
+    let not_yet = Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount_as_scoped(&app.email_server)
+        .await;
+    let issue_id = create_draft(&app, "Draft", "First version").await;
+    assert_eq!(
+        edit_draft(&app, issue_id, "Draft", "Second version")
+            .await
+            .status(),
+        200
+    );
+    app.dispatch_all_pending_emails().await;
+    drop(not_yet);
+
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    let response = app
+        .post_admin(&format!("/admin/newsletters/{issue_id}/send"), &[])
+        .await;
+    assert_eq!(response.status(), 202);
+    app.dispatch_all_pending_emails().await;
+
+    let requests = app.email_server.received_requests().await.unwrap();
+    let email_request = requests.last().unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    assert!(body["TextBody"]
+        .as_str()
+        .unwrap()
+        .contains("Second version"));
+}
+
+#[tokio::test]
+async fn drafts_can_be_scheduled() {
+    let app = spawn_app().await;
+    let issue_id = create_draft(&app, "Draft", "Body").await;
+
+    let send_at = (chrono::Utc::now() + chrono::Duration::hours(1)).to_rfc3339();
+    let response = app
+        .post_admin(
+            &format!("/admin/newsletters/{issue_id}/send"),
+            &[("send_at", &send_at)],
+        )
+        .await;
+    assert_eq!(response.status(), 202);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["status"], "scheduled");
+
+    let past = (chrono::Utc::now() - chrono::Duration::hours(1)).to_rfc3339();
+    let issue_id = create_draft(&app, "Other draft", "Body").await;
+    let response = app
+        .post_admin(
+            &format!("/admin/newsletters/{issue_id}/send"),
+            &[("send_at", &past)],
+        )
+        .await;
+    assert_eq!(response.status(), 400);
+}
+
+#[tokio::test]
+async fn only_drafts_can_be_edited_or_sent() {
+    let app = spawn_app().await;
+    let issue_id = create_draft(&app, "Draft", "Body").await;
+    let send = format!("/admin/newsletters/{issue_id}/send");
+    assert_eq!(app.post_admin(&send, &[]).await.status(), 202);
+
+    assert_eq!(
+        edit_draft(&app, issue_id, "Too late", "Body")
+            .await
+            .status(),
+        409
+    );
+    assert_eq!(app.post_admin(&send, &[]).await.status(), 409);
+    let restore = format!("/admin/newsletters/{issue_id}/revisions/1/restore");
+    assert_eq!(app.post_admin(&restore, &[]).await.status(), 409);
+}
+
+#[tokio::test]
+async fn edits_keep_a_revision_history_that_can_be_diffed_and_restored() {
+    let app = spawn_app().await;
+    let issue_id = create_draft(&app, "Title", "First line\nSecond line").await;
+    edit_draft(&app, issue_id, "Better title", "First line\nChanged line").await;
+
+    let revisions: Vec<serde_json::Value> = app
+        .get_admin(&format!("/admin/newsletters/{issue_id}/revisions"), &[])
+        .await
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(revisions.len(), 2);
+    assert_eq!(revisions[0]["title"], "Title");
+    assert_eq!(revisions[1]["title"], "Better title");
+    assert_eq!(revisions[1]["author"], app.test_user.username);
+
+    let diff: serde_json::Value = app
+        .get_admin(
+            &format!("/admin/newsletters/{issue_id}/diff"),
+            &[("from", "1")],
+        )
+        .await
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(diff["to"], 2);
+    let text_diff = diff["text_content"].as_str().unwrap();
+    assert!(text_diff.contains("-Second line"));
+    assert!(text_diff.contains("+Changed line"));
+    assert!(!text_diff.contains("-First line"));
+
+    let response = app
+        .post_admin(
+            &format!("/admin/newsletters/{issue_id}/revisions/1/restore"),
+            &[],
+        )
+        .await;
+    assert_eq!(response.status(), 200);
+    let issue: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(issue["revision"], 3);
+    assert_eq!(issue["title"], "Title");
+    assert_eq!(issue["text_content"], "First line\nSecond line");
+
+    let revision: serde_json::Value = app
+        .get_admin(&format!("/admin/newsletters/{issue_id}/revisions/2"), &[])
+        .await
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(revision["text_content"], "First line\nChanged line");
+}
+
+#[tokio::test]
+async fn the_preview_shows_both_versions_of_the_email() {
+    let app = spawn_app().await;
+    let issue_id = create_draft(&app, "Title", "Hello & welcome").await;
+
+    let response = app
+        .get_admin(&format!("/admin/newsletters/{issue_id}/preview"), &[])
+        .await;
+    assert_eq!(response.status(), 200);
+    let page = response.text().await.unwrap();
+    // the HTML version is escaped into the sandboxed frame
+    assert!(page.contains("srcdoc=\"&lt;p&gt;Hello &amp; welcome&lt;/p&gt;"));
+    assert!(page.contains("<pre>Hello &amp; welcome"));
+}
+
+#[tokio::test]
+async fn unknown_issues_and_revisions_are_not_found() {
+    let app = spawn_app().await;
+    let missing = Uuid::new_v4();
+    for path in [
+        format!("/admin/newsletters/{missing}/preview"),
+        format!("/admin/newsletters/{missing}/revisions"),
+        format!("/admin/newsletters/{missing}/revisions/1"),
+    ] {
+        assert_eq!(app.get_admin(&path, &[]).await.status(), 404);
+    }
+    assert_eq!(
+        edit_draft(&app, missing, "Title", "Body").await.status(),
+        404
+    );
+
+    let issue_id = create_draft(&app, "Title", "Body").await;
+    let path = format!("/admin/newsletters/{issue_id}/revisions/7/restore");
+    assert_eq!(app.post_admin(&path, &[]).await.status(), 404);
+}
+
+#[tokio::test]
+async fn drafts_require_an_authenticated_user() {
+    let app = spawn_app().await;
+    let response = reqwest::Client::new()
+        .post(format!("{}/admin/newsletters", &app.address))
+        .json(&draft_body("Title", "Body"))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 401);
+}
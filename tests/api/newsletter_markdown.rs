@@ -0,0 +1,120 @@
+use crate::helpers::{new_sub_request_body, spawn_app};
+use reqwest::Method;
+use wiremock::{matchers::path, Mock, ResponseTemplate};
+
+const MARKDOWN: &str = "\
+# Weekly news
+
+Hi {{ name }}, read [the post](https://example.com/post).
+
+<script>alert('no')</script>
+";
+
+#[tokio::test]
+async fn markdown_issues_are_sent_as_html_in_the_layout_and_as_text() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber(new_sub_request_body())
+        .await;
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_newsletters(&serde_json::json!({
+            "title": "Newsletter title",
+            "content": { "markdown": MARKDOWN },
+            "send": true,
+        }))
+        .await;
+    assert_eq!(response.status(), 202);
+    app.dispatch_all_pending_emails().await;
+
+    let requests = app.email_server.received_requests().await.unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&requests.last().unwrap().body).unwrap();
+    let html = body["HtmlBody"].as_str().unwrap();
+    assert!(html.starts_with("<!DOCTYPE html>"));
+    assert!(html.contains("<h1>Weekly news</h1>"));
+    assert!(html.contains("<a href=\"https://example.com/post\""));
+    assert!(!html.contains("<script>"));
+    assert!(!html.contains("{{"));
+
+    let text = body["TextBody"].as_str().unwrap();
+    assert!(text.starts_with("Weekly news\n===========\n\nHi "));
+    assert!(text.contains(", read the post[1].\n\n[1] https://example.com/post"));
+}
+
+#[tokio::test]
+async fn drafts_keep_their_markdown_source() {
+    let app = spawn_app().await;
+    let response = app
+        .json_admin(
+            Method::POST,
+            "/admin/newsletters",
+            &serde_json::json!({
+                "title": "Draft",
+                "content": { "markdown": "First *version*" },
+            }),
+        )
+        .await;
+    assert_eq!(response.status(), 201);
+    let issue: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(issue["markdown_content"], "First *version*");
+    assert_eq!(issue["text_content"], "First version");
+    let issue_id = issue["id"].as_str().unwrap();
+
+    // explicit bodies replace the rendered ones
+    let response = app
+        .json_admin(
+            Method::PUT,
+            &format!("/admin/newsletters/{issue_id}"),
+            &serde_json::json!({
+                "title": "Draft",
+                "content": { "markdown": "Second version", "text": "Hand written" },
+            }),
+        )
+        .await;
+    assert_eq!(response.status(), 200);
+    let issue: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(issue["text_content"], "Hand written");
+    assert!(issue["html_content"]
+        .as_str()
+        .unwrap()
+        .contains("<p>Second version</p>"));
+
+    let revision: serde_json::Value = app
+        .get_admin(&format!("/admin/newsletters/{issue_id}/revisions/1"), &[])
+        .await
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(revision["markdown_content"], "First *version*");
+    let diff: serde_json::Value = app
+        .get_admin(
+            &format!("/admin/newsletters/{issue_id}/diff"),
+            &[("from", "1")],
+        )
+        .await
+        .json()
+        .await
+        .unwrap();
+    assert!(diff["markdown_content"]
+        .as_str()
+        .unwrap()
+        .contains("+Second version"));
+}
+
+#[tokio::test]
+async fn content_needs_markdown_or_html() {
+    let app = spawn_app().await;
+    for content in [
+        serde_json::json!({}),
+        serde_json::json!({ "text": "Only text" }),
+    ] {
+        let response = app
+            .post_newsletters(&serde_json::json!({ "title": "Title", "content": content }))
+            .await;
+        assert_eq!(response.status(), 400);
+    }
+}
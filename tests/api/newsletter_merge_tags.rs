@@ -0,0 +1,99 @@
+use crate::helpers::spawn_app;
+use reqwest::Method;
+use wiremock::{matchers::path, Mock, ResponseTemplate};
+
+#[tokio::test]
+async fn issues_are_personalized_for_every_subscriber() {
+    let app = spawn_app().await;
+    let csv = "\
+email,name,Company
+ursula@example.com,Ursula,Acme & Co
+octavia@example.com,Octavia,
+";
+    assert_eq!(app.post_subscribers_import(csv, true).await.status(), 200);
+    let stored =
+        sqlx::query!("SELECT attributes FROM subscriptions WHERE email = 'ursula@example.com'")
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap();
+    assert_eq!(
+        stored.attributes,
+        serde_json::json!({ "company": "Acme & Co" })
+    );
+
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+    let response = app
+        .post_newsletters(&serde_json::json!({
+            "title": "News for {{ name }}",
+            "content": {
+                "text": "Hi {{ name }} of {{ company | default: \"nowhere\" }}",
+                "html": "<p>Hi {{ name }} of {{ company | default: \"nowhere\" }}</p>\
+                    <a href=\"{{ unsubscribe_url }}\">Unsubscribe</a>",
+            },
+            "send": true,
+        }))
+        .await;
+    assert_eq!(response.status(), 202);
+    app.dispatch_all_pending_emails().await;
+
+    let mut emails: Vec<serde_json::Value> = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .iter()
+        .map(|r| serde_json::from_slice(&r.body).unwrap())
+        .collect();
+    emails.sort_by_key(|e| e["To"].as_str().unwrap().to_string());
+
+    assert_eq!(emails[0]["To"], "octavia@example.com");
+    assert_eq!(emails[0]["Subject"], "News for Octavia");
+    assert!(emails[0]["TextBody"]
+        .as_str()
+        .unwrap()
+        .starts_with("Hi Octavia of nowhere"));
+
+    assert_eq!(emails[1]["Subject"], "News for Ursula");
+    assert!(emails[1]["TextBody"]
+        .as_str()
+        .unwrap()
+        .starts_with("Hi Ursula of Acme & Co"));
+    let html = emails[1]["HtmlBody"].as_str().unwrap();
+    assert!(html.starts_with("<p>Hi Ursula of Acme &amp; Co</p>"));
+    assert!(html.contains("<a href=\"http://127.0.0.1:"));
+    assert!(!html.contains("{{"));
+}
+
+#[tokio::test]
+async fn merge_tag_syntax_errors_are_rejected_when_the_issue_is_created() {
+    let app = spawn_app().await;
+    let broken = |title: &str, text: &str| {
+        serde_json::json!({
+            "title": title,
+            "content": { "text": text, "html": "<p>Fine</p>" },
+        })
+    };
+
+    for body in [
+        broken("Hi {{ name", "Fine"),
+        broken("Fine", "Hi {{ first name }}"),
+        broken("Fine", "Hi {{ name | upper }}"),
+    ] {
+        let response = app.post_newsletters(&body).await;
+        assert_eq!(response.status(), 400);
+        let response = app
+            .json_admin(Method::POST, "/admin/newsletters", &body)
+            .await;
+        assert_eq!(response.status(), 400);
+    }
+
+    let issues = sqlx::query!("SELECT COUNT(*) AS \"count!\" FROM newsletter_issues")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(issues.count, 0);
+}
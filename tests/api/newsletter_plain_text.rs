@@ -0,0 +1,103 @@
+use crate::helpers::{new_sub_request_body, spawn_app};
+use reqwest::Method;
+use wiremock::{matchers::path, Mock, ResponseTemplate};
+
+const HTML: &str = "\
+<h1>Weekly news</h1>
+<p>Hi there, read <a href=\"https://example.com/post\">the post</a>.</p>
+<ul><li>First point</li><li>Second point</li></ul>
+<p>See you next week.</p>";
+
+#[tokio::test]
+async fn the_text_body_is_derived_from_the_html_when_missing() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber(new_sub_request_body())
+        .await;
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_newsletters(&serde_json::json!({
+            "title": "Newsletter title",
+            "content": { "html": HTML },
+            "send": true,
+        }))
+        .await;
+    assert_eq!(response.status(), 202);
+    app.dispatch_all_pending_emails().await;
+
+    let requests = app.email_server.received_requests().await.unwrap();
+    let body: serde_json::Value = serde_json::from_slice(&requests.last().unwrap().body).unwrap();
+    // the preferences footer follows the issue
+    assert!(body["TextBody"].as_str().unwrap().starts_with(
+        "Weekly news\n===========\n\n\
+        Hi there, read the post[1].\n\n\
+        - First point\n- Second point\n\n\
+        See you next week.\n\n\
+        [1] https://example.com/post\n\n"
+    ));
+}
+
+#[tokio::test]
+async fn a_matching_text_body_passes_the_check() {
+    let app = spawn_app().await;
+
+    let response = app
+        .json_admin(
+            Method::POST,
+            "/admin/newsletters/text-check",
+            &serde_json::json!({
+                "html": HTML,
+                "text": "Weekly news\n\nHi there, read the post: https://example.com/post\n\n\
+                    - First point\n- Second point\n\nSee you next week.",
+            }),
+        )
+        .await;
+    assert_eq!(response.status(), 200);
+    let check: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(check["mismatch"], false);
+    assert_eq!(check["warnings"], serde_json::json!([]));
+    assert!(check["derived_text"]
+        .as_str()
+        .unwrap()
+        .contains("- First point"));
+}
+
+#[tokio::test]
+async fn a_placeholder_text_body_is_flagged() {
+    let app = spawn_app().await;
+
+    let response = app
+        .json_admin(
+            Method::POST,
+            "/admin/newsletters/text-check",
+            &serde_json::json!({
+                "html": HTML,
+                "text": "View online.",
+            }),
+        )
+        .await;
+    assert_eq!(response.status(), 200);
+    let check: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(check["mismatch"], true);
+    let warnings = check["warnings"].as_array().unwrap();
+    assert_eq!(warnings.len(), 3);
+    assert!(warnings[2]
+        .as_str()
+        .unwrap()
+        .contains("https://example.com/post"));
+}
+
+#[tokio::test]
+async fn the_text_check_requires_an_authenticated_user() {
+    let app = spawn_app().await;
+    let response = reqwest::Client::new()
+        .post(format!("{}/admin/newsletters/text-check", &app.address))
+        .json(&serde_json::json!({ "html": HTML, "text": "Weekly news" }))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 401);
+}
@@ -0,0 +1,284 @@
+use crate::helpers::{new_sub_request_body, spawn_app, TestApp};
+use chrono::{Duration, SecondsFormat, Utc};
+use uuid::Uuid;
+use wiremock::{
+    matchers::{any, path},
+    Mock, ResponseTemplate,
+};
+use zero2prod::newsletter_issues::start_due_issues;
+
+fn in_an_hour() -> String {
+    (Utc::now() + Duration::hours(1)).to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+/// Schedules an issue an hour from now and returns its id
+async fn schedule(app: &TestApp) -> Uuid {
+    let response = app
+        .post_newsletters(&serde_json::json!({
+            "title": "Newsletter title",
+            "content": {
+                "text": "Newsletter body as plain text",
+                "html": "<p>Newsletter body as HTML</p>",
+            },
+            "send": true,
+            "send_at": in_an_hour(),
+        }))
+        .await;
+    assert_eq!(response.status(), 202);
+    let body: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(body["status"], "scheduled");
+    body["issue_id"].as_str().unwrap().parse().unwrap()
+}
+
+/// Moves the issue's `send_at` into the past, as if its time had come
+async fn make_due(app: &TestApp, issue_id: Uuid) {
+    sqlx::query!(
+        "UPDATE newsletter_issues SET send_at = now() - interval '1 minute' WHERE id = $1",
+        issue_id
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+}
+
+async fn issue_status(app: &TestApp, issue_id: Uuid) -> String {
+    sqlx::query!(
+        "SELECT status FROM newsletter_issues WHERE id = $1",
+        issue_id
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap()
+    .status
+}
+
+#[tokio::test]
+async fn scheduled_issues_are_sent_once_their_time_comes() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber(new_sub_request_body())
+        .await;
+
+    let issue_id = schedule(&app).await;
+    let not_yet = Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount_as_scoped(&app.email_server)
+        .await;
+    assert!(start_due_issues(&app.db_pool).await.unwrap().is_empty());
+    app.dispatch_all_pending_emails().await;
+    assert_eq!(issue_status(&app, issue_id).await, "scheduled");
+    drop(not_yet);
+
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    make_due(&app, issue_id).await;
+    assert_eq!(
+        start_due_issues(&app.db_pool).await.unwrap(),
+        vec![issue_id]
+    );
+    app.dispatch_all_pending_emails().await;
+
+    assert_eq!(issue_status(&app, issue_id).await, "sent");
+}
+
+#[tokio::test]
+async fn scheduled_issues_reach_each_subscriber_at_their_local_time() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber(new_sub_request_body())
+        .await;
+    app.create_confirmed_subscriber(new_sub_request_body())
+        .await;
+    // one subscriber is nine hours ahead of UTC, all year round
+    let tokyo = sqlx::query!("SELECT id FROM subscriptions LIMIT 1")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .id;
+    sqlx::query!(
+        "UPDATE subscriptions SET time_zone = 'Asia/Tokyo' WHERE id = $1",
+        tokyo
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    // an hour from now in UTC came eight hours ago in Tokyo
+    let issue_id = schedule(&app).await;
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    assert_eq!(
+        start_due_issues(&app.db_pool).await.unwrap(),
+        vec![issue_id]
+    );
+    app.dispatch_all_pending_emails().await;
+
+    let deliveries = sqlx::query!(
+        r#"
+        SELECT d.subscriber_id, d.status, d.next_attempt_at, i.send_at AS "send_at!"
+        FROM newsletter_deliveries d JOIN newsletter_issues i ON i.id = d.issue_id
+        "#
+    )
+    .fetch_all(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(deliveries.len(), 2);
+    for delivery in deliveries {
+        if delivery.subscriber_id == tokyo {
+            assert_eq!(delivery.status, "sent");
+        } else {
+            assert_eq!(delivery.status, "pending");
+            assert_eq!(delivery.next_attempt_at, delivery.send_at);
+        }
+    }
+    assert_eq!(issue_status(&app, issue_id).await, "sending");
+}
+
+#[tokio::test]
+async fn issues_cannot_be_scheduled_in_the_past() {
+    let app = spawn_app().await;
+
+    let response = app
+        .post_newsletters(&serde_json::json!({
+            "title": "Newsletter title",
+            "content": {"text": "Plain text", "html": "<p>HTML</p>"},
+            "send": true,
+            "send_at": "2020-01-01T08:00:00Z",
+        }))
+        .await;
+
+    assert_eq!(response.status(), 400);
+}
+
+#[tokio::test]
+async fn scheduled_issues_can_be_rescheduled() {
+    let app = spawn_app().await;
+    let issue_id = schedule(&app).await;
+    let send_at = (Utc::now() + Duration::days(2)).to_rfc3339_opts(SecondsFormat::Secs, true);
+
+    let issue: serde_json::Value = app
+        .post_admin(
+            &format!("/admin/newsletters/{issue_id}/reschedule"),
+            &[("send_at", &send_at)],
+        )
+        .await
+        .error_for_status()
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+
+    assert_eq!(issue["status"], "scheduled");
+    assert_eq!(
+        issue["send_at"]
+            .as_str()
+            .unwrap()
+            .parse::<chrono::DateTime<Utc>>()
+            .unwrap(),
+        send_at.parse::<chrono::DateTime<Utc>>().unwrap()
+    );
+    let response = app
+        .post_admin(
+            &format!("/admin/newsletters/{issue_id}/reschedule"),
+            &[("send_at", "2020-01-01T08:00:00Z")],
+        )
+        .await;
+    assert_eq!(response.status(), 400);
+}
+
+#[tokio::test]
+async fn cancelled_issues_are_never_sent() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber(new_sub_request_body())
+        .await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+    let issue_id = schedule(&app).await;
+
+    let issue: serde_json::Value = app
+        .post_admin(&format!("/admin/newsletters/{issue_id}/cancel"), &[])
+        .await
+        .error_for_status()
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(issue["status"], "cancelled");
+
+    make_due(&app, issue_id).await;
+    assert!(start_due_issues(&app.db_pool).await.unwrap().is_empty());
+    app.dispatch_all_pending_emails().await;
+    assert_eq!(issue_status(&app, issue_id).await, "cancelled");
+    let audit = sqlx::query!(
+        "SELECT action FROM audit_log WHERE subject = $1",
+        issue_id.to_string()
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(audit.action, "issue_cancelled");
+}
+
+#[tokio::test]
+async fn issues_that_started_sending_cannot_be_changed() {
+    let app = spawn_app().await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    let cancelled = schedule(&app).await;
+    app.post_admin(&format!("/admin/newsletters/{cancelled}/cancel"), &[])
+        .await
+        .error_for_status()
+        .unwrap();
+    let sent: serde_json::Value = app
+        .post_newsletters(&serde_json::json!({
+            "title": "Newsletter title",
+            "content": {"text": "Plain text", "html": "<p>HTML</p>"},
+            "send": true,
+        }))
+        .await
+        .json()
+        .await
+        .unwrap();
+    let sent = sent["issue_id"].as_str().unwrap();
+
+    for issue_id in [cancelled.to_string(), sent.to_string()] {
+        let response = app
+            .post_admin(&format!("/admin/newsletters/{issue_id}/cancel"), &[])
+            .await;
+        assert_eq!(response.status(), 409);
+        let response = app
+            .post_admin(
+                &format!("/admin/newsletters/{issue_id}/reschedule"),
+                &[("send_at", &in_an_hour())],
+            )
+            .await;
+        assert_eq!(response.status(), 409);
+    }
+
+    let response = app
+        .post_admin(
+            &format!("/admin/newsletters/{}/cancel", Uuid::new_v4()),
+            &[],
+        )
+        .await;
+    assert_eq!(response.status(), 404);
+    let response = reqwest::Client::new()
+        .post(format!(
+            "{}/admin/newsletters/{cancelled}/cancel",
+            app.address
+        ))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 401);
+}
@@ -0,0 +1,260 @@
+use crate::helpers::{new_sub_request_body, spawn_app, spawn_app_with, TestApp};
+use reqwest::Method;
+use uuid::Uuid;
+use wiremock::{
+    matchers::{any, method, path},
+    Mock, ResponseTemplate,
+};
+
+async fn create_draft(app: &TestApp) -> Uuid {
+    let response = app
+        .json_admin(
+            Method::POST,
+            "/admin/newsletters",
+            &serde_json::json!({
+                "title": "Newsletter title",
+                "content": {
+                    "text": "Newsletter body as plain text",
+                    "html": "<p>Newsletter body as HTML</p>",
+                },
+            }),
+        )
+        .await;
+    assert_eq!(response.status(), 201);
+    let body: serde_json::Value = response.json().await.unwrap();
+    body["id"].as_str().unwrap().parse().unwrap()
+}
+
+/// The `To` and `Subject` of every email sent through the email server
+async fn sent_emails(app: &TestApp) -> Vec<(String, String)> {
+    app.email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .iter()
+        .map(|request| {
+            let body: serde_json::Value = serde_json::from_slice(&request.body).unwrap();
+            (
+                body["To"].as_str().unwrap().to_string(),
+                body["Subject"].as_str().unwrap().to_string(),
+            )
+        })
+        .collect()
+}
+
+#[tokio::test]
+async fn test_sends_go_to_the_given_addresses_only() {
+    let app = spawn_app().await;
+    let issue_id = create_draft(&app).await;
+    app.create_confirmed_subscriber(new_sub_request_body())
+        .await;
+    let before = app.email_server.received_requests().await.unwrap().len();
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+    let response = app
+        .post_admin(
+            &format!("/admin/newsletters/{issue_id}/test"),
+            &[("recipients", "editor@example.com, phone@example.com")],
+        )
+        .await;
+    assert_eq!(response.status(), 200);
+    let body: Vec<serde_json::Value> = response.json().await.unwrap();
+    assert!(body.iter().all(|d| d["sent"] == true));
+
+    let emails = sent_emails(&app).await;
+    assert_eq!(
+        emails[before..],
+        [
+            (
+                "editor@example.com".to_string(),
+                "[TEST] Newsletter title".to_string()
+            ),
+            (
+                "phone@example.com".to_string(),
+                "[TEST] Newsletter title".to_string()
+            ),
+        ]
+    );
+}
+
+#[tokio::test]
+async fn test_sends_leave_the_issue_and_its_stats_alone() {
+    let app = spawn_app().await;
+    let issue_id = create_draft(&app).await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_admin(
+            &format!("/admin/newsletters/{issue_id}/test"),
+            &[("recipients", "editor@example.com")],
+        )
+        .await;
+    assert_eq!(response.status(), 200);
+
+    let issue: serde_json::Value = app
+        .get_admin(&format!("/admin/newsletters/{issue_id}"), &[])
+        .await
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(issue["status"], "draft");
+    assert_eq!(
+        issue["deliveries"],
+        serde_json::json!({ "pending": 0, "digest": 0, "sent": 0, "skipped": 0, "dead_letter": 0 })
+    );
+    let deliveries = sqlx::query!(
+        "SELECT COUNT(*) AS \"count!\" FROM newsletter_deliveries WHERE issue_id = $1",
+        issue_id
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(deliveries.count, 0);
+}
+
+#[tokio::test]
+async fn test_sends_default_to_the_configured_seed_list() {
+    let app = spawn_app_with(|c| {
+        c.delivery.test_recipients = vec!["seed@example.com".into()];
+    })
+    .await;
+    let issue_id = create_draft(&app).await;
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_admin(&format!("/admin/newsletters/{issue_id}/test"), &[])
+        .await;
+    assert_eq!(response.status(), 200);
+    assert_eq!(sent_emails(&app).await[0].0, "seed@example.com");
+}
+
+#[tokio::test]
+async fn test_sends_need_valid_recipients() {
+    let app = spawn_app().await;
+    let issue_id = create_draft(&app).await;
+    let test = format!("/admin/newsletters/{issue_id}/test");
+
+    // no address given and no seed list configured
+    assert_eq!(app.post_admin(&test, &[]).await.status(), 400);
+    assert_eq!(
+        app.post_admin(&test, &[("recipients", "not-an-email")])
+            .await
+            .status(),
+        400
+    );
+    let missing = format!("/admin/newsletters/{}/test", Uuid::new_v4());
+    assert_eq!(
+        app.post_admin(&missing, &[("recipients", "editor@example.com")])
+            .await
+            .status(),
+        404
+    );
+}
+
+#[tokio::test]
+async fn failed_test_sends_are_reported() {
+    let app = spawn_app().await;
+    let issue_id = create_draft(&app).await;
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(500))
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_admin(
+            &format!("/admin/newsletters/{issue_id}/test"),
+            &[("recipients", "editor@example.com")],
+        )
+        .await;
+    assert_eq!(response.status(), 502);
+    let body: Vec<serde_json::Value> = response.json().await.unwrap();
+    assert_eq!(body[0]["sent"], false);
+}
+
+#[tokio::test]
+async fn test_sends_go_to_ten_addresses_at_most() {
+    let app = spawn_app().await;
+    let issue_id = create_draft(&app).await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let recipients = (0..11)
+        .map(|i| format!("editor{i}@example.com"))
+        .collect::<Vec<_>>()
+        .join(",");
+    let response = app
+        .post_admin(
+            &format!("/admin/newsletters/{issue_id}/test"),
+            &[("recipients", &recipients)],
+        )
+        .await;
+
+    assert_eq!(response.status(), 400);
+}
+
+#[tokio::test]
+async fn test_sends_can_go_to_the_logged_in_user() {
+    let app =
+        spawn_app_with(|c| c.delivery.test_recipients = vec!["seed@example.com".into()]).await;
+    let issue_id = create_draft(&app).await;
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    // without an address on the account, there is nowhere to send it
+    let response = app
+        .post_admin(
+            &format!("/admin/newsletters/{issue_id}/test"),
+            &[("include_me", "true")],
+        )
+        .await;
+    assert_eq!(response.status(), 400);
+
+    sqlx::query!(
+        "UPDATE users SET email = 'me@example.com' WHERE user_id = $1",
+        app.test_user.user_id
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    let response = app
+        .post_admin(
+            &format!("/admin/newsletters/{issue_id}/test"),
+            &[("include_me", "true")],
+        )
+        .await;
+    assert_eq!(response.status(), 200);
+    let response = app
+        .post_admin(
+            &format!("/admin/newsletters/{issue_id}/test"),
+            &[("recipients", "editor@example.com"), ("include_me", "true")],
+        )
+        .await;
+    assert_eq!(response.status(), 200);
+
+    let recipients: Vec<String> = sent_emails(&app)
+        .await
+        .into_iter()
+        .map(|(to, _)| to)
+        .collect();
+    assert_eq!(
+        recipients,
+        ["me@example.com", "editor@example.com", "me@example.com"]
+    );
+}
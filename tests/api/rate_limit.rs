@@ -0,0 +1,126 @@
+use crate::helpers::{new_sub_request_body, spawn_app_with, TestApp};
+use uuid::Uuid;
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+use zero2prod::configuration::{FieldLimitSettings, LimitSettings, RouteLimitSettings, Settings};
+
+fn limit_subscriptions(c: &mut Settings, per_ip: u32, per_email: u32) {
+    c.rate_limit.routes = vec![RouteLimitSettings {
+        method: "POST".into(),
+        path: "/subscriptions".into(),
+        per_ip: LimitSettings {
+            burst: per_ip,
+            per_minute: 1,
+        },
+        per_field: Some(FieldLimitSettings {
+            field: "email".into(),
+            limits: LimitSettings {
+                burst: per_email,
+                per_minute: 1,
+            },
+        }),
+    }];
+}
+
+async fn mock_email_server(app: &TestApp) {
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+}
+
+#[tokio::test]
+async fn requests_over_the_per_ip_limit_get_a_429_with_retry_after() {
+    let app = spawn_app_with(|c| limit_subscriptions(c, 2, 10)).await;
+    mock_email_server(&app).await;
+
+    for _ in 0..2 {
+        let response = app.post_subscriptions(new_sub_request_body()).await;
+        assert_eq!(response.status().as_u16(), 200);
+    }
+
+    let response = app.post_subscriptions(new_sub_request_body()).await;
+    assert_eq!(response.status().as_u16(), 429);
+    let retry_after: u64 = response.headers()["Retry-After"]
+        .to_str()
+        .unwrap()
+        .parse()
+        .unwrap();
+    assert!(retry_after > 0 && retry_after <= 60);
+
+    // routes without a limit are not affected
+    let response = reqwest::get(format!("{}/health_check", app.address))
+        .await
+        .unwrap();
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn requests_over_the_per_email_limit_get_a_429() {
+    let app = spawn_app_with(|c| limit_subscriptions(c, 10, 1)).await;
+    mock_email_server(&app).await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+
+    // the body read by the middleware still reaches the handler
+    let response = app.post_subscriptions(body.into()).await;
+    assert_eq!(response.status().as_u16(), 200);
+    let saved = sqlx::query!("SELECT email FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.email, "ursula_le_guin@gmail.com");
+
+    let response = app
+        .post_subscriptions("name=le%20guin&email=Ursula_Le_Guin%40gmail.com".into())
+        .await;
+    assert_eq!(response.status().as_u16(), 429);
+
+    let response = app.post_subscriptions(new_sub_request_body()).await;
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn forwarded_for_identifies_clients_behind_a_trusted_proxy() {
+    let app = spawn_app_with(|c| {
+        limit_subscriptions(c, 1, 10);
+        c.rate_limit.trusted_proxies = vec!["127.0.0.0/8".into(), "::1".into()];
+    })
+    .await;
+    mock_email_server(&app).await;
+    let client = reqwest::Client::new();
+    let post = |forwarded_for: &'static str| {
+        client
+            .post(format!("{}/subscriptions", app.address))
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .header("X-Forwarded-For", forwarded_for)
+            .body(new_sub_request_body())
+            .send()
+    };
+
+    assert_eq!(post("198.51.100.1").await.unwrap().status().as_u16(), 200);
+    assert_eq!(post("198.51.100.2").await.unwrap().status().as_u16(), 200);
+    assert_eq!(post("198.51.100.1").await.unwrap().status().as_u16(), 429);
+}
+
+#[tokio::test]
+async fn routes_are_limited_whatever_their_path_parameters() {
+    let app = spawn_app_with(|c| {
+        c.rate_limit.routes = vec![RouteLimitSettings {
+            method: "GET".into(),
+            path: "/admin/newsletters/{issue_id}".into(),
+            per_ip: LimitSettings {
+                burst: 1,
+                per_minute: 1,
+            },
+            per_field: None,
+        }]
+    })
+    .await;
+
+    for expected in [404, 429] {
+        let path = format!("/admin/newsletters/{}", Uuid::new_v4());
+        let response = app.get_admin(&path, &[]).await;
+        assert_eq!(response.status().as_u16(), expected);
+    }
+}
@@ -0,0 +1,109 @@
+use crate::helpers::{spawn_app, TestApp};
+use wiremock::{matchers::path, Mock, ResponseTemplate};
+
+async fn subscribe(app: &TestApp, email: &str, source: &[(&str, &str)]) -> reqwest::Response {
+    let mut form = vec![("name", "le guin"), ("email", email)];
+    form.extend_from_slice(source);
+    reqwest::Client::new()
+        .post(format!("{}/subscriptions", app.address))
+        .form(&form)
+        .send()
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn signups_and_confirmation_rates_are_reported_by_source() {
+    let app = spawn_app().await;
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    subscribe(&app, "a@example.com", &[("source", "podcast")]).await;
+    subscribe(&app, "b@example.com", &[("source", "podcast")]).await;
+    subscribe(&app, "c@example.com", &[("utm_source", "twitter")]).await;
+    subscribe(&app, "d@example.com", &[]).await;
+    // confirm the first podcast signup
+    let requests = app.email_server.received_requests().await.unwrap();
+    let link = app.get_confirmation_links(&requests[0]).0;
+    reqwest::get(link)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    let report: serde_json::Value = app
+        .get_admin(
+            "/admin/reports/signups",
+            &[("format", "json"), ("interval", "day")],
+        )
+        .await
+        .error_for_status()
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+
+    assert_eq!(report["interval"], "day");
+    let rows = report["rows"].as_array().unwrap();
+    assert_eq!(rows.len(), 3);
+    assert_eq!(rows[0]["source"], "podcast");
+    assert_eq!(rows[0]["signups"], 2);
+    assert_eq!(rows[0]["confirmed"], 1);
+    assert_eq!(rows[0]["confirmation_rate"], 0.5);
+    let sources: Vec<_> = rows.iter().map(|r| r["source"].as_str().unwrap()).collect();
+    assert!(sources.contains(&"twitter"));
+    assert!(sources.contains(&"(none)"));
+
+    let html = app
+        .get_admin("/admin/reports/signups", &[])
+        .await
+        .text()
+        .await
+        .unwrap();
+    assert!(html.contains("<td>podcast</td><td>2</td><td>1</td><td>50.0%</td>"));
+}
+
+#[tokio::test]
+async fn the_report_can_be_restricted_to_a_date_range() {
+    let app = spawn_app().await;
+    app.post_subscribers_import("email,name\nursula@example.com,Ursula\n", true)
+        .await
+        .error_for_status()
+        .unwrap();
+
+    let query = |from: &'static str| [("format", "json"), ("from", from), ("to", "")];
+    let report: serde_json::Value = app
+        .get_admin("/admin/reports/signups", &query("2000-01-01"))
+        .await
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(report["rows"][0]["source"], "import");
+    assert_eq!(report["rows"][0]["confirmation_rate"], 1.0);
+
+    let report: serde_json::Value = app
+        .get_admin("/admin/reports/signups", &query("2999-01-01"))
+        .await
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(report["rows"].as_array().unwrap().len(), 0);
+
+    let response = app
+        .get_admin("/admin/reports/signups", &[("interval", "hour")])
+        .await;
+    assert_eq!(response.status(), 400);
+}
+
+#[tokio::test]
+async fn the_report_requires_authentication() {
+    let app = spawn_app().await;
+
+    let response = reqwest::get(format!("{}/admin/reports/signups", app.address))
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), 401);
+}
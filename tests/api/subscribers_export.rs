@@ -0,0 +1,104 @@
+use crate::helpers::{spawn_app, TestApp};
+
+async fn import_fixture(app: &TestApp) {
+    app.post_subscribers_import(
+        "email,name\noctavia@example.com,\"Butler, Octavia\"\niain@example.com,Iain Banks\n",
+        true,
+    )
+    .await
+    .error_for_status()
+    .unwrap();
+    app.create_unconfirmed_subscriber("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+}
+
+#[tokio::test]
+async fn export_streams_all_subscribers_as_csv_by_default() {
+    let app = spawn_app().await;
+    import_fixture(&app).await;
+
+    let response = app.get_subscribers_export(&[]).await;
+    assert_eq!(response.status(), 200);
+    assert_eq!(response.headers()["Content-Type"], "text/csv");
+
+    let body = response.text().await.unwrap();
+    let lines: Vec<_> = body.lines().collect();
+    assert_eq!(lines.len(), 4);
+    assert_eq!(lines[0], "id,email,name,status,subscribed_at");
+    assert!(body.contains(",octavia@example.com,\"Butler, Octavia\",confirmed,"));
+    assert!(body.contains(",ursula_le_guin@gmail.com,le guin,pending_confirmation,"));
+}
+
+#[tokio::test]
+async fn export_can_filter_by_status_as_ndjson() {
+    let app = spawn_app().await;
+    import_fixture(&app).await;
+
+    let response = app
+        .get_subscribers_export(&[("format", "ndjson"), ("status", "confirmed")])
+        .await;
+    assert_eq!(response.status(), 200);
+
+    let body = response.text().await.unwrap();
+    let mut emails: Vec<String> = body
+        .lines()
+        .map(|l| serde_json::from_str::<serde_json::Value>(l).unwrap())
+        .map(|v| v["email"].as_str().unwrap().to_string())
+        .collect();
+    emails.sort();
+    assert_eq!(emails, vec!["iain@example.com", "octavia@example.com"]);
+}
+
+#[tokio::test]
+async fn export_can_filter_by_list() {
+    let app = spawn_app().await;
+    import_fixture(&app).await;
+    sqlx::query!(
+        r#"
+        WITH events AS (
+            INSERT INTO lists (id, slug, name, created_at)
+            VALUES ('5d4bf1c0-6a3c-4b34-9d3f-1b8e7f4c2a10', 'events', 'Events', now())
+            RETURNING id
+        )
+        INSERT INTO subscription_lists (subscriber_id, list_id)
+        SELECT subscriptions.id, events.id FROM subscriptions, events
+        WHERE email = 'octavia@example.com'
+        "#
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+
+    let body = app
+        .get_subscribers_export(&[("list", "events")])
+        .await
+        .text()
+        .await
+        .unwrap();
+
+    let lines: Vec<_> = body.lines().collect();
+    assert_eq!(lines.len(), 2);
+    assert!(lines[1].contains(",octavia@example.com,"));
+}
+
+#[tokio::test]
+async fn export_rejects_unknown_formats_and_statuses() {
+    let app = spawn_app().await;
+
+    let response = app.get_subscribers_export(&[("format", "xml")]).await;
+    assert_eq!(response.status(), 400);
+
+    let response = app.get_subscribers_export(&[("status", "pending")]).await;
+    assert_eq!(response.status(), 400);
+}
+
+#[tokio::test]
+async fn export_requires_authentication() {
+    let app = spawn_app().await;
+
+    let response = reqwest::get(format!("{}/admin/subscribers/export", &app.address))
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), 401);
+}
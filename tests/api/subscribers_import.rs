@@ -0,0 +1,151 @@
+use crate::helpers::spawn_app;
+use wiremock::{
+    matchers::{any, method, path},
+    Mock, ResponseTemplate,
+};
+
+#[tokio::test]
+async fn import_stores_valid_rows_and_reports_the_rest() {
+    let app = spawn_app().await;
+    app.create_unconfirmed_subscriber("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+
+    let csv = "\
+email,name
+ursula_le_guin@gmail.com,Ursula
+octavia@example.com,Octavia Butler
+not-an-email,Somebody
+octavia@example.com,Octavia Again
+ted@example.com,
+iain@example.com,Iain Banks
+";
+    let response = app.post_subscribers_import(csv, true).await;
+    assert_eq!(response.status(), 200);
+
+    let report: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(report["imported"], 2);
+
+    let lines = |key: &str| -> Vec<u64> {
+        report[key]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|r| r["line"].as_u64().unwrap())
+            .collect()
+    };
+    assert_eq!(lines("duplicates"), vec![5, 2]);
+    assert_eq!(lines("invalid"), vec![4, 6]);
+
+    let saved = sqlx::query!(
+        "SELECT email, status FROM subscriptions WHERE email <> 'ursula_le_guin@gmail.com' ORDER BY email"
+    )
+    .fetch_all(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(saved.len(), 2);
+    assert_eq!(saved[0].email, "iain@example.com");
+    assert!(saved.iter().all(|s| s.status == "confirmed"));
+}
+
+#[tokio::test]
+async fn pending_imports_go_through_double_opt_in() {
+    let app = spawn_app().await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+
+    let csv = "name,email\nOctavia Butler,octavia@example.com\nIain Banks,iain@example.com\n";
+    let response = app.post_subscribers_import(csv, false).await;
+    assert_eq!(response.status(), 200);
+
+    let saved = sqlx::query!(
+        "SELECT s.status, t.subscription_token FROM subscriptions s \
+        JOIN subscription_tokens t ON t.subscriber_id = s.id"
+    )
+    .fetch_all(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(saved.len(), 2);
+    assert!(saved.iter().all(|s| s.status == "pending_confirmation"));
+
+    // Mock verifies on Drop that a confirmation email was sent to each imported subscriber
+}
+
+#[tokio::test]
+async fn imported_subscribers_are_emailed_in_their_locale() {
+    let app = spawn_app().await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+
+    let csv = "email,name,locale
+octavia@example.com,Octavia Butler,pt-BR
+iain@example.com,Iain Banks,
+";
+    let response = app.post_subscribers_import(csv, false).await;
+    assert_eq!(response.status(), 200);
+
+    let saved = sqlx::query!("SELECT locale FROM subscriptions ORDER BY email")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved[0].locale, "en");
+    assert_eq!(saved[1].locale, "pt");
+
+    let subjects: Vec<String> = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .iter()
+        .map(|r| {
+            let body: serde_json::Value = serde_json::from_slice(&r.body).unwrap();
+            body["Subject"].as_str().unwrap().to_string()
+        })
+        .collect();
+    assert!(subjects.contains(&"Bem-vindo!".to_string()));
+    assert!(subjects.contains(&"Welcome!".to_string()));
+}
+
+#[tokio::test]
+async fn import_rejects_a_csv_without_the_required_columns() {
+    let app = spawn_app().await;
+
+    let response = app
+        .post_subscribers_import("email\noctavia@example.com\n", true)
+        .await;
+
+    assert_eq!(response.status(), 400);
+}
+
+#[tokio::test]
+async fn import_requires_authentication() {
+    let app = spawn_app().await;
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let response = reqwest::Client::new()
+        .post(format!("{}/admin/subscribers/import", &app.address))
+        .body("email,name\noctavia@example.com,Octavia\n")
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), 401);
+    assert_eq!(
+        r#"Basic realm="admin""#,
+        response.headers()["WWW-Authenticate"]
+    );
+}
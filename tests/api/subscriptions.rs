@@ -1,4 +1,9 @@
-use crate::helpers::{new_sub_request_body, spawn_app};
+use crate::helpers::{new_sub_request_body, spawn_app, spawn_app_with};
+use chrono::{Duration, Utc};
+use wiremock::matchers::{any, body_string_contains, method, path};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+use zero2prod::bot_protection::FormToken;
+use zero2prod::configuration::ChallengeSettings;
 
 #[tokio::test]
 async fn subscribe_persists_the_new_subscriber() {
@@ -107,3 +112,412 @@ async fn subscribe_fails_if_there_is_a_fatal_database_error() {
     let response = app.post_subscriptions(body).await;
     assert_eq!(response.status(), 500);
 }
+
+#[tokio::test]
+async fn subscribe_keeps_the_typed_email_and_stores_its_normalized_form() {
+    let app = spawn_app().await;
+    let body = "name=le%20guin&email=%20Ursula_Le_Guin%40GMail.com%20";
+    app.create_unconfirmed_subscriber(body.into()).await;
+
+    let saved = sqlx::query!("SELECT email, email_normalized FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .expect("Failed to fetch saved subscription.");
+
+    assert_eq!(saved.email, "Ursula_Le_Guin@GMail.com");
+    assert_eq!(saved.email_normalized, "Ursula_Le_Guin@gmail.com");
+}
+
+#[tokio::test]
+async fn emails_differing_only_by_case_are_the_same_subscriber() {
+    let app = spawn_app().await;
+    app.create_unconfirmed_subscriber("name=le%20guin&email=ursula%40example.com".into())
+        .await;
+
+    let response = app
+        .post_subscribers_import("email,name\nUrsula@Example.COM,Ursula\n", true)
+        .await;
+    let report: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(report["imported"], 0);
+    assert_eq!(report["duplicates"][0]["line"], 2);
+
+    let saved = sqlx::query!("SELECT count(*) as \"count!\" FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.count, 1);
+}
+
+#[tokio::test]
+async fn subscribing_again_with_another_case_resends_the_confirmation() {
+    let app = spawn_app().await;
+    app.create_unconfirmed_subscriber("name=le%20guin&email=ursula%40example.com".into())
+        .await;
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_subscriptions("name=le%20guin&email=Ursula%40Example.com".into())
+        .await;
+
+    assert_eq!(response.status(), 200);
+    let tokens = sqlx::query!("SELECT count(*) as \"count!\" FROM subscription_tokens")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(tokens.count, 2);
+}
+
+#[tokio::test]
+async fn subscribing_again_once_confirmed_is_a_conflict() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber("name=le%20guin&email=ursula%40example.com".into())
+        .await;
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_subscriptions("name=le%20guin&email=Ursula%40Example.com".into())
+        .await;
+
+    assert_eq!(response.status(), 409);
+    let saved = sqlx::query!("SELECT count(*) as \"count!\" FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.count, 1);
+}
+
+#[tokio::test]
+async fn subscribe_rejects_blocklisted_addresses_with_a_400() {
+    let app = spawn_app().await;
+    let test_cases = vec![
+        (
+            "name=Ursula&email=ursula%40mailinator.com",
+            "disposable domain",
+        ),
+        (
+            "name=Ursula&email=ursula%40eu.mailinator.com",
+            "disposable subdomain",
+        ),
+        ("name=Ursula&email=NoReply%40example.com", "role address"),
+    ];
+
+    for (body, desc) in test_cases {
+        let response = app.post_subscriptions(body.to_string()).await;
+
+        assert_eq!(
+            400,
+            response.status().as_u16(),
+            "The API did not return a 400 Bad Request for a {}.",
+            desc
+        );
+        assert!(response.text().await.unwrap().contains("not accepted"));
+    }
+}
+
+#[tokio::test]
+async fn submissions_filling_in_the_honeypot_are_silently_dropped() {
+    let app = spawn_app().await;
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app
+        .post_subscriptions(format!(
+            "{}&website=spam.example.com",
+            new_sub_request_body()
+        ))
+        .await;
+
+    assert_eq!(response.status().as_u16(), 200);
+    let saved = sqlx::query!("SELECT count(*) as \"count!\" FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.count, 0);
+}
+
+#[tokio::test]
+async fn the_subscribe_form_embeds_a_form_token() {
+    let app = spawn_app().await;
+
+    let html = reqwest::get(format!("{}/subscriptions", app.address))
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+
+    assert!(html.contains(r#"name="form_token""#));
+    assert!(html.contains(r#"name="website""#));
+}
+
+#[tokio::test]
+async fn form_tokens_are_checked_for_time_to_submit_and_signature() {
+    let app = spawn_app_with(|c| c.bot_protection.require_form_token = true).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    let body = |token: &str| format!("{}&form_token={token}", new_sub_request_body());
+    let test_cases = vec![
+        (new_sub_request_body(), "no token", "invalid"),
+        (
+            body(&FormToken::issue(&app.hmac_secret)),
+            "a form submitted straight away",
+            "too quickly",
+        ),
+        (
+            body(&FormToken::issue_at(
+                &app.hmac_secret,
+                Utc::now() - Duration::days(2),
+            )),
+            "an expired form",
+            "expired",
+        ),
+        (
+            body(&FormToken::issue_at(
+                &secrecy::Secret::new("forged".into()),
+                Utc::now() - Duration::seconds(10),
+            )),
+            "a forged token",
+            "invalid",
+        ),
+    ];
+    for (body, desc, message) in test_cases {
+        let response = app.post_subscriptions(body).await;
+        assert_eq!(
+            400,
+            response.status().as_u16(),
+            "The API did not return a 400 Bad Request for {}.",
+            desc
+        );
+        assert!(response.text().await.unwrap().contains(message));
+    }
+
+    let token = FormToken::issue_at(&app.hmac_secret, Utc::now() - Duration::seconds(10));
+    let response = app.post_subscriptions(body(&token)).await;
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn a_form_token_cannot_be_replayed() {
+    let app = spawn_app_with(|c| c.bot_protection.require_form_token = true).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    let token = FormToken::issue_at(&app.hmac_secret, Utc::now() - Duration::seconds(10));
+    let body = || format!("{}&form_token={token}", new_sub_request_body());
+    let response = app.post_subscriptions(body()).await;
+    assert_eq!(response.status().as_u16(), 200);
+
+    let response = app.post_subscriptions(body()).await;
+    assert_eq!(response.status().as_u16(), 400);
+    assert!(response.text().await.unwrap().contains("already submitted"));
+}
+
+#[tokio::test]
+async fn subscribe_requires_a_solved_challenge_when_one_is_configured() {
+    let challenge_server = MockServer::start().await;
+    let verify_url = format!("{}/siteverify", challenge_server.uri());
+    let app = spawn_app_with(|c| {
+        c.bot_protection.challenge = Some(ChallengeSettings {
+            verify_url,
+            secret: secrecy::Secret::new("challenge-secret".into()),
+            timeout_milliseconds: 1000,
+        })
+    })
+    .await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    Mock::given(path("/siteverify"))
+        .and(body_string_contains("response=solved"))
+        .respond_with(
+            ResponseTemplate::new(200).set_body_json(serde_json::json!({"success": true})),
+        )
+        .mount(&challenge_server)
+        .await;
+    Mock::given(path("/siteverify"))
+        .and(body_string_contains("response=wrong"))
+        .respond_with(
+            ResponseTemplate::new(200).set_body_json(serde_json::json!({"success": false})),
+        )
+        .mount(&challenge_server)
+        .await;
+
+    let response = app.post_subscriptions(new_sub_request_body()).await;
+    assert_eq!(response.status().as_u16(), 400);
+
+    let response = app
+        .post_subscriptions(format!(
+            "{}&challenge_response=wrong",
+            new_sub_request_body()
+        ))
+        .await;
+    assert_eq!(response.status().as_u16(), 400);
+
+    let response = app
+        .post_subscriptions(format!(
+            "{}&challenge_response=solved",
+            new_sub_request_body()
+        ))
+        .await;
+    assert_eq!(response.status().as_u16(), 200);
+}
+
+#[tokio::test]
+async fn the_confirmation_email_is_sent_in_the_language_of_the_subscriber() {
+    let app = spawn_app().await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    reqwest::Client::new()
+        .post(format!("{}/subscriptions", app.address))
+        .header("Content-Type", "application/x-www-form-urlencoded")
+        .header("Accept-Language", "de-AT, en;q=0.8")
+        .body("name=le%20guin&email=ursula_le_guin%40gmail.com")
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    let email_request = &app.email_server.received_requests().await.unwrap()[0];
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    assert_eq!(body["Subject"], "Willkommen!");
+    assert!(body["TextBody"]
+        .as_str()
+        .unwrap()
+        .contains("/subscriptions/confirm?subscription_token="));
+
+    let saved = sqlx::query!("SELECT locale FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.locale, "de");
+}
+
+#[tokio::test]
+async fn the_locale_form_field_overrides_accept_language_and_falls_back_to_the_default() {
+    let app = spawn_app().await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    let client = reqwest::Client::new();
+    for (email, locale) in [("a%40example.com", "es"), ("b%40example.com", "tlh")] {
+        client
+            .post(format!("{}/subscriptions", app.address))
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .header("Accept-Language", "ja")
+            .body(format!("name=le%20guin&email={email}&locale={locale}"))
+            .send()
+            .await
+            .unwrap()
+            .error_for_status()
+            .unwrap();
+    }
+
+    let saved = sqlx::query!("SELECT locale FROM subscriptions ORDER BY email")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved[0].locale, "es");
+    assert_eq!(saved[1].locale, "en");
+}
+
+#[tokio::test]
+async fn the_subscribe_form_passes_the_attribution_along() {
+    let app = spawn_app().await;
+
+    let html = reqwest::Client::new()
+        .get(format!(
+            "{}/subscriptions?utm_source=newsletter&utm_campaign=spring%20%26%20summer",
+            app.address
+        ))
+        .header("Referer", "https://search.example.com/?q=rust")
+        .send()
+        .await
+        .unwrap()
+        .text()
+        .await
+        .unwrap();
+
+    assert!(html.contains(r#"name="utm_source" value="newsletter""#));
+    assert!(html.contains(r#"name="utm_campaign" value="spring &amp; summer""#));
+    assert!(html.contains(r#"name="referrer" value="https://search.example.com/?q=rust""#));
+    assert!(html.contains(r#"name="landing_url" value="http://127.0.0.1"#));
+}
+
+#[tokio::test]
+async fn subscribe_stores_the_attribution() {
+    let app = spawn_app().await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+
+    reqwest::Client::new()
+        .post(format!("{}/subscriptions", app.address))
+        .header("Referer", "https://blog.example.com/post")
+        .form(&[
+            ("name", "le guin"),
+            ("email", "ursula_le_guin@gmail.com"),
+            ("source", " footer "),
+            ("utm_source", "twitter"),
+            ("utm_medium", ""),
+            ("referrer", "https://twitter.com/"),
+        ])
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    let saved = sqlx::query!(
+        "SELECT source, utm_source, utm_medium, referrer, landing_url FROM subscriptions"
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(saved.source.as_deref(), Some("footer"));
+    assert_eq!(saved.utm_source.as_deref(), Some("twitter"));
+    assert_eq!(saved.utm_medium, None);
+    assert_eq!(saved.referrer.as_deref(), Some("https://twitter.com/"));
+    // without a landing url, the page the form was posted from is used
+    assert_eq!(
+        saved.landing_url.as_deref(),
+        Some("https://blog.example.com/post")
+    );
+}
@@ -48,3 +48,73 @@ async fn clicking_on_the_confirmation_link_confirms_a_subscriber() {
     assert_eq!(saved.name, "le guin");
     assert_eq!(saved.status, "confirmed");
 }
+
+#[tokio::test]
+async fn confirmation_is_recorded_in_the_status_history() {
+    let app = spawn_app().await;
+    let link = app
+        .create_unconfirmed_subscriber(new_sub_request_body())
+        .await;
+
+    reqwest::get(link.clone())
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    // clicking the link again is harmless
+    let response = reqwest::get(link).await.unwrap();
+    assert_eq!(response.status(), 200);
+
+    let history = sqlx::query!(
+        "SELECT from_status, to_status FROM subscription_status_history ORDER BY changed_at"
+    )
+    .fetch_all(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(history.len(), 2);
+    assert_eq!(history[0].from_status, None);
+    assert_eq!(history[0].to_status, "pending_confirmation");
+    assert_eq!(
+        history[1].from_status.as_deref(),
+        Some("pending_confirmation")
+    );
+    assert_eq!(history[1].to_status, "confirmed");
+}
+
+#[tokio::test]
+async fn unsubscribed_subscribers_cannot_be_confirmed_with_an_old_link() {
+    let app = spawn_app().await;
+    let link = app
+        .create_unconfirmed_subscriber(new_sub_request_body())
+        .await;
+    sqlx::query!("UPDATE subscriptions SET status = 'unsubscribed'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+
+    let response = reqwest::get(link).await.unwrap();
+
+    assert_eq!(response.status(), 409);
+}
+
+#[tokio::test]
+async fn the_confirmation_page_is_shown_in_the_language_of_the_subscriber() {
+    let app = spawn_app().await;
+    let link = app
+        .create_unconfirmed_subscriber(format!("{}&locale=fr", new_sub_request_body()))
+        .await;
+
+    let page = reqwest::get(link).await.unwrap().text().await.unwrap();
+    assert!(page.contains("Votre abonnement est confirmé"));
+
+    // unknown links fall back to Accept-Language
+    let response = reqwest::Client::new()
+        .get(format!("{}/subscriptions/confirm", app.address))
+        .query(&[("subscription_token", "not-a-token")])
+        .header("Accept-Language", "es")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 401);
+    assert!(response.text().await.unwrap().contains("no es válido"));
+}
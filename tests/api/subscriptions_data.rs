@@ -0,0 +1,186 @@
+use crate::helpers::spawn_app;
+use wiremock::{
+    matchers::{any, path},
+    Mock, ResponseTemplate,
+};
+
+const EMAIL: &str = "ursula_le_guin@gmail.com";
+
+#[tokio::test]
+async fn data_requests_for_unknown_addresses_do_not_send_an_email() {
+    let app = spawn_app().await;
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app.post_data_request("nobody@example.com").await;
+
+    assert_eq!(response.status(), 200);
+}
+
+#[tokio::test]
+async fn the_data_access_link_exports_the_subscriber_data() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+
+    let link = app.get_data_access_link(EMAIL).await;
+    let page = reqwest::get(link.clone()).await.unwrap();
+    assert_eq!(page.status(), 200);
+
+    let mut export_link = link.clone();
+    export_link.set_path("/subscriptions/data/export");
+    let data: serde_json::Value = reqwest::get(export_link)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+
+    assert_eq!(data["subscription"]["email"], EMAIL);
+    assert_eq!(data["subscription"]["status"], "confirmed");
+    assert_eq!(data["subscription_tokens"].as_array().unwrap().len(), 1);
+    assert_eq!(data["data_access_tokens"].as_array().unwrap().len(), 1);
+    assert_eq!(data["status_history"].as_array().unwrap().len(), 2);
+}
+
+#[tokio::test]
+async fn an_invalid_data_access_token_is_rejected_with_a_401() {
+    let app = spawn_app().await;
+
+    let response = reqwest::get(format!(
+        "{}/subscriptions/data/export?token=not-a-token",
+        app.address
+    ))
+    .await
+    .unwrap();
+
+    assert_eq!(response.status(), 401);
+}
+
+#[tokio::test]
+async fn erasure_via_magic_link_deletes_the_subscriber_and_suppresses_the_address() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+
+    let link = app.get_data_access_link(EMAIL).await;
+    let token = link
+        .query_pairs()
+        .find(|(k, _)| k == "token")
+        .unwrap()
+        .1
+        .to_string();
+    let response = reqwest::Client::new()
+        .post(format!("{}/subscriptions/data/erase", app.address))
+        .form(&[("token", &token)])
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+
+    let remaining = sqlx::query!("SELECT count(*) as \"count!\" FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(remaining.count, 0);
+
+    let audit = sqlx::query!(
+        "SELECT action, actor_user_id FROM audit_log WHERE action = 'subscriber_erased'"
+    )
+    .fetch_one(&app.db_pool)
+    .await
+    .unwrap();
+    assert_eq!(audit.actor_user_id, None);
+
+    // the erased address is not imported again
+    let response = app
+        .post_subscribers_import(&format!("email,name\n{EMAIL},Ursula\n"), true)
+        .await;
+    let report: serde_json::Value = response.json().await.unwrap();
+    assert_eq!(report["imported"], 0);
+    assert_eq!(report["suppressed"][0]["line"], 2);
+}
+
+#[tokio::test]
+async fn admins_can_export_and_erase_subscriber_data() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    app.post_newsletters(&serde_json::json!({
+        "title": "Newsletter title",
+        "content": {"text": "Plain text", "html": "<p>HTML</p>"},
+        "send": true,
+    }))
+    .await
+    .error_for_status()
+    .unwrap();
+    app.dispatch_all_pending_emails().await;
+    let client = reqwest::Client::new();
+
+    let data: serde_json::Value = client
+        .get(format!("{}/admin/subscribers/data", app.address))
+        .query(&[("email", EMAIL)])
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap()
+        .json()
+        .await
+        .unwrap();
+    assert_eq!(data["subscription"]["name"], "le guin");
+    assert_eq!(data["deliveries"][0]["title"], "Newsletter title");
+    assert_eq!(data["deliveries"][0]["status"], "sent");
+
+    let response = client
+        .post(format!("{}/admin/subscribers/erase", app.address))
+        .form(&[("email", EMAIL)])
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+
+    let audit = sqlx::query!("SELECT action, actor_user_id FROM audit_log ORDER BY created_at")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(audit.len(), 2);
+    assert_eq!(audit[1].action, "subscriber_erased");
+    assert_eq!(audit[1].actor_user_id, Some(app.test_user.user_id));
+
+    // a second erasure finds nothing to erase
+    let response = client
+        .post(format!("{}/admin/subscribers/erase", app.address))
+        .form(&[("email", EMAIL)])
+        .basic_auth(&app.test_user.username, Some(&app.test_user.password))
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 404);
+}
+
+#[tokio::test]
+async fn admin_data_endpoints_require_authentication() {
+    let app = spawn_app().await;
+
+    let response = reqwest::Client::new()
+        .post(format!("{}/admin/subscribers/erase", app.address))
+        .form(&[("email", EMAIL)])
+        .send()
+        .await
+        .unwrap();
+
+    assert_eq!(response.status(), 401);
+}
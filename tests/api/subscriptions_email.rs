@@ -0,0 +1,185 @@
+use crate::helpers::{spawn_app, TestApp};
+use wiremock::{
+    matchers::{method, path},
+    Mock, ResponseTemplate,
+};
+
+const EMAIL: &str = "ursula_le_guin@gmail.com";
+const NEW_EMAIL: &str = "ursula@earthsea.org";
+
+/// The data access token of the manage subscription page of `email`
+async fn manage_token(app: &TestApp, email: &str) -> String {
+    let link = app.get_data_access_link(email).await;
+    link.query_pairs()
+        .find(|(k, _)| k == "token")
+        .unwrap()
+        .1
+        .into_owned()
+}
+
+async fn current_email(app: &TestApp) -> String {
+    sqlx::query!("SELECT email FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .email
+}
+
+#[tokio::test]
+async fn the_email_only_changes_once_the_new_address_is_confirmed() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+    let token = manage_token(&app, EMAIL).await;
+
+    Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&app.email_server)
+        .await;
+    let response = app.post_email_change(&token, NEW_EMAIL).await;
+    assert_eq!(response.status(), 200);
+    assert_eq!(current_email(&app).await, EMAIL);
+
+    let requests = app.email_server.received_requests().await.unwrap();
+    let sent: Vec<serde_json::Value> = requests
+        .iter()
+        .rev()
+        .take(2)
+        .map(|r| serde_json::from_slice(&r.body).unwrap())
+        .collect();
+    let notice = sent.iter().find(|e| e["To"] == EMAIL).unwrap();
+    assert!(notice["TextBody"].as_str().unwrap().contains(NEW_EMAIL));
+    let confirmation = requests
+        .iter()
+        .rev()
+        .find(|r| serde_json::from_slice::<serde_json::Value>(&r.body).unwrap()["To"] == NEW_EMAIL)
+        .unwrap();
+    let link = app.get_confirmation_links(confirmation).0;
+
+    let response = reqwest::get(link.clone()).await.unwrap();
+    assert_eq!(response.status(), 200);
+    assert_eq!(current_email(&app).await, NEW_EMAIL);
+
+    // the status and its history are kept
+    let history = sqlx::query!(r#"SELECT count(*) as "count!" FROM subscription_status_history"#)
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(history.count, 2);
+
+    // the link can only be used once
+    let response = reqwest::get(link).await.unwrap();
+    assert_eq!(response.status(), 401);
+}
+
+#[tokio::test]
+async fn an_address_used_by_another_subscriber_cannot_be_confirmed() {
+    let app = spawn_app().await;
+    app.post_subscribers_import(
+        &format!("email,name\n{EMAIL},Ursula\n{NEW_EMAIL},Someone else\n"),
+        true,
+    )
+    .await
+    .error_for_status()
+    .unwrap();
+    let token = manage_token(&app, EMAIL).await;
+
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    app.post_email_change(&token, NEW_EMAIL)
+        .await
+        .error_for_status()
+        .unwrap();
+    let requests = app.email_server.received_requests().await.unwrap();
+    let confirmation = requests
+        .iter()
+        .find(|r| serde_json::from_slice::<serde_json::Value>(&r.body).unwrap()["To"] == NEW_EMAIL)
+        .unwrap();
+    let link = app.get_confirmation_links(confirmation).0;
+
+    let response = reqwest::get(link).await.unwrap();
+
+    assert_eq!(response.status(), 409);
+}
+
+#[tokio::test]
+async fn an_address_taken_while_confirming_is_a_conflict() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+    let token = manage_token(&app, EMAIL).await;
+
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&app.email_server)
+        .await;
+    app.post_email_change(&token, NEW_EMAIL)
+        .await
+        .error_for_status()
+        .unwrap();
+    let requests = app.email_server.received_requests().await.unwrap();
+    let confirmation = requests
+        .iter()
+        .find(|r| serde_json::from_slice::<serde_json::Value>(&r.body).unwrap()["To"] == NEW_EMAIL)
+        .unwrap();
+    let link = app.get_confirmation_links(confirmation).0;
+
+    // a signup takes the address after the check, and commits while the change waits on it
+    let mut signup = app.db_pool.begin().await.unwrap();
+    sqlx::query!(
+        r#"
+        INSERT INTO subscriptions (id, email, email_normalized, name, subscribed_at, status, status_changed_at, locale)
+        VALUES ($1, $2, $2, 'Someone else', now(), 'pending_confirmation', now(), 'en')
+        "#,
+        uuid::Uuid::new_v4(),
+        NEW_EMAIL,
+    )
+    .execute(&mut signup)
+    .await
+    .unwrap();
+    let confirm = tokio::spawn(reqwest::get(link));
+    tokio::time::sleep(std::time::Duration::from_millis(500)).await;
+    signup.commit().await.unwrap();
+
+    let response = confirm.await.unwrap().unwrap();
+    assert_eq!(response.status(), 409);
+    assert_eq!(
+        sqlx::query!("SELECT email FROM subscriptions WHERE name = 'le guin'")
+            .fetch_one(&app.db_pool)
+            .await
+            .unwrap()
+            .email,
+        EMAIL
+    );
+}
+
+#[tokio::test]
+async fn email_change_requests_are_validated() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+    let token = manage_token(&app, EMAIL).await;
+
+    let test_cases = vec![
+        (token.as_str(), "not-an-email", 400, "an invalid address"),
+        (
+            token.as_str(),
+            "Ursula_Le_Guin@GMAIL.com",
+            400,
+            "the current address",
+        ),
+        ("not-a-token", NEW_EMAIL, 401, "an invalid token"),
+    ];
+    for (token, email, status, description) in test_cases {
+        let response = app.post_email_change(token, email).await;
+        assert_eq!(
+            response.status().as_u16(),
+            status,
+            "The API did not reject {description}"
+        );
+    }
+}
@@ -0,0 +1,385 @@
+use crate::helpers::{spawn_app, TestApp};
+use reqwest::Url;
+use uuid::Uuid;
+use wiremock::{
+    matchers::{any, method, path},
+    Mock, ResponseTemplate,
+};
+
+fn newsletter(list: Option<&str>) -> serde_json::Value {
+    serde_json::json!({
+        "title": "Newsletter title",
+        "content": {
+            "text": "Newsletter body as plain text",
+            "html": "<p>Newsletter body as HTML</p>",
+        },
+        "list": list,
+        "send": true,
+    })
+}
+
+/// Publishes an issue to the single confirmed subscriber and returns the preferences link it contains
+async fn get_preferences_link(app: &TestApp) -> Url {
+    let _guard = Mock::given(path("/email"))
+        .and(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+    app.post_newsletters(&newsletter(None))
+        .await
+        .error_for_status()
+        .unwrap();
+    app.dispatch_all_pending_emails().await;
+    let email_request = app
+        .email_server
+        .received_requests()
+        .await
+        .unwrap()
+        .pop()
+        .unwrap();
+    let (html_link, text_link) = app.get_confirmation_links(&email_request);
+    assert_eq!(html_link, text_link);
+    html_link
+}
+
+fn token(link: &Url) -> String {
+    link.query_pairs()
+        .find(|(k, _)| k == "token")
+        .unwrap()
+        .1
+        .into_owned()
+}
+
+async fn create_list(app: &TestApp, slug: &str, name: &str) -> Uuid {
+    let id = Uuid::new_v4();
+    sqlx::query!(
+        "INSERT INTO lists (id, slug, name, created_at) VALUES ($1, $2, $3, now())",
+        id,
+        slug,
+        name
+    )
+    .execute(&app.db_pool)
+    .await
+    .unwrap();
+    id
+}
+
+async fn newsletter_list(app: &TestApp) -> Uuid {
+    sqlx::query!("SELECT id FROM lists WHERE slug = 'newsletter'")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap()
+        .id
+}
+
+async fn post_preferences(app: &TestApp, form: &[(&str, &str)]) -> reqwest::Response {
+    reqwest::Client::new()
+        .post(format!("{}/subscriptions/preferences", app.address))
+        .form(form)
+        .send()
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn every_newsletter_links_to_the_preference_center() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+
+    let link = get_preferences_link(&app).await;
+    let response = reqwest::get(link).await.unwrap();
+
+    assert_eq!(response.status(), 200);
+    let html = response.text().await.unwrap();
+    assert!(html.contains(r#"value="le guin""#));
+    assert!(html.contains("Newsletter"));
+}
+
+#[tokio::test]
+async fn subscribers_can_update_their_name_lists_frequency_and_time_zone() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+    let token = token(&get_preferences_link(&app).await);
+    let events = create_list(&app, "events", "Events").await;
+
+    let events = events.to_string();
+    let response = post_preferences(
+        &app,
+        &[
+            ("token", &token),
+            ("name", "Ursula K. Le Guin"),
+            ("frequency", "weekly_digest"),
+            ("time_zone", "America/Los_Angeles"),
+            ("list", &events),
+        ],
+    )
+    .await;
+
+    assert_eq!(response.status(), 200);
+    let saved = sqlx::query!("SELECT name, delivery_frequency, time_zone FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.name, "Ursula K. Le Guin");
+    assert_eq!(saved.delivery_frequency, "weekly_digest");
+    assert_eq!(saved.time_zone, "America/Los_Angeles");
+    let lists = sqlx::query!("SELECT slug FROM lists JOIN subscription_lists ON list_id = id")
+        .fetch_all(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(lists.len(), 1);
+    assert_eq!(lists[0].slug, "events");
+}
+
+#[tokio::test]
+async fn invalid_preferences_are_rejected_with_a_400() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+    let token = token(&get_preferences_link(&app).await);
+
+    let test_cases = vec![
+        (
+            vec![("name", ""), ("frequency", "every_issue")],
+            "an empty name",
+        ),
+        (
+            vec![("name", "Ursula"), ("frequency", "daily")],
+            "an unknown frequency",
+        ),
+        (
+            vec![
+                ("name", "Ursula"),
+                ("frequency", "every_issue"),
+                ("list", "42"),
+            ],
+            "an invalid list",
+        ),
+        (
+            vec![
+                ("name", "Ursula"),
+                ("frequency", "every_issue"),
+                ("time_zone", "Mars/Olympus_Mons"),
+            ],
+            "an unknown time zone",
+        ),
+    ];
+    for (mut form, description) in test_cases {
+        form.push(("token", &token));
+        let response = post_preferences(&app, &form).await;
+        assert_eq!(
+            response.status(),
+            400,
+            "The API did not reject {description}"
+        );
+    }
+}
+
+#[tokio::test]
+async fn subscribers_can_unsubscribe_from_the_preference_center() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+    let token = token(&get_preferences_link(&app).await);
+
+    let response = reqwest::Client::new()
+        .post(format!(
+            "{}/subscriptions/preferences/unsubscribe",
+            app.address
+        ))
+        .form(&[("token", &token)])
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(response.status(), 200);
+
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, "unsubscribed");
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+    app.post_newsletters(&newsletter(None))
+        .await
+        .error_for_status()
+        .unwrap();
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn subscribers_who_unsubscribed_can_subscribe_again() {
+    let app = spawn_app().await;
+    let body = "name=le%20guin&email=ursula_le_guin%40gmail.com";
+    app.create_confirmed_subscriber(body.into()).await;
+    let token = token(&get_preferences_link(&app).await);
+    reqwest::Client::new()
+        .post(format!(
+            "{}/subscriptions/preferences/unsubscribe",
+            app.address
+        ))
+        .form(&[("token", &token)])
+        .send()
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+
+    let confirmation_link = app.create_unconfirmed_subscriber(body.into()).await;
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, "pending_confirmation");
+
+    reqwest::get(confirmation_link)
+        .await
+        .unwrap()
+        .error_for_status()
+        .unwrap();
+    let saved = sqlx::query!("SELECT status FROM subscriptions")
+        .fetch_one(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(saved.status, "confirmed");
+}
+
+#[tokio::test]
+async fn forged_preference_links_are_rejected_with_a_401() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+    let token = token(&get_preferences_link(&app).await);
+    let (_, signature) = token.split_once('.').unwrap();
+
+    for forged in [format!("{}.{signature}", Uuid::new_v4()), "nope".into()] {
+        let response = reqwest::get(format!(
+            "{}/subscriptions/preferences?token={forged}",
+            app.address
+        ))
+        .await
+        .unwrap();
+        assert_eq!(response.status(), 401);
+    }
+}
+
+#[tokio::test]
+async fn newsletters_sent_to_a_list_only_reach_its_members() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+    // created after the subscriber signed up, so they are not a member
+    create_list(&app, "events", "Events").await;
+
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+
+    let response = app.post_newsletters(&newsletter(Some("events"))).await;
+    assert_eq!(response.status(), 202);
+    let response = app.post_newsletters(&newsletter(Some("newsletter"))).await;
+    assert_eq!(response.status(), 202);
+    let response = app.post_newsletters(&newsletter(Some("unknown"))).await;
+    assert_eq!(response.status(), 400);
+    app.dispatch_all_pending_emails().await;
+}
+
+#[tokio::test]
+async fn weekly_digest_subscribers_get_every_issue_of_the_week_in_one_email() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+    let token = token(&get_preferences_link(&app).await);
+    post_preferences(
+        &app,
+        &[
+            ("token", &token),
+            ("name", "le guin"),
+            ("frequency", "weekly_digest"),
+            ("list", &newsletter_list(&app).await.to_string()),
+        ],
+    )
+    .await
+    .error_for_status()
+    .unwrap();
+
+    let guard = Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount_as_scoped(&app.email_server)
+        .await;
+    for title in ["First issue", "Second issue"] {
+        let mut issue = newsletter(None);
+        issue["title"] = title.into();
+        let response = app.post_newsletters(&issue).await;
+        assert_eq!(response.status(), 202);
+    }
+    // nothing goes out with the issues
+    app.dispatch_all_pending_emails().await;
+    assert_eq!(app.send_due_digests().await, 1);
+    assert_eq!(app.send_due_digests().await, 0);
+
+    let email_request = &guard.received_requests().await[0];
+    let body: serde_json::Value = serde_json::from_slice(&email_request.body).unwrap();
+    assert_eq!(
+        body["Subject"],
+        "Your weekly digest: First issue and 1 more"
+    );
+    let text = body["TextBody"].as_str().unwrap();
+    assert!(text.find("First issue").unwrap() < text.find("Second issue").unwrap());
+    drop(guard);
+
+    // the next issue waits for the following week
+    Mock::given(path("/email"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&app.email_server)
+        .await;
+    app.post_newsletters(&newsletter(None))
+        .await
+        .error_for_status()
+        .unwrap();
+    assert_eq!(app.send_due_digests().await, 0);
+    sqlx::query!("UPDATE subscriptions SET last_digest_at = now() - interval '8 days'")
+        .execute(&app.db_pool)
+        .await
+        .unwrap();
+    assert_eq!(app.send_due_digests().await, 1);
+}
+
+#[tokio::test]
+async fn newsletters_sent_to_every_list_skip_subscribers_who_left_them_all() {
+    let app = spawn_app().await;
+    app.create_confirmed_subscriber("name=le%20guin&email=ursula_le_guin%40gmail.com".into())
+        .await;
+    let token = token(&get_preferences_link(&app).await);
+    post_preferences(
+        &app,
+        &[
+            ("token", &token),
+            ("name", "le guin"),
+            ("frequency", "every_issue"),
+        ],
+    )
+    .await
+    .error_for_status()
+    .unwrap();
+
+    Mock::given(any())
+        .respond_with(ResponseTemplate::new(200))
+        .expect(0)
+        .mount(&app.email_server)
+        .await;
+    let response = app.post_newsletters(&newsletter(None)).await;
+    assert_eq!(response.status(), 202);
+    app.dispatch_all_pending_emails().await;
+}